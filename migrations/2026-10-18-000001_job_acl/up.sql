@@ -0,0 +1,15 @@
+-- Grants read access to a job for a specific user or for everyone holding a role
+CREATE TABLE IF NOT EXISTS job_acl (
+    id SERIAL PRIMARY KEY NOT NULL,
+    job_id UUID NOT NULL,
+    user_id INTEGER,
+    role_name role_name,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_job_acl_job_id FOREIGN KEY(job_id) REFERENCES jobs(job_id) ON DELETE CASCADE,
+    CONSTRAINT fk_job_acl_user_id FOREIGN KEY(user_id) REFERENCES users(id),
+    CONSTRAINT chk_job_acl_single_grantee CHECK ((user_id IS NULL) <> (role_name IS NULL)),
+    CONSTRAINT uq_job_acl_user UNIQUE (job_id, user_id),
+    CONSTRAINT uq_job_acl_role UNIQUE (job_id, role_name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_acl_job_id ON job_acl(job_id);
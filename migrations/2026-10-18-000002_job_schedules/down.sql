@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS job_schedules;
+DROP TYPE IF EXISTS missed_run_policy;
@@ -0,0 +1,22 @@
+-- Recurring jobs started by the scheduler worker on a cron schedule
+CREATE TYPE missed_run_policy AS ENUM ('skip', 'run_once');
+
+CREATE TABLE IF NOT EXISTS job_schedules (
+    id SERIAL PRIMARY KEY NOT NULL,
+    name VARCHAR NOT NULL,
+    cron_expression VARCHAR NOT NULL,
+    args TEXT[] NOT NULL DEFAULT '{}',
+    pty BOOLEAN NOT NULL DEFAULT FALSE,
+    created_by INTEGER NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    missed_run_policy missed_run_policy NOT NULL DEFAULT 'skip',
+    last_run_at TIMESTAMP,
+    last_job_id UUID,
+    next_run_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_job_schedules_created_by FOREIGN KEY(created_by) REFERENCES users(id),
+    CONSTRAINT fk_job_schedules_last_job_id FOREIGN KEY(last_job_id) REFERENCES jobs(job_id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_schedules_next_run_at ON job_schedules(next_run_at) WHERE enabled;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS pipeline_steps;
+DROP TYPE IF EXISTS step_failure_policy;
@@ -0,0 +1,19 @@
+-- Steps of a pipeline. The pipeline and each of its steps are jobs, the
+-- pipeline job aggregates the status of its steps.
+CREATE TYPE step_failure_policy AS ENUM ('fail_fast', 'continue');
+
+CREATE TABLE IF NOT EXISTS pipeline_steps (
+    id SERIAL PRIMARY KEY NOT NULL,
+    pipeline_job_id UUID NOT NULL,
+    job_id UUID NOT NULL UNIQUE,
+    name VARCHAR NOT NULL,
+    args TEXT[] NOT NULL DEFAULT '{}',
+    pty BOOLEAN NOT NULL DEFAULT FALSE,
+    depends_on TEXT[] NOT NULL DEFAULT '{}',
+    on_failure step_failure_policy NOT NULL DEFAULT 'fail_fast',
+    output VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_pipeline_steps_pipeline_job_id FOREIGN KEY(pipeline_job_id) REFERENCES jobs(job_id) ON DELETE CASCADE,
+    CONSTRAINT fk_pipeline_steps_job_id FOREIGN KEY(job_id) REFERENCES jobs(job_id) ON DELETE CASCADE,
+    CONSTRAINT uq_pipeline_steps_name UNIQUE (pipeline_job_id, name)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE pipeline_steps DROP COLUMN IF EXISTS artifacts;
+ALTER TABLE job_schedules DROP COLUMN IF EXISTS artifacts;
+DROP TABLE IF EXISTS job_artifacts;
@@ -0,0 +1,18 @@
+-- Files collected from the working directory of a job, stored in MinIO
+CREATE TABLE IF NOT EXISTS job_artifacts (
+    id SERIAL PRIMARY KEY NOT NULL,
+    job_id UUID NOT NULL,
+    path VARCHAR NOT NULL,
+    object_key VARCHAR NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    content_type VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_job_artifacts_job_id FOREIGN KEY(job_id) REFERENCES jobs(job_id) ON DELETE CASCADE,
+    CONSTRAINT uq_job_artifacts_path UNIQUE (job_id, path)
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_artifacts_created_at ON job_artifacts(created_at);
+
+-- Glob patterns of the files to collect, relative to the job working directory
+ALTER TABLE job_schedules ADD COLUMN IF NOT EXISTS artifacts TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE pipeline_steps ADD COLUMN IF NOT EXISTS artifacts TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS uq_jobs_idempotency_key;
+ALTER TABLE job_schedules DROP COLUMN IF EXISTS retry;
+ALTER TABLE jobs DROP COLUMN IF EXISTS request;
+ALTER TABLE jobs DROP COLUMN IF EXISTS idempotency_key;
+ALTER TABLE jobs DROP CONSTRAINT IF EXISTS fk_jobs_retry_of;
+ALTER TABLE jobs DROP COLUMN IF EXISTS retry_of;
+ALTER TABLE jobs DROP COLUMN IF EXISTS attempt;
@@ -0,0 +1,14 @@
+-- Automatic retries rerun the same job and bump its attempt, manual retries
+-- create a new job linked to the one they retry
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS attempt INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS retry_of UUID;
+ALTER TABLE jobs ADD CONSTRAINT fk_jobs_retry_of FOREIGN KEY(retry_of) REFERENCES jobs(job_id) ON DELETE SET NULL;
+-- Client supplied key, a repeated key returns the job it created
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS idempotency_key VARCHAR;
+-- The run request, kept so that the job can be retried
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS request JSONB;
+
+-- Retry policy of the jobs started by a schedule
+ALTER TABLE job_schedules ADD COLUMN IF NOT EXISTS retry JSONB;
+
+CREATE UNIQUE INDEX IF NOT EXISTS uq_jobs_idempotency_key ON jobs(started_by, idempotency_key) WHERE idempotency_key IS NOT NULL;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS runner_jobs;
+DROP TABLE IF EXISTS runners;
@@ -0,0 +1,31 @@
+-- Runner processes executing jobs away from the API hosts
+CREATE TABLE IF NOT EXISTS runners (
+    id SERIAL PRIMARY KEY NOT NULL,
+    name VARCHAR NOT NULL UNIQUE,
+    -- SHA-256 of the runner token, the token itself is only shown once
+    token_hash VARCHAR NOT NULL UNIQUE,
+    labels TEXT[] NOT NULL DEFAULT '{}',
+    created_by INTEGER NOT NULL,
+    last_heartbeat_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_runners_created_by FOREIGN KEY(created_by) REFERENCES users(id)
+);
+
+-- Jobs queued for runners, a job is claimed by the first runner having all
+-- of its labels
+CREATE TABLE IF NOT EXISTS runner_jobs (
+    job_id UUID PRIMARY KEY NOT NULL,
+    labels TEXT[] NOT NULL DEFAULT '{}',
+    runner_id INTEGER,
+    claimed_at TIMESTAMP,
+    -- Times the job went back to the queue after its runner died
+    requeues INTEGER NOT NULL DEFAULT 0,
+    abort_requested BOOLEAN NOT NULL DEFAULT FALSE,
+    finished_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_runner_jobs_job_id FOREIGN KEY(job_id) REFERENCES jobs(job_id) ON DELETE CASCADE,
+    CONSTRAINT fk_runner_jobs_runner_id FOREIGN KEY(runner_id) REFERENCES runners(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_runner_jobs_queued ON runner_jobs(created_at) WHERE runner_id IS NULL AND finished_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_runner_jobs_runner_id ON runner_jobs(runner_id) WHERE finished_at IS NULL;
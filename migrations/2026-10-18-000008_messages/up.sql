@@ -0,0 +1,16 @@
+-- Direct messages between users, the Redis stream of the receiver only
+-- buffers them for live delivery
+CREATE TABLE IF NOT EXISTS messages (
+    id SERIAL PRIMARY KEY NOT NULL,
+    sender_id INTEGER NOT NULL,
+    receiver_id INTEGER NOT NULL,
+    message_text TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    is_read BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT fk_messages_sender_id FOREIGN KEY(sender_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_messages_receiver_id FOREIGN KEY(receiver_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_messages_conversation ON messages(receiver_id, sender_id, id);
+CREATE INDEX IF NOT EXISTS idx_messages_sender_id ON messages(sender_id);
+CREATE INDEX IF NOT EXISTS idx_messages_unread ON messages(receiver_id) WHERE NOT is_read;
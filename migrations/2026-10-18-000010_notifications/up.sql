@@ -0,0 +1,15 @@
+-- System events of a user, such as finished jobs and new logins
+CREATE TYPE notification_kind AS ENUM ('job_finished', 'new_session');
+
+CREATE TABLE IF NOT EXISTS notifications (
+    id SERIAL PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL,
+    kind notification_kind NOT NULL,
+    data JSONB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMP,
+    CONSTRAINT fk_notifications_user_id FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_user_id ON notifications(user_id, id);
+CREATE INDEX IF NOT EXISTS idx_notifications_unread ON notifications(user_id) WHERE read_at IS NULL;
@@ -0,0 +1,7 @@
+DELETE FROM notifications WHERE kind = 'suspicious_login';
+
+ALTER TYPE notification_kind RENAME TO notification_kind_old;
+CREATE TYPE notification_kind AS ENUM ('job_finished', 'new_session');
+ALTER TABLE notifications
+    ALTER COLUMN kind TYPE notification_kind USING kind::text::notification_kind;
+DROP TYPE notification_kind_old;
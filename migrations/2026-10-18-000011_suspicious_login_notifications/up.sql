@@ -0,0 +1,2 @@
+-- Logins from a new device, country or network, or after impossible travel
+ALTER TYPE notification_kind ADD VALUE IF NOT EXISTS 'suspicious_login';
@@ -0,0 +1,6 @@
+-- Highest fencing token seen from the leader of each worker, writes of
+-- leaders with an older token are rejected
+CREATE TABLE IF NOT EXISTS worker_fences (
+    worker VARCHAR NOT NULL PRIMARY KEY,
+    fencing_token BIGINT NOT NULL
+);
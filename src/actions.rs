@@ -1,2 +1,11 @@
+pub mod artifacts;
+pub mod conversations;
+pub mod messages;
 pub mod misc;
+pub mod notifications;
+pub mod pipelines;
+pub mod runners;
+pub mod schedules;
 pub mod users;
+pub mod webhooks;
+pub mod workers;
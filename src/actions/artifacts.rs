@@ -0,0 +1,80 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::artifact::{JobArtifact, NewJobArtifact},
+    types::DbConnection,
+};
+
+/// Replaces the artifacts of an earlier attempt of the job with `artifacts`.
+/// Returns the new artifacts and the replaced ones.
+pub fn replace_job_artifacts(
+    job_id: uuid::Uuid,
+    artifacts: &[NewJobArtifact],
+    conn: &mut DbConnection,
+) -> Result<(Vec<JobArtifact>, Vec<JobArtifact>), DomainError> {
+    use crate::schema::job_artifacts::dsl as job_artifacts;
+    let res = conn.transaction(|conn| {
+        let replaced = diesel::delete(
+            job_artifacts::job_artifacts
+                .filter(job_artifacts::job_id.eq(job_id)),
+        )
+        .get_results::<JobArtifact>(conn)?;
+        let created = diesel::insert_into(job_artifacts::job_artifacts)
+            .values(artifacts)
+            .get_results::<JobArtifact>(conn)?;
+        Ok::<_, diesel::result::Error>((created, replaced))
+    })?;
+    Ok(res)
+}
+
+pub fn get_job_artifacts(
+    job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<Vec<JobArtifact>, DomainError> {
+    use crate::schema::job_artifacts::dsl as job_artifacts;
+    Ok(job_artifacts::job_artifacts
+        .filter(job_artifacts::job_id.eq(job_id))
+        .order_by(job_artifacts::path)
+        .load::<JobArtifact>(conn)?)
+}
+
+pub fn get_job_artifact(
+    job_id: uuid::Uuid,
+    artifact_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<JobArtifact>, DomainError> {
+    use crate::schema::job_artifacts::dsl as job_artifacts;
+    Ok(job_artifacts::job_artifacts
+        .filter(job_artifacts::id.eq(artifact_id))
+        .filter(job_artifacts::job_id.eq(job_id))
+        .first::<JobArtifact>(conn)
+        .optional()?)
+}
+
+/// Oldest artifacts created before `before`, at most `limit` of them
+pub fn get_expired_job_artifacts(
+    before: NaiveDateTime,
+    limit: i64,
+    conn: &mut DbConnection,
+) -> Result<Vec<JobArtifact>, DomainError> {
+    use crate::schema::job_artifacts::dsl as job_artifacts;
+    Ok(job_artifacts::job_artifacts
+        .filter(job_artifacts::created_at.lt(before))
+        .order_by(job_artifacts::created_at)
+        .limit(limit)
+        .load::<JobArtifact>(conn)?)
+}
+
+pub fn delete_job_artifacts(
+    artifact_ids: &[i32],
+    conn: &mut DbConnection,
+) -> Result<usize, DomainError> {
+    use crate::schema::job_artifacts::dsl as job_artifacts;
+    Ok(diesel::delete(
+        job_artifacts::job_artifacts
+            .filter(job_artifacts::id.eq_any(artifact_ids)),
+    )
+    .execute(conn)?)
+}
@@ -0,0 +1,344 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        conversation::{
+            Conversation, ConversationDetails, ConversationMember,
+            ConversationMessage, ConversationMessagesPage, ConversationRole,
+            NewConversation, NewConversationMember, NewConversationMessage,
+        },
+        message::MessagesQuery,
+        users::UserId,
+    },
+    types::DbConnection,
+};
+
+/// Creates a conversation owned by its creator with the other users as
+/// members
+pub fn create_conversation(
+    new_conversation: &NewConversation,
+    member_ids: &[UserId],
+    conn: &mut DbConnection,
+) -> Result<ConversationDetails, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    use crate::schema::conversations::dsl as conversations;
+    use crate::schema::users::dsl as users;
+
+    let mut member_ids = member_ids
+        .iter()
+        .filter(|user_id| **user_id != new_conversation.created_by)
+        .copied()
+        .collect::<Vec<_>>();
+    member_ids.sort_by_key(|user_id| user_id.as_uint());
+    member_ids.dedup();
+
+    conn.transaction(|conn| {
+        let found = users::users
+            .filter(users::id.eq_any(member_ids.clone()))
+            .filter(users::deleted_at.is_null())
+            .count()
+            .get_result::<i64>(conn)?;
+        if usize::try_from(found).ok() != Some(member_ids.len()) {
+            return Err(DomainError::new_bad_input_error(
+                "Some of the members do not exist".to_owned(),
+            ));
+        }
+
+        let conversation = diesel::insert_into(conversations::conversations)
+            .values(new_conversation)
+            .get_result::<Conversation>(conn)?;
+        let new_members = std::iter::once(NewConversationMember {
+            conversation_id: conversation.id,
+            user_id: new_conversation.created_by,
+            role: ConversationRole::Owner,
+        })
+        .chain(member_ids.iter().map(|user_id| NewConversationMember {
+            conversation_id: conversation.id,
+            user_id: *user_id,
+            role: ConversationRole::Member,
+        }))
+        .collect::<Vec<_>>();
+        let _ = diesel::insert_into(members::conversation_members)
+            .values(&new_members)
+            .execute(conn)?;
+        let members = get_conversation_members(conversation.id, conn)?;
+
+        Ok(ConversationDetails {
+            conversation,
+            members,
+        })
+    })
+}
+
+/// Conversations the user is a member of, most recently updated first
+pub fn get_conversations_of_user(
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<Vec<Conversation>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    use crate::schema::conversations::dsl as conversations;
+    Ok(conversations::conversations
+        .inner_join(members::conversation_members)
+        .filter(members::user_id.eq(user_id))
+        .select(crate::schema::conversations::all_columns)
+        .order_by((conversations::updated_at.desc(), conversations::id.desc()))
+        .load::<Conversation>(conn)?)
+}
+
+pub fn get_conversation(
+    conversation_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<Conversation>, DomainError> {
+    use crate::schema::conversations::dsl as conversations;
+    Ok(conversations::conversations
+        .filter(conversations::id.eq(conversation_id))
+        .first::<Conversation>(conn)
+        .optional()?)
+}
+
+pub fn get_conversation_members(
+    conversation_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Vec<ConversationMember>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    Ok(members::conversation_members
+        .filter(members::conversation_id.eq(conversation_id))
+        .order_by((members::joined_at, members::user_id))
+        .load::<ConversationMember>(conn)?)
+}
+
+pub fn get_conversation_member(
+    conversation_id: i32,
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<Option<ConversationMember>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    Ok(members::conversation_members
+        .filter(members::conversation_id.eq(conversation_id))
+        .filter(members::user_id.eq(user_id))
+        .first::<ConversationMember>(conn)
+        .optional()?)
+}
+
+pub fn rename_conversation(
+    conversation_id: i32,
+    name: &str,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<Conversation, DomainError> {
+    use crate::schema::conversations::dsl as conversations;
+    Ok(diesel::update(
+        conversations::conversations
+            .filter(conversations::id.eq(conversation_id)),
+    )
+    .set((
+        conversations::name.eq(name),
+        conversations::updated_at.eq(now),
+    ))
+    .get_result::<Conversation>(conn)?)
+}
+
+/// Adds a member unless the conversation already has `max_members`, returns
+/// `None` when the user already is one. The conversation is locked while
+/// its members are counted, so that concurrent additions can't exceed the
+/// limit.
+pub fn add_conversation_member(
+    new_member: &NewConversationMember,
+    max_members: usize,
+    conn: &mut DbConnection,
+) -> Result<Option<ConversationMember>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    use crate::schema::conversations::dsl as conversations;
+    conn.transaction(|conn| {
+        let _ = conversations::conversations
+            .filter(conversations::id.eq(new_member.conversation_id))
+            .select(conversations::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| {
+                DomainError::new_entity_does_not_exist_error(format!(
+                    "No conversation with id: {}",
+                    new_member.conversation_id
+                ))
+            })?;
+        let count = members::conversation_members
+            .filter(members::conversation_id.eq(new_member.conversation_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        if usize::try_from(count).unwrap_or(usize::MAX) >= max_members {
+            return Err(DomainError::new_bad_input_error(format!(
+                "At most {max_members} members are allowed per conversation"
+            )));
+        }
+        Ok(diesel::insert_into(members::conversation_members)
+            .values(new_member)
+            .on_conflict_do_nothing()
+            .get_result::<ConversationMember>(conn)
+            .optional()?)
+    })
+}
+
+/// Changes the role of a member. Making someone the owner hands the
+/// ownership over, the previous owner becomes an admin.
+pub fn update_conversation_member_role(
+    conversation_id: i32,
+    user_id: UserId,
+    role: ConversationRole,
+    conn: &mut DbConnection,
+) -> Result<Vec<ConversationMember>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    conn.transaction(|conn| {
+        let mut changed = Vec::new();
+        if role == ConversationRole::Owner {
+            changed = diesel::update(
+                members::conversation_members
+                    .filter(members::conversation_id.eq(conversation_id))
+                    .filter(members::role.eq(ConversationRole::Owner))
+                    .filter(members::user_id.ne(user_id)),
+            )
+            .set(members::role.eq(ConversationRole::Admin))
+            .get_results::<ConversationMember>(conn)?;
+        }
+        let member = diesel::update(
+            members::conversation_members
+                .filter(members::conversation_id.eq(conversation_id))
+                .filter(members::user_id.eq(user_id)),
+        )
+        .set(members::role.eq(role))
+        .get_result::<ConversationMember>(conn)?;
+        changed.push(member);
+        Ok(changed)
+    })
+}
+
+/// Removes a member. When the owner leaves, the longest standing of the
+/// admins, or else of the members, becomes the owner and is returned. The
+/// conversation is deleted along with its last member.
+pub fn remove_conversation_member(
+    conversation_id: i32,
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<Option<ConversationMember>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    use crate::schema::conversations::dsl as conversations;
+    conn.transaction(|conn| {
+        let removed = diesel::delete(
+            members::conversation_members
+                .filter(members::conversation_id.eq(conversation_id))
+                .filter(members::user_id.eq(user_id)),
+        )
+        .get_result::<ConversationMember>(conn)
+        .optional()?;
+        let Some(removed) = removed else {
+            return Ok(None);
+        };
+
+        let successor = members::conversation_members
+            .filter(members::conversation_id.eq(conversation_id))
+            .order_by((
+                members::role.eq(ConversationRole::Admin).desc(),
+                members::joined_at,
+                members::user_id,
+            ))
+            .first::<ConversationMember>(conn)
+            .optional()?;
+        match successor {
+            None => {
+                let _ = diesel::delete(
+                    conversations::conversations
+                        .filter(conversations::id.eq(conversation_id)),
+                )
+                .execute(conn)?;
+                Ok(None)
+            }
+            Some(successor) if removed.role == ConversationRole::Owner => {
+                let owner = diesel::update(
+                    members::conversation_members
+                        .filter(members::conversation_id.eq(conversation_id))
+                        .filter(members::user_id.eq(successor.user_id)),
+                )
+                .set(members::role.eq(ConversationRole::Owner))
+                .get_result::<ConversationMember>(conn)?;
+                Ok(Some(owner))
+            }
+            Some(_) => Ok(None),
+        }
+    })
+}
+
+/// Stores a message sent to a conversation and bumps the conversation to the
+/// top of the lists of its members
+pub fn create_conversation_message(
+    new_message: &NewConversationMessage,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<ConversationMessage, DomainError> {
+    use crate::schema::conversation_messages::dsl as messages;
+    use crate::schema::conversations::dsl as conversations;
+    conn.transaction(|conn| {
+        let message = diesel::insert_into(messages::conversation_messages)
+            .values(new_message)
+            .get_result::<ConversationMessage>(conn)?;
+        let _ = diesel::update(
+            conversations::conversations
+                .filter(conversations::id.eq(new_message.conversation_id)),
+        )
+        .set(conversations::updated_at.eq(now))
+        .execute(conn)?;
+        Ok(message)
+    })
+}
+
+/// Messages of a conversation, newest first
+pub fn get_conversation_messages(
+    conversation_id: i32,
+    query: &MessagesQuery,
+    conn: &mut DbConnection,
+) -> Result<ConversationMessagesPage, DomainError> {
+    use crate::schema::conversation_messages::dsl as messages;
+
+    let limit = query.limit();
+    let mut q = messages::conversation_messages
+        .filter(messages::conversation_id.eq(conversation_id))
+        .into_boxed();
+    if let Some(before) = query.before {
+        q = q.filter(messages::id.lt(before));
+    }
+    // one more than asked for tells whether there is another page
+    let mut messages = q
+        .order_by(messages::id.desc())
+        .limit(i64::from(limit) + 1)
+        .load::<ConversationMessage>(conn)?;
+
+    let next_cursor = if messages.len() > usize::from(limit) {
+        messages.truncate(usize::from(limit));
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    };
+    Ok(ConversationMessagesPage {
+        messages,
+        next_cursor,
+    })
+}
+
+/// Users sharing at least one conversation with the user
+pub fn get_conversation_peers(
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<Vec<UserId>, DomainError> {
+    use crate::schema::conversation_members::dsl as members;
+    let conversation_ids = members::conversation_members
+        .filter(members::user_id.eq(user_id))
+        .select(members::conversation_id)
+        .load::<i32>(conn)?;
+    Ok(members::conversation_members
+        .filter(members::conversation_id.eq_any(conversation_ids))
+        .filter(members::user_id.ne(user_id))
+        .select(members::user_id)
+        .distinct()
+        .load::<UserId>(conn)?)
+}
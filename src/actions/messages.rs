@@ -0,0 +1,125 @@
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        message::{
+            Message, MessagesPage, MessagesQuery, NewMessage, UnreadCount,
+            UnreadCounts,
+        },
+        users::UserId,
+    },
+    types::DbConnection,
+};
+
+pub fn create_message(
+    new_message: &NewMessage,
+    conn: &mut DbConnection,
+) -> Result<Message, DomainError> {
+    use crate::schema::messages::dsl as messages;
+    Ok(diesel::insert_into(messages::messages)
+        .values(new_message)
+        .get_result::<Message>(conn)?)
+}
+
+/// Messages exchanged between `user_id` and `peer`, newest first
+pub fn get_conversation(
+    user_id: UserId,
+    peer: UserId,
+    query: &MessagesQuery,
+    conn: &mut DbConnection,
+) -> Result<MessagesPage, DomainError> {
+    use crate::schema::messages::dsl as messages;
+
+    let limit = query.limit();
+    let mut q = messages::messages
+        .filter(
+            messages::sender_id
+                .eq(user_id)
+                .and(messages::receiver_id.eq(peer))
+                .or(messages::sender_id
+                    .eq(peer)
+                    .and(messages::receiver_id.eq(user_id))),
+        )
+        .into_boxed();
+    if let Some(before) = query.before {
+        q = q.filter(messages::id.lt(before));
+    }
+    // one more than asked for tells whether there is another page
+    let mut messages = q
+        .order_by(messages::id.desc())
+        .limit(i64::from(limit) + 1)
+        .load::<Message>(conn)?;
+
+    let next_cursor = if messages.len() > usize::from(limit) {
+        messages.truncate(usize::from(limit));
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    };
+    Ok(MessagesPage {
+        messages,
+        next_cursor,
+    })
+}
+
+/// Marks the messages `peer` sent to `user_id` as read, up to the message
+/// with id `up_to` if given. Returns how many were marked.
+pub fn mark_conversation_read(
+    user_id: UserId,
+    peer: UserId,
+    up_to: Option<i32>,
+    conn: &mut DbConnection,
+) -> Result<usize, DomainError> {
+    use crate::schema::messages::dsl as messages;
+    let unread = messages::messages
+        .filter(messages::receiver_id.eq(user_id))
+        .filter(messages::sender_id.eq(peer))
+        .filter(messages::is_read.eq(false))
+        .filter(messages::id.le(up_to.unwrap_or(i32::MAX)));
+    Ok(diesel::update(unread)
+        .set(messages::is_read.eq(true))
+        .execute(conn)?)
+}
+
+pub fn get_unread_counts(
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<UnreadCounts, DomainError> {
+    use crate::schema::messages::dsl as messages;
+    use diesel::dsl::count_star;
+    let peers = messages::messages
+        .filter(messages::receiver_id.eq(user_id))
+        .filter(messages::is_read.eq(false))
+        .group_by(messages::sender_id)
+        .select((messages::sender_id, count_star()))
+        .order_by(messages::sender_id)
+        .load::<UnreadCount>(conn)?;
+    Ok(UnreadCounts {
+        total: peers.iter().map(|peer| peer.count).sum(),
+        peers,
+    })
+}
+
+/// Users who exchanged direct messages with the user
+pub fn get_message_peers(
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<Vec<UserId>, DomainError> {
+    use crate::schema::messages::dsl as messages;
+    let mut peers = messages::messages
+        .filter(messages::sender_id.eq(user_id))
+        .select(messages::receiver_id)
+        .distinct()
+        .load::<UserId>(conn)?;
+    peers.extend(
+        messages::messages
+            .filter(messages::receiver_id.eq(user_id))
+            .select(messages::sender_id)
+            .distinct()
+            .load::<UserId>(conn)?,
+    );
+    peers.sort_by_key(|peer| peer.as_uint());
+    peers.dedup();
+    Ok(peers)
+}
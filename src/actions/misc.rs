@@ -1,29 +1,42 @@
 mod create_database;
 use chrono::Duration;
 pub use create_database::*;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 
 use crate::{
     errors::DomainError,
     models::{
-        misc::{Job, JobCount, JobStatus, NewJob},
+        misc::{
+            Job, JobAclEntry, JobCount, JobCursor, JobStatus, JobsPage,
+            JobsQuery, NewJob, NewJobAclEntry, SortOrder,
+        },
         users::UserId,
     },
+    schema::{jobs, users},
     types::DbConnection,
 };
 
+type BoxedJobsQuery<'a> = diesel::dsl::IntoBoxed<
+    'a,
+    diesel::dsl::LeftJoin<jobs::table, users::table>,
+    Pg,
+>;
+
 pub fn get_jobs(conn: &mut DbConnection) -> Result<Vec<Job>, DomainError> {
     use crate::schema::jobs::dsl as jobs;
     use crate::schema::users::dsl as users;
     Ok(jobs::jobs
-        .inner_join(users::users)
+        .left_join(users::users)
         .select((
             jobs::id,
             jobs::job_id,
-            users::id,
+            users::id.nullable(),
             jobs::status,
             jobs::status_message,
             jobs::created_at,
+            jobs::attempt,
+            jobs::retry_of,
         ))
         .load::<Job>(conn)?)
 }
@@ -35,16 +48,42 @@ pub fn get_jobs_by_user(
     use crate::schema::jobs::dsl as jobs;
     use crate::schema::users::dsl as users;
     Ok(jobs::jobs
-        .inner_join(users::users)
+        .left_join(users::users)
+        .select((
+            jobs::id,
+            jobs::job_id,
+            users::id.nullable(),
+            jobs::status,
+            jobs::status_message,
+            jobs::created_at,
+            jobs::attempt,
+            jobs::retry_of,
+        ))
+        .filter(jobs::started_by.eq(*user_id))
+        .load::<Job>(conn)?)
+}
+
+/// Jobs of a user that are still running
+pub fn get_pending_jobs_by_user(
+    user_id: &UserId,
+    conn: &mut DbConnection,
+) -> Result<Vec<Job>, DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    use crate::schema::users::dsl as users;
+    Ok(jobs::jobs
+        .left_join(users::users)
         .select((
             jobs::id,
             jobs::job_id,
-            users::id,
+            users::id.nullable(),
             jobs::status,
             jobs::status_message,
             jobs::created_at,
+            jobs::attempt,
+            jobs::retry_of,
         ))
-        .filter(users::id.eq(user_id))
+        .filter(jobs::started_by.eq(*user_id))
+        .filter(jobs::status.eq(JobStatus::Pending))
         .load::<Job>(conn)?)
 }
 
@@ -55,20 +94,210 @@ pub fn get_job_by_uuid(
     use crate::schema::jobs::dsl as jobs;
     use crate::schema::users::dsl as users;
     Ok(jobs::jobs
-        .inner_join(users::users)
+        .left_join(users::users)
         .select((
             jobs::id,
             jobs::job_id,
-            users::id,
+            users::id.nullable(),
             jobs::status,
             jobs::status_message,
             jobs::created_at,
+            jobs::attempt,
+            jobs::retry_of,
         ))
         .filter(jobs::job_id.eq(job_id))
         .first::<Job>(conn)
         .optional()?)
 }
 
+/// Jobs joined with their owner and narrowed down by the query filters.
+/// The cursor is not applied here so the same query can be used for counting.
+fn filtered_jobs(query: &JobsQuery) -> BoxedJobsQuery<'static> {
+    use crate::schema::jobs::dsl as jobs;
+    use crate::schema::users::dsl as users;
+
+    let mut q = jobs::jobs.left_join(users::users).into_boxed();
+    if let Some(status) = query.status.clone() {
+        q = q.filter(jobs::status.eq(status));
+    }
+    if let Some(after) = query.created_after {
+        q = q.filter(jobs::created_at.ge(after));
+    }
+    if let Some(before) = query.created_before {
+        q = q.filter(jobs::created_at.lt(before));
+    }
+    if let Some(user_id) = query.started_by {
+        q = q.filter(jobs::started_by.eq(user_id));
+    }
+    q
+}
+
+pub fn list_jobs(
+    query: &JobsQuery,
+    conn: &mut DbConnection,
+) -> Result<JobsPage, DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    use crate::schema::users::dsl as users;
+
+    let limit = query.limit();
+
+    conn.transaction(|conn| {
+        let total = filtered_jobs(query).count().get_result::<i64>(conn)?;
+
+        let mut q = filtered_jobs(query).select((
+            jobs::id,
+            jobs::job_id,
+            users::id.nullable(),
+            jobs::status,
+            jobs::status_message,
+            jobs::created_at,
+            jobs::attempt,
+            jobs::retry_of,
+        ));
+
+        q = match (query.order, query.cursor) {
+            (SortOrder::Desc, Some(c)) => {
+                q.filter(jobs::created_at.lt(c.created_at).or(
+                    jobs::created_at.eq(c.created_at).and(jobs::id.lt(c.id)),
+                ))
+            }
+            (SortOrder::Asc, Some(c)) => {
+                q.filter(jobs::created_at.gt(c.created_at).or(
+                    jobs::created_at.eq(c.created_at).and(jobs::id.gt(c.id)),
+                ))
+            }
+            (_, None) => q,
+        };
+
+        q = match query.order {
+            SortOrder::Desc => {
+                q.order((jobs::created_at.desc(), jobs::id.desc()))
+            }
+            SortOrder::Asc => q.order((jobs::created_at.asc(), jobs::id.asc())),
+        };
+
+        // fetch one extra row to find out whether there is a next page
+        let mut page = q.limit(i64::from(limit) + 1).load::<Job>(conn)?;
+
+        let next_cursor = if page.len() > usize::from(limit) {
+            page.truncate(limit.into());
+            page.last().map(|j| JobCursor::from_job(j).to_string())
+        } else {
+            None
+        };
+
+        Ok(JobsPage {
+            jobs: page,
+            total,
+            next_cursor,
+        })
+    })
+}
+
+/// Whether `user_id` may read the job and follow its output. Owners and
+/// admins always can, everyone else needs a matching `job_acl` grant.
+pub fn can_view_job(
+    job: &Job,
+    user_id: &UserId,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::job_acl::dsl as job_acl;
+
+    if job.started_by == Some(*user_id) {
+        Ok(true)
+    } else {
+        let roles = super::users::get_roles_for_user(user_id, conn)?;
+        if roles.iter().any(|r| r.is_admin()) {
+            Ok(true)
+        } else {
+            Ok(diesel::select(diesel::dsl::exists(
+                job_acl::job_acl
+                    .filter(job_acl::job_id.eq(job.job_id))
+                    .filter(
+                        job_acl::user_id
+                            .eq(*user_id)
+                            .or(job_acl::role_name.eq_any(roles)),
+                    ),
+            ))
+            .get_result::<bool>(conn)?)
+        }
+    }
+}
+
+/// Loads a job on behalf of `user_id`, failing if it does not exist or the
+/// user is not allowed to see it
+pub fn get_viewable_job(
+    job_id: uuid::Uuid,
+    user_id: &UserId,
+    conn: &mut DbConnection,
+) -> Result<Job, DomainError> {
+    let job = get_job_by_uuid(job_id, conn)?.ok_or_else(|| {
+        DomainError::new_entity_does_not_exist_error(format!(
+            "Job with id: {job_id} does not exist"
+        ))
+    })?;
+    if can_view_job(&job, user_id, conn)? {
+        Ok(job)
+    } else {
+        Err(DomainError::new_auth_error(format!(
+            "Forbidden: Not allowed to view job {job_id}"
+        )))
+    }
+}
+
+pub fn get_job_acl(
+    job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<Vec<JobAclEntry>, DomainError> {
+    use crate::schema::job_acl::dsl as job_acl;
+    Ok(job_acl::job_acl
+        .filter(job_acl::job_id.eq(job_id))
+        .order_by(job_acl::id)
+        .load::<JobAclEntry>(conn)?)
+}
+
+pub fn add_job_acl_entry(
+    entry: &NewJobAclEntry,
+    conn: &mut DbConnection,
+) -> Result<JobAclEntry, DomainError> {
+    use crate::schema::job_acl::dsl as job_acl;
+    match diesel::insert_into(job_acl::job_acl)
+        .values(entry)
+        .get_result::<JobAclEntry>(conn)
+    {
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(DomainError::new_field_validation_error(
+            "Job is already shared with this grantee".to_owned(),
+        )),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+            info,
+        )) => Err(DomainError::new_entity_does_not_exist_error(format!(
+            "Grantee does not exist: {}",
+            info.message()
+        ))),
+        res => Ok(res?),
+    }
+}
+
+/// Returns whether an entry was actually removed
+pub fn delete_job_acl_entry(
+    job_id: uuid::Uuid,
+    acl_id: i32,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::job_acl::dsl as job_acl;
+    let deleted = diesel::delete(
+        job_acl::job_acl
+            .filter(job_acl::job_id.eq(job_id))
+            .filter(job_acl::id.eq(acl_id)),
+    )
+    .execute(conn)?;
+    Ok(deleted > 0)
+}
+
 pub fn update_job_status(
     job_id: uuid::Uuid,
     new_status: JobStatus,
@@ -106,6 +335,72 @@ pub fn create_job(
     Ok(job)
 }
 
+/// Creates a job unless the user already created one with the same
+/// idempotency key. Returns the job and whether it was created.
+pub fn create_job_idempotent(
+    new_job: &NewJob,
+    conn: &mut DbConnection,
+) -> Result<(Job, bool), DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    let Some(idempotency_key) = new_job.idempotency_key.as_ref() else {
+        return create_job(new_job, conn).map(|job| (job, true));
+    };
+    conn.transaction(|conn| {
+        let inserted = diesel::insert_into(jobs::jobs)
+            .values(new_job)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        let job_id = if inserted > 0 {
+            new_job.job_id
+        } else {
+            jobs::jobs
+                .select(jobs::job_id)
+                .filter(jobs::started_by.eq(new_job.started_by))
+                .filter(jobs::idempotency_key.eq(idempotency_key))
+                .first::<uuid::Uuid>(conn)?
+        };
+        let job = get_job_by_uuid(job_id, conn)?.ok_or_else(|| {
+            DomainError::new_internal_error(
+                "failed to retrieve created job".to_owned(),
+            )
+        })?;
+        Ok((job, inserted > 0))
+    })
+}
+
+/// The run request stored with a job, jobs started before requests were
+/// stored and pipeline jobs have none
+pub fn get_job_request(
+    job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<Option<serde_json::Value>, DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    Ok(jobs::jobs
+        .select(jobs::request)
+        .filter(jobs::job_id.eq(job_id))
+        .first::<Option<serde_json::Value>>(conn)
+        .optional()?
+        .flatten())
+}
+
+/// Puts a failed job back to pending for its next attempt
+pub fn update_job_attempt(
+    job_id: uuid::Uuid,
+    attempt: i32,
+    status_message: Option<String>,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    diesel::update(jobs::jobs.filter(jobs::job_id.eq(job_id)))
+        .set((
+            jobs::attempt.eq(attempt),
+            jobs::status.eq(JobStatus::Pending),
+            jobs::status_message.eq(status_message),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn get_job_metrics(
     conn: &mut DbConnection,
     hours_since: Option<i8>,
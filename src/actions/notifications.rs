@@ -0,0 +1,93 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        notification::{
+            MarkNotificationsReadRequest, NewNotification, Notification,
+            NotificationsPage, NotificationsQuery,
+        },
+        users::UserId,
+    },
+    types::DbConnection,
+};
+
+pub fn create_notification(
+    new_notification: &NewNotification,
+    conn: &mut DbConnection,
+) -> Result<Notification, DomainError> {
+    use crate::schema::notifications::dsl as notifications;
+    Ok(diesel::insert_into(notifications::notifications)
+        .values(new_notification)
+        .get_result::<Notification>(conn)?)
+}
+
+/// Notifications of the user, newest first
+pub fn get_notifications(
+    user_id: UserId,
+    query: &NotificationsQuery,
+    conn: &mut DbConnection,
+) -> Result<NotificationsPage, DomainError> {
+    use crate::schema::notifications::dsl as notifications;
+
+    let limit = query.limit();
+    let mut q = notifications::notifications
+        .filter(notifications::user_id.eq(user_id))
+        .into_boxed();
+    if let Some(before) = query.before {
+        q = q.filter(notifications::id.lt(before));
+    }
+    if query.unread_only {
+        q = q.filter(notifications::read_at.is_null());
+    }
+    // one more than asked for tells whether there is another page
+    let mut page = q
+        .order_by(notifications::id.desc())
+        .limit(i64::from(limit) + 1)
+        .load::<Notification>(conn)?;
+
+    let next_cursor = if page.len() > usize::from(limit) {
+        page.truncate(usize::from(limit));
+        page.last().map(|notification| notification.id)
+    } else {
+        None
+    };
+    let unread_count = notifications::notifications
+        .filter(notifications::user_id.eq(user_id))
+        .filter(notifications::read_at.is_null())
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(NotificationsPage {
+        notifications: page,
+        next_cursor,
+        unread_count,
+    })
+}
+
+/// Marks unread notifications of the user as read, returns how many were
+/// marked
+pub fn mark_notifications_read(
+    user_id: UserId,
+    request: &MarkNotificationsReadRequest,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<usize, DomainError> {
+    use crate::schema::notifications::dsl as notifications;
+    let unread = notifications::notifications
+        .filter(notifications::user_id.eq(user_id))
+        .filter(notifications::read_at.is_null());
+    let marked = match request.up_to {
+        Some(up_to) => {
+            diesel::update(unread.filter(notifications::id.le(up_to)))
+                .set(notifications::read_at.eq(now))
+                .execute(conn)?
+        }
+        None => diesel::update(
+            unread.filter(notifications::id.eq_any(request.ids.clone())),
+        )
+        .set(notifications::read_at.eq(now))
+        .execute(conn)?,
+    };
+    Ok(marked)
+}
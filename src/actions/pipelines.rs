@@ -0,0 +1,74 @@
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        misc::{JobStatus, NewJob},
+        pipeline::{NewPipelineStep, PipelineStep, PipelineStepDetails},
+    },
+    types::DbConnection,
+};
+
+/// Creates the pipeline job together with the jobs and rows of its steps
+pub fn create_pipeline(
+    pipeline_job: &NewJob,
+    steps: &[(NewJob, NewPipelineStep)],
+    conn: &mut DbConnection,
+) -> Result<Vec<PipelineStep>, DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    use crate::schema::pipeline_steps::dsl as pipeline_steps;
+    let steps = conn.transaction(|conn| {
+        diesel::insert_into(jobs::jobs)
+            .values(pipeline_job)
+            .execute(conn)?;
+        diesel::insert_into(jobs::jobs)
+            .values(steps.iter().map(|(job, _)| job).collect::<Vec<_>>())
+            .execute(conn)?;
+        diesel::insert_into(pipeline_steps::pipeline_steps)
+            .values(steps.iter().map(|(_, step)| step).collect::<Vec<_>>())
+            .get_results::<PipelineStep>(conn)
+    })?;
+    Ok(steps)
+}
+
+/// Steps of a pipeline with the status of their jobs, in creation order
+pub fn get_pipeline_steps(
+    pipeline_job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<Vec<PipelineStepDetails>, DomainError> {
+    use crate::schema::jobs::dsl as jobs;
+    use crate::schema::pipeline_steps::dsl as pipeline_steps;
+    let steps = pipeline_steps::pipeline_steps
+        .inner_join(jobs::jobs.on(jobs::job_id.eq(pipeline_steps::job_id)))
+        .select((
+            crate::schema::pipeline_steps::all_columns,
+            jobs::status,
+            jobs::status_message,
+        ))
+        .filter(pipeline_steps::pipeline_job_id.eq(pipeline_job_id))
+        .order_by(pipeline_steps::id)
+        .load::<(PipelineStep, JobStatus, Option<String>)>(conn)?;
+    Ok(steps
+        .into_iter()
+        .map(|(step, status, status_message)| PipelineStepDetails {
+            step,
+            status,
+            status_message,
+        })
+        .collect())
+}
+
+pub fn set_pipeline_step_output(
+    job_id: uuid::Uuid,
+    output: Option<String>,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::pipeline_steps::dsl as pipeline_steps;
+    diesel::update(
+        pipeline_steps::pipeline_steps
+            .filter(pipeline_steps::job_id.eq(job_id)),
+    )
+    .set(pipeline_steps::output.eq(output))
+    .execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        misc::{Job, JobStatus, NewJob},
+        runner::{NewRunner, NewRunnerJob, Runner, RunnerJob},
+    },
+    types::DbConnection,
+};
+
+pub fn create_runner(
+    new_runner: &NewRunner,
+    conn: &mut DbConnection,
+) -> Result<Runner, DomainError> {
+    use crate::schema::runners::dsl as runners;
+    Ok(diesel::insert_into(runners::runners)
+        .values(new_runner)
+        .get_result::<Runner>(conn)?)
+}
+
+pub fn get_runners(
+    conn: &mut DbConnection,
+) -> Result<Vec<Runner>, DomainError> {
+    use crate::schema::runners::dsl as runners;
+    Ok(runners::runners
+        .order_by(runners::id)
+        .load::<Runner>(conn)?)
+}
+
+pub fn get_runner_by_name(
+    name: &str,
+    conn: &mut DbConnection,
+) -> Result<Option<Runner>, DomainError> {
+    use crate::schema::runners::dsl as runners;
+    Ok(runners::runners
+        .filter(runners::name.eq(name))
+        .first::<Runner>(conn)
+        .optional()?)
+}
+
+pub fn get_runner_by_token_hash(
+    token_hash: &str,
+    conn: &mut DbConnection,
+) -> Result<Option<Runner>, DomainError> {
+    use crate::schema::runners::dsl as runners;
+    Ok(runners::runners
+        .filter(runners::token_hash.eq(token_hash))
+        .first::<Runner>(conn)
+        .optional()?)
+}
+
+pub fn delete_runner(
+    runner_id: i32,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::runners::dsl as runners;
+    let deleted =
+        diesel::delete(runners::runners.filter(runners::id.eq(runner_id)))
+            .execute(conn)?;
+    Ok(deleted > 0)
+}
+
+pub fn set_runner_heartbeat(
+    runner_id: i32,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::runners::dsl as runners;
+    let _ = diesel::update(runners::runners.filter(runners::id.eq(runner_id)))
+        .set(runners::last_heartbeat_at.eq(Some(now)))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn create_runner_job(
+    new_job: &NewRunnerJob,
+    conn: &mut DbConnection,
+) -> Result<RunnerJob, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(diesel::insert_into(runner_jobs::runner_jobs)
+        .values(new_job)
+        .get_result::<RunnerJob>(conn)?)
+}
+
+/// Creates a job and queues it for a runner with all of the `labels`
+pub fn create_queued_job(
+    new_job: &NewJob,
+    labels: Vec<String>,
+    conn: &mut DbConnection,
+) -> Result<(Job, bool), DomainError> {
+    conn.transaction(|conn| {
+        let (job, created) = super::misc::create_job_idempotent(new_job, conn)?;
+        if created {
+            let _ = create_runner_job(
+                &NewRunnerJob {
+                    job_id: job.job_id,
+                    labels,
+                },
+                conn,
+            )?;
+        }
+        Ok((job, created))
+    })
+}
+
+pub fn get_runner_job(
+    job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<Option<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(runner_jobs::runner_jobs
+        .filter(runner_jobs::job_id.eq(job_id))
+        .first::<RunnerJob>(conn)
+        .optional()?)
+}
+
+/// Hands the oldest queued job the runner has all the labels of to the
+/// runner
+pub fn claim_runner_job(
+    runner: &Runner,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<Option<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    conn.transaction(|conn| {
+        let job_id = runner_jobs::runner_jobs
+            .select(runner_jobs::job_id)
+            .filter(runner_jobs::runner_id.is_null())
+            .filter(runner_jobs::finished_at.is_null())
+            .filter(runner_jobs::labels.is_contained_by(&runner.labels))
+            .order_by(runner_jobs::created_at)
+            .limit(1)
+            .for_update()
+            .skip_locked()
+            .first::<uuid::Uuid>(conn)
+            .optional()?;
+        let Some(job_id) = job_id else {
+            return Ok(None);
+        };
+        Ok(Some(
+            diesel::update(
+                runner_jobs::runner_jobs.filter(runner_jobs::job_id.eq(job_id)),
+            )
+            .set((
+                runner_jobs::runner_id.eq(Some(runner.id)),
+                runner_jobs::claimed_at.eq(Some(now)),
+            ))
+            .get_result::<RunnerJob>(conn)?,
+        ))
+    })
+}
+
+/// The job, as long as it is claimed by the runner and not finished
+pub fn get_claimed_runner_job(
+    job_id: uuid::Uuid,
+    runner_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(runner_jobs::runner_jobs
+        .filter(runner_jobs::job_id.eq(job_id))
+        .filter(runner_jobs::runner_id.eq(runner_id))
+        .filter(runner_jobs::finished_at.is_null())
+        .first::<RunnerJob>(conn)
+        .optional()?)
+}
+
+pub fn finish_runner_job(
+    job_id: uuid::Uuid,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<RunnerJob, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(diesel::update(
+        runner_jobs::runner_jobs.filter(runner_jobs::job_id.eq(job_id)),
+    )
+    .set(runner_jobs::finished_at.eq(Some(now)))
+    .get_result::<RunnerJob>(conn)?)
+}
+
+/// Flags an unfinished job for abort. A job no runner claimed yet is taken
+/// off the queue right away. Returns the job when it was still unfinished.
+pub fn request_runner_job_abort(
+    job_id: uuid::Uuid,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<Option<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    conn.transaction(|conn| {
+        let job = runner_jobs::runner_jobs
+            .filter(runner_jobs::job_id.eq(job_id))
+            .filter(runner_jobs::finished_at.is_null())
+            .for_update()
+            .first::<RunnerJob>(conn)
+            .optional()?;
+        let Some(job) = job else {
+            return Ok(None);
+        };
+        let finished_at = job.runner_id.is_none().then_some(now);
+        Ok(Some(
+            diesel::update(
+                runner_jobs::runner_jobs.filter(runner_jobs::job_id.eq(job_id)),
+            )
+            .set((
+                runner_jobs::abort_requested.eq(true),
+                runner_jobs::finished_at.eq(finished_at),
+            ))
+            .get_result::<RunnerJob>(conn)?,
+        ))
+    })
+}
+
+/// Aborts an unfinished job queued for or running on a runner, returns
+/// false when there is no such job
+pub fn abort_runner_job(
+    job_id: uuid::Uuid,
+    status_message: String,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    conn.transaction(|conn| {
+        if request_runner_job_abort(job_id, now, conn)?.is_none() {
+            return Ok(false);
+        }
+        super::misc::update_job_status(
+            job_id,
+            JobStatus::Aborted,
+            Some(status_message),
+            conn,
+        )?;
+        Ok(true)
+    })
+}
+
+/// Unfinished jobs of the runner that were aborted
+pub fn get_aborted_runner_jobs(
+    runner_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Vec<uuid::Uuid>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(runner_jobs::runner_jobs
+        .select(runner_jobs::job_id)
+        .filter(runner_jobs::runner_id.eq(runner_id))
+        .filter(runner_jobs::finished_at.is_null())
+        .filter(runner_jobs::abort_requested.eq(true))
+        .load::<uuid::Uuid>(conn)?)
+}
+
+/// Unfinished jobs claimed by runners whose last heartbeat is older than
+/// `heartbeat_before`
+pub fn get_jobs_of_dead_runners(
+    heartbeat_before: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<Vec<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    use crate::schema::runners::dsl as runners;
+    Ok(runner_jobs::runner_jobs
+        .inner_join(runners::runners)
+        .select(crate::schema::runner_jobs::all_columns)
+        .filter(runner_jobs::finished_at.is_null())
+        .filter(
+            runners::last_heartbeat_at
+                .is_null()
+                .or(runners::last_heartbeat_at.lt(heartbeat_before)),
+        )
+        .load::<RunnerJob>(conn)?)
+}
+
+/// Locks a job of a dead runner while it is still unfinished and claimed by
+/// that runner. Returns `None` when the runner finished the job or it was
+/// handed on in the meantime.
+pub fn lock_dead_runner_job(
+    job_id: uuid::Uuid,
+    runner_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<RunnerJob>, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    Ok(runner_jobs::runner_jobs
+        .filter(runner_jobs::job_id.eq(job_id))
+        .filter(runner_jobs::finished_at.is_null())
+        .filter(runner_jobs::runner_id.eq(runner_id))
+        .for_update()
+        .first::<RunnerJob>(conn)
+        .optional()?)
+}
+
+/// Puts a job of a dead runner back in the queue, unless the runner finished
+/// it or it was handed on in the meantime. Returns whether it was requeued.
+pub fn requeue_runner_job(
+    job_id: uuid::Uuid,
+    runner_id: i32,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::runner_jobs::dsl as runner_jobs;
+    let updated = diesel::update(
+        runner_jobs::runner_jobs
+            .filter(runner_jobs::job_id.eq(job_id))
+            .filter(runner_jobs::finished_at.is_null())
+            .filter(runner_jobs::runner_id.eq(runner_id)),
+    )
+    .set((
+        runner_jobs::runner_id.eq(None::<i32>),
+        runner_jobs::claimed_at.eq(None::<NaiveDateTime>),
+        runner_jobs::requeues.eq(runner_jobs::requeues + 1),
+    ))
+    .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Records the final status of a job a runner finished, a job aborted in the
+/// meantime keeps its status. Returns the status the job ended with.
+pub fn complete_runner_job(
+    job_id: uuid::Uuid,
+    status: JobStatus,
+    status_message: Option<String>,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<JobStatus, DomainError> {
+    conn.transaction(|conn| {
+        let runner_job = finish_runner_job(job_id, now, conn)?;
+        if runner_job.abort_requested {
+            return Ok(JobStatus::Aborted);
+        }
+        super::misc::update_job_status(
+            job_id,
+            status.clone(),
+            status_message,
+            conn,
+        )?;
+        Ok(status)
+    })
+}
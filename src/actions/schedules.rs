@@ -0,0 +1,143 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        schedule::{JobSchedule, JobScheduleChangeset, NewJobSchedule},
+        users::UserId,
+        worker::WorkerFence,
+    },
+    types::DbConnection,
+};
+
+pub fn create_job_schedule(
+    new_schedule: &NewJobSchedule,
+    conn: &mut DbConnection,
+) -> Result<JobSchedule, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    Ok(diesel::insert_into(job_schedules::job_schedules)
+        .values(new_schedule)
+        .get_result::<JobSchedule>(conn)?)
+}
+
+/// Lists the schedules created by `user_id`, or all of them when `None`
+pub fn get_job_schedules(
+    user_id: Option<UserId>,
+    conn: &mut DbConnection,
+) -> Result<Vec<JobSchedule>, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    let mut query = job_schedules::job_schedules
+        .order_by(job_schedules::id)
+        .into_boxed();
+    if let Some(user_id) = user_id {
+        query = query.filter(job_schedules::created_by.eq(user_id));
+    }
+    Ok(query.load::<JobSchedule>(conn)?)
+}
+
+pub fn get_job_schedule(
+    schedule_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<JobSchedule>, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    Ok(job_schedules::job_schedules
+        .filter(job_schedules::id.eq(schedule_id))
+        .first::<JobSchedule>(conn)
+        .optional()?)
+}
+
+pub fn update_job_schedule(
+    schedule_id: i32,
+    changeset: &JobScheduleChangeset,
+    conn: &mut DbConnection,
+) -> Result<JobSchedule, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    Ok(diesel::update(
+        job_schedules::job_schedules.filter(job_schedules::id.eq(schedule_id)),
+    )
+    .set(changeset)
+    .get_result::<JobSchedule>(conn)?)
+}
+
+pub fn delete_job_schedule(
+    schedule_id: i32,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    let deleted = diesel::delete(
+        job_schedules::job_schedules.filter(job_schedules::id.eq(schedule_id)),
+    )
+    .execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Enabled schedules whose next run is at or before `now`, skipping the ones
+/// whose creator has deleted their account
+pub fn get_due_job_schedules(
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<Vec<JobSchedule>, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    use crate::schema::users::dsl as users;
+    Ok(job_schedules::job_schedules
+        .inner_join(users::users)
+        .select(crate::schema::job_schedules::all_columns)
+        .filter(job_schedules::enabled.eq(true))
+        .filter(job_schedules::next_run_at.le(now))
+        .filter(users::deleted_at.is_null())
+        .order_by(job_schedules::next_run_at)
+        .load::<JobSchedule>(conn)?)
+}
+
+/// Moves a due schedule on to its next run. The update only applies while
+/// `next_run_at` still holds the value the caller read, so a run can only be
+/// claimed once even if two schedulers race for it.
+pub fn claim_job_schedule_run(
+    schedule: &JobSchedule,
+    next_run_at: Option<NaiveDateTime>,
+    last_run_at: Option<NaiveDateTime>,
+    fence: Option<&WorkerFence>,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction(|conn| {
+        if let Some(fence) = fence {
+            super::workers::check_worker_fence(fence, conn)?;
+        }
+        let target = job_schedules::job_schedules
+            .filter(job_schedules::id.eq(schedule.id))
+            .filter(job_schedules::next_run_at.eq(schedule.next_run_at));
+        let updated = match last_run_at {
+            Some(last_run_at) => diesel::update(target)
+                .set((
+                    job_schedules::next_run_at.eq(next_run_at),
+                    job_schedules::last_run_at.eq(last_run_at),
+                    job_schedules::updated_at.eq(now),
+                ))
+                .execute(conn)?,
+            None => diesel::update(target)
+                .set((
+                    job_schedules::next_run_at.eq(next_run_at),
+                    job_schedules::updated_at.eq(now),
+                ))
+                .execute(conn)?,
+        };
+        Ok(updated > 0)
+    })
+}
+
+pub fn set_job_schedule_last_job(
+    schedule_id: i32,
+    job_id: uuid::Uuid,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::job_schedules::dsl as job_schedules;
+    diesel::update(
+        job_schedules::job_schedules.filter(job_schedules::id.eq(schedule_id)),
+    )
+    .set(job_schedules::last_job_id.eq(job_id))
+    .execute(conn)?;
+    Ok(())
+}
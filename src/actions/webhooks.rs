@@ -0,0 +1,219 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError,
+    models::{
+        users::UserId,
+        webhook::{
+            JobEvent, NewWebhook, NewWebhookDelivery, Webhook,
+            WebhookChangeset, WebhookDelivery, WebhookDeliveryStatus,
+        },
+    },
+    types::DbConnection,
+};
+
+pub fn create_webhook(
+    new_webhook: &NewWebhook,
+    conn: &mut DbConnection,
+) -> Result<Webhook, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    Ok(diesel::insert_into(webhooks::webhooks)
+        .values(new_webhook)
+        .get_result::<Webhook>(conn)?)
+}
+
+pub fn count_webhooks_by_user(
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<i64, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    Ok(webhooks::webhooks
+        .filter(webhooks::user_id.eq(user_id))
+        .count()
+        .get_result::<i64>(conn)?)
+}
+
+/// Lists the webhooks of `user_id`, or all of them when `None`
+pub fn get_webhooks(
+    user_id: Option<UserId>,
+    conn: &mut DbConnection,
+) -> Result<Vec<Webhook>, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    let mut query = webhooks::webhooks.order_by(webhooks::id).into_boxed();
+    if let Some(user_id) = user_id {
+        query = query.filter(webhooks::user_id.eq(user_id));
+    }
+    Ok(query.load::<Webhook>(conn)?)
+}
+
+pub fn get_webhook(
+    webhook_id: i32,
+    conn: &mut DbConnection,
+) -> Result<Option<Webhook>, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    Ok(webhooks::webhooks
+        .filter(webhooks::id.eq(webhook_id))
+        .first::<Webhook>(conn)
+        .optional()?)
+}
+
+pub fn update_webhook(
+    webhook_id: i32,
+    changeset: &WebhookChangeset,
+    conn: &mut DbConnection,
+) -> Result<Webhook, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    Ok(
+        diesel::update(webhooks::webhooks.filter(webhooks::id.eq(webhook_id)))
+            .set(changeset)
+            .get_result::<Webhook>(conn)?,
+    )
+}
+
+pub fn delete_webhook(
+    webhook_id: i32,
+    conn: &mut DbConnection,
+) -> Result<bool, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    let deleted =
+        diesel::delete(webhooks::webhooks.filter(webhooks::id.eq(webhook_id)))
+            .execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Enabled webhooks of `user_id` subscribed to `event`
+pub fn get_subscribed_webhooks(
+    user_id: UserId,
+    event: JobEvent,
+    conn: &mut DbConnection,
+) -> Result<Vec<Webhook>, DomainError> {
+    use crate::schema::webhooks::dsl as webhooks;
+    Ok(webhooks::webhooks
+        .filter(webhooks::user_id.eq(user_id))
+        .filter(webhooks::enabled.eq(true))
+        .filter(webhooks::events.contains(vec![event.as_str()]))
+        .load::<Webhook>(conn)?)
+}
+
+pub fn create_webhook_deliveries(
+    deliveries: &[NewWebhookDelivery],
+    conn: &mut DbConnection,
+) -> Result<usize, DomainError> {
+    use crate::schema::webhook_deliveries::dsl as webhook_deliveries;
+    Ok(diesel::insert_into(webhook_deliveries::webhook_deliveries)
+        .values(deliveries)
+        .execute(conn)?)
+}
+
+/// Claims up to `limit` pending deliveries that are due at `now`, along with
+/// their webhooks. Claimed deliveries are pushed back to `lease_until`, so
+/// that other instances leave them alone while they are being sent.
+pub fn claim_due_webhook_deliveries(
+    now: NaiveDateTime,
+    lease_until: NaiveDateTime,
+    limit: i64,
+    conn: &mut DbConnection,
+) -> Result<Vec<(WebhookDelivery, Webhook)>, DomainError> {
+    use crate::schema::webhook_deliveries::dsl as webhook_deliveries;
+    use crate::schema::webhooks::dsl as webhooks;
+    conn.transaction(|conn| {
+        let ids = webhook_deliveries::webhook_deliveries
+            .select(webhook_deliveries::id)
+            .filter(
+                webhook_deliveries::status.eq(WebhookDeliveryStatus::Pending),
+            )
+            .filter(webhook_deliveries::next_attempt_at.le(now))
+            .order_by(webhook_deliveries::next_attempt_at)
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load::<i32>(conn)?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _ = diesel::update(
+            webhook_deliveries::webhook_deliveries
+                .filter(webhook_deliveries::id.eq_any(&ids)),
+        )
+        .set(webhook_deliveries::next_attempt_at.eq(lease_until))
+        .execute(conn)?;
+        Ok(webhook_deliveries::webhook_deliveries
+            .inner_join(webhooks::webhooks)
+            .filter(webhook_deliveries::id.eq_any(&ids))
+            .order_by(webhook_deliveries::id)
+            .load::<(WebhookDelivery, Webhook)>(conn)?)
+    })
+}
+
+pub fn set_webhook_delivered(
+    delivery_id: i32,
+    response_status: i32,
+    now: NaiveDateTime,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::webhook_deliveries::dsl as webhook_deliveries;
+    let _ = diesel::update(
+        webhook_deliveries::webhook_deliveries
+            .filter(webhook_deliveries::id.eq(delivery_id)),
+    )
+    .set((
+        webhook_deliveries::status.eq(WebhookDeliveryStatus::Delivered),
+        webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+        webhook_deliveries::response_status.eq(Some(response_status)),
+        webhook_deliveries::last_error.eq(None::<String>),
+        webhook_deliveries::delivered_at.eq(Some(now)),
+    ))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Records a failed attempt, scheduling the next one at `next_attempt_at` or
+/// giving up on the delivery when there is none
+pub fn set_webhook_delivery_failed(
+    delivery_id: i32,
+    response_status: Option<i32>,
+    error: String,
+    next_attempt_at: Option<NaiveDateTime>,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::webhook_deliveries::dsl as webhook_deliveries;
+    let query = diesel::update(
+        webhook_deliveries::webhook_deliveries
+            .filter(webhook_deliveries::id.eq(delivery_id)),
+    );
+    let changes = (
+        webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+        webhook_deliveries::response_status.eq(response_status),
+        webhook_deliveries::last_error.eq(Some(error)),
+    );
+    let _ = match next_attempt_at {
+        Some(next_attempt_at) => query
+            .set((
+                changes,
+                webhook_deliveries::next_attempt_at.eq(next_attempt_at),
+            ))
+            .execute(conn)?,
+        None => query
+            .set((
+                changes,
+                webhook_deliveries::status.eq(WebhookDeliveryStatus::Failed),
+            ))
+            .execute(conn)?,
+    };
+    Ok(())
+}
+
+/// Latest deliveries of a webhook first
+pub fn get_webhook_deliveries(
+    webhook_id: i32,
+    limit: i64,
+    conn: &mut DbConnection,
+) -> Result<Vec<WebhookDelivery>, DomainError> {
+    use crate::schema::webhook_deliveries::dsl as webhook_deliveries;
+    Ok(webhook_deliveries::webhook_deliveries
+        .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+        .order_by(webhook_deliveries::id.desc())
+        .limit(limit)
+        .load::<WebhookDelivery>(conn)?)
+}
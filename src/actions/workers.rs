@@ -0,0 +1,33 @@
+use diesel::prelude::*;
+
+use crate::{
+    errors::DomainError, models::worker::WorkerFence, types::DbConnection,
+};
+
+/// Records the fencing token of a leader, failing when a newer leader of the
+/// worker already wrote. Has to run in the transaction of the guarded write,
+/// the row stays locked until it commits.
+pub fn check_worker_fence(
+    fence: &WorkerFence,
+    conn: &mut DbConnection,
+) -> Result<(), DomainError> {
+    use crate::schema::worker_fences::dsl as worker_fences;
+    let token = i64::try_from(fence.token).unwrap_or(i64::MAX);
+    let updated = diesel::insert_into(worker_fences::worker_fences)
+        .values((
+            worker_fences::worker.eq(fence.worker),
+            worker_fences::fencing_token.eq(token),
+        ))
+        .on_conflict(worker_fences::worker)
+        .do_update()
+        .set(worker_fences::fencing_token.eq(token))
+        .filter(worker_fences::fencing_token.le(token))
+        .execute(conn)?;
+    if updated == 0 {
+        return Err(DomainError::new_internal_error(format!(
+            "Fencing token {} of worker {} is stale, another instance took over",
+            fence.token, fence.worker
+        )));
+    }
+    Ok(())
+}
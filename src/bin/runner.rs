@@ -0,0 +1,348 @@
+#![forbid(unsafe_code)]
+//! Remote job runner
+//!
+//! Claims jobs queued for its labels from the API, runs them and streams
+//! their output back. The runner is registered by an admin through
+//! `POST /api/runners`, which returns the token it authenticates with.
+
+use std::{future::Future, time::Duration};
+
+use actix_demo::{
+    config::RunnerEnvConfig,
+    models::{
+        runner::{
+            ClaimJobQuery, CompleteRunnerJobRequest, RunnerHeartbeat,
+            RunnerJobAssignment, RunnerJobControl, RUNNER_TOKEN_HEADER,
+        },
+        ws::MyProcessItem,
+    },
+    utils::job_process::{self, JobProcess, JobSignal},
+};
+use anyhow::Context;
+use futures::{stream::BoxStream, StreamExt};
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+use tracing_subscriber::EnvFilter;
+
+/// Output is sent once this many items are buffered or on the flush tick
+const MAX_OUTPUT_BATCH: usize = 50;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+const ERROR_BACKOFF: Duration = Duration::from_secs(5);
+/// How long requests failing with a server or connection error are retried
+/// while a job runs, before the job is given up
+const API_RETRY_MAX_ELAPSED: Duration = Duration::from_secs(60);
+/// Exit code reported for jobs whose process could not be started
+const SPAWN_FAILED_EXIT_CODE: i32 = 127;
+/// Exit code reported for jobs killed because the API could not be reached
+const API_UNREACHABLE_EXIT_CODE: i32 = -1;
+
+struct RunnerClient {
+    http: reqwest::Client,
+    config: RunnerEnvConfig,
+}
+
+impl RunnerClient {
+    fn url(&self, path: &str) -> anyhow::Result<url::Url> {
+        self.config
+            .api_url
+            .join(path)
+            .with_context(|| format!("Invalid runner API path {path}"))
+    }
+
+    fn post(&self, path: &str) -> anyhow::Result<reqwest::RequestBuilder> {
+        Ok(self
+            .http
+            .post(self.url(path)?)
+            .header(RUNNER_TOKEN_HEADER, &self.config.token))
+    }
+
+    async fn claim(&self) -> anyhow::Result<Option<RunnerJobAssignment>> {
+        let resp = self
+            .post("/runner/jobs/claim")?
+            .query(&ClaimJobQuery {
+                wait_secs: Some(ClaimJobQuery::MAX_WAIT_SECS),
+            })
+            .timeout(Duration::from_secs(ClaimJobQuery::MAX_WAIT_SECS + 30))
+            .send()
+            .await?
+            .error_for_status()?;
+        if resp.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(resp.json::<RunnerJobAssignment>().await?))
+    }
+
+    async fn send_output(
+        &self,
+        job_id: uuid::Uuid,
+        items: &[MyProcessItem],
+    ) -> anyhow::Result<RunnerJobControl> {
+        Ok(self
+            .post(&format!("/runner/jobs/{job_id}/output"))?
+            .json(items)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RunnerJobControl>()
+            .await?)
+    }
+
+    async fn heartbeat(&self) -> anyhow::Result<RunnerHeartbeat> {
+        Ok(self
+            .post("/runner/heartbeat")?
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RunnerHeartbeat>()
+            .await?)
+    }
+
+    async fn complete(
+        &self,
+        job_id: uuid::Uuid,
+        exit_code: i32,
+    ) -> anyhow::Result<()> {
+        let _ = self
+            .post(&format!("/runner/jobs/{job_id}/complete"))?
+            .json(&CompleteRunnerJobRequest { exit_code })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Retries `request` with exponential backoff for up to
+/// `API_RETRY_MAX_ELAPSED`. Client errors, such as a job that is no longer
+/// claimed by this runner, are not retried.
+async fn retry<T, Fut>(
+    what: &str,
+    mut request: impl FnMut() -> Fut,
+) -> anyhow::Result<T>
+where
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let backoff = backoff::ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_millis(500))
+        .with_max_interval(ERROR_BACKOFF)
+        .with_max_elapsed_time(Some(API_RETRY_MAX_ELAPSED))
+        .build();
+    backoff::future::retry_notify(
+        backoff,
+        || {
+            let res = request();
+            async move {
+                res.await.map_err(|err| {
+                    let client_error = err
+                        .downcast_ref::<reqwest::Error>()
+                        .and_then(reqwest::Error::status)
+                        .is_some_and(|status| status.is_client_error());
+                    if client_error {
+                        backoff::Error::permanent(err)
+                    } else {
+                        backoff::Error::transient(err)
+                    }
+                })
+            }
+        },
+        |err, after| {
+            let _ = tracing::warn!(
+                "Failed to {what}, retrying in {after:?}: {err:?}"
+            );
+        },
+    )
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let _ = dotenvy::dotenv();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_env("ACTIX_DEMO_RUST_LOG"))
+        .init();
+
+    let config = envy::prefixed("ACTIX_DEMO_RUNNER_")
+        .from_env::<RunnerEnvConfig>()
+        .context("Failed to parse runner config")?;
+    let client = RunnerClient {
+        http: reqwest::Client::new(),
+        config,
+    };
+    let _ = tracing::info!(
+        "Runner waiting for jobs from {}",
+        client.config.api_url
+    );
+
+    loop {
+        match client.claim().await {
+            Ok(Some(job)) => {
+                let job_id = job.job_id;
+                if let Err(err) = run_job(&client, job).await {
+                    let _ =
+                        tracing::error!("Failed to run job {job_id}: {err:?}");
+                    sleep(ERROR_BACKOFF).await;
+                }
+            }
+            Ok(None) => {
+                let _ = tracing::trace!("No job queued");
+            }
+            Err(err) => {
+                let _ = tracing::error!("Failed to claim a job: {err:?}");
+                sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Runs a claimed job until its process exits and reports its exit code.
+/// When the API can't be reached for longer than `API_RETRY_MAX_ELAPSED`,
+/// the process is killed and the job reported as failed.
+async fn run_job(
+    client: &RunnerClient,
+    job: RunnerJobAssignment,
+) -> anyhow::Result<()> {
+    let job_id = job.job_id;
+    let _ = tracing::info!("Running job {job_id}: {:?}", job.args);
+    let command = job_process::job_command(
+        &client.config.job_bin_path,
+        &client.config.job_pty_wrapper_path,
+        &job.args,
+        job.pty,
+    );
+    let JobProcess {
+        pid, mut output, ..
+    } = match JobProcess::spawn(command) {
+        Ok(process) => process,
+        Err(err) => {
+            let cause = format!("Failed to run process: {err}");
+            let _ = tracing::error!("{cause}");
+            let items = [MyProcessItem::Error { cause }];
+            let _ =
+                retry("send job output", || client.send_output(job_id, &items))
+                    .await?;
+            return complete(client, job_id, SPAWN_FAILED_EXIT_CODE).await;
+        }
+    };
+
+    let exit_code = match supervise_job(client, job_id, pid, &mut output).await
+    {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            let _ = tracing::error!(
+                "Lost the API while running job {job_id}, killing it: {err:?}"
+            );
+            signal_job(pid, JobSignal::Kill);
+            // read the output to its end so that the process is reaped
+            let grace_period =
+                Duration::from_secs(client.config.job_abort_grace_period_secs);
+            let _ = tokio::time::timeout(grace_period, async {
+                while output.next().await.is_some() {}
+            })
+            .await;
+            API_UNREACHABLE_EXIT_CODE
+        }
+    };
+    let _ = tracing::info!("Job {job_id} exited with code {exit_code}");
+    complete(client, job_id, exit_code).await
+}
+
+async fn complete(
+    client: &RunnerClient,
+    job_id: uuid::Uuid,
+    exit_code: i32,
+) -> anyhow::Result<()> {
+    retry("complete job", || client.complete(job_id, exit_code)).await
+}
+
+/// Follows a job process until it exits, sending its output in batches and
+/// stopping it when the job is aborted. Returns the exit code of the process.
+async fn supervise_job(
+    client: &RunnerClient,
+    job_id: uuid::Uuid,
+    pid: u32,
+    output: &mut BoxStream<'static, MyProcessItem>,
+) -> anyhow::Result<i32> {
+    let grace_period =
+        Duration::from_secs(client.config.job_abort_grace_period_secs);
+    let mut buffer = Vec::with_capacity(MAX_OUTPUT_BATCH);
+    let mut flush = interval(FLUSH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut heartbeat =
+        interval(Duration::from_secs(client.config.heartbeat_interval_secs));
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut aborted = false;
+    // set once the job is aborted, the process is killed when it is due
+    let mut kill_at = None::<Instant>;
+    let mut exit_code = None::<i32>;
+
+    while exit_code.is_none() {
+        let abort = tokio::select! {
+            item = output.next() => {
+                match item {
+                    Some(MyProcessItem::Done { code }) => {
+                        exit_code = Some(code.parse::<i32>().unwrap_or(-1));
+                    }
+                    Some(item) => buffer.push(item),
+                    // the stream always ends with a Done item
+                    None => exit_code = Some(-1),
+                }
+                if buffer.len() >= MAX_OUTPUT_BATCH {
+                    send_output(client, job_id, &mut buffer).await?
+                } else {
+                    false
+                }
+            }
+            _ = flush.tick() => {
+                if kill_at.is_some_and(|at| Instant::now() >= at) {
+                    if job_process::is_job_process_running(pid) {
+                        let _ = tracing::warn!(
+                            "Job {job_id} still running after {grace_period:?}, killing it"
+                        );
+                        signal_job(pid, JobSignal::Kill);
+                    }
+                    kill_at = None;
+                }
+                send_output(client, job_id, &mut buffer).await?
+            }
+            _ = heartbeat.tick() => {
+                retry("send heartbeat", || client.heartbeat())
+                    .await?
+                    .abort
+                    .contains(&job_id)
+            }
+        };
+        if abort && !aborted && exit_code.is_none() {
+            aborted = true;
+            let _ = tracing::info!("Job {job_id} was aborted, stopping it");
+            signal_job(pid, JobSignal::Terminate);
+            kill_at = Some(Instant::now() + grace_period);
+        }
+    }
+
+    let _ = send_output(client, job_id, &mut buffer).await?;
+    Ok(exit_code.unwrap_or(-1))
+}
+
+/// Sends the buffered output, if any, returning whether the job was aborted
+async fn send_output(
+    client: &RunnerClient,
+    job_id: uuid::Uuid,
+    buffer: &mut Vec<MyProcessItem>,
+) -> anyhow::Result<bool> {
+    if buffer.is_empty() {
+        return Ok(false);
+    }
+    let items: &[MyProcessItem] = buffer;
+    let control =
+        retry("send job output", || client.send_output(job_id, items)).await?;
+    buffer.clear();
+    Ok(control.abort)
+}
+
+/// Signals the process group of a job, a process that already exited is not
+/// an error
+fn signal_job(pid: u32, signal: JobSignal) {
+    if let Err(err) = job_process::signal_job_process(pid, signal) {
+        let _ =
+            tracing::warn!("Failed to send {signal:?} to job process: {err}");
+    }
+}
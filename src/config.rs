@@ -1,4 +1,5 @@
 use crate::*;
+use models::health::HealthCheckConfig;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -6,6 +7,12 @@ pub struct EnvConfig {
     // system
     pub loki_url: url::Url,
     pub prometheus_url: url::Url,
+    /// Health endpoints of Loki and Prometheus, for when they are not at
+    /// their usual paths
+    #[serde(default)]
+    pub loki_health_url: Option<url::Url>,
+    #[serde(default)]
+    pub prometheus_health_url: Option<url::Url>,
     pub database_url: String,
     pub http_host: String,
     #[serde(default = "models::defaults::default_hash_cost")]
@@ -14,6 +21,62 @@ pub struct EnvConfig {
     pub jwt_key: String,
     pub redis_url: String,
     pub job_bin_path: String,
+    #[serde(default = "models::defaults::default_job_pty_wrapper_path")]
+    pub job_pty_wrapper_path: String,
+    #[serde(default = "models::defaults::default_job_abort_grace_period_secs")]
+    pub job_abort_grace_period_secs: u64,
+    #[serde(default = "models::defaults::default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    #[serde(default = "models::defaults::default_job_output_retention_secs")]
+    pub job_output_retention_secs: u64,
+    #[serde(default = "models::defaults::default_job_output_max_entries")]
+    pub job_output_max_entries: usize,
+    #[serde(default = "models::defaults::default_message_stream_max_entries")]
+    pub message_stream_max_entries: usize,
+    #[serde(default = "models::defaults::default_job_scheduler_interval_secs")]
+    pub job_scheduler_interval_secs: u64,
+    #[serde(
+        default = "models::defaults::default_job_scheduler_missed_run_tolerance_secs"
+    )]
+    pub job_scheduler_missed_run_tolerance_secs: u64,
+    #[serde(default = "models::defaults::default_job_workdir_root")]
+    pub job_workdir_root: String,
+    #[serde(
+        default = "models::defaults::default_job_artifact_max_file_size_bytes"
+    )]
+    pub job_artifact_max_file_size_bytes: u64,
+    #[serde(default = "models::defaults::default_job_artifact_retention_days")]
+    pub job_artifact_retention_days: u32,
+    #[serde(
+        default = "models::defaults::default_job_artifact_cleanup_interval_secs"
+    )]
+    pub job_artifact_cleanup_interval_secs: u64,
+    #[serde(
+        default = "models::defaults::default_webhook_delivery_interval_secs"
+    )]
+    pub webhook_delivery_interval_secs: u64,
+    #[serde(default = "models::defaults::default_webhook_max_attempts")]
+    pub webhook_max_attempts: u32,
+    #[serde(default = "models::defaults::default_webhook_timeout_secs")]
+    pub webhook_timeout_secs: u64,
+    #[serde(
+        default = "models::defaults::default_webhook_retry_initial_interval_secs"
+    )]
+    pub webhook_retry_initial_interval_secs: u64,
+    #[serde(
+        default = "models::defaults::default_webhook_retry_max_interval_secs"
+    )]
+    pub webhook_retry_max_interval_secs: u64,
+    #[serde(default)]
+    pub webhook_allow_private_targets: bool,
+    #[serde(
+        default = "models::defaults::default_runner_heartbeat_timeout_secs"
+    )]
+    pub runner_heartbeat_timeout_secs: u64,
+    #[serde(
+        default = "models::defaults::default_runner_monitor_interval_secs"
+    )]
+    pub runner_monitor_interval_secs: u64,
     #[serde(
         default = "models::defaults::default_rate_limit_auth_max_requests"
     )]
@@ -51,6 +114,15 @@ pub struct EnvConfig {
     pub session_max_renewals: u32,
     #[serde(default)]
     pub session_disable: bool,
+    #[serde(default = "models::defaults::default_session_country_header")]
+    pub session_country_header: String,
+    /// Comma separated addresses of the proxies in front of the app
+    #[serde(default)]
+    pub session_trusted_proxies: Vec<std::net::IpAddr>,
+    #[serde(
+        default = "models::defaults::default_session_impossible_travel_secs"
+    )]
+    pub session_impossible_travel_secs: u64,
     // worker
     #[serde(
         default = "models::defaults::default_worker_initial_interval_secs"
@@ -68,6 +140,13 @@ pub struct EnvConfig {
     pub worker_run_interval_secs: u8,
     #[serde(default = "models::defaults::default_health_check_timeout_secs")]
     pub health_check_timeout_secs: u8,
+    #[serde(default = "models::defaults::default_health_check_cache_ttl_secs")]
+    pub health_check_cache_ttl_secs: u64,
+    #[serde(default = "models::defaults::default_health_probe_interval_secs")]
+    pub health_probe_interval_secs: u64,
+    /// Additional checks, a JSON array of [`HealthCheckConfig`]
+    #[serde(default, deserialize_with = "deserialize_health_checks")]
+    pub health_checks: Vec<HealthCheckConfig>,
     // MinIO configuration
     pub minio_endpoint: String,
     pub minio_access_key: String,
@@ -91,6 +170,24 @@ pub struct EnvConfig {
     pub smtp_from_email: String,
 }
 
+impl EnvConfig {
+    /// `LOKI_HEALTH_URL`, or the readiness endpoint of `LOKI_URL`
+    pub fn loki_health_endpoint(&self) -> Result<url::Url, url::ParseError> {
+        self.loki_health_url
+            .clone()
+            .map_or_else(|| self.loki_url.join("/ready"), Ok)
+    }
+
+    /// `PROMETHEUS_HEALTH_URL`, or the health endpoint of `PROMETHEUS_URL`
+    pub fn prometheus_health_endpoint(
+        &self,
+    ) -> Result<url::Url, url::ParseError> {
+        self.prometheus_health_url
+            .clone()
+            .map_or_else(|| self.prometheus_url.join("/-/healthy"), Ok)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct MinioConfig {
     // Bucket name for avatars
@@ -101,6 +198,35 @@ pub struct MinioConfig {
     pub max_avatar_size_bytes: u64,
 }
 
+fn deserialize_health_checks<'de, D>(
+    deserializer: D,
+) -> Result<Vec<HealthCheckConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let json = String::deserialize(deserializer)?;
+    serde_json::from_str(&json).map_err(serde::de::Error::custom)
+}
+
 pub fn default_avatar_size_limit() -> u64 {
     2 * 1024 * 1024 // 2MB
 }
+
+/// Configuration of the `actix-demo-runner` binary, read from the
+/// environment with the `ACTIX_DEMO_RUNNER_` prefix
+#[derive(Deserialize, Debug, Clone)]
+pub struct RunnerEnvConfig {
+    /// Base URL of the API, e.g. `http://localhost:7800`
+    pub api_url: url::Url,
+    pub token: String,
+    pub job_bin_path: String,
+    #[serde(default = "models::defaults::default_job_pty_wrapper_path")]
+    pub job_pty_wrapper_path: String,
+    #[serde(default = "models::defaults::default_job_abort_grace_period_secs")]
+    pub job_abort_grace_period_secs: u64,
+    /// Should stay well below `RUNNER_HEARTBEAT_TIMEOUT_SECS` of the API
+    #[serde(
+        default = "models::defaults::default_runner_heartbeat_interval_secs"
+    )]
+    pub heartbeat_interval_secs: u64,
+}
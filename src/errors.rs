@@ -26,6 +26,7 @@ custom_error! { #[derive(new)] #[allow(clippy::enum_variant_names)]
    FileUploadFailed {message: String} = "Failed to upload file: {message}",
    PayloadError { source: actix_web::error::PayloadError } = "Payload error: {source}",
    AccountDeletedError { message: String } = "Account deletion failed: {message}",
+   ServiceUnavailableError { message: String } = "Service unavailable - {message}",
 }
 
 impl DomainError {
@@ -122,6 +123,10 @@ impl ResponseError for DomainError {
                 HttpResponse::Conflict()
                     .json(ErrorResponse::new(self.to_string()))
             }
+            DomainError::ServiceUnavailableError { message: _ } => {
+                HttpResponse::ServiceUnavailable()
+                    .json(ErrorResponse::new(self.to_string()))
+            }
         }
     }
 }
@@ -1,12 +1,22 @@
-use std::time::Duration;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use url::Url;
 
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
 use redis::aio::ConnectionManager;
 use reqwest::Client;
 use tokio::time::error::Elapsed;
 
 use crate::errors::DomainError;
+use crate::models::health::{
+    Criticality, HealthCheckConfig, HealthCheckTarget, ServiceHealth,
+    ServiceStatus,
+};
 use crate::types::DbPool;
+use crate::workers::WorkerLiveness;
 
 #[derive(Debug)]
 pub enum HealthCheckError {
@@ -38,50 +48,115 @@ impl HealthCheckError {
     }
 }
 
+/// Checks one dependency, or the instance itself. Timeouts are applied by
+/// [`HealthCheck::run`].
+pub trait HealthChecker: Send + Sync {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>>;
+
+    /// Checks of the instance itself, for the liveness probe. The rest are
+    /// dependencies, for the readiness and startup probes.
+    fn is_liveness(&self) -> bool {
+        false
+    }
+}
+
+/// Runs a query on the database of the application
 #[derive(Debug, Clone)]
 pub struct PostgresHealthChecker {
     pool: DbPool,
+    query: String,
 }
 
 impl PostgresHealthChecker {
     pub fn new(pool: DbPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            query: "SELECT 1".to_owned(),
+        }
     }
 
-    pub async fn check_health(
-        &self,
-        timeout: Duration,
-    ) -> Result<(), HealthCheckError> {
+    pub fn with_query(mut self, query: String) -> Self {
+        self.query = query;
+        self
+    }
+}
+
+impl HealthChecker for PostgresHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
         use diesel::prelude::*;
         let pool = self.pool.clone();
-        tokio::time::timeout(timeout, async move {
-            let mut conn = pool.get().map_err(|e| {
-                HealthCheckError::ServiceError(format!(
-                    "Failed to get DB connection: {e}"
-                ))
-            })?;
+        let query = self.query.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut conn = pool.get().map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Failed to get DB connection: {e}"
+                    ))
+                })?;
+                let _ = diesel::sql_query(query).execute(&mut conn).map_err(
+                    |e| {
+                        HealthCheckError::ServiceError(format!(
+                            "DB query failed: {e}"
+                        ))
+                    },
+                )?;
+                Ok(())
+            })
+            .await
+            .map_err(|e| {
+                HealthCheckError::ServiceError(format!("Task join error: {e}"))
+            })?
+        })
+    }
+}
+
+/// Runs a query on another database, connecting for every check
+#[derive(Debug, Clone)]
+pub struct SqlHealthChecker {
+    database_url: String,
+    query: String,
+}
 
+impl SqlHealthChecker {
+    pub fn new(database_url: String, query: String) -> Self {
+        Self {
+            database_url,
+            query,
+        }
+    }
+}
+
+impl HealthChecker for SqlHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        use diesel::prelude::*;
+        let database_url = self.database_url.clone();
+        let query = self.query.clone();
+        Box::pin(async move {
             tokio::task::spawn_blocking(move || {
-                diesel::sql_query("SELECT 1")
-                    .execute(&mut conn)
+                let mut conn = diesel::PgConnection::establish(&database_url)
                     .map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Failed to connect to the database: {e}"
+                    ))
+                })?;
+                let _ = diesel::sql_query(query).execute(&mut conn).map_err(
+                    |e| {
                         HealthCheckError::ServiceError(format!(
                             "DB query failed: {e}"
                         ))
-                    })
+                    },
+                )?;
+                Ok(())
             })
             .await
             .map_err(|e| {
                 HealthCheckError::ServiceError(format!("Task join error: {e}"))
-            })??;
-
-            Ok(())
+            })?
         })
-        .await
-        .map_err(HealthCheckError::Timeout)?
     }
 }
 
+/// Pings the Redis of the application
 #[derive(Clone)]
 pub struct RedisHealthChecker {
     conn_manager: ConnectionManager,
@@ -91,13 +166,48 @@ impl RedisHealthChecker {
     pub fn new(conn_manager: ConnectionManager) -> Self {
         Self { conn_manager }
     }
+}
 
-    pub async fn check_health(
-        &self,
-        timeout: Duration,
-    ) -> Result<(), HealthCheckError> {
-        tokio::time::timeout(timeout, async move {
-            let mut conn = self.conn_manager.clone();
+impl HealthChecker for RedisHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        let mut conn = self.conn_manager.clone();
+        Box::pin(async move {
+            let () = redis::cmd("PING").query_async(&mut conn).await.map_err(
+                |e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Redis ping failed: {e}"
+                    ))
+                },
+            )?;
+            Ok(())
+        })
+    }
+}
+
+/// Pings another Redis, connecting for every check
+#[derive(Clone)]
+pub struct RedisClientHealthChecker {
+    client: redis::Client,
+}
+
+impl RedisClientHealthChecker {
+    pub fn new(client: redis::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl HealthChecker for RedisClientHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        Box::pin(async move {
+            let mut conn = self
+                .client
+                .get_multiplexed_async_connection()
+                .await
+                .map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Failed to connect to Redis: {e}"
+                    ))
+                })?;
             let () = redis::cmd("PING").query_async(&mut conn).await.map_err(
                 |e| {
                     HealthCheckError::ServiceError(format!(
@@ -107,8 +217,6 @@ impl RedisHealthChecker {
             )?;
             Ok(())
         })
-        .await
-        .map_err(HealthCheckError::Timeout)?
     }
 }
 
@@ -127,22 +235,22 @@ impl UrlHealthChecker {
             service_name,
         }
     }
+}
 
-    pub async fn check_health(
-        &self,
-        timeout: Duration,
-    ) -> Result<(), HealthCheckError> {
-        let client = self.client.clone();
-        tokio::time::timeout(timeout, async move {
-            let response =
-                client.get(self.endpoint.clone()).send().await.map_err(
-                    |e| {
-                        HealthCheckError::ServiceError(format!(
-                            "Failed to send request to {}: {e}",
-                            self.service_name
-                        ))
-                    },
-                )?;
+impl HealthChecker for UrlHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .get(self.endpoint.clone())
+                .send()
+                .await
+                .map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Failed to send request to {}: {e}",
+                        self.service_name
+                    ))
+                })?;
 
             if response.status().is_success() {
                 Ok(())
@@ -154,77 +262,330 @@ impl UrlHealthChecker {
                 )))
             }
         })
-        .await
-        .map_err(HealthCheckError::Timeout)?
     }
 }
 
-pub enum HealthChecker {
-    Postgres(PostgresHealthChecker),
-    Redis(RedisHealthChecker),
-    Loki(UrlHealthChecker),
-    Prometheus(UrlHealthChecker),
+/// Succeeds once a TCP connection to `address` is established
+#[derive(Debug, Clone)]
+pub struct TcpHealthChecker {
+    address: String,
+}
+
+impl TcpHealthChecker {
+    pub fn new(address: String) -> Self {
+        Self { address }
+    }
+}
+
+impl HealthChecker for TcpHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        Box::pin(async move {
+            let _ = tokio::net::TcpStream::connect(&self.address)
+                .await
+                .map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "Failed to connect to {}: {e}",
+                        self.address
+                    ))
+                })?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct MinioHealthChecker {
+    minio: minior::Minio,
+    bucket: String,
+}
+
+impl MinioHealthChecker {
+    pub fn new(minio: minior::Minio, bucket: String) -> Self {
+        Self { minio, bucket }
+    }
+}
+
+impl HealthChecker for MinioHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        Box::pin(async move {
+            let _ = self
+                .minio
+                .client
+                .head_bucket()
+                .bucket(&self.bucket)
+                .send()
+                .await
+                .map_err(|e| {
+                    HealthCheckError::ServiceError(format!(
+                        "MinIO bucket {} is not reachable: {e}",
+                        self.bucket
+                    ))
+                })?;
+            Ok(())
+        })
+    }
+}
+
+/// Fails when a worker of this instance stopped reporting, a stuck worker
+/// is only fixed by a restart
+#[derive(Clone)]
+pub struct WorkerHealthChecker {
+    liveness: WorkerLiveness,
+}
+
+impl WorkerHealthChecker {
+    pub fn new(liveness: WorkerLiveness) -> Self {
+        Self { liveness }
+    }
+}
+
+impl HealthChecker for WorkerHealthChecker {
+    fn check_health(&self) -> LocalBoxFuture<'_, Result<(), HealthCheckError>> {
+        let stale = self.liveness.stale_workers();
+        Box::pin(async move {
+            if stale.is_empty() {
+                Ok(())
+            } else {
+                Err(HealthCheckError::ServiceError(format!(
+                    "Workers stopped reporting: {}",
+                    stale.join(", ")
+                )))
+            }
+        })
+    }
+
+    fn is_liveness(&self) -> bool {
+        true
+    }
+}
+
+pub struct HealthCheck {
+    pub name: String,
+    pub criticality: Criticality,
+    pub checker: Box<dyn HealthChecker>,
+    last: Mutex<Option<(Instant, ServiceHealth)>>,
 }
 
-impl HealthChecker {
-    pub async fn check_health(
+impl HealthCheck {
+    pub fn new(
+        name: impl Into<String>,
+        criticality: Criticality,
+        checker: impl HealthChecker + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            criticality,
+            checker: Box::new(checker),
+            last: Mutex::new(None),
+        }
+    }
+
+    pub fn critical(
+        name: impl Into<String>,
+        checker: impl HealthChecker + 'static,
+    ) -> Self {
+        Self::new(name, Criticality::Critical, checker)
+    }
+
+    pub fn optional(
+        name: impl Into<String>,
+        checker: impl HealthChecker + 'static,
+    ) -> Self {
+        Self::new(name, Criticality::Optional, checker)
+    }
+
+    /// Runs the check, or returns the last result while it is younger than
+    /// `cache_ttl`
+    pub async fn run(
         &self,
         timeout: Duration,
-    ) -> Result<(), HealthCheckError> {
-        match self {
-            HealthChecker::Postgres(checker) => {
-                checker.check_health(timeout).await
+        cache_ttl: Duration,
+    ) -> ServiceHealth {
+        if let Some((at, health)) = self.last.lock().unwrap().as_ref() {
+            if at.elapsed() < cache_ttl {
+                return ServiceHealth {
+                    cached: true,
+                    ..health.clone()
+                };
             }
-            HealthChecker::Redis(checker) => {
-                checker.check_health(timeout).await
-            }
-            HealthChecker::Loki(checker) => checker.check_health(timeout).await,
-            HealthChecker::Prometheus(checker) => {
-                checker.check_health(timeout).await
+        }
+
+        let started = Instant::now();
+        let result =
+            tokio::time::timeout(timeout, self.checker.check_health()).await;
+        let status = match result.map_err(HealthCheckError::Timeout) {
+            Ok(Ok(())) => ServiceStatus::Healthy,
+            Ok(Err(err)) | Err(err) => {
+                let _ = tracing::warn!(
+                    "Health check failed for {}: {err}",
+                    self.name
+                );
+                ServiceStatus::Unhealthy(err.to_string())
             }
+        };
+        let health = ServiceHealth {
+            status,
+            criticality: self.criticality,
+            latency_ms: started.elapsed().as_millis() as u64,
+            cached: false,
+        };
+        *self.last.lock().unwrap() = Some((Instant::now(), health.clone()));
+        health
+    }
+}
+
+/// The health checks of the instance, and whether it finished starting up
+#[derive(Default)]
+pub struct HealthChecks {
+    checks: Vec<HealthCheck>,
+    started: AtomicBool,
+}
+
+impl HealthChecks {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        Self {
+            checks,
+            started: AtomicBool::new(false),
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    pub fn liveness(&self) -> impl Iterator<Item = &HealthCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.checker.is_liveness())
+    }
+
+    pub fn dependencies(&self) -> impl Iterator<Item = &HealthCheck> {
+        self.checks
+            .iter()
+            .filter(|check| !check.checker.is_liveness())
+    }
+
+    /// Set once the critical dependencies were reachable, the startup probe
+    /// succeeds from then on
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    pub fn mark_started(&self) {
+        self.started.store(true, Ordering::Relaxed);
+    }
 }
 
-pub type HealthcheckName = &'static str;
+/// Dependencies of the application that are checked besides its database,
+/// Redis and MinIO
+pub struct HealthCheckEndpoints {
+    pub loki: Url,
+    pub prometheus: Url,
+    /// Checks declared by the deployment
+    pub custom: Vec<HealthCheckConfig>,
+}
 
+/// Builds the health checks, failing on invalid or duplicate declared checks
 pub fn create_health_checkers(
     pool: DbPool,
     conn_manager: ConnectionManager,
-    loki_endpoint: url::Url,
-    prometheus_endpoint: url::Url,
+    minio: minior::Minio,
+    minio_bucket: String,
+    endpoints: HealthCheckEndpoints,
     client: Client,
-) -> Vec<(HealthcheckName, HealthChecker)> {
-    let loki_hc = loki_endpoint
-        .join("/ready")
-        .expect("Expect valid loki endpoint");
-    let prometheus_hc = prometheus_endpoint
-        .join("/-/healthy")
-        .expect("Expect valid prometheus endpoint");
-    vec![
-        (
+    worker_liveness: WorkerLiveness,
+) -> anyhow::Result<HealthChecks> {
+    let mut checks = vec![
+        HealthCheck::critical(
             "postgresql",
-            HealthChecker::Postgres(PostgresHealthChecker::new(pool)),
+            PostgresHealthChecker::new(pool.clone()),
         ),
-        (
+        HealthCheck::critical(
             "redis",
-            HealthChecker::Redis(RedisHealthChecker::new(conn_manager)),
+            RedisHealthChecker::new(conn_manager.clone()),
         ),
-        (
+        HealthCheck::critical(
+            "minio",
+            MinioHealthChecker::new(minio, minio_bucket),
+        ),
+        HealthCheck::critical(
+            "workers",
+            WorkerHealthChecker::new(worker_liveness),
+        ),
+        HealthCheck::optional(
             "loki",
-            HealthChecker::Loki(UrlHealthChecker::new(
+            UrlHealthChecker::new(
                 client.clone(),
-                loki_hc,
+                endpoints.loki,
                 "Loki".to_owned(),
-            )),
+            ),
         ),
-        (
+        HealthCheck::optional(
             "prometheus",
-            HealthChecker::Prometheus(UrlHealthChecker::new(
-                client,
-                prometheus_hc,
+            UrlHealthChecker::new(
+                client.clone(),
+                endpoints.prometheus,
                 "Prometheus".to_owned(),
-            )),
+            ),
         ),
-    ]
+    ];
+
+    for config in endpoints.custom {
+        let HealthCheckConfig {
+            name,
+            criticality,
+            target,
+        } = config;
+        let check = match target {
+            HealthCheckTarget::Http { url } => HealthCheck::new(
+                name.clone(),
+                criticality,
+                UrlHealthChecker::new(client.clone(), url, name),
+            ),
+            HealthCheckTarget::Tcp { address } => HealthCheck::new(
+                name,
+                criticality,
+                TcpHealthChecker::new(address),
+            ),
+            HealthCheckTarget::Sql {
+                database_url: Some(database_url),
+                query,
+            } => HealthCheck::new(
+                name,
+                criticality,
+                SqlHealthChecker::new(database_url, query),
+            ),
+            HealthCheckTarget::Sql {
+                database_url: None,
+                query,
+            } => HealthCheck::new(
+                name,
+                criticality,
+                PostgresHealthChecker::new(pool.clone()).with_query(query),
+            ),
+            HealthCheckTarget::Redis { url: Some(url) } => {
+                let redis_client =
+                    redis::Client::open(url).with_context(|| {
+                        format!("Invalid Redis URL of health check {name}")
+                    })?;
+                HealthCheck::new(
+                    name,
+                    criticality,
+                    RedisClientHealthChecker::new(redis_client),
+                )
+            }
+            HealthCheckTarget::Redis { url: None } => HealthCheck::new(
+                name,
+                criticality,
+                RedisHealthChecker::new(conn_manager.clone()),
+            ),
+        };
+        checks.push(check);
+    }
+
+    let mut names = HashSet::new();
+    if let Some(check) = checks.iter().find(|c| !names.insert(c.name.clone())) {
+        anyhow::bail!("Health check {} is declared twice", check.name);
+    }
+    Ok(HealthChecks::new(checks))
 }
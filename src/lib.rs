@@ -34,20 +34,25 @@ use actix_web::web::{Data, ServiceConfig};
 use actix_web::{middleware, web, App, HttpServer};
 use actix_web_grants::GrantsMiddleware;
 use config::MinioConfig;
-use health::{HealthChecker, HealthcheckName};
+use health::HealthChecks;
 use jwt_simple::prelude::HS256Key;
 use metrics::Metrics;
 use models::rate_limit::{RateLimitConfig, RateLimitPolicy};
 use models::session::SessionConfig;
 use models::users::UserId;
+use models::worker::{
+    JobArtifactsConfig, JobSchedulerConfig, RunnerConfig, WebhookConfig,
+    WorkerBackoffConfig,
+};
 use redis::aio::ConnectionManager;
 use redis::Client;
 use serde::Deserialize;
 use telemetry::DomainRootSpanBuilder;
-use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 use types::{DbPool, RedisPrefixFn};
+use utils::notifier::Notifier;
 use utils::redis_credentials_repo::RedisCredentialsRepo;
+use utils::shutdown::Shutdown;
 use utils::InstrumentedRedisCache;
 
 build_info::build_info!(pub fn get_build_info);
@@ -71,9 +76,30 @@ pub struct SmtpConfig {
 pub struct AppConfig {
     pub hash_cost: u32,
     pub job_bin_path: String,
+    pub job_pty_wrapper_path: String,
+    pub job_abort_grace_period_secs: u64,
+    /// How long the graceful shutdown waits for jobs, workers and connections
+    pub shutdown_timeout_secs: u64,
+    /// How long the output of a job is kept for replay after its last line
+    pub job_output_retention_secs: u64,
+    /// Most output entries kept per job, older ones are trimmed
+    pub job_output_max_entries: usize,
+    /// Most messages kept in the stream of a receiver for live delivery,
+    /// the history is in Postgres
+    pub message_stream_max_entries: usize,
+    pub job_scheduler: JobSchedulerConfig,
+    pub job_artifacts: JobArtifactsConfig,
+    pub webhooks: WebhookConfig,
+    pub runners: RunnerConfig,
+    /// Backoff of the workers after failed runs
+    pub worker_backoff: WorkerBackoffConfig,
     pub rate_limit: RateLimitConfig,
     pub session: SessionConfig,
     pub health_check_timeout_secs: u8,
+    /// How long health check results are reused
+    pub health_check_cache_ttl_secs: u64,
+    /// How often the dependencies are probed for the `dependency_up` metrics
+    pub health_probe_interval_secs: u64,
     pub minio: MinioConfig,
     pub timezone: chrono_tz::Tz,
     pub smtp: SmtpConfig,
@@ -84,16 +110,21 @@ pub struct AppData {
     pub config: AppConfig,
     pub pool: DbPool,
     pub credentials_repo: RedisCredentialsRepo,
+    pub notifier: Notifier,
     pub jwt_key: HS256Key,
     pub redis_conn_factory: Client,
     pub redis_conn_manager: ConnectionManager,
     pub redis_prefix: RedisPrefixFn,
-    pub sessions_cleanup_worker_handle: Option<JoinHandle<()>>,
     pub metrics: Metrics,
     pub prometheus: PrometheusMetrics,
     pub user_ids_cache: InstrumentedRedisCache<String, Vec<UserId>>,
-    pub health_checkers: Vec<(HealthcheckName, HealthChecker)>,
+    pub health_checks: HealthChecks,
     pub minio: minior::Minio,
+    /// Client for outgoing requests, such as health checks of dependencies
+    pub http_client: reqwest::Client,
+    /// Client for webhook deliveries, kept off internal addresses
+    pub webhook_client: reqwest::Client,
+    pub shutdown: Shutdown,
 }
 
 pub fn configure_app(
@@ -121,7 +152,7 @@ pub fn configure_app(
 
         let in_memory_rate_limiter = {
             let backend = rate_limit::initialize_hc_backend(
-                !app_data.health_checkers.is_empty(),
+                !app_data.config.rate_limit.disable,
             );
             rate_limit::create_hc_rate_limiter(
                 &app_data.config.rate_limit,
@@ -133,7 +164,14 @@ pub fn configure_app(
             .service(
                 web::scope("/hc")
                     .wrap(in_memory_rate_limiter)
-                    .route("", web::get().to(routes::healthcheck::healthcheck)),
+                    // kept for probes configured before the split
+                    .route("", web::get().to(routes::healthcheck::ready))
+                    .route("/live", web::get().to(routes::healthcheck::live))
+                    .route("/ready", web::get().to(routes::healthcheck::ready))
+                    .route(
+                        "/startup",
+                        web::get().to(routes::healthcheck::startup),
+                    ),
             )
             .service(
                 web::resource("/api/login")
@@ -161,6 +199,29 @@ pub fn configure_app(
                     ))
                     .route("", web::get().to(routes::ws::ws)),
             )
+            // api of the remote job runners, authenticated by runner token
+            .service(
+                web::scope("/runner")
+                    .wrap(api_rate_limiter(&app_data.config.rate_limit.api))
+                    .route(
+                        "/heartbeat",
+                        web::post().to(routes::runners::handle_runner_heartbeat),
+                    )
+                    .route(
+                        "/jobs/claim",
+                        web::post().to(routes::runners::handle_claim_runner_job),
+                    )
+                    .route(
+                        "/jobs/{job_id}/output",
+                        web::post()
+                            .to(routes::runners::handle_runner_job_output),
+                    )
+                    .route(
+                        "/jobs/{job_id}/complete",
+                        web::post()
+                            .to(routes::runners::handle_complete_runner_job),
+                    ),
+            )
             // public api
             .service(
                 web::scope("/api/public")
@@ -175,10 +236,23 @@ pub fn configure_app(
                         "/metrics/cmd",
                         web::get().to(routes::command::handle_get_job_metrics),
                     )
+                    .route(
+                        "/ws/schema",
+                        web::get().to(routes::ws::handle_get_ws_schema),
+                    )
                     .route(
                         "/avatars/{user_id}",
                         web::get().to(routes::users::get_user_avatar),
                     )
+                    .route(
+                        "/sessions/revoke/{token}",
+                        web::get().to(routes::auth::confirm_session_revocation),
+                    )
+                    .route(
+                        "/sessions/revoke/{token}",
+                        web::post()
+                            .to(routes::auth::revoke_session_with_token),
+                    )
                     .service(
                         web::scope("/users")
                             .route("", web::get().to(routes::users::get_users))
@@ -206,10 +280,51 @@ pub fn configure_app(
                         ),
                     ))
                     .wrap(from_fn(utils::cookie_auth))
+                    .route(
+                        "/cmd",
+                        web::get().to(routes::command::handle_list_jobs),
+                    )
                     .route(
                         "/cmd",
                         web::post().to(routes::command::handle_run_command),
                     )
+                    .route(
+                        "/cmd/schedules",
+                        web::get()
+                            .to(routes::schedules::handle_list_job_schedules),
+                    )
+                    .route(
+                        "/cmd/schedules",
+                        web::post()
+                            .to(routes::schedules::handle_create_job_schedule),
+                    )
+                    .route(
+                        "/cmd/schedules/{schedule_id}",
+                        web::get()
+                            .to(routes::schedules::handle_get_job_schedule),
+                    )
+                    .route(
+                        "/cmd/schedules/{schedule_id}",
+                        web::patch()
+                            .to(routes::schedules::handle_update_job_schedule),
+                    )
+                    .route(
+                        "/cmd/schedules/{schedule_id}",
+                        web::delete()
+                            .to(routes::schedules::handle_delete_job_schedule),
+                    )
+                    .route(
+                        "/cmd/pipelines",
+                        web::post().to(routes::pipelines::handle_run_pipeline),
+                    )
+                    .route(
+                        "/cmd/pipelines/{job_id}",
+                        web::get().to(routes::pipelines::handle_get_pipeline),
+                    )
+                    .route(
+                        "/cmd/abort",
+                        web::post().to(routes::command::handle_abort_my_jobs),
+                    )
                     .route(
                         "/cmd/{job_id}",
                         web::get().to(routes::command::handle_get_job),
@@ -218,6 +333,44 @@ pub fn configure_app(
                         "/cmd/{job_id}",
                         web::delete().to(routes::command::handle_abort_job),
                     )
+                    .route(
+                        "/cmd/{job_id}/input",
+                        web::post().to(routes::command::handle_job_input),
+                    )
+                    .route(
+                        "/cmd/{job_id}/retry",
+                        web::post().to(routes::command::handle_retry_job),
+                    )
+                    .route(
+                        "/cmd/{job_id}/stream",
+                        web::get()
+                            .to(routes::job_output::handle_stream_job_output),
+                    )
+                    .route(
+                        "/cmd/{job_id}/artifacts",
+                        web::get()
+                            .to(routes::artifacts::handle_list_job_artifacts),
+                    )
+                    .route(
+                        "/cmd/{job_id}/artifacts/{artifact_id}",
+                        web::get().to(
+                            routes::artifacts::handle_download_job_artifact,
+                        ),
+                    )
+                    .route(
+                        "/cmd/{job_id}/acl",
+                        web::get().to(routes::command::handle_get_job_acl),
+                    )
+                    .route(
+                        "/cmd/{job_id}/acl",
+                        web::post()
+                            .to(routes::command::handle_add_job_acl_entry),
+                    )
+                    .route(
+                        "/cmd/{job_id}/acl/{acl_id}",
+                        web::delete()
+                            .to(routes::command::handle_delete_job_acl_entry),
+                    )
                     .service(
                         web::scope("/avatars")
                             .route(
@@ -231,12 +384,178 @@ pub fn configure_app(
                                     .to(routes::users::delete_user_avatar),
                             ),
                     )
+                    .service(
+                        web::scope("/webhooks")
+                            .route(
+                                "",
+                                web::get()
+                                    .to(routes::webhooks::handle_list_webhooks),
+                            )
+                            .route(
+                                "",
+                                web::post()
+                                    .to(routes::webhooks::handle_create_webhook),
+                            )
+                            .route(
+                                "/{webhook_id}",
+                                web::get()
+                                    .to(routes::webhooks::handle_get_webhook),
+                            )
+                            .route(
+                                "/{webhook_id}",
+                                web::patch()
+                                    .to(routes::webhooks::handle_update_webhook),
+                            )
+                            .route(
+                                "/{webhook_id}",
+                                web::delete()
+                                    .to(routes::webhooks::handle_delete_webhook),
+                            )
+                            .route(
+                                "/{webhook_id}/deliveries",
+                                web::get().to(
+                                    routes::webhooks::handle_list_webhook_deliveries,
+                                ),
+                            ),
+                    )
+                    .route(
+                        "/presence",
+                        web::get().to(routes::presence::handle_get_presence),
+                    )
+                    .service(
+                        web::scope("/notifications")
+                            .route(
+                                "",
+                                web::get().to(
+                                    routes::notifications::handle_get_notifications,
+                                ),
+                            )
+                            .route(
+                                "",
+                                web::patch().to(
+                                    routes::notifications::handle_mark_notifications_read,
+                                ),
+                            ),
+                    )
+                    .service(
+                        web::scope("/conversations")
+                            .route(
+                                "",
+                                web::get().to(
+                                    routes::conversations::handle_list_conversations,
+                                ),
+                            )
+                            .route(
+                                "",
+                                web::post().to(
+                                    routes::conversations::handle_create_conversation,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}",
+                                web::get().to(
+                                    routes::conversations::handle_get_conversation,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}",
+                                web::patch().to(
+                                    routes::conversations::handle_rename_conversation,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}/members",
+                                web::post().to(
+                                    routes::conversations::handle_add_conversation_member,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}/members/{user_id}",
+                                web::patch().to(
+                                    routes::conversations::handle_update_conversation_member,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}/members/{user_id}",
+                                web::delete().to(
+                                    routes::conversations::handle_remove_conversation_member,
+                                ),
+                            )
+                            .route(
+                                "/{conversation_id}/messages",
+                                web::get().to(
+                                    routes::conversations::handle_get_conversation_messages,
+                                ),
+                            ),
+                    )
+                    .service(
+                        web::scope("/messages")
+                            .route(
+                                "/unread",
+                                web::get().to(
+                                    routes::messages::handle_get_unread_counts,
+                                ),
+                            )
+                            .route(
+                                "/{peer}",
+                                web::get().to(
+                                    routes::messages::handle_get_conversation,
+                                ),
+                            )
+                            .route(
+                                "/{peer}/read",
+                                web::post().to(
+                                    routes::messages::handle_mark_conversation_read,
+                                ),
+                            ),
+                    )
+                    .service(
+                        web::scope("/runners")
+                            .route(
+                                "",
+                                web::get()
+                                    .to(routes::runners::handle_list_runners),
+                            )
+                            .route(
+                                "",
+                                web::post()
+                                    .to(routes::runners::handle_create_runner),
+                            )
+                            .route(
+                                "/{runner_id}",
+                                web::delete()
+                                    .to(routes::runners::handle_delete_runner),
+                            ),
+                    )
+                    .route(
+                        "/workers",
+                        web::get().to(routes::workers::handle_get_workers),
+                    )
                     .service(
                         web::scope("/sessions")
                             .route(
                                 "",
                                 web::get().to(routes::auth::list_sessions),
                             )
+                            .route(
+                                "/active",
+                                web::get()
+                                    .to(routes::auth::list_active_sessions),
+                            )
+                            .route(
+                                "/users/{user_id}",
+                                web::get().to(routes::auth::list_user_sessions),
+                            )
+                            .route(
+                                "/users/{user_id}",
+                                web::delete()
+                                    .to(routes::auth::revoke_user_sessions),
+                            )
+                            .route(
+                                "/users/{user_id}/{session_id}",
+                                web::delete()
+                                    .to(routes::auth::revoke_user_session),
+                            )
                             .route(
                                 "/{session_id}",
                                 web::delete().to(routes::auth::revoke_session),
@@ -285,15 +604,36 @@ pub async fn run(addr: String, app_data: Data<AppData>) -> anyhow::Result<()> {
              \/     \/              \/              \/    \/      \/
          "#
     );
+    let shutdown_app_data = app_data.clone();
+    let shutdown_timeout_secs = app_data.config.shutdown_timeout_secs;
     let app = move || {
         App::new()
             .wrap(app_data.prometheus.clone())
             .configure(configure_app(app_data.clone()))
             .wrap(TracingLogger::<DomainRootSpanBuilder>::new())
     };
-    HttpServer::new(app)
+    let server = HttpServer::new(app)
         .bind(addr)?
-        .run()
-        .await
-        .map_err(|err| anyhow::anyhow!(err))
+        // signals are handled by the graceful shutdown, which drains jobs and
+        // connections before stopping the server
+        .disable_signals()
+        .shutdown_timeout(shutdown_timeout_secs)
+        .run();
+    let server_handle = server.handle();
+    let shutdown = shutdown_app_data.shutdown.clone();
+    let _shutdown = actix_rt::spawn(async move {
+        utils::shutdown::shutdown_on_signal(&shutdown_app_data, server_handle)
+            .await;
+    });
+    // The server gets its own shutdown timeout once the jobs were drained,
+    // which must not stretch the shutdown past its deadline
+    tokio::select! {
+        res = server => res.map_err(|err| anyhow::anyhow!(err)),
+        () = shutdown.deadline_passed() => {
+            let _ = tracing::warn!(
+                "HTTP server did not stop before the shutdown deadline"
+            );
+            Ok(())
+        }
+    }
 }
@@ -6,13 +6,18 @@ use std::time::{Duration, SystemTime};
 
 use actix_demo::actions::misc::create_database_if_needed;
 use actix_demo::config::MinioConfig;
-use actix_demo::health::create_health_checkers;
+use actix_demo::health::{create_health_checkers, HealthCheckEndpoints};
 use actix_demo::models::rate_limit::{
     KeyStrategy, RateLimitConfig, RateLimitPolicy,
 };
 use actix_demo::models::session::{SessionConfig, SessionRenewalPolicy};
-use actix_demo::models::worker::{WorkerBackoffConfig, WorkerConfig};
+use actix_demo::models::worker::{
+    JobArtifactsConfig, JobSchedulerConfig, RunnerConfig, WebhookConfig,
+    WorkerBackoffConfig, WorkerConfig,
+};
+use actix_demo::utils::notifier::Notifier;
 use actix_demo::utils::redis_credentials_repo::RedisCredentialsRepo;
+use actix_demo::utils::shutdown::Shutdown;
 use actix_demo::utils::InstrumentedRedisCache;
 use actix_demo::SmtpConfig;
 use actix_demo::{
@@ -25,18 +30,24 @@ use cached::RedisCacheBuilder;
 use diesel::r2d2::ConnectionManager;
 use diesel_migrations::{FileBasedMigrations, MigrationHarness};
 use diesel_tracing::pg::InstrumentedPgConnection;
+use futures::future::join_all;
 use jwt_simple::prelude::HS256Key;
 use minior::aws_sdk_s3;
 use minior::aws_sdk_s3::config::{Credentials, Region};
 use reqwest::Client;
 use tokio::task::JoinHandle;
+use tokio::time::{timeout, timeout_at, Instant};
 use tracing::subscriber::set_global_default;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_loki::BackgroundTaskController;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+/// How long the buffered logs get to reach Loki once the app shut down
+const LOKI_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     let start_time = SystemTime::now();
@@ -47,7 +58,7 @@ async fn main() -> anyhow::Result<()> {
         .context("Failed to parse config")?;
 
     //bind guard to variable instead of _
-    let _guard = setup_logger(
+    let (_guard, loki_controller, loki_task) = setup_logger(
         env_config.logger_format.clone(),
         env_config.loki_url.clone(),
     )?;
@@ -98,6 +109,9 @@ async fn main() -> anyhow::Result<()> {
         cleanup_interval_secs: env_config.session_cleanup_interval_secs,
         max_concurrent_sessions: env_config.max_concurrent_sessions,
         disable: env_config.session_disable,
+        country_header: env_config.session_country_header.clone(),
+        trusted_proxies: env_config.session_trusted_proxies.clone(),
+        impossible_travel_secs: env_config.session_impossible_travel_secs,
     };
 
     let prometheus = PrometheusMetricsBuilder::new("api")
@@ -109,13 +123,21 @@ async fn main() -> anyhow::Result<()> {
     let metrics =
         actix_demo::metrics::Metrics::new(prometheus.clone().registry);
 
+    let notifier = Notifier::new(
+        pool.clone(),
+        cm.clone(),
+        redis_prefix(&"messages"),
+        env_config.message_stream_max_entries,
+    );
     let credentials_repo = RedisCredentialsRepo::new(
         redis_prefix(&"user-sessions"),
         cm.clone(),
         session_config.max_concurrent_sessions,
-        session_config.renewal.renewal_window_secs,
+        session_config.renewal.extension_secs(),
+        session_config.impossible_travel_secs,
         metrics.active_sessions.clone(),
-    );
+    )
+    .with_notifier(notifier.clone());
     let jwt_key = HS256Key::from_bytes(env_config.jwt_key.as_bytes());
 
     let rate_limit_config = RateLimitConfig {
@@ -135,9 +157,6 @@ async fn main() -> anyhow::Result<()> {
         disable: env_config.rate_limit_disable,
     };
 
-    let credentials_repo_clone = credentials_repo.clone();
-    let pool_clone = pool.clone();
-
     let user_ids_cache = InstrumentedRedisCache::new(
         RedisCacheBuilder::new("user_ids", Duration::from_secs(3600))
             .connection_string(&env_config.redis_url)
@@ -148,24 +167,19 @@ async fn main() -> anyhow::Result<()> {
         metrics.cache.clone(),
     );
 
-    let sessions_cleanup_worker_handle: JoinHandle<()> = {
-        let config = WorkerConfig {
-            backoff: WorkerBackoffConfig {
-                initial_interval_secs: env_config.worker_initial_interval_secs,
-                multiplier: env_config.worker_multiplier,
-                max_interval_secs: env_config.worker_max_interval_secs,
-                max_elapsed_time_secs: env_config.worker_max_elapsed_time_secs,
-            },
-            run_interval: env_config.session_cleanup_interval_secs,
-        };
-        workers::start_sessions_cleanup_worker(
-            config,
-            credentials_repo_clone,
-            user_ids_cache.clone(),
-            pool_clone,
-        )
-        .await
+    let worker_backoff = WorkerBackoffConfig {
+        initial_interval_secs: env_config.worker_initial_interval_secs,
+        multiplier: env_config.worker_multiplier,
+        max_interval_secs: env_config.worker_max_interval_secs,
+        max_elapsed_time_secs: env_config.worker_max_elapsed_time_secs,
     };
+    let sessions_cleanup_worker = workers::sessions_cleanup_worker(
+        WorkerConfig {
+            backoff: worker_backoff.clone(),
+            run_interval: env_config.session_cleanup_interval_secs,
+        },
+        credentials_repo.clone(),
+    );
 
     let http_client = Client::builder()
         .timeout(Duration::from_secs(
@@ -174,6 +188,11 @@ async fn main() -> anyhow::Result<()> {
         .build()
         .context("Failed to create HTTP client")?;
 
+    let webhook_client = utils::webhooks::webhook_client(
+        env_config.webhook_allow_private_targets,
+    )
+    .context("Failed to create webhook HTTP client")?;
+
     let cred = Credentials::new(
         &env_config.minio_access_key,
         &env_config.minio_secret_key,
@@ -219,22 +238,76 @@ async fn main() -> anyhow::Result<()> {
         client: Arc::new(s3_client),
     };
 
-    let health_checkers = create_health_checkers(
+    let mut worker_registry =
+        workers::WorkerRegistry::new(cm.clone(), redis_prefix(&"workers"));
+
+    let health_checks = create_health_checkers(
         pool.clone(),
         cm.clone(),
-        env_config.loki_url.clone(),
-        env_config.prometheus_url.clone(),
-        http_client,
-    );
+        minior::Minio {
+            client: minio.client.clone(),
+        },
+        env_config.minio_bucket_name.clone(),
+        HealthCheckEndpoints {
+            loki: env_config
+                .loki_health_endpoint()
+                .context("Invalid Loki health URL")?,
+            prometheus: env_config
+                .prometheus_health_endpoint()
+                .context("Invalid Prometheus health URL")?,
+            custom: env_config.health_checks.clone(),
+        },
+        http_client.clone(),
+        worker_registry.liveness(),
+    )
+    .context("Failed to set up the health checks")?;
 
     let app_data = Data::new(AppData {
         start_time,
         config: AppConfig {
             hash_cost: env_config.hash_cost,
             job_bin_path: env_config.job_bin_path,
+            job_pty_wrapper_path: env_config.job_pty_wrapper_path,
+            job_abort_grace_period_secs: env_config.job_abort_grace_period_secs,
+            shutdown_timeout_secs: env_config.shutdown_timeout_secs,
+            job_output_retention_secs: env_config.job_output_retention_secs,
+            job_output_max_entries: env_config.job_output_max_entries,
+            message_stream_max_entries: env_config.message_stream_max_entries,
+            job_scheduler: JobSchedulerConfig {
+                interval_secs: env_config.job_scheduler_interval_secs,
+                missed_run_tolerance_secs: env_config
+                    .job_scheduler_missed_run_tolerance_secs,
+            },
+            job_artifacts: JobArtifactsConfig {
+                workdir_root: env_config.job_workdir_root,
+                max_file_size_bytes: env_config
+                    .job_artifact_max_file_size_bytes,
+                retention_days: env_config.job_artifact_retention_days,
+                cleanup_interval_secs: env_config
+                    .job_artifact_cleanup_interval_secs,
+            },
+            webhooks: WebhookConfig {
+                delivery_interval_secs: env_config
+                    .webhook_delivery_interval_secs,
+                max_attempts: env_config.webhook_max_attempts,
+                timeout_secs: env_config.webhook_timeout_secs,
+                retry_initial_interval_secs: env_config
+                    .webhook_retry_initial_interval_secs,
+                retry_max_interval_secs: env_config
+                    .webhook_retry_max_interval_secs,
+                allow_private_targets: env_config.webhook_allow_private_targets,
+            },
+            runners: RunnerConfig {
+                heartbeat_timeout_secs: env_config
+                    .runner_heartbeat_timeout_secs,
+                monitor_interval_secs: env_config.runner_monitor_interval_secs,
+            },
+            worker_backoff,
             rate_limit: rate_limit_config,
             session: session_config,
             health_check_timeout_secs: env_config.health_check_timeout_secs,
+            health_check_cache_ttl_secs: env_config.health_check_cache_ttl_secs,
+            health_probe_interval_secs: env_config.health_probe_interval_secs,
             minio: MinioConfig {
                 bucket_name: env_config.minio_bucket_name,
                 max_avatar_size_bytes: env_config.max_avatar_size_bytes,
@@ -250,21 +323,63 @@ async fn main() -> anyhow::Result<()> {
         },
         pool,
         credentials_repo,
+        notifier,
         jwt_key,
         redis_conn_factory: client.clone(),
         redis_conn_manager: cm.clone(),
         redis_prefix,
-        sessions_cleanup_worker_handle: Some(sessions_cleanup_worker_handle),
         metrics,
         prometheus,
         user_ids_cache,
-        health_checkers,
+        health_checks,
         minio,
+        http_client,
+        webhook_client,
+        shutdown: Shutdown::default(),
     });
 
-    let _app =
-        actix_demo::run(format!("{}:7800", env_config.http_host), app_data)
-            .await?;
+    let _ = worker_registry
+        .register(sessions_cleanup_worker)
+        .register(workers::job_scheduler_worker(app_data.clone()))
+        .register(workers::job_artifacts_cleanup_worker(app_data.clone()))
+        .register(workers::webhook_delivery_worker(app_data.clone()))
+        .register(workers::runner_monitor_worker(app_data.clone()))
+        .register(workers::dependency_prober_worker(app_data.clone()));
+
+    // The job scheduler starts jobs with actix_rt::spawn, which needs a LocalSet
+    let local = tokio::task::LocalSet::new();
+    let addr = format!("{}:7800", env_config.http_host);
+    let shutdown = app_data.shutdown.clone();
+    let shutdown_timeout =
+        Duration::from_secs(env_config.shutdown_timeout_secs);
+    let _app = local
+        .run_until(async move {
+            let workers = worker_registry.start(shutdown.token());
+            actix_demo::run(addr, app_data).await?;
+
+            // workers stop once their current run is over, getting whatever
+            // is left until the deadline
+            let deadline = shutdown
+                .deadline()
+                .unwrap_or_else(|| Instant::now() + shutdown_timeout);
+            if timeout_at(deadline, join_all(workers)).await.is_err() {
+                let _ = tracing::warn!(
+                    "Workers did not stop before the shutdown deadline"
+                );
+            }
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+
+    // Flush the logs still buffered for Loki
+    let _ = tracing::info!("Shut down, flushing logs");
+    loki_controller.shutdown().await;
+    if timeout(LOKI_FLUSH_TIMEOUT, loki_task).await.is_err() {
+        // still written to stdout
+        let _ = tracing::warn!(
+            "Failed to flush the logs to Loki in {LOKI_FLUSH_TIMEOUT:?}"
+        );
+    }
 
     Ok(())
 }
@@ -272,7 +387,7 @@ async fn main() -> anyhow::Result<()> {
 pub fn setup_logger(
     format: LoggerFormat,
     loki_url: url::Url,
-) -> anyhow::Result<(WorkerGuard, JoinHandle<()>)> {
+) -> anyhow::Result<(WorkerGuard, BackgroundTaskController, JoinHandle<()>)> {
     let env_filter = EnvFilter::try_from_env("ACTIX_DEMO_RUST_LOG")
         .context("Failed to set up env logger")?;
 
@@ -283,11 +398,11 @@ pub fn setup_logger(
 
     let bi = actix_demo::get_build_info();
 
-    let (loki_layer, loki_task) = tracing_loki::builder()
+    let (loki_layer, loki_controller, loki_task) = tracing_loki::builder()
         .label("host", "mine")?
         .extra_field("pid", format!("{}", std::process::id()))?
         .label("app", format!("actix-demo-{}", bi.crate_info.version))?
-        .build_url(loki_url)?;
+        .build_controller_url(loki_url)?;
 
     let subscriber = Registry::default().with(env_filter).with(loki_layer);
 
@@ -316,5 +431,5 @@ pub fn setup_logger(
     };
 
     let loki_guard = tokio::spawn(loki_task);
-    Ok((_guard, loki_guard))
+    Ok((_guard, loki_controller, loki_guard))
 }
@@ -1,20 +1,24 @@
-use prometheus::{opts, GaugeVec, HistogramVec, IntCounterVec, Registry};
+use std::time::Duration;
+
+use prometheus::{
+    histogram_opts, opts, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec,
+    Registry,
+};
+
+use crate::models::{misc::JobStatus, ws::MyProcessItem};
 
 #[derive(Clone)]
 pub struct Metrics {
     pub active_sessions: GaugeVec,
     pub active_ws_connections: GaugeVec,
+    /// Whether a dependency passed its last health check, by check name
+    pub dependency_up: IntGaugeVec,
     pub cache: CacheMetrics,
+    pub jobs: JobMetrics,
 }
 
 impl Metrics {
     pub fn new(registry: Registry) -> Self {
-        let job_counter = IntCounterVec::new(
-            opts!("api_jobs_total", "Total job executions"),
-            &["status"], // running, completed, aborted
-        )
-        .unwrap();
-
         let active_sessions = GaugeVec::new(
             opts!("active_sessions_total", "Currently active user sessions"),
             &["user_id"],
@@ -30,18 +34,29 @@ impl Metrics {
         )
         .unwrap();
 
-        registry.register(Box::new(job_counter.clone())).unwrap();
+        let dependency_up = IntGaugeVec::new(
+            opts!(
+                "dependency_up",
+                "Whether a dependency passed its last health check"
+            ),
+            &["name"],
+        )
+        .unwrap();
+
         registry
             .register(Box::new(active_sessions.clone()))
             .unwrap();
         registry
             .register(Box::new(active_ws_connections.clone()))
             .unwrap();
+        registry.register(Box::new(dependency_up.clone())).unwrap();
 
         Self {
             active_sessions,
             active_ws_connections,
+            dependency_up,
             cache: CacheMetrics::new(&registry),
+            jobs: JobMetrics::new(&registry),
         }
     }
 }
@@ -93,3 +108,141 @@ impl CacheMetrics {
         }
     }
 }
+
+/// What a job was started from, the `template` label of the job metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JobTemplate {
+    /// Run through the command endpoint, including manual retries
+    #[default]
+    Command,
+    Schedule,
+    /// A step of a pipeline
+    Pipeline,
+    /// Run on a remote runner
+    Runner,
+}
+
+impl JobTemplate {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Schedule => "schedule",
+            Self::Pipeline => "pipeline",
+            Self::Runner => "runner",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobMetrics {
+    /// Job executions by status, `started` once the process is running and
+    /// the final status once it is over
+    pub executions: IntCounterVec,
+    /// Job processes currently running on this instance
+    pub running: IntGaugeVec,
+    pub duration: HistogramVec,
+    pub output_bytes: IntCounterVec,
+    pub spawn_failures: IntCounterVec,
+}
+
+impl JobMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        let executions = IntCounterVec::new(
+            opts!("api_jobs_total", "Total job executions"),
+            &["status", "template"], // started, completed, failed, aborted
+        )
+        .unwrap();
+
+        let running = IntGaugeVec::new(
+            opts!("api_jobs_running", "Currently running job processes"),
+            &["template"],
+        )
+        .unwrap();
+
+        let duration = HistogramVec::new(
+            histogram_opts!(
+                "api_job_duration_seconds",
+                "Time from the start of a job process until it is over",
+                vec![0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 14400.0]
+            ),
+            &["status", "template"],
+        )
+        .unwrap();
+
+        let output_bytes = IntCounterVec::new(
+            opts!("api_job_output_bytes_total", "Total bytes of job output"),
+            &["kind", "template"], // line, error
+        )
+        .unwrap();
+
+        let spawn_failures = IntCounterVec::new(
+            opts!(
+                "api_job_spawn_failures_total",
+                "Total job processes that failed to start"
+            ),
+            &["template"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(executions.clone())).unwrap();
+        registry.register(Box::new(running.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        registry.register(Box::new(output_bytes.clone())).unwrap();
+        registry.register(Box::new(spawn_failures.clone())).unwrap();
+
+        Self {
+            executions,
+            running,
+            duration,
+            output_bytes,
+            spawn_failures,
+        }
+    }
+
+    pub fn job_started(&self, template: JobTemplate) {
+        self.executions
+            .with_label_values(&["started", template.as_str()])
+            .inc();
+    }
+
+    /// Counts a job that is over by its final status, along with how long
+    /// its process ran when it got to start one
+    pub fn job_finished(
+        &self,
+        template: JobTemplate,
+        status: &JobStatus,
+        elapsed: Option<Duration>,
+    ) {
+        let status = match status {
+            JobStatus::Pending => "pending",
+            JobStatus::Completed => "completed",
+            JobStatus::Aborted => "aborted",
+            JobStatus::Failed => "failed",
+        };
+        self.executions
+            .with_label_values(&[status, template.as_str()])
+            .inc();
+        if let Some(elapsed) = elapsed {
+            self.duration
+                .with_label_values(&[status, template.as_str()])
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+
+    pub fn job_output(&self, template: JobTemplate, item: &MyProcessItem) {
+        let (kind, bytes) = match item {
+            MyProcessItem::Line { value } => ("line", value.len()),
+            MyProcessItem::Error { cause } => ("error", cause.len()),
+            MyProcessItem::Done { .. } => return,
+        };
+        self.output_bytes
+            .with_label_values(&[kind, template.as_str()])
+            .inc_by(u64::try_from(bytes).unwrap_or(u64::MAX));
+    }
+
+    pub fn spawn_failed(&self, template: JobTemplate) {
+        self.spawn_failures
+            .with_label_values(&[template.as_str()])
+            .inc();
+    }
+}
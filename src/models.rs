@@ -1,8 +1,18 @@
+pub mod artifact;
+pub mod conversation;
 pub mod defaults;
+pub mod health;
+pub mod message;
 pub mod misc;
+pub mod notification;
+pub mod pipeline;
+pub mod presence;
 pub mod rate_limit;
 pub mod roles;
+pub mod runner;
+pub mod schedule;
 pub mod session;
 pub mod users;
+pub mod webhook;
 pub mod worker;
 pub mod ws;
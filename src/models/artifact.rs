@@ -0,0 +1,88 @@
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::schema::job_artifacts;
+
+pub const MAX_ARTIFACT_PATTERNS: usize = 10;
+/// Files matched beyond this are ignored
+pub const MAX_ARTIFACTS_PER_JOB: usize = 100;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = job_artifacts)]
+pub struct JobArtifact {
+    pub id: i32,
+    pub job_id: uuid::Uuid,
+    /// Path of the file relative to the job working directory
+    pub path: String,
+    #[serde(skip_serializing, default)]
+    pub object_key: String,
+    pub size_bytes: i64,
+    pub content_type: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl JobArtifact {
+    /// Object key of an artifact, artifacts live under `jobs/{job_id}/`
+    pub fn object_key_for(job_id: uuid::Uuid, path: &str) -> String {
+        format!("jobs/{job_id}/{path}")
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = job_artifacts)]
+pub struct NewJobArtifact {
+    pub job_id: uuid::Uuid,
+    pub path: String,
+    pub object_key: String,
+    pub size_bytes: i64,
+    pub content_type: String,
+}
+
+/// Artifact patterns are globs such as `reports/*.html` or `dist/**/*.tar.gz`
+/// that have to stay inside the job working directory
+pub fn validate_artifact_patterns(patterns: &[String]) -> Result<(), String> {
+    if patterns.len() > MAX_ARTIFACT_PATTERNS {
+        return Err(format!(
+            "At most {MAX_ARTIFACT_PATTERNS} artifact patterns are allowed"
+        ));
+    }
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        let escapes = path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if pattern.is_empty() || escapes {
+            return Err(format!(
+                "Artifact pattern {pattern:?} must be a path relative to the job working directory"
+            ));
+        }
+        let _ = glob::Pattern::new(pattern).map_err(|err| {
+            format!("Invalid artifact pattern {pattern:?}: {err}")
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_patterns_test() {
+        let patterns = ["reports/*.html", "dist/**/*.tar.gz", "./build.log"]
+            .map(|p| p.to_owned());
+        assert!(validate_artifact_patterns(&patterns).is_ok());
+
+        for pattern in ["../secrets", "/etc/passwd", "", "reports/[.txt"] {
+            assert!(
+                validate_artifact_patterns(&[pattern.to_owned()]).is_err(),
+                "{pattern} should be rejected"
+            );
+        }
+    }
+}
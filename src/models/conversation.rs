@@ -0,0 +1,194 @@
+use chrono::NaiveDateTime;
+use diesel_derive_enum::DbEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::{
+    conversation_members, conversation_messages, conversations,
+};
+
+use super::users::UserId;
+
+pub const MAX_CONVERSATION_NAME_LENGTH: usize = 100;
+pub const MAX_CONVERSATION_MEMBERS: usize = 100;
+
+/// Role of a member inside a conversation, ordered by what it allows
+#[derive(
+    DbEnum,
+    Debug,
+    Default,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[ExistingTypePath = "crate::schema::sql_types::ConversationRole"]
+pub enum ConversationRole {
+    #[default]
+    Member,
+    /// Renames the conversation and invites and removes members
+    Admin,
+    /// Also changes the roles of members, there is one owner per
+    /// conversation
+    Owner,
+}
+
+impl ConversationRole {
+    pub fn can_manage(self) -> bool {
+        self >= ConversationRole::Admin
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = conversations)]
+pub struct Conversation {
+    pub id: i32,
+    pub name: String,
+    /// `None` once the user who created the conversation has been deleted
+    pub created_by: Option<UserId>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = conversations)]
+pub struct NewConversation {
+    pub name: String,
+    pub created_by: UserId,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable)]
+#[diesel(table_name = conversation_members)]
+pub struct ConversationMember {
+    pub conversation_id: i32,
+    pub user_id: UserId,
+    pub role: ConversationRole,
+    pub joined_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = conversation_members)]
+pub struct NewConversationMember {
+    pub conversation_id: i32,
+    pub user_id: UserId,
+    pub role: ConversationRole,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConversationDetails {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub members: Vec<ConversationMember>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = conversation_messages)]
+pub struct ConversationMessage {
+    pub id: i32,
+    pub conversation_id: i32,
+    /// `None` once the sender has been deleted
+    pub sender_id: Option<UserId>,
+    pub message_text: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = conversation_messages)]
+pub struct NewConversationMessage {
+    pub conversation_id: i32,
+    pub sender_id: UserId,
+    pub message_text: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConversationMessagesPage {
+    /// Newest first
+    pub messages: Vec<ConversationMessage>,
+    pub next_cursor: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateConversationRequest {
+    pub name: String,
+    /// Users joining as members besides the creator, who becomes the owner
+    #[serde(default)]
+    pub member_ids: Vec<UserId>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RenameConversationRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddConversationMemberRequest {
+    pub user_id: UserId,
+    /// Member unless given, there is only one owner
+    #[serde(default)]
+    pub role: ConversationRole,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateConversationMemberRequest {
+    pub role: ConversationRole,
+}
+
+/// What changed in a conversation, delivered to its members
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "change")]
+pub enum ConversationChange {
+    MemberJoined {
+        user_id: UserId,
+        role: ConversationRole,
+    },
+    MemberLeft {
+        user_id: UserId,
+    },
+    RoleChanged {
+        user_id: UserId,
+        role: ConversationRole,
+    },
+    Renamed {
+        name: String,
+    },
+}
+
+pub fn validate_conversation_name(name: &str) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_CONVERSATION_NAME_LENGTH {
+        Err(format!(
+            "Conversation name must be 1 to {MAX_CONVERSATION_NAME_LENGTH} bytes long"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversation_role_test() {
+        assert!(ConversationRole::Owner > ConversationRole::Admin);
+        assert!(ConversationRole::Admin > ConversationRole::Member);
+        assert!(ConversationRole::Admin.can_manage());
+        assert!(!ConversationRole::Member.can_manage());
+    }
+
+    #[test]
+    fn validate_conversation_name_test() {
+        assert!(validate_conversation_name("team").is_ok());
+        assert!(validate_conversation_name(" ").is_err());
+        assert!(validate_conversation_name(
+            &"a".repeat(MAX_CONVERSATION_NAME_LENGTH + 1)
+        )
+        .is_err());
+    }
+}
@@ -2,6 +2,98 @@ pub fn default_hash_cost() -> u32 {
     8
 }
 
+pub fn default_job_pty_wrapper_path() -> String {
+    "/usr/bin/script".to_owned()
+}
+
+pub fn default_job_abort_grace_period_secs() -> u64 {
+    10
+}
+
+pub fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+pub fn default_job_output_retention_secs() -> u64 {
+    24 * 3600
+}
+
+pub fn default_job_output_max_entries() -> usize {
+    10_000
+}
+
+pub fn default_message_stream_max_entries() -> usize {
+    1000
+}
+
+pub fn default_job_scheduler_interval_secs() -> u64 {
+    10
+}
+
+pub fn default_job_scheduler_missed_run_tolerance_secs() -> u64 {
+    60
+}
+
+pub fn default_retry_initial_interval_secs() -> u64 {
+    5
+}
+
+pub fn default_retry_multiplier() -> f64 {
+    2.0
+}
+
+pub fn default_retry_max_interval_secs() -> u64 {
+    300
+}
+
+pub fn default_job_workdir_root() -> String {
+    "/tmp/actix-demo/jobs".to_owned()
+}
+
+pub fn default_job_artifact_max_file_size_bytes() -> u64 {
+    50 * 1024 * 1024 // 50MB
+}
+
+pub fn default_job_artifact_retention_days() -> u32 {
+    7
+}
+
+pub fn default_job_artifact_cleanup_interval_secs() -> u64 {
+    3600
+}
+
+pub fn default_webhook_delivery_interval_secs() -> u64 {
+    5
+}
+
+pub fn default_webhook_max_attempts() -> u32 {
+    8
+}
+
+pub fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+pub fn default_webhook_retry_initial_interval_secs() -> u64 {
+    30
+}
+
+pub fn default_webhook_retry_max_interval_secs() -> u64 {
+    3600
+}
+
+pub fn default_runner_heartbeat_timeout_secs() -> u64 {
+    30
+}
+
+pub fn default_runner_heartbeat_interval_secs() -> u64 {
+    10
+}
+
+pub fn default_runner_monitor_interval_secs() -> u64 {
+    10
+}
+
 pub fn default_rate_limit_auth_max_requests() -> u32 {
     5
 }
@@ -50,6 +142,14 @@ pub fn default_session_max_renewals() -> u32 {
     3
 }
 
+pub fn default_session_country_header() -> String {
+    "CF-IPCountry".to_owned()
+}
+
+pub fn default_session_impossible_travel_secs() -> u64 {
+    3600
+}
+
 pub fn default_worker_initial_interval_secs() -> u64 {
     3
 }
@@ -72,6 +172,12 @@ pub fn default_worker_run_interval_secs() -> u8 {
 pub fn default_health_check_timeout_secs() -> u8 {
     10
 }
+pub fn default_health_check_cache_ttl_secs() -> u64 {
+    5
+}
+pub fn default_health_probe_interval_secs() -> u64 {
+    15
+}
 pub fn default_timezone() -> chrono_tz::Tz {
     chrono_tz::Tz::UTC
 }
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Whether a failing check takes the instance out of rotation. Optional
+/// checks are reported, but only degrade the instance.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Criticality {
+    #[default]
+    Critical,
+    Optional,
+}
+
+/// A check declared by the deployment in `HEALTH_CHECKS`, a JSON array such
+/// as `[{"name": "search", "kind": "http", "url": "http://search:9200"}]`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HealthCheckConfig {
+    pub name: String,
+    #[serde(default)]
+    pub criticality: Criticality,
+    #[serde(flatten)]
+    pub target: HealthCheckTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HealthCheckTarget {
+    /// Succeeds on a 2xx response to a GET request
+    Http { url: url::Url },
+    /// Succeeds once a connection is established, `address` is `host:port`
+    Tcp { address: String },
+    /// Runs the query, on the database of the application unless
+    /// `database_url` is given
+    Sql {
+        #[serde(default)]
+        database_url: Option<String>,
+        #[serde(default = "default_sql_check_query")]
+        query: String,
+    },
+    /// Pings the Redis of the application unless `url` is given
+    Redis {
+        #[serde(default)]
+        url: Option<String>,
+    },
+}
+
+fn default_sql_check_query() -> String {
+    "SELECT 1".to_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStatus {
+    Healthy,
+    Unhealthy(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServiceHealth {
+    pub status: ServiceStatus,
+    pub criticality: Criticality,
+    /// How long the check took
+    pub latency_ms: u64,
+    /// Whether the result is from an earlier run, checks are cached for a
+    /// short while so that probes don't hammer the dependencies
+    pub cached: bool,
+}
+
+impl ServiceHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.status == ServiceStatus::Healthy
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthCheckResponse {
+    pub version: String,
+    pub timestamp: String,
+    pub uptime: u64,
+    /// Every critical check passed
+    pub success: bool,
+    /// Some optional check failed
+    pub degraded: bool,
+    pub services: HashMap<String, ServiceHealth>,
+}
+
+impl HealthCheckResponse {
+    pub fn new(
+        version: String,
+        uptime: u64,
+        services: HashMap<String, ServiceHealth>,
+    ) -> Self {
+        let success = services.values().all(|service| {
+            service.criticality == Criticality::Optional || service.is_healthy()
+        });
+        let degraded = services.values().any(|service| !service.is_healthy());
+        Self {
+            version,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            uptime,
+            success,
+            degraded: success && degraded,
+            services,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(criticality: Criticality, healthy: bool) -> ServiceHealth {
+        ServiceHealth {
+            status: if healthy {
+                ServiceStatus::Healthy
+            } else {
+                ServiceStatus::Unhealthy("down".to_owned())
+            },
+            criticality,
+            latency_ms: 1,
+            cached: false,
+        }
+    }
+
+    fn response(services: &[(&str, ServiceHealth)]) -> HealthCheckResponse {
+        HealthCheckResponse::new(
+            "1.0.0".to_owned(),
+            0,
+            services
+                .iter()
+                .map(|(name, service)| ((*name).to_owned(), service.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn health_check_response_test() {
+        let res = response(&[
+            ("postgresql", service(Criticality::Critical, true)),
+            ("loki", service(Criticality::Optional, true)),
+        ]);
+        assert!(res.success);
+        assert!(!res.degraded);
+
+        // an optional service failing keeps the instance in rotation
+        let res = response(&[
+            ("postgresql", service(Criticality::Critical, true)),
+            ("loki", service(Criticality::Optional, false)),
+        ]);
+        assert!(res.success);
+        assert!(res.degraded);
+
+        let res = response(&[
+            ("postgresql", service(Criticality::Critical, false)),
+            ("loki", service(Criticality::Optional, false)),
+        ]);
+        assert!(!res.success);
+        assert!(!res.degraded);
+
+        assert!(response(&[]).success);
+    }
+
+    #[test]
+    fn health_check_config_test() {
+        let checks: Vec<HealthCheckConfig> = serde_json::from_str(
+            r#"[
+                {"name": "search", "kind": "http", "url": "http://search:9200/"},
+                {"name": "smtp", "kind": "tcp", "address": "smtp:587",
+                 "criticality": "optional"},
+                {"name": "replica", "kind": "sql",
+                 "database_url": "postgres://replica/app"},
+                {"name": "cache", "kind": "redis"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            checks,
+            vec![
+                HealthCheckConfig {
+                    name: "search".to_owned(),
+                    criticality: Criticality::Critical,
+                    target: HealthCheckTarget::Http {
+                        url: "http://search:9200/".parse().unwrap(),
+                    },
+                },
+                HealthCheckConfig {
+                    name: "smtp".to_owned(),
+                    criticality: Criticality::Optional,
+                    target: HealthCheckTarget::Tcp {
+                        address: "smtp:587".to_owned(),
+                    },
+                },
+                HealthCheckConfig {
+                    name: "replica".to_owned(),
+                    criticality: Criticality::Critical,
+                    target: HealthCheckTarget::Sql {
+                        database_url: Some("postgres://replica/app".to_owned()),
+                        query: "SELECT 1".to_owned(),
+                    },
+                },
+                HealthCheckConfig {
+                    name: "cache".to_owned(),
+                    criticality: Criticality::Critical,
+                    target: HealthCheckTarget::Redis { url: None },
+                },
+            ]
+        );
+
+        // unknown kinds are refused at startup
+        assert!(serde_json::from_str::<Vec<HealthCheckConfig>>(
+            r#"[{"name": "mq", "kind": "amqp"}]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn service_health_serialization_test() {
+        let json = serde_json::to_value(service(Criticality::Optional, false))
+            .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "status": {"unhealthy": "down"},
+                "criticality": "optional",
+                "latency_ms": 1,
+                "cached": false,
+            })
+        );
+    }
+}
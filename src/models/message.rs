@@ -0,0 +1,106 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::messages;
+
+use super::{misc::PaginationLimit, users::UserId};
+
+pub const MAX_MESSAGE_LENGTH: usize = 4000;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = messages)]
+pub struct Message {
+    pub id: i32,
+    pub sender_id: UserId,
+    pub receiver_id: UserId,
+    pub message_text: String,
+    pub created_at: NaiveDateTime,
+    /// Set once the receiver marked the conversation as read
+    pub is_read: bool,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = messages)]
+pub struct NewMessage {
+    pub sender_id: UserId,
+    pub receiver_id: UserId,
+    pub message_text: String,
+}
+
+pub fn validate_message_text(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        Err("Message must not be empty".to_owned())
+    } else if text.len() > MAX_MESSAGE_LENGTH {
+        Err(format!(
+            "Message must be at most {MAX_MESSAGE_LENGTH} bytes long"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Pages through a conversation from the newest message backwards
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagesQuery {
+    /// Only messages older than the message with this id, the
+    /// `next_cursor` of the previous page
+    pub before: Option<i32>,
+    pub limit: Option<PaginationLimit>,
+}
+
+impl MessagesQuery {
+    pub const DEFAULT_LIMIT: u16 = 50;
+
+    pub fn limit(&self) -> u16 {
+        self.limit
+            .as_ref()
+            .map_or(Self::DEFAULT_LIMIT, PaginationLimit::as_uint)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessagesPage {
+    /// Newest first
+    pub messages: Vec<Message>,
+    pub next_cursor: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MarkReadQuery {
+    /// Only marks messages up to this id as read, so that messages that
+    /// arrived after the client fetched the conversation stay unread
+    pub up_to: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MarkedRead {
+    pub marked_read: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable)]
+pub struct UnreadCount {
+    pub peer: UserId,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnreadCounts {
+    pub total: i64,
+    /// Unread messages by sender
+    pub peers: Vec<UnreadCount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_message_text_test() {
+        assert!(validate_message_text("hello").is_ok());
+        assert!(validate_message_text("  ").is_err());
+        assert!(validate_message_text(&"a".repeat(MAX_MESSAGE_LENGTH)).is_ok());
+        assert!(
+            validate_message_text(&"a".repeat(MAX_MESSAGE_LENGTH + 1)).is_err()
+        );
+    }
+}
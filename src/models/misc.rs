@@ -1,7 +1,11 @@
-use crate::schema::jobs;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::schema::{job_acl, jobs};
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
 
+use super::roles::RoleEnum;
 use super::users::UserId;
 
 #[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, new)]
@@ -120,10 +124,15 @@ pub enum JobStatus {
 pub struct Job {
     pub id: i32,
     pub job_id: uuid::Uuid,
-    pub started_by: UserId,
+    /// `None` once the user who started the job has been deleted
+    pub started_by: Option<UserId>,
     pub status: JobStatus,
     pub status_message: Option<String>,
     pub created_at: chrono::NaiveDateTime,
+    /// Starts at 1 and goes up with every automatic retry of the job
+    pub attempt: i32,
+    /// The job this one was manually retried from
+    pub retry_of: Option<uuid::Uuid>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
@@ -133,6 +142,10 @@ pub struct NewJob {
     pub started_by: UserId,
     pub status: JobStatus,
     pub status_message: Option<String>,
+    pub retry_of: Option<uuid::Uuid>,
+    pub idempotency_key: Option<String>,
+    /// The run request, needed to retry the job
+    pub request: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Queryable)]
@@ -141,6 +154,297 @@ pub struct JobCount {
     count: i64,
 }
 
+/// A read grant on a job, given either to a single user or to a role
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = job_acl)]
+pub struct JobAclEntry {
+    pub id: i32,
+    pub job_id: uuid::Uuid,
+    pub user_id: Option<UserId>,
+    pub role_name: Option<RoleEnum>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = job_acl)]
+pub struct NewJobAclEntry {
+    pub job_id: uuid::Uuid,
+    pub user_id: Option<UserId>,
+    pub role_name: Option<RoleEnum>,
+}
+
+/// Body of `POST /api/cmd/{job_id}/acl`, e.g. `{"user_id":2}` or
+/// `{"role":"role_user"}`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum JobAclGrantee {
+    User { user_id: UserId },
+    Role { role: RoleEnum },
+}
+
+impl JobAclGrantee {
+    pub fn into_new_entry(self, job_id: uuid::Uuid) -> NewJobAclEntry {
+        match self {
+            JobAclGrantee::User { user_id } => NewJobAclEntry {
+                job_id,
+                user_id: Some(user_id),
+                role_name: None,
+            },
+            JobAclGrantee::Role { role } => NewJobAclEntry {
+                job_id,
+                user_id: None,
+                role_name: Some(role),
+            },
+        }
+    }
+}
+
+/// Message published on a job's abort channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, new)]
+pub struct JobAbortMessage {
+    /// `None` when the instance running the job aborted it
+    pub aborted_by: Option<UserId>,
+    pub reason: Option<String>,
+    /// Kill the process right away instead of sending SIGTERM first
+    pub force: bool,
+}
+
+impl JobAbortMessage {
+    pub fn status_message(&self) -> String {
+        let aborted = match self.aborted_by {
+            Some(user_id) => format!("Job aborted by user {user_id}"),
+            None => "Job aborted".to_owned(),
+        };
+        match &self.reason {
+            Some(reason) => format!("{aborted}: {reason}"),
+            None => aborted,
+        }
+    }
+}
+
+/// Automatic retries of a failed job, waiting with exponential backoff
+/// between attempts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    #[serde(default = "super::defaults::default_retry_initial_interval_secs")]
+    pub initial_interval_secs: u64,
+    #[serde(default = "super::defaults::default_retry_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "super::defaults::default_retry_max_interval_secs")]
+    pub max_interval_secs: u64,
+}
+
+impl RetryPolicy {
+    pub const MAX_ATTEMPTS: u32 = 10;
+    pub const MAX_INTERVAL_SECS: u64 = 3600;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 || self.max_attempts > Self::MAX_ATTEMPTS {
+            Err(format!(
+                "max_attempts must be between 1 and {}",
+                Self::MAX_ATTEMPTS
+            ))
+        } else if self.initial_interval_secs > self.max_interval_secs
+            || self.max_interval_secs > Self::MAX_INTERVAL_SECS
+        {
+            Err(format!(
+                "Retry intervals must be at most {} seconds, with the initial interval not above the max interval",
+                Self::MAX_INTERVAL_SECS
+            ))
+        } else if !(1.0..=10.0).contains(&self.multiplier) {
+            Err("Retry multiplier must be between 1 and 10".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Backoff between attempts, the same kind the sessions worker uses
+    pub fn backoff(&self) -> backoff::ExponentialBackoff {
+        backoff::ExponentialBackoffBuilder::new()
+            .with_initial_interval(std::time::Duration::from_secs(
+                self.initial_interval_secs,
+            ))
+            .with_multiplier(self.multiplier)
+            .with_max_interval(std::time::Duration::from_secs(
+                self.max_interval_secs,
+            ))
+            .with_max_elapsed_time(None)
+            .build()
+    }
+}
+
+/// `Idempotency-Key` header of `POST /api/cmd`, keys are scoped to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub const HEADER: &'static str = "Idempotency-Key";
+    pub const MAX_LENGTH: usize = 255;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.is_empty()
+            || value.len() > Self::MAX_LENGTH
+            || !value.chars().all(|c| c.is_ascii_graphic())
+        {
+            Err(format!(
+                "{} must be 1 to {} printable ASCII characters",
+                Self::HEADER,
+                Self::MAX_LENGTH
+            ))
+        } else {
+            Ok(IdempotencyKey(value))
+        }
+    }
+}
+
+/// `Last-Event-ID` header a job output stream resumes from, the id of a
+/// Redis stream entry formatted as `{millis}-{seq}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastEventId(String);
+
+impl LastEventId {
+    pub const HEADER: &'static str = "Last-Event-ID";
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl TryFrom<String> for LastEventId {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let valid = value.split_once('-').is_some_and(|(millis, seq)| {
+            millis.parse::<u64>().is_ok() && seq.parse::<u64>().is_ok()
+        });
+        if valid {
+            Ok(LastEventId(value))
+        } else {
+            Err(format!("Invalid {}: {value:?}", Self::HEADER))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AbortJobQuery {
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub force: bool,
+}
+
+impl AbortJobQuery {
+    pub const MAX_REASON_LENGTH: usize = 500;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbortedJobs {
+    pub job_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Keyset cursor over `(created_at, id)`, serialized as `{micros}_{id}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct JobCursor {
+    pub created_at: chrono::NaiveDateTime,
+    pub id: i32,
+}
+
+impl JobCursor {
+    pub fn from_job(job: &Job) -> JobCursor {
+        JobCursor {
+            created_at: job.created_at,
+            id: job.id,
+        }
+    }
+}
+
+impl fmt::Display for JobCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.created_at.and_utc().timestamp_micros(),
+            self.id
+        )
+    }
+}
+
+impl FromStr for JobCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (micros, id) = s
+            .split_once('_')
+            .ok_or_else(|| format!("malformed cursor: {s}"))?;
+        let created_at = micros
+            .parse::<i64>()
+            .ok()
+            .and_then(chrono::DateTime::from_timestamp_micros)
+            .map(|dt| dt.naive_utc())
+            .ok_or_else(|| format!("invalid timestamp in cursor: {s}"))?;
+        let id = id
+            .parse::<i32>()
+            .map_err(|err| format!("invalid id in cursor {s}: {err}"))?;
+        Ok(JobCursor { created_at, id })
+    }
+}
+
+impl TryFrom<String> for JobCursor {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        JobCursor::from_str(&value)
+    }
+}
+
+/// Query string accepted by `GET /api/cmd`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JobsQuery {
+    pub status: Option<JobStatus>,
+    pub created_after: Option<chrono::NaiveDateTime>,
+    pub created_before: Option<chrono::NaiveDateTime>,
+    /// Only honoured for admins, regular users always see their own jobs
+    pub started_by: Option<UserId>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub cursor: Option<JobCursor>,
+    pub limit: Option<PaginationLimit>,
+}
+
+impl JobsQuery {
+    pub const DEFAULT_LIMIT: u16 = 20;
+
+    pub fn limit(&self) -> u16 {
+        self.limit
+            .as_ref()
+            .map(|l| l.as_uint())
+            .unwrap_or(Self::DEFAULT_LIMIT)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobsPage {
+    pub jobs: Vec<Job>,
+    /// Number of jobs matching the filters, ignoring the cursor
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -157,4 +461,48 @@ mod tests {
             serde_json::from_str::<Pagination>(r#"{"limit":5,"page":51}"#);
         assert!(mb_pag.is_err());
     }
+
+    #[test]
+    fn retry_policy_test() {
+        let policy = serde_json::from_str::<RetryPolicy>(
+            r#"{"max_attempts":3,"initial_interval_secs":1}"#,
+        )
+        .unwrap();
+        assert!(policy.validate().is_ok());
+        assert_eq!(policy.max_interval_secs, 300);
+        let policy = RetryPolicy {
+            max_attempts: 11,
+            ..policy
+        };
+        assert!(policy.validate().is_err());
+
+        assert!(IdempotencyKey::try_from("order-42".to_owned()).is_ok());
+        assert!(IdempotencyKey::try_from(String::new()).is_err());
+        assert!(IdempotencyKey::try_from("has space".to_owned()).is_err());
+    }
+
+    #[test]
+    fn job_cursor_roundtrip_test() {
+        let cursor = JobCursor {
+            created_at: chrono::NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_micro_opt(12, 30, 15, 123_456)
+                .unwrap(),
+            id: 42,
+        };
+        let parsed = JobCursor::from_str(&cursor.to_string()).unwrap();
+        assert_eq!(parsed, cursor);
+        assert!(JobCursor::from_str("garbage").is_err());
+        assert!(JobCursor::from_str("abc_1").is_err());
+    }
+
+    #[test]
+    fn last_event_id_test() {
+        for id in ["1700000000000-0", "0-1"] {
+            assert!(LastEventId::try_from(id.to_owned()).is_ok(), "{id}");
+        }
+        for id in ["", "1700000000000", "a-0", "1-b", "1-2-3", "$"] {
+            assert!(LastEventId::try_from(id.to_owned()).is_err(), "{id}");
+        }
+    }
 }
@@ -0,0 +1,187 @@
+use chrono::NaiveDateTime;
+use diesel_derive_enum::DbEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::notifications;
+
+use super::{
+    misc::JobStatus, misc::PaginationLimit, session::LoginAnomaly,
+    users::UserId,
+};
+
+#[derive(
+    DbEnum,
+    Debug,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[ExistingTypePath = "crate::schema::sql_types::NotificationKind"]
+pub enum NotificationKind {
+    JobFinished,
+    NewSession,
+    SuspiciousLogin,
+}
+
+/// What a notification is about, stored as its `data`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A job the user started completed, failed or was aborted
+    JobFinished {
+        job_id: uuid::Uuid,
+        status: JobStatus,
+    },
+    /// Someone logged into the account
+    NewSession {
+        session_id: uuid::Uuid,
+        device_name: Option<String>,
+    },
+    /// Someone logged into the account in a way the user usually does not.
+    /// Sent over WebSocket only, accounts have no email address to alert.
+    SuspiciousLogin {
+        session_id: uuid::Uuid,
+        device_name: Option<String>,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        country: Option<String>,
+        anomalies: Vec<LoginAnomaly>,
+        /// Revokes the session when opened, for when it was not the user
+        revoke_url: String,
+    },
+}
+
+impl NotificationEvent {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            NotificationEvent::JobFinished { .. } => {
+                NotificationKind::JobFinished
+            }
+            NotificationEvent::NewSession { .. } => {
+                NotificationKind::NewSession
+            }
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, JsonSchema,
+)]
+#[diesel(table_name = notifications)]
+pub struct Notification {
+    pub id: i32,
+    pub user_id: UserId,
+    pub kind: NotificationKind,
+    /// The `NotificationEvent`, tagged with its `kind`
+    pub data: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub read_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = notifications)]
+pub struct NewNotification {
+    pub user_id: UserId,
+    pub kind: NotificationKind,
+    pub data: serde_json::Value,
+}
+
+impl NewNotification {
+    pub fn new(user_id: UserId, event: &NotificationEvent) -> Self {
+        Self {
+            user_id,
+            kind: event.kind(),
+            data: serde_json::to_value(event)
+                .expect("notification events serialize to JSON"),
+        }
+    }
+}
+
+/// Pages through the notifications from the newest backwards
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotificationsQuery {
+    /// Only notifications older than the one with this id, the
+    /// `next_cursor` of the previous page
+    pub before: Option<i32>,
+    pub limit: Option<PaginationLimit>,
+    #[serde(default)]
+    pub unread_only: bool,
+}
+
+impl NotificationsQuery {
+    pub const DEFAULT_LIMIT: u16 = 50;
+
+    pub fn limit(&self) -> u16 {
+        self.limit
+            .as_ref()
+            .map_or(Self::DEFAULT_LIMIT, PaginationLimit::as_uint)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotificationsPage {
+    /// Newest first
+    pub notifications: Vec<Notification>,
+    pub next_cursor: Option<i32>,
+    pub unread_count: i64,
+}
+
+/// Marks the notifications with the given ids, or all of them up to and
+/// including `up_to`, as read
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MarkNotificationsReadRequest {
+    pub ids: Vec<i32>,
+    pub up_to: Option<i32>,
+}
+
+impl MarkNotificationsReadRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ids.is_empty() == self.up_to.is_none() {
+            Err("Either ids or up_to must be given".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn new_notification_test() {
+        let event = NotificationEvent::JobFinished {
+            job_id: uuid::Uuid::nil(),
+            status: JobStatus::Failed,
+        };
+        let notification =
+            NewNotification::new(UserId::from_str("1").unwrap(), &event);
+        assert_eq!(notification.kind, NotificationKind::JobFinished);
+        assert_eq!(notification.data["kind"], "job_finished");
+        assert_eq!(notification.data["status"], "failed");
+        assert_eq!(
+            serde_json::from_value::<NotificationEvent>(notification.data)
+                .unwrap(),
+            event
+        );
+    }
+
+    #[test]
+    fn mark_notifications_read_request_test() {
+        let request = |ids: Vec<i32>, up_to: Option<i32>| {
+            MarkNotificationsReadRequest { ids, up_to }.validate()
+        };
+        assert!(request(vec![1, 2], None).is_ok());
+        assert!(request(Vec::new(), Some(3)).is_ok());
+        assert!(request(Vec::new(), None).is_err());
+        assert!(request(vec![1], Some(3)).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::pipeline_steps;
+
+use super::artifact::validate_artifact_patterns;
+use super::misc::{Job, JobStatus};
+
+/// What happens to the rest of a pipeline when one of its steps fails
+#[derive(
+    DbEnum, Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+#[ExistingTypePath = "crate::schema::sql_types::StepFailurePolicy"]
+pub enum StepFailurePolicy {
+    /// Abort the running steps and skip the ones that did not start yet
+    #[default]
+    FailFast,
+    /// Skip the steps depending on the failed one, independent branches keep
+    /// running
+    Continue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineStepDefinition {
+    /// Unique within the pipeline, made of lowercase letters, digits and
+    /// underscores
+    pub name: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub pty: bool,
+    /// Names of the steps that have to complete before this one starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    pub on_failure: StepFailurePolicy,
+    /// Glob patterns of the files to keep from the step working directory
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+}
+
+impl PipelineStepDefinition {
+    /// Environment variable the output of this step is passed to dependent
+    /// steps in, e.g. `PIPELINE_STEP_BUILD_OUTPUT` for a step named `build`
+    pub fn output_env_var(name: &str) -> String {
+        format!("PIPELINE_STEP_{}_OUTPUT", name.to_uppercase())
+    }
+}
+
+/// Body of `POST /api/cmd/pipelines`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunPipelineRequest {
+    pub steps: Vec<PipelineStepDefinition>,
+}
+
+pub const MAX_PIPELINE_STEPS: usize = 20;
+pub const MAX_STEP_NAME_LENGTH: usize = 50;
+/// Longer step outputs are truncated before being stored and passed on
+pub const MAX_STEP_OUTPUT_LENGTH: usize = 4096;
+
+impl RunPipelineRequest {
+    /// Checks the step names and dependencies, returning the step indices in
+    /// topological order
+    pub fn validate(&self) -> Result<Vec<usize>, String> {
+        if self.steps.is_empty() || self.steps.len() > MAX_PIPELINE_STEPS {
+            return Err(format!(
+                "A pipeline must have between 1 and {MAX_PIPELINE_STEPS} steps"
+            ));
+        }
+
+        let mut indices = HashMap::with_capacity(self.steps.len());
+        for (idx, step) in self.steps.iter().enumerate() {
+            let valid_name = !step.name.is_empty()
+                && step.name.len() <= MAX_STEP_NAME_LENGTH
+                && step.name.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+                });
+            if !valid_name {
+                return Err(format!(
+                    "Invalid step name {:?}, expected 1 to {MAX_STEP_NAME_LENGTH} lowercase letters, digits or underscores",
+                    step.name
+                ));
+            }
+            validate_artifact_patterns(&step.artifacts)?;
+            if indices.insert(step.name.as_str(), idx).is_some() {
+                return Err(format!("Duplicate step name {}", step.name));
+            }
+        }
+
+        let mut dependents = vec![Vec::new(); self.steps.len()];
+        let mut in_degree = vec![0usize; self.steps.len()];
+        for (idx, step) in self.steps.iter().enumerate() {
+            let mut seen = HashSet::new();
+            for dep in &step.depends_on {
+                let dep_idx = *indices.get(dep.as_str()).ok_or_else(|| {
+                    format!("Step {} depends on unknown step {dep}", step.name)
+                })?;
+                if dep_idx == idx {
+                    return Err(format!(
+                        "Step {} depends on itself",
+                        step.name
+                    ));
+                }
+                if seen.insert(dep_idx) {
+                    dependents[dep_idx].push(idx);
+                    in_degree[idx] += 1;
+                }
+            }
+        }
+
+        // Kahn's algorithm, any step left over is part of a cycle
+        let mut ready = (0..self.steps.len())
+            .filter(|idx| in_degree[*idx] == 0)
+            .collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(self.steps.len());
+        while let Some(idx) = ready.pop() {
+            order.push(idx);
+            for dependent in &dependents[idx] {
+                in_degree[*dependent] -= 1;
+                if in_degree[*dependent] == 0 {
+                    ready.push(*dependent);
+                }
+            }
+        }
+
+        if order.len() == self.steps.len() {
+            Ok(order)
+        } else {
+            let cycle = self
+                .steps
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| in_degree[*idx] > 0)
+                .map(|(_, step)| step.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(format!("Pipeline steps have a dependency cycle: {cycle}"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = pipeline_steps)]
+pub struct PipelineStep {
+    pub id: i32,
+    pub pipeline_job_id: uuid::Uuid,
+    /// The job running this step, its output is published on `job.{job_id}`
+    pub job_id: uuid::Uuid,
+    pub name: String,
+    pub args: Vec<String>,
+    pub pty: bool,
+    pub depends_on: Vec<String>,
+    pub on_failure: StepFailurePolicy,
+    /// Last line the step wrote to stdout once it completed
+    pub output: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub artifacts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = pipeline_steps)]
+pub struct NewPipelineStep {
+    pub pipeline_job_id: uuid::Uuid,
+    pub job_id: uuid::Uuid,
+    pub name: String,
+    pub args: Vec<String>,
+    pub pty: bool,
+    pub depends_on: Vec<String>,
+    pub on_failure: StepFailurePolicy,
+    pub artifacts: Vec<String>,
+}
+
+/// A pipeline step along with the status of its job
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PipelineStepDetails {
+    #[serde(flatten)]
+    pub step: PipelineStep,
+    pub status: JobStatus,
+    pub status_message: Option<String>,
+}
+
+/// Response of the pipeline endpoints, `job` is the pipeline job whose
+/// status aggregates the status of the steps
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PipelineDetails {
+    pub job: Job,
+    pub steps: Vec<PipelineStepDetails>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(name: &str, depends_on: &[&str]) -> PipelineStepDefinition {
+        PipelineStepDefinition {
+            name: name.to_owned(),
+            args: Vec::new(),
+            pty: false,
+            depends_on: depends_on.iter().map(|d| (*d).to_owned()).collect(),
+            on_failure: StepFailurePolicy::FailFast,
+            artifacts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pipeline_validation_test() {
+        let req = RunPipelineRequest {
+            steps: vec![
+                step("test", &["build"]),
+                step("lint", &[]),
+                step("build", &[]),
+                step("deploy", &["test", "lint"]),
+            ],
+        };
+        let order = req.validate().unwrap();
+        let position = |name: &str| {
+            order
+                .iter()
+                .position(|idx| req.steps[*idx].name == name)
+                .unwrap()
+        };
+        assert!(position("build") < position("test"));
+        assert!(position("test") < position("deploy"));
+        assert!(position("lint") < position("deploy"));
+
+        let req = RunPipelineRequest {
+            steps: vec![
+                step("a", &["c"]),
+                step("b", &["a"]),
+                step("c", &["b"]),
+            ],
+        };
+        assert!(req.validate().is_err());
+
+        let req = RunPipelineRequest {
+            steps: vec![step("a", &["missing"])],
+        };
+        assert!(req.validate().is_err());
+
+        let req = RunPipelineRequest {
+            steps: vec![step("a", &[]), step("a", &[])],
+        };
+        assert!(req.validate().is_err());
+
+        let req = RunPipelineRequest {
+            steps: vec![step("Not Valid", &[])],
+        };
+        assert!(req.validate().is_err());
+
+        assert_eq!(
+            PipelineStepDefinition::output_env_var("build_1"),
+            "PIPELINE_STEP_BUILD_1_OUTPUT"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::users::{UserId, UserWithRoles};
+
+/// How long a connection counts as online after its last heartbeat, three
+/// missed heartbeats of the WebSocket connection
+pub const PRESENCE_TTL_SECS: i64 = 90;
+pub const MAX_PRESENCE_USERS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UserPresence {
+    pub user_id: UserId,
+    pub online: bool,
+    /// Devices with an open WebSocket connection
+    pub devices: usize,
+    /// Last time the user was connected, `None` if they never were
+    pub last_seen_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresenceQuery {
+    /// Comma separated ids of the users
+    pub user_ids: String,
+}
+
+impl PresenceQuery {
+    pub fn user_ids(&self) -> Result<Vec<UserId>, String> {
+        let mut user_ids = self
+            .user_ids
+            .split(',')
+            .map(str::trim)
+            .filter(|user_id| !user_id.is_empty())
+            .map(|user_id| {
+                user_id.parse::<UserId>().map_err(|err| {
+                    format!("Invalid user id {user_id:?}: {err}")
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        user_ids.sort_by_key(|user_id| user_id.as_uint());
+        user_ids.dedup();
+        if user_ids.is_empty() || user_ids.len() > MAX_PRESENCE_USERS {
+            return Err(format!(
+                "Between 1 and {MAX_PRESENCE_USERS} user ids are required"
+            ));
+        }
+        Ok(user_ids)
+    }
+}
+
+/// Public profile of a user along with their presence
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PublicUserProfile {
+    #[serde(flatten)]
+    pub user: UserWithRoles,
+    pub online: bool,
+    pub last_seen_at: Option<NaiveDateTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(user_ids: &str) -> PresenceQuery {
+        PresenceQuery {
+            user_ids: user_ids.to_owned(),
+        }
+    }
+
+    #[test]
+    fn presence_query_test() {
+        let user_ids = query("3, 1,3,").user_ids().unwrap();
+        assert_eq!(
+            user_ids.iter().map(UserId::as_uint).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert!(query("").user_ids().is_err());
+        assert!(query("1,a").user_ids().is_err());
+        let too_many = (1..=MAX_PRESENCE_USERS + 1)
+            .map(|user_id| user_id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        assert!(query(&too_many).user_ids().is_err());
+    }
+}
@@ -18,6 +18,13 @@ pub enum RoleEnum {
     RoleUser,
 }
 
+impl RoleEnum {
+    /// Roles that are allowed to act on resources owned by other users
+    pub fn is_admin(&self) -> bool {
+        matches!(self, RoleEnum::RoleSuperUser | RoleEnum::RoleAdmin)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
 #[diesel(table_name = roles)]
 pub struct Role {
@@ -0,0 +1,170 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::schema::{runner_jobs, runners};
+
+use super::users::UserId;
+
+/// Header runners authenticate with
+pub const RUNNER_TOKEN_HEADER: &str = "X-RUNNER-TOKEN";
+pub const RUNNER_TOKEN_LENGTH: usize = 40;
+pub const MAX_RUNNER_NAME_LENGTH: usize = 50;
+pub const MAX_LABELS: usize = 20;
+pub const MAX_LABEL_LENGTH: usize = 50;
+/// A job whose runner died more often than this fails instead of going back
+/// to the queue
+pub const MAX_JOB_REQUEUES: i32 = 3;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = runners)]
+pub struct Runner {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing, default)]
+    pub token_hash: String,
+    pub labels: Vec<String>,
+    pub created_by: UserId,
+    pub last_heartbeat_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Returned once when the runner is registered, the token is not shown again
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatedRunner {
+    #[serde(flatten)]
+    pub runner: Runner,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = runners)]
+pub struct NewRunner {
+    pub name: String,
+    pub token_hash: String,
+    pub labels: Vec<String>,
+    pub created_by: UserId,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateRunnerRequest {
+    pub name: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// Only the hash of a runner token is stored, tokens are random enough for a
+/// plain SHA-256 to do
+pub fn hash_runner_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Runner names and labels are made of lowercase letters, digits, `-`, `_`
+/// and `.`
+pub fn validate_label(label: &str, max_length: usize) -> Result<(), String> {
+    let valid = !label.is_empty()
+        && label.len() <= max_length
+        && label.chars().all(|c| {
+            c.is_ascii_lowercase()
+                || c.is_ascii_digit()
+                || matches!(c, '-' | '_' | '.')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{label:?} must be 1 to {max_length} lowercase letters, digits, '-', '_' or '.'"
+        ))
+    }
+}
+
+pub fn validate_labels(labels: &[String]) -> Result<Vec<String>, String> {
+    if labels.len() > MAX_LABELS {
+        return Err(format!("At most {MAX_LABELS} labels are allowed"));
+    }
+    for label in labels {
+        validate_label(label, MAX_LABEL_LENGTH)?;
+    }
+    let mut labels = labels.to_vec();
+    labels.sort();
+    labels.dedup();
+    Ok(labels)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = runner_jobs, primary_key(job_id))]
+pub struct RunnerJob {
+    pub job_id: uuid::Uuid,
+    pub labels: Vec<String>,
+    pub runner_id: Option<i32>,
+    pub claimed_at: Option<NaiveDateTime>,
+    pub requeues: i32,
+    pub abort_requested: bool,
+    pub finished_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = runner_jobs)]
+pub struct NewRunnerJob {
+    pub job_id: uuid::Uuid,
+    pub labels: Vec<String>,
+}
+
+/// A job handed out to a runner
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunnerJobAssignment {
+    pub job_id: uuid::Uuid,
+    pub args: Vec<String>,
+    pub pty: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClaimJobQuery {
+    /// How long to wait for a job when none is queued
+    pub wait_secs: Option<u64>,
+}
+
+impl ClaimJobQuery {
+    pub const MAX_WAIT_SECS: u64 = 30;
+
+    pub fn wait_secs(&self) -> u64 {
+        self.wait_secs.unwrap_or(0).min(Self::MAX_WAIT_SECS)
+    }
+}
+
+/// Response to output sent by a runner
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunnerJobControl {
+    /// The job was aborted, the runner should stop its process
+    pub abort: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompleteRunnerJobRequest {
+    pub exit_code: i32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunnerHeartbeat {
+    /// Jobs of the runner that were aborted
+    pub abort: Vec<uuid::Uuid>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runner_labels_test() {
+        let labels = ["gpu", "linux", "gpu", "x86_64"].map(|l| l.to_owned());
+        assert_eq!(
+            validate_labels(&labels).unwrap(),
+            vec!["gpu", "linux", "x86_64"]
+        );
+        for label in ["", "GPU", "has space", &"a".repeat(51)] {
+            assert!(validate_labels(&[label.to_owned()]).is_err());
+        }
+        assert_eq!(hash_runner_token("token").len(), 64);
+    }
+}
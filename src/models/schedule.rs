@@ -0,0 +1,220 @@
+use std::str::FromStr;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::job_schedules;
+
+use super::{misc::RetryPolicy, users::UserId};
+
+/// What the scheduler does with a run that was due while it wasn't running
+#[derive(
+    DbEnum, Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+#[ExistingTypePath = "crate::schema::sql_types::MissedRunPolicy"]
+pub enum MissedRunPolicy {
+    /// Drop the missed runs and wait for the next occurrence
+    #[default]
+    Skip,
+    /// Run once as soon as possible, however many occurrences were missed
+    RunOnce,
+}
+
+/// Cron expression with a seconds field, e.g. `0 30 3 * * *`. Classic five
+/// field expressions are accepted and run at second zero.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronExpression(String);
+
+impl CronExpression {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// First occurrence strictly after `after`, with the expression evaluated
+    /// in `tz`. Both timestamps are naive UTC like the ones in the database.
+    pub fn next_run_after(
+        &self,
+        after: NaiveDateTime,
+        tz: chrono_tz::Tz,
+    ) -> Option<NaiveDateTime> {
+        parse_schedule(&self.0).ok().and_then(|schedule| {
+            schedule
+                .after(&after.and_utc().with_timezone(&tz))
+                .next()
+                .map(|next| next.naive_utc())
+        })
+    }
+}
+
+impl TryFrom<String> for CronExpression {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let value = if value.split_whitespace().count() == 5 {
+            format!("0 {value}")
+        } else {
+            value.to_owned()
+        };
+        parse_schedule(&value)
+            .map_err(|err| format!("Invalid cron expression {value}: {err}"))
+            .and_then(|schedule| {
+                schedule
+                    .upcoming(Utc)
+                    .next()
+                    .map(|_| CronExpression(value.clone()))
+                    .ok_or_else(|| {
+                        format!("Cron expression {value} never fires")
+                    })
+            })
+    }
+}
+
+impl From<CronExpression> for String {
+    fn from(value: CronExpression) -> Self {
+        value.0
+    }
+}
+
+fn parse_schedule(value: &str) -> Result<cron::Schedule, cron::error::Error> {
+    cron::Schedule::from_str(value)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = job_schedules)]
+pub struct JobSchedule {
+    pub id: i32,
+    pub name: String,
+    pub cron_expression: String,
+    pub args: Vec<String>,
+    pub pty: bool,
+    pub created_by: UserId,
+    pub enabled: bool,
+    pub missed_run_policy: MissedRunPolicy,
+    pub last_run_at: Option<NaiveDateTime>,
+    pub last_job_id: Option<uuid::Uuid>,
+    pub next_run_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub artifacts: Vec<String>,
+    pub retry: Option<serde_json::Value>,
+}
+
+impl JobSchedule {
+    /// The stored policy was validated on the way in
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry
+            .clone()
+            .and_then(|retry| serde_json::from_value(retry).ok())
+    }
+
+    /// The stored expression was validated on the way in
+    pub fn cron_expression(&self) -> Result<CronExpression, String> {
+        CronExpression::try_from(self.cron_expression.clone())
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = job_schedules)]
+pub struct NewJobSchedule {
+    pub name: String,
+    pub cron_expression: String,
+    pub args: Vec<String>,
+    pub pty: bool,
+    pub created_by: UserId,
+    pub enabled: bool,
+    pub missed_run_policy: MissedRunPolicy,
+    pub next_run_at: Option<NaiveDateTime>,
+    pub artifacts: Vec<String>,
+    pub retry: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, AsChangeset)]
+#[diesel(table_name = job_schedules)]
+pub struct JobScheduleChangeset {
+    pub name: Option<String>,
+    pub cron_expression: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub pty: Option<bool>,
+    pub enabled: Option<bool>,
+    pub missed_run_policy: Option<MissedRunPolicy>,
+    pub next_run_at: Option<Option<NaiveDateTime>>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub artifacts: Option<Vec<String>>,
+    pub retry: Option<Option<serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateJobScheduleRequest {
+    pub name: String,
+    pub cron_expression: CronExpression,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub pty: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub missed_run_policy: MissedRunPolicy,
+    /// Glob patterns of the files to keep from the job working directory
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+    /// Retries of failed jobs started by the schedule
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateJobScheduleRequest {
+    pub name: Option<String>,
+    pub cron_expression: Option<CronExpression>,
+    pub args: Option<Vec<String>>,
+    pub pty: Option<bool>,
+    pub enabled: Option<bool>,
+    pub missed_run_policy: Option<MissedRunPolicy>,
+    pub artifacts: Option<Vec<String>>,
+    /// Replaces the retry policy, a `max_attempts` of 1 turns retries off
+    pub retry: Option<RetryPolicy>,
+}
+
+pub const MAX_SCHEDULE_NAME_LENGTH: usize = 100;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn cron_expression_test() {
+        let expr = CronExpression::try_from("30 3 * * *".to_owned()).unwrap();
+        assert_eq!(expr.as_str(), "0 30 3 * * *");
+
+        let after = NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let next = expr.next_run_after(after, chrono_tz::Tz::UTC).unwrap();
+        assert_eq!(next, after + chrono::Duration::minutes(210));
+
+        // 03:30 in Kolkata is 22:00 UTC on the previous day
+        let next = expr
+            .next_run_after(after, chrono_tz::Tz::Asia__Kolkata)
+            .unwrap();
+        assert_eq!(
+            next,
+            NaiveDate::from_ymd_opt(2026, 1, 1)
+                .unwrap()
+                .and_hms_opt(22, 0, 0)
+                .unwrap()
+        );
+
+        assert!(CronExpression::try_from("not a cron".to_owned()).is_err());
+    }
+}
@@ -1,7 +1,11 @@
+use std::net::IpAddr;
+
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::{misc::PaginationLimit, users::UserId};
+
 /// Configuration for session management
 #[derive(Debug, Clone, Deserialize, Builder)]
 #[serde(rename_all = "snake_case")]
@@ -23,6 +27,18 @@ pub struct SessionConfig {
     /// Whether session management is disabled
     #[builder(default = "false")]
     pub disable: bool,
+    /// Header with the country code of the client, set by a proxy in front
+    /// of the app
+    #[builder(default = "\"CF-IPCountry\".to_owned()")]
+    pub country_header: String,
+    /// Proxies whose forwarding and country headers are trusted. The headers
+    /// of other clients are ignored, as anyone could set them.
+    #[builder(default = "Vec::new()")]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Logins from two countries within this many seconds are flagged as
+    /// impossible travel
+    #[builder(default = "3600")]
+    pub impossible_travel_secs: u64,
 }
 
 /// Policy configuration for session renewal
@@ -39,19 +55,111 @@ pub struct SessionRenewalPolicy {
     pub max_renewals: u32,
 }
 
+impl SessionRenewalPolicy {
+    /// Seconds a session is extended by when used, none when renewal is
+    /// disabled
+    pub fn extension_secs(&self) -> u64 {
+        if self.enabled {
+            self.renewal_window_secs
+        } else {
+            0
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SessionInfo {
     pub session_id: Uuid,
     pub device_id: Uuid,
     pub device_name: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Two letter country code, when the proxy in front of the app sets it
+    #[serde(default)]
+    pub country: Option<String>,
+    /// Parsed from the `user_agent`
+    #[serde(default)]
+    pub platform: Option<ClientPlatform>,
     pub created_at: chrono::NaiveDateTime,
     pub last_used_at: chrono::NaiveDateTime,
-    pub token: String,
+    /// How many times the expiry was pushed back by using the session
+    #[serde(default)]
+    pub renewal_count: u32,
     #[serde(skip)]
     // Skip serialization/deserialization since it's a computed value
     pub ttl_remaining: Option<i64>,
 }
 
+/// Operating system of a client, as told by its User-Agent
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientPlatform {
+    Android,
+    ChromeOs,
+    Ios,
+    Linux,
+    MacOs,
+    Windows,
+    Other,
+}
+
+impl ClientPlatform {
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        // iPads claim to be Macs and Android and ChromeOS to be Linux, so
+        // they are checked first
+        const PATTERNS: [(&str, ClientPlatform); 8] = [
+            ("iPhone", ClientPlatform::Ios),
+            ("iPad", ClientPlatform::Ios),
+            ("Android", ClientPlatform::Android),
+            ("CrOS", ClientPlatform::ChromeOs),
+            ("Windows", ClientPlatform::Windows),
+            ("Macintosh", ClientPlatform::MacOs),
+            ("Mac OS X", ClientPlatform::MacOs),
+            ("Linux", ClientPlatform::Linux),
+        ];
+        PATTERNS
+            .iter()
+            .find(|(pattern, _)| user_agent.contains(pattern))
+            .map_or(ClientPlatform::Other, |(_, platform)| *platform)
+    }
+}
+
+/// A live session of any user, for admins
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActiveSession {
+    pub user_id: UserId,
+    #[serde(flatten)]
+    pub session: SessionInfo,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// Pages through the live sessions, latest to expire first
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActiveSessionsQuery {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<PaginationLimit>,
+}
+
+impl ActiveSessionsQuery {
+    pub const DEFAULT_LIMIT: u16 = 50;
+
+    pub fn limit(&self) -> u16 {
+        self.limit
+            .as_ref()
+            .map_or(Self::DEFAULT_LIMIT, PaginationLimit::as_uint)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActiveSessionsPage {
+    pub sessions: Vec<ActiveSession>,
+    /// Live sessions of all users
+    pub total: usize,
+}
+
 #[derive(PartialEq)]
 pub enum SessionStatus {
     Expired,
@@ -67,3 +175,224 @@ impl SessionStatus {
         }
     }
 }
+
+/// Country code out of a header set by a proxy, `XX` is what Cloudflare
+/// sends when it does not know the country
+pub fn parse_country_code(value: &str) -> Option<String> {
+    let value = value.trim().to_ascii_uppercase();
+    (value.len() == 2
+        && value.chars().all(|c| c.is_ascii_alphanumeric())
+        && value != "XX")
+        .then_some(value)
+}
+
+/// Most expired sessions removed at once by the cleanup worker
+pub const SESSION_CLEANUP_BATCH_SIZE: usize = 500;
+
+/// How many logins of a user are kept to compare new ones against
+pub const MAX_LOGIN_HISTORY: isize = 100;
+
+/// A past login of a user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoginRecord {
+    pub device_id: Uuid,
+    pub ip: Option<String>,
+    pub country: Option<String>,
+    pub at: chrono::NaiveDateTime,
+}
+
+impl LoginRecord {
+    pub fn from_session(session_info: &SessionInfo) -> Self {
+        Self {
+            device_id: session_info.device_id,
+            ip: session_info.ip.clone(),
+            country: session_info.country.clone(),
+            at: session_info.created_at,
+        }
+    }
+
+    /// The /24 network of an IPv4 address, the /48 one of an IPv6 address
+    pub fn subnet(&self) -> Option<String> {
+        match self.ip.as_deref()?.parse::<IpAddr>().ok()? {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                Some(format!("{a}.{b}.{c}.0/24"))
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, c, ..] = ip.segments();
+                Some(format!("{a:x}:{b:x}:{c:x}::/48"))
+            }
+        }
+    }
+}
+
+/// Why a login looks like it was not made by the user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginAnomaly {
+    NewDevice,
+    NewCountry,
+    NewSubnet,
+    /// From another country than the previous login, sooner than one could
+    /// have travelled there
+    ImpossibleTravel,
+}
+
+/// Compares a login with the previous ones of the user, newest first. The
+/// first login of a user is never flagged.
+pub fn detect_login_anomalies(
+    history: &[LoginRecord],
+    login: &LoginRecord,
+    impossible_travel_secs: u64,
+) -> Vec<LoginAnomaly> {
+    let mut anomalies = Vec::new();
+    if history.is_empty() {
+        return anomalies;
+    }
+    if history
+        .iter()
+        .all(|record| record.device_id != login.device_id)
+    {
+        anomalies.push(LoginAnomaly::NewDevice);
+    }
+    if let Some(country) = &login.country {
+        if history
+            .iter()
+            .all(|record| record.country.as_ref() != Some(country))
+        {
+            anomalies.push(LoginAnomaly::NewCountry);
+        }
+        let previous = history.iter().find(|record| record.country.is_some());
+        if let Some(previous) = previous {
+            let secs_since = (login.at - previous.at).num_seconds();
+            if previous.country.as_ref() != Some(country)
+                && u64::try_from(secs_since)
+                    .is_ok_and(|secs| secs < impossible_travel_secs)
+            {
+                anomalies.push(LoginAnomaly::ImpossibleTravel);
+            }
+        }
+    }
+    if let Some(subnet) = login.subnet() {
+        if history
+            .iter()
+            .all(|record| record.subnet().as_ref() != Some(&subnet))
+        {
+            anomalies.push(LoginAnomaly::NewSubnet);
+        }
+    }
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(
+        device_id: u128,
+        ip: &str,
+        country: &str,
+        minutes: i64,
+    ) -> LoginRecord {
+        LoginRecord {
+            device_id: Uuid::from_u128(device_id),
+            ip: Some(ip.to_owned()),
+            country: Some(country.to_owned()),
+            at: chrono::DateTime::from_timestamp(minutes * 60, 0)
+                .unwrap()
+                .naive_utc(),
+        }
+    }
+
+    #[test]
+    fn client_platform_test() {
+        let platform = ClientPlatform::from_user_agent;
+        assert_eq!(
+            platform("Mozilla/5.0 (iPad; CPU OS 17_0 like Mac OS X)"),
+            ClientPlatform::Ios
+        );
+        assert_eq!(
+            platform("Mozilla/5.0 (Linux; Android 14; Pixel 8)"),
+            ClientPlatform::Android
+        );
+        assert_eq!(
+            platform("Mozilla/5.0 (Windows NT 10.0; Win64; x64)"),
+            ClientPlatform::Windows
+        );
+        assert_eq!(
+            platform("Mozilla/5.0 (X11; Linux x86_64)"),
+            ClientPlatform::Linux
+        );
+        assert_eq!(platform("curl/8.5.0"), ClientPlatform::Other);
+    }
+
+    #[test]
+    fn renewal_extension_test() {
+        let policy = SessionRenewalPolicyBuilder::default()
+            .renewal_window_secs(60)
+            .build()
+            .unwrap();
+        assert_eq!(policy.extension_secs(), 60);
+        let disabled = SessionRenewalPolicyBuilder::default()
+            .enabled(false)
+            .build()
+            .unwrap();
+        assert_eq!(disabled.extension_secs(), 0);
+    }
+
+    #[test]
+    fn parse_country_code_test() {
+        assert_eq!(parse_country_code(" de").as_deref(), Some("DE"));
+        assert_eq!(parse_country_code("XX"), None);
+        assert_eq!(parse_country_code("DEU"), None);
+    }
+
+    #[test]
+    fn login_subnet_test() {
+        assert_eq!(
+            login(1, "10.1.2.3", "DE", 0).subnet().as_deref(),
+            Some("10.1.2.0/24")
+        );
+        assert_eq!(
+            login(1, "2001:db8:1:2::1", "DE", 0).subnet().as_deref(),
+            Some("2001:db8:1::/48")
+        );
+        assert_eq!(login(1, "unknown", "DE", 0).subnet(), None);
+    }
+
+    #[test]
+    fn detect_login_anomalies_test() {
+        let history = vec![login(1, "10.1.2.3", "DE", 0)];
+        assert!(detect_login_anomalies(&[], &history[0], 3600).is_empty());
+        assert!(detect_login_anomalies(
+            &history,
+            &login(1, "10.1.2.200", "DE", 10),
+            3600
+        )
+        .is_empty());
+        assert_eq!(
+            detect_login_anomalies(
+                &history,
+                &login(2, "10.9.9.9", "DE", 10),
+                3600
+            ),
+            vec![LoginAnomaly::NewDevice, LoginAnomaly::NewSubnet]
+        );
+        assert_eq!(
+            detect_login_anomalies(
+                &history,
+                &login(1, "10.1.2.3", "FR", 10),
+                3600
+            ),
+            vec![LoginAnomaly::NewCountry, LoginAnomaly::ImpossibleTravel]
+        );
+        assert_eq!(
+            detect_login_anomalies(
+                &history,
+                &login(1, "10.1.2.3", "FR", 120),
+                3600
+            ),
+            vec![LoginAnomaly::NewCountry]
+        );
+    }
+}
@@ -31,6 +31,17 @@ impl UserId {
         self.0.try_into().unwrap()
     }
 }
+impl schemars::JsonSchema for UserId {
+    fn schema_name() -> String {
+        "UserId".to_owned()
+    }
+
+    fn json_schema(
+        gen: &mut schemars::gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        u32::json_schema(gen)
+    }
+}
 impl From<UserId> for u32 {
     fn from(s: UserId) -> u32 {
         //this should be safe to unwrap since our newtype
@@ -137,6 +148,9 @@ pub struct UserLogin {
     #[serde(skip_serializing)]
     pub password: Password,
     pub device_name: Option<String>,
+    /// Persistent id of the device, kept by the client across logins. A
+    /// new one is generated when missing.
+    pub device_id: Option<uuid::Uuid>,
 }
 
 #[derive(Debug, Clone, Deserialize, Queryable)]
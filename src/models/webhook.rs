@@ -0,0 +1,342 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use chrono::NaiveDateTime;
+use diesel_derive_enum::DbEnum;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::schema::{webhook_deliveries, webhooks};
+
+use super::{
+    misc::{Job, JobStatus},
+    users::UserId,
+};
+
+pub const MAX_WEBHOOKS_PER_USER: usize = 10;
+pub const MAX_WEBHOOK_URL_LENGTH: usize = 2048;
+pub const WEBHOOK_SECRET_LENGTH: usize = 32;
+
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// `sha256=` followed by the hex encoded HMAC-SHA256 of
+/// `{timestamp}.{body}`, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Lifecycle events of a job a webhook can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobEvent {
+    Created,
+    /// Sent for every attempt of a job that is retried
+    Started,
+    Completed,
+    Failed,
+    Aborted,
+}
+
+impl JobEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobEvent::Created => "created",
+            JobEvent::Started => "started",
+            JobEvent::Completed => "completed",
+            JobEvent::Failed => "failed",
+            JobEvent::Aborted => "aborted",
+        }
+    }
+
+    /// The event a job ending with `status` triggers
+    pub fn for_final_status(status: &JobStatus) -> Option<JobEvent> {
+        match status {
+            JobStatus::Pending => None,
+            JobStatus::Completed => Some(JobEvent::Completed),
+            JobStatus::Failed => Some(JobEvent::Failed),
+            JobStatus::Aborted => Some(JobEvent::Aborted),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = webhooks)]
+pub struct Webhook {
+    pub id: i32,
+    pub user_id: UserId,
+    pub url: String,
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Webhook {
+    /// Value of the signature header of a delivery sent at `timestamp`
+    pub fn signature(&self, timestamp: i64, body: &str) -> String {
+        format!(
+            "sha256={}",
+            hmac_sha256_hex(&self.secret, &format!("{timestamp}.{body}"))
+        )
+    }
+}
+
+pub fn hmac_sha256_hex(key: &str, message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Returned once when the webhook is created, the secret is not shown again
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = webhooks)]
+pub struct NewWebhook {
+    pub user_id: UserId,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, AsChangeset)]
+#[diesel(table_name = webhooks)]
+pub struct WebhookChangeset {
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub events: Vec<JobEvent>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    pub events: Option<Vec<JobEvent>>,
+    pub enabled: Option<bool>,
+}
+
+/// Webhooks are called with POST requests to http or https URLs
+pub fn validate_webhook_url(url: &str) -> Result<String, String> {
+    if url.len() > MAX_WEBHOOK_URL_LENGTH {
+        return Err(format!(
+            "Webhook URL must be at most {MAX_WEBHOOK_URL_LENGTH} characters long"
+        ));
+    }
+    let parsed = url::Url::parse(url)
+        .map_err(|err| format!("Invalid webhook URL {url}: {err}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("Webhook URL {url} must use http or https"));
+    }
+    Ok(parsed.into())
+}
+
+/// Whether webhooks may be sent to the address. Loopback, private,
+/// link-local (including cloud metadata endpoints), shared and other special
+/// purpose addresses belong to the internal network of the server.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    let shared = a == 100 && (64..128).contains(&b);
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || shared
+        || a == 0)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    let unique_local = first & 0xfe00 == 0xfc00;
+    let link_local = first & 0xffc0 == 0xfe80;
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || unique_local
+        || link_local)
+}
+
+pub fn validate_webhook_events(
+    events: Vec<JobEvent>,
+) -> Result<Vec<String>, String> {
+    if events.is_empty() {
+        return Err("A webhook needs at least one event".to_owned());
+    }
+    let mut events = events
+        .iter()
+        .map(|event| event.as_str().to_owned())
+        .collect::<Vec<_>>();
+    events.sort();
+    events.dedup();
+    Ok(events)
+}
+
+#[derive(DbEnum, Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[ExistingTypePath = "crate::schema::sql_types::WebhookDeliveryStatus"]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    /// Gave up after the maximum number of attempts
+    Failed,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub job_id: uuid::Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    /// HTTP status of the last attempt, if the endpoint responded
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct NewWebhookDelivery {
+    pub webhook_id: i32,
+    pub job_id: uuid::Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+}
+
+/// Body of a webhook delivery
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobEventPayload {
+    pub event: JobEvent,
+    pub job: Job,
+    pub occurred_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WebhookDeliveriesQuery {
+    pub limit: Option<i64>,
+}
+
+impl WebhookDeliveriesQuery {
+    pub const DEFAULT_LIMIT: i64 = 50;
+    pub const MAX_LIMIT: i64 = 200;
+
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+}
+
+/// Delay before the next attempt of a delivery that failed `attempts` times,
+/// doubling from `initial_secs` up to `max_secs`
+pub fn delivery_retry_delay(
+    attempts: i32,
+    initial_secs: u64,
+    max_secs: u64,
+) -> chrono::Duration {
+    let exponent = u32::try_from(attempts.saturating_sub(1))
+        .unwrap_or(0)
+        .min(31);
+    let secs = initial_secs
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(max_secs);
+    chrono::Duration::seconds(i64::try_from(secs).unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature_test() {
+        assert_eq!(
+            hmac_sha256_hex(
+                "key",
+                "The quick brown fox jumps over the lazy dog"
+            ),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn webhook_validation_test() {
+        assert!(validate_webhook_url("https://example.com/hooks").is_ok());
+        assert!(validate_webhook_url("ftp://example.com/hooks").is_err());
+        assert!(validate_webhook_url("not a url").is_err());
+
+        let events = validate_webhook_events(vec![
+            JobEvent::Failed,
+            JobEvent::Completed,
+            JobEvent::Failed,
+        ])
+        .unwrap();
+        assert_eq!(events, vec!["completed", "failed"]);
+        assert!(validate_webhook_events(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn public_ip_test() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip} is internal");
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[test]
+    fn delivery_retry_delay_test() {
+        assert_eq!(delivery_retry_delay(1, 30, 3600).num_seconds(), 30);
+        assert_eq!(delivery_retry_delay(3, 30, 3600).num_seconds(), 120);
+        assert_eq!(delivery_retry_delay(20, 30, 3600).num_seconds(), 3600);
+    }
+}
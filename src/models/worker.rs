@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct WorkerBackoffConfig {
@@ -8,8 +9,91 @@ pub struct WorkerBackoffConfig {
     pub max_elapsed_time_secs: u64,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobSchedulerConfig {
+    /// How often the leader looks for due schedules
+    pub interval_secs: u64,
+    /// Runs later than this are considered missed and handled according to
+    /// the schedule's missed run policy
+    pub missed_run_tolerance_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobArtifactsConfig {
+    /// Every job gets a scratch working directory named after it in here
+    pub workdir_root: String,
+    /// Matching files larger than this are not collected
+    pub max_file_size_bytes: u64,
+    /// Artifacts older than this are deleted by the cleanup worker
+    pub retention_days: u32,
+    pub cleanup_interval_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    /// How often the delivery worker looks for due deliveries
+    pub delivery_interval_secs: u64,
+    /// Deliveries are given up on after this many failed attempts
+    pub max_attempts: u32,
+    pub timeout_secs: u64,
+    pub retry_initial_interval_secs: u64,
+    pub retry_max_interval_secs: u64,
+    /// Allows webhooks to internal addresses, such as loopback and private
+    /// networks. Off unless every user is trusted.
+    pub allow_private_targets: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RunnerConfig {
+    /// Jobs of runners silent for longer than this go back to the queue
+    pub heartbeat_timeout_secs: u64,
+    /// How often the runner monitor looks for runners that stopped responding
+    pub monitor_interval_secs: u64,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct WorkerConfig {
     pub backoff: WorkerBackoffConfig,
     pub run_interval: u16,
 }
+
+/// Outcome of the latest runs of a worker, on whichever instance ran them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct WorkerRunStatus {
+    /// The instance of the latest run
+    pub instance_id: Option<String>,
+    pub last_run_at: Option<chrono::NaiveDateTime>,
+    pub last_success_at: Option<chrono::NaiveDateTime>,
+    /// Items processed by the latest successful run
+    pub last_processed: Option<usize>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<chrono::NaiveDateTime>,
+    #[serde(default)]
+    pub consecutive_failures: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct WorkerLeader {
+    pub instance_id: String,
+    /// Increases with every change of leader
+    pub fencing_token: u64,
+    pub lease_expires_in_ms: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct WorkerStatus {
+    pub name: String,
+    /// `None` for workers running on every instance, or while no instance
+    /// holds the lease
+    pub leader: Option<WorkerLeader>,
+    #[serde(flatten)]
+    pub run: WorkerRunStatus,
+}
+
+/// Fencing token of the leader doing a write, see
+/// [`crate::actions::workers::check_worker_fence`]
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerFence {
+    pub worker: &'static str,
+    pub token: u64,
+}
@@ -1,8 +1,54 @@
-use crate::models::users::UserId;
+use std::{fmt, str::FromStr};
+
+use chrono::NaiveDateTime;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+
+use crate::models::{
+    conversation::ConversationChange, notification::Notification, users::UserId,
+};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How long the delivery cursor of a device is kept after its last ack
+pub const MESSAGE_CURSOR_TTL_SECS: u64 = 30 * 24 * 3600;
+
+/// Versions of the WebSocket protocol, negotiated through the
+/// `Sec-WebSocket-Protocol` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WsProtocol {
+    /// Bare client events, only failed commands are answered, with error
+    /// events without an id. Used when the client asks for no protocol.
+    V1,
+    /// Client events in a `WsClientFrame`, every command is answered with
+    /// an `Ack` or a `Nack` carrying its `request_id`
+    V2,
+}
+
+impl WsProtocol {
+    pub const ALL: [WsProtocol; 2] = [WsProtocol::V1, WsProtocol::V2];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WsProtocol::V1 => "actix-demo.v1",
+            WsProtocol::V2 => "actix-demo.v2",
+        }
+    }
+
+    /// Picks the newest of the comma separated protocols the client offers
+    pub fn negotiate(offered: &str) -> Option<WsProtocol> {
+        offered
+            .split(',')
+            .map(str::trim)
+            .filter_map(|offered| {
+                Self::ALL
+                    .into_iter()
+                    .find(|protocol| protocol.as_str() == offered)
+            })
+            .max()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "kind")]
 pub enum MyProcessItem {
     Line { value: String },
@@ -10,7 +56,17 @@ pub enum MyProcessItem {
     Done { code: String },
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// Frame of a client command
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct WsClientFrame {
+    /// Chosen by the client, the `Ack` or `Nack` of the command carries it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub event: WsClientEvent,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(tag = "kind")]
 pub enum WsClientEvent {
     SendMessage {
@@ -21,26 +77,189 @@ pub enum WsClientEvent {
     SubscribeJob {
         job_id: uuid::Uuid,
     },
+    #[serde(rename_all = "camelCase")]
+    JobInput {
+        job_id: uuid::Uuid,
+        data: String,
+    },
+    /// Sends a message to all members of a conversation the user is a
+    /// member of
+    #[serde(rename_all = "camelCase")]
+    SendConversationMessage {
+        conversation_id: i32,
+        message: String,
+    },
+    /// Acknowledges the delivery of the messages up to and including the
+    /// one with this id, they are not delivered to the device again
+    AckMessages {
+        id: MessageStreamId,
+    },
     Error {
         cause: String,
     },
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// Id of an entry in a Redis stream, formatted as `{millis}-{seq}`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MessageStreamId {
+    pub millis: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for MessageStreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.millis, self.seq)
+    }
+}
 
-pub struct SentMessage {
-    pub sender: UserId,
-    pub message: String,
+impl FromStr for MessageStreamId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('-')
+            .and_then(|(millis, seq)| {
+                Some(MessageStreamId {
+                    millis: millis.parse().ok()?,
+                    seq: seq.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| format!("Invalid stream id: {s:?}"))
+    }
 }
 
+impl TryFrom<String> for MessageStreamId {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl JsonSchema for MessageStreamId {
+    fn schema_name() -> String {
+        "MessageStreamId".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl Serialize for MessageStreamId {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Entry of the message stream of a user, delivered to each of their
+/// devices
 #[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(tag = "kind")]
+pub enum UserStreamEvent {
+    SentMessage {
+        /// Id of the message in the history
+        message_id: i32,
+        sender: UserId,
+        message: String,
+    },
+    ConversationMessage {
+        conversation_id: i32,
+        /// Id of the message in the history of the conversation
+        message_id: i32,
+        sender: UserId,
+        message: String,
+    },
+    ConversationUpdated {
+        conversation_id: i32,
+        change: ConversationChange,
+    },
+    /// A user the receiver shares a conversation or direct messages with
+    /// came online or went offline
+    PresenceChanged {
+        user_id: UserId,
+        online: bool,
+        last_seen_at: Option<NaiveDateTime>,
+    },
+    /// A notification was stored for the receiver
+    Notification { notification: Notification },
+}
+
+/// Entry of the message stream of a user as read back. Entries written
+/// before the stream carried other events than direct messages have no
+/// `kind` and are read as sent messages.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum StoredUserStreamEvent {
+    Event(UserStreamEvent),
+    LegacySentMessage {
+        message_id: i32,
+        sender: UserId,
+        message: String,
+    },
+}
+
+impl From<StoredUserStreamEvent> for UserStreamEvent {
+    fn from(stored: StoredUserStreamEvent) -> Self {
+        match stored {
+            StoredUserStreamEvent::Event(event) => event,
+            StoredUserStreamEvent::LegacySentMessage {
+                message_id,
+                sender,
+                message,
+            } => UserStreamEvent::SentMessage {
+                message_id,
+                sender,
+                message,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(tag = "kind")]
 pub enum WsServerEvent {
+    /// The client command with this request id was carried out
+    Ack {
+        request_id: Option<String>,
+        /// Id of the stored message, for commands sending one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_id: Option<i32>,
+    },
+    /// The client command with this request id was rejected
+    Nack {
+        request_id: Option<String>,
+        cause: String,
+    },
     SentMessage {
         id: String,
+        message_id: i32,
         sender: UserId,
         message: String,
     },
+    ConversationMessage {
+        id: String,
+        conversation_id: i32,
+        message_id: i32,
+        sender: UserId,
+        message: String,
+    },
+    ConversationUpdated {
+        id: String,
+        conversation_id: i32,
+        change: ConversationChange,
+    },
+    PresenceChanged {
+        id: String,
+        user_id: UserId,
+        online: bool,
+        last_seen_at: Option<NaiveDateTime>,
+    },
+    Notification {
+        id: String,
+        notification: Notification,
+    },
     CommandMessage {
         message: MyProcessItem,
     },
@@ -49,3 +268,86 @@ pub enum WsServerEvent {
         cause: String,
     },
 }
+
+/// JSON Schema of the frames of the newest protocol
+pub fn ws_protocol_schema() -> serde_json::Value {
+    serde_json::json!({
+        "protocols": WsProtocol::ALL.map(WsProtocol::as_str),
+        "client": schemars::schema_for!(WsClientFrame),
+        "server": schemars::schema_for!(WsServerEvent),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ws_protocol_test() {
+        assert_eq!(
+            WsProtocol::negotiate("actix-demo.v1"),
+            Some(WsProtocol::V1)
+        );
+        assert_eq!(
+            WsProtocol::negotiate("actix-demo.v1, actix-demo.v2"),
+            Some(WsProtocol::V2)
+        );
+        assert_eq!(WsProtocol::negotiate("chat, mqtt"), None);
+    }
+
+    #[test]
+    fn ws_client_frame_test() {
+        let frame = serde_json::from_str::<WsClientFrame>(
+            r#"{"request_id":"r1","kind":"AckMessages","id":"5-1"}"#,
+        )
+        .unwrap();
+        assert_eq!(frame.request_id.as_deref(), Some("r1"));
+        assert!(matches!(frame.event, WsClientEvent::AckMessages { .. }));
+        // frames of protocol v1 carry no request id
+        let frame = serde_json::from_str::<WsClientFrame>(
+            r#"{"kind":"Error","cause":"oops"}"#,
+        )
+        .unwrap();
+        assert!(frame.request_id.is_none());
+    }
+
+    #[test]
+    fn stored_user_stream_event_test() {
+        let event = serde_json::to_string(&UserStreamEvent::SentMessage {
+            message_id: 1,
+            sender: UserId::from_str("2").unwrap(),
+            message: "hi".to_owned(),
+        })
+        .unwrap();
+        let stored =
+            serde_json::from_str::<StoredUserStreamEvent>(&event).unwrap();
+        assert!(matches!(stored, StoredUserStreamEvent::Event(_)));
+        // entries written before the events were tagged with their kind
+        let stored = serde_json::from_str::<StoredUserStreamEvent>(
+            r#"{"message_id":3,"sender":2,"message":"hello"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            UserStreamEvent::from(stored),
+            UserStreamEvent::SentMessage { message_id: 3, .. }
+        ));
+        assert!(serde_json::from_str::<StoredUserStreamEvent>(
+            r#"{"kind":"Unknown"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn message_stream_id_test() {
+        let id = MessageStreamId::from_str("1700000000000-2").unwrap();
+        assert_eq!(id.to_string(), "1700000000000-2");
+        assert!(id < MessageStreamId::from_str("1700000000000-10").unwrap());
+        assert!(id > MessageStreamId::from_str("1699999999999-99").unwrap());
+        assert!(MessageStreamId::from_str("1700000000000").is_err());
+        assert!(MessageStreamId::from_str("a-1").is_err());
+        assert_eq!(
+            serde_json::from_str::<MessageStreamId>(r#""5-1""#).unwrap(),
+            MessageStreamId { millis: 5, seq: 1 }
+        );
+    }
+}
@@ -1,6 +1,17 @@
+pub mod artifacts;
 pub mod auth;
 pub mod command;
+pub mod conversations;
 pub mod healthcheck;
+pub mod job_output;
+pub mod messages;
 pub mod misc;
+pub mod notifications;
+pub mod pipelines;
+pub mod presence;
+pub mod runners;
+pub mod schedules;
 pub mod users;
+pub mod webhooks;
+pub mod workers;
 pub mod ws;
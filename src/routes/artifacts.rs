@@ -0,0 +1,75 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+
+use crate::{
+    actions, errors::DomainError, routes::command::parse_job_id, utils, AppData,
+};
+
+/// Lists the artifacts a job stored
+///
+/// Anyone who can view the job can list and download its artifacts.
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_list_job_artifacts(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job_id = parse_job_id(job_id.into_inner())?;
+
+    let pool = app_data.pool.clone();
+    let artifacts = web::block(move || {
+        let mut conn = pool.get()?;
+        let _ = actions::misc::get_viewable_job(job_id, &user_id, &mut conn)?;
+        actions::artifacts::get_job_artifacts(job_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(artifacts))
+}
+
+/// Streams an artifact of a job from MinIO as a file download
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_download_job_artifact(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let (job_id, artifact_id) = path.into_inner();
+    let job_id = parse_job_id(job_id)?;
+
+    let pool = app_data.pool.clone();
+    let artifact = web::block(move || {
+        let mut conn = pool.get()?;
+        let _ = actions::misc::get_viewable_job(job_id, &user_id, &mut conn)?;
+        actions::artifacts::get_job_artifact(job_id, artifact_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_entity_does_not_exist_error(format!(
+            "No artifact {artifact_id} for job {job_id}"
+        ))
+    })?;
+    let _ = tracing::info!("Downloading artifact {}", artifact.object_key);
+
+    let object = app_data
+        .minio
+        .client
+        .get_object()
+        .bucket(&app_data.config.minio.bucket_name)
+        .key(&artifact.object_key)
+        .send()
+        .await
+        .map_err(|err| DomainError::new_internal_error(format!("{err:?}")))?;
+
+    // Convert ByteStream to AsyncRead and create a streaming response
+    let reader = object.body.into_async_read();
+    let stream = tokio_util::io::ReaderStream::new(reader);
+
+    Ok(HttpResponse::Ok()
+        .content_type(artifact.content_type.as_str())
+        .insert_header(header::ContentDisposition::attachment(
+            artifact.file_name(),
+        ))
+        .streaming(stream))
+}
@@ -1,15 +1,20 @@
 use crate::actions::users::get_user_auth_details;
 use crate::errors::DomainError;
 use crate::models::roles::RoleEnum;
-use crate::models::session::{SessionInfo, SessionStatus};
+use crate::models::session::{
+    parse_country_code, ActiveSessionsPage, ActiveSessionsQuery,
+    ClientPlatform, SessionInfo, SessionStatus,
+};
 use crate::models::users::{UserId, UserLogin, Username};
 use crate::utils::redis_credentials_repo::RedisCredentialsRepo;
 use crate::{utils, AppData};
-use actix_http::header::{HeaderName, HeaderValue};
+use actix_http::header::{HeaderName, HeaderValue, USER_AGENT};
 use actix_web::dev::ServiceRequest;
 use actix_web::error::ErrorUnauthorized;
+use actix_web::http::header::ContentType;
 use actix_web::web::{self, Data};
 use actix_web::{Error, HttpRequest, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
 use awc::cookie::{Cookie, SameSite};
 use bcrypt::verify;
 use chrono::Utc;
@@ -107,8 +112,9 @@ pub async fn validate_token(
     }
 }
 
-#[tracing::instrument(level = "info", skip(app_data, login_request))]
+#[tracing::instrument(level = "info", skip(app_data, login_request, req))]
 pub async fn login(
+    req: HttpRequest,
     login_request: web::Json<UserLogin>,
     app_data: web::Data<AppData>,
 ) -> Result<HttpResponse, DomainError> {
@@ -138,7 +144,7 @@ pub async fn login(
 
     let session_id = Uuid::new_v4();
     // Generate a unique device ID if not provided
-    let device_id = Uuid::new_v4();
+    let device_id = login_request.device_id.unwrap_or_else(Uuid::new_v4);
 
     let auth_data = VerifiedAuthDetails {
         user_id: user.id,
@@ -157,13 +163,27 @@ pub async fn login(
     let now = Utc::now().naive_utc();
 
     let ttl_seconds = app_data.config.session.expiration_secs;
+    let user_agent = header_value(&req, USER_AGENT.as_str());
+    let from_trusted_proxy = req.peer_addr().is_some_and(|peer| {
+        app_data.config.session.trusted_proxies.contains(&peer.ip())
+    });
     let session_info = SessionInfo {
         session_id,
         device_id,
         device_name: login_request.device_name,
+        ip: client_ip(&req, from_trusted_proxy),
+        user_agent: user_agent.clone(),
+        platform: user_agent.as_deref().map(ClientPlatform::from_user_agent),
+        country: from_trusted_proxy
+            .then(|| {
+                header_value(&req, &app_data.config.session.country_header)
+            })
+            .flatten()
+            .as_deref()
+            .and_then(parse_country_code),
         created_at: now,
         last_used_at: now,
-        token: token.clone(),
+        renewal_count: 0,
         ttl_remaining: Some(ttl_seconds as i64),
     };
 
@@ -182,6 +202,25 @@ pub async fn login(
     Ok(HttpResponse::Ok().cookie(cookie).finish())
 }
 
+/// Address of the client, from the forwarding headers only for requests of
+/// a trusted proxy
+fn client_ip(req: &HttpRequest, from_trusted_proxy: bool) -> Option<String> {
+    if from_trusted_proxy {
+        req.connection_info()
+            .realip_remote_addr()
+            .map(str::to_owned)
+    } else {
+        req.peer_addr().map(|peer| peer.ip().to_string())
+    }
+}
+
+fn header_value(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
 // New endpoint to list all active sessions for a user
 #[tracing::instrument(level = "info", skip(app_data, req))]
 pub async fn list_sessions(
@@ -262,6 +301,49 @@ pub async fn revoke_session(
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Page behind the "it wasn't me" link sent along with a suspicious login
+/// notification, asking to confirm the revocation. Following a link must not
+/// change anything, link previews and mail scanners open it too.
+pub async fn confirm_session_revocation() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(REVOKE_SESSION_PAGE)
+}
+
+const REVOKE_SESSION_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Revoke session</title></head>
+<body>
+<p>A new login to your account was flagged as suspicious. If it wasn't you,
+revoke its session and change your password.</p>
+<form method="post"><button type="submit">Revoke the session</button></form>
+</body>
+</html>
+"#;
+
+/// Revokes the session of an "it wasn't me" link, once confirmed. Public, as
+/// the user may not have a session of their own at hand.
+#[tracing::instrument(level = "info", skip(app_data, token))]
+pub async fn revoke_session_with_token(
+    token: web::Path<String>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let (user_id, session_id) = app_data
+        .credentials_repo
+        .revoke_with_token(&token)
+        .await?
+        .ok_or_else(|| {
+            DomainError::new_entity_does_not_exist_error(
+                "Revoke link is invalid or was already used".to_owned(),
+            )
+        })?;
+    let _ = tracing::warn!(
+        "User {user_id} revoked suspicious session {session_id}"
+    );
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 // New endpoint to revoke all sessions except the current one
 #[tracing::instrument(level = "info", skip(app_data, req))]
 pub async fn revoke_other_sessions(
@@ -293,3 +375,94 @@ pub async fn revoke_other_sessions(
 
     Ok(HttpResponse::Ok().finish())
 }
+
+fn ensure_admin(auth: &AuthDetails<RoleEnum>) -> Result<(), DomainError> {
+    if utils::is_admin(auth) {
+        Ok(())
+    } else {
+        Err(DomainError::new_auth_error(
+            "Forbidden: Only admins can manage the sessions of other users"
+                .to_owned(),
+        ))
+    }
+}
+
+/// Lists the live sessions of all users, latest to expire first. Admin only.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn list_active_sessions(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    query: web::Query<ActiveSessionsQuery>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+
+    let (sessions, total) = app_data
+        .credentials_repo
+        .load_active_sessions(query.offset, usize::from(query.limit()))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(ActiveSessionsPage { sessions, total }))
+}
+
+/// Lists the sessions of a user. Admin only.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn list_user_sessions(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    user_id: web::Path<UserId>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+
+    let sessions = app_data
+        .credentials_repo
+        .load_all_sessions(&user_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Revokes a session of a user. Admin only.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn revoke_user_session(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    path: web::Path<(UserId, Uuid)>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+    let (user_id, session_id) = path.into_inner();
+
+    let credentials_repo = &app_data.credentials_repo;
+    if credentials_repo
+        .load_session(&user_id, &session_id)
+        .await?
+        .is_none()
+    {
+        return Err(DomainError::new_entity_does_not_exist_error(format!(
+            "Session {session_id} of user {user_id} does not exist"
+        )));
+    }
+    let _ = credentials_repo
+        .delete_session(&user_id, &session_id)
+        .await?;
+    let _ = tracing::info!("Revoked session {session_id} of user {user_id}");
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revokes all sessions of a user. Admin only.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn revoke_user_sessions(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    user_id: web::Path<UserId>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+
+    let _ = app_data
+        .credentials_repo
+        .delete_all_sessions(&user_id)
+        .await?;
+    let _ = tracing::info!("Revoked all sessions of user {user_id}");
+
+    Ok(HttpResponse::Ok().finish())
+}
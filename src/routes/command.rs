@@ -1,28 +1,78 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
+use backoff::backoff::Backoff;
 use futures::StreamExt;
-use process_stream::{Process, ProcessExt, ProcessItem};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::ChildStdin};
 use tracing::{info_span, Instrument};
 use uuid::Uuid;
 
 use crate::{
     actions,
     errors::DomainError,
+    metrics::JobTemplate,
     models::{
-        misc::{Job, JobStatus, NewJob},
+        artifact::validate_artifact_patterns,
+        misc::{
+            AbortJobQuery, AbortedJobs, IdempotencyKey, Job, JobAbortMessage,
+            JobAclGrantee, JobStatus, JobsQuery, NewJob, RetryPolicy,
+        },
+        notification::NotificationEvent,
+        roles::RoleEnum,
+        runner::validate_labels,
+        users::UserId,
+        webhook::JobEvent,
         ws::MyProcessItem,
     },
     types::Task,
-    utils::{self, extract_user_id_from_header},
+    utils::{
+        self, extract_user_id_from_header, job_artifacts, job_output,
+        job_process::{self, JobProcess, JobSignal},
+        webhooks::notify_job_event,
+    },
     AppData,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunCommandRequest {
     pub args: Vec<String>,
+    /// Runs the command inside a pseudo terminal so that interactive tools
+    /// and progress bars behave as they would in a shell
+    #[serde(default)]
+    pub pty: bool,
+    /// Glob patterns of files in the job working directory to keep as
+    /// artifacts once the job finishes
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+    /// Reruns the job with backoff in between when it fails
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Labels of the remote runner to run the job on instead of this
+    /// instance, a runner needs all of them to pick the job up
+    #[serde(default)]
+    pub runs_on: Vec<String>,
+}
+
+/// How a job came to be started, besides its run request
+#[derive(Debug, Clone, Default)]
+pub struct StartJobOptions {
+    /// A repeated key returns the job started with it instead
+    pub idempotency_key: Option<IdempotencyKey>,
+    /// The job this one manually retries
+    pub retry_of: Option<Uuid>,
+    pub template: JobTemplate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInputRequest {
+    pub data: String,
 }
 
 /// Executes a long-running command as a background job
@@ -32,22 +82,82 @@ pub struct RunCommandRequest {
 /// * `app_data` - Shared application state
 /// * `payload` - JSON payload containing command arguments
 ///
+/// An `Idempotency-Key` header makes the request safe to repeat, a key the
+/// user already sent returns the job it started.
+///
 /// # Returns
-/// Returns HTTP 200 with job details if successful, or an error response
+/// Returns HTTP 200 with job details if successful, HTTP 503 while the
+/// server is shutting down, or an error response
 ///
 /// # Process
 /// 1. Creates a new job record in database
 /// 2. Spawns process with provided arguments
 /// 3. Publishes process output to Redis channel
-/// 4. Handles job abort requests
-/// 5. Updates job status on completion
+/// 4. Forwards input published on the job's input channel to the process stdin
+/// 5. Handles job abort requests, sending SIGTERM and escalating to SIGKILL
+///    once the grace period is over
+/// 6. Updates job status on completion, retrying failed jobs as per the
+///    `retry` policy
 #[tracing::instrument(level = "info", skip_all, fields(payload))]
 pub async fn handle_run_command(
     req: HttpRequest,
     app_data: web::Data<AppData>,
     payload: web::Json<RunCommandRequest>,
 ) -> Result<HttpResponse, DomainError> {
+    // Extract and validate user ID from auth header
+    let user_id = extract_user_id_from_header(req.headers())?;
+
+    tracing::debug!("Authenticated user ID: {}", user_id);
+
+    let idempotency_key = req
+        .headers()
+        .get(IdempotencyKey::HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|err| err.to_string())
+                .and_then(|value| IdempotencyKey::try_from(value.to_owned()))
+                .map_err(DomainError::new_bad_input_error)
+        })
+        .transpose()?;
+    let options = StartJobOptions {
+        idempotency_key,
+        ..StartJobOptions::default()
+    };
+
+    let job =
+        start_job(app_data, user_id, payload.into_inner(), options).await?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// Starts a background job on behalf of `user_id`. This is the path shared by
+/// the HTTP endpoint and the job scheduler.
+///
+/// The job runs on the current actix runtime, the returned job is still
+/// pending. When the idempotency key was already used, the job started with
+/// it is returned and nothing is run.
+pub async fn start_job(
+    app_data: web::Data<AppData>,
+    user_id: UserId,
+    payload: RunCommandRequest,
+    options: StartJobOptions,
+) -> Result<Job, DomainError> {
     tracing::info!("Starting new command execution job");
+    validate_artifact_patterns(&payload.artifacts)
+        .map_err(DomainError::new_bad_input_error)?;
+    if let Some(retry) = &payload.retry {
+        retry.validate().map_err(DomainError::new_bad_input_error)?;
+    }
+    let runs_on = validate_labels(&payload.runs_on)
+        .map_err(DomainError::new_bad_input_error)?;
+    if !runs_on.is_empty()
+        && (!payload.artifacts.is_empty() || payload.retry.is_some())
+    {
+        return Err(DomainError::new_bad_input_error(
+            "Artifacts and retries are not supported for jobs on runners"
+                .to_owned(),
+        ));
+    }
     let mut conn = app_data.redis_conn_manager.clone();
     // Health check publish to verify Redis connection
     let () = conn.publish("hc", "hc").await?;
@@ -55,57 +165,276 @@ pub async fn handle_run_command(
     // Generate unique job ID
     let job_id = uuid::Uuid::new_v4();
     tracing::debug!("Generated new job ID: {}", job_id);
-    let app_data = app_data.clone();
-    let bin_path = app_data.config.job_bin_path.clone();
-    let redis_prefix = app_data.redis_prefix.as_ref();
-    let job_chan_name = redis_prefix(&format!("job.{job_id}"));
-    let abort_chan_name = redis_prefix(&format!("job.{job_id}.abort"));
-    let payload = payload.into_inner();
-    let args = payload.args;
-    // Extract and validate user ID from auth header
-    let user_id = extract_user_id_from_header(req.headers())?;
-
-    tracing::debug!("Authenticated user ID: {}", user_id);
+    // Refused once the server is shutting down, the job is tracked until it
+    // finished so the shutdown can wait for it
+    let running = app_data.shutdown.track_job(job_id)?;
 
     // Create new job record in database
     let pool = app_data.pool.clone();
-    let pool2 = pool.clone();
     tracing::debug!("Creating new job record in database");
-    let job = web::block(move || {
-        let mut conn = pool2.get()?;
-        let nj = NewJob {
-            job_id,
-            started_by: user_id,
-            status: JobStatus::Pending,
-            status_message: None,
-        };
-        actions::misc::create_job(&nj, &mut conn)
+    let nj = NewJob {
+        job_id,
+        started_by: user_id,
+        status: JobStatus::Pending,
+        status_message: None,
+        retry_of: options.retry_of,
+        idempotency_key: options
+            .idempotency_key
+            .map(|key| key.as_str().to_owned()),
+        request: Some(utils::jvalue(&payload)),
+    };
+    let runs_on2 = runs_on.clone();
+    let (job, created) = web::block(move || {
+        let mut conn = pool.get()?;
+        if runs_on2.is_empty() {
+            actions::misc::create_job_idempotent(&nj, &mut conn)
+        } else {
+            actions::runners::create_queued_job(&nj, runs_on2, &mut conn)
+        }
     })
     .await??;
+    if !created {
+        tracing::info!(
+            "Idempotency key was already used for job {}",
+            job.job_id
+        );
+        return Ok(job);
+    }
     tracing::info!("Successfully created job with ID: {}", job.job_id);
+    notify_job_event(&app_data, job_id, JobEvent::Created).await;
+
+    if !runs_on.is_empty() {
+        tracing::info!("Queued job {} for a runner with {:?}", job_id, runs_on);
+        return Ok(job);
+    }
 
-    let pool2 = pool.clone();
     let _task: Task<()> = actix_rt::spawn(
         async move {
-            // Create and configure process
-            let proc = Rc::new(RefCell::new(Process::new(bin_path)));
-            {
-                tracing::debug!("Setting process arguments: {:?}", args);
-                let _ = proc.borrow_mut().args(&args);
+            let _running = running;
+            run_job_with_retries(
+                app_data,
+                job_id,
+                user_id,
+                payload,
+                options.template,
+            )
+            .await
+        }
+        .instrument(info_span!("job", job_id = job_id.to_string())),
+    );
+    Ok(job)
+}
+
+/// Runs a job, rerunning it after a backoff delay while it fails and the
+/// retry policy of the request allows another attempt. The user who started
+/// it is notified once it finished.
+async fn run_job_with_retries(
+    app_data: web::Data<AppData>,
+    job_id: Uuid,
+    started_by: UserId,
+    payload: RunCommandRequest,
+    template: JobTemplate,
+) -> Result<(), DomainError> {
+    let max_attempts = payload.retry.as_ref().map_or(1, |r| r.max_attempts);
+    let mut backoff = payload.retry.as_ref().map(RetryPolicy::backoff);
+    let mut attempt = 1;
+    let status = loop {
+        let outcome = run_job(
+            app_data.clone(),
+            job_id,
+            payload.clone(),
+            Vec::new(),
+            template,
+        )
+        .await?;
+        if outcome.status != JobStatus::Failed || attempt >= max_attempts {
+            break outcome.status;
+        }
+        let delay = backoff
+            .as_mut()
+            .and_then(|backoff| backoff.next_backoff())
+            .unwrap_or_default();
+        attempt += 1;
+        tracing::warn!(
+            "Job {} failed, starting attempt {} of {} in {:?}",
+            job_id,
+            attempt,
+            max_attempts,
+            delay
+        );
+        if !wait_for_retry(&app_data, job_id, attempt, delay).await? {
+            break JobStatus::Aborted;
+        }
+    };
+    app_data
+        .notifier
+        .notify_or_log(
+            started_by,
+            NotificationEvent::JobFinished { job_id, status },
+        )
+        .await;
+    Ok(())
+}
+
+/// Marks the job as pending again and waits until its next attempt is due.
+/// Returns false when the job got aborted in the meantime.
+async fn wait_for_retry(
+    app_data: &AppData,
+    job_id: Uuid,
+    attempt: u32,
+    delay: Duration,
+) -> Result<bool, DomainError> {
+    let abort_chan_name =
+        (app_data.redis_prefix)(&format!("job.{job_id}.abort"));
+    // Subscribe first, so that the job can be aborted as soon as it shows up
+    // as pending
+    let mut ps = app_data
+        .redis_conn_factory
+        .get_async_pubsub()
+        .await
+        .map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to initialize pubsub connection: {err}"
+            ))
+        })?;
+    let _ = ps.subscribe(&abort_chan_name).await.map_err(|err| {
+        DomainError::new_internal_error(format!(
+            "Failed to subscribe to abort channel: {err}"
+        ))
+    })?;
+
+    let pool = app_data.pool.clone();
+    let status_message = format!(
+        "Attempt {} failed, retrying in {}s",
+        attempt - 1,
+        delay.as_secs()
+    );
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::update_job_attempt(
+            job_id,
+            i32::try_from(attempt).unwrap_or(i32::MAX),
+            Some(status_message),
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let mut aborts = ps.on_message();
+    let sleep = actix_rt::time::sleep(delay);
+    tokio::pin!(sleep);
+    loop {
+        tokio::select! {
+            () = &mut sleep => return Ok(true),
+            Some(msg) = aborts.next() => {
+                let msg = msg.get_payload::<String>().unwrap_or_default();
+                let Ok(abort) = serde_json::from_str::<JobAbortMessage>(&msg) else {
+                    tracing::warn!("Ignoring invalid abort message {}", msg);
+                    continue;
+                };
+                tracing::info!("Job {} aborted while waiting for a retry", job_id);
+                let pool = app_data.pool.clone();
+                let status_message = abort.status_message();
+                web::block(move || {
+                    let mut conn = pool.get()?;
+                    actions::misc::update_job_status(
+                        job_id,
+                        JobStatus::Aborted,
+                        Some(status_message),
+                        &mut conn,
+                    )
+                })
+                .await??;
+                notify_job_event(app_data, job_id, JobEvent::Aborted).await;
+                return Ok(false);
             }
-           // Track job start
-            let proc2 = proc.clone();
+        }
+    }
+}
 
-            // Track abort state
-            let aborted = Rc::new(RefCell::new(false));
-            tracing::debug!("Initialized abort state tracking");
+/// How a job process ended
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    pub status: JobStatus,
+    /// Last non empty line the process wrote to stdout
+    pub output: Option<String>,
+}
 
+/// Runs the process of an already created job until it exits and records
+/// its final status. The process runs in a scratch working directory that is
+/// removed afterwards, `env` is added to its environment. The job metrics
+/// are labelled with its `template`.
+///
+/// # Process
+/// 1. Spawns process with provided arguments
+/// 2. Publishes process output to Redis channel
+/// 3. Forwards input published on the job's input channel to the process stdin
+/// 4. Handles job abort requests, sending SIGTERM and escalating to SIGKILL
+///    once the grace period is over
+/// 5. Uploads the declared artifacts from the job working directory
+/// 6. Updates job status on completion
+pub async fn run_job(
+    app_data: web::Data<AppData>,
+    job_id: Uuid,
+    payload: RunCommandRequest,
+    env: Vec<(String, String)>,
+    template: JobTemplate,
+) -> Result<JobOutcome, DomainError> {
+    let bin_path = app_data.config.job_bin_path.clone();
+    let pty_wrapper_path = app_data.config.job_pty_wrapper_path.clone();
+    let redis_prefix = app_data.redis_prefix.as_ref();
+    let abort_chan_name = redis_prefix(&format!("job.{job_id}.abort"));
+    let input_chan_name = redis_prefix(&format!("job.{job_id}.input"));
+    let redis_client = app_data.redis_conn_factory.clone();
+    let args = payload.args;
+    let pty = payload.pty;
+    let artifacts = payload.artifacts;
+    let grace_period =
+        Duration::from_secs(app_data.config.job_abort_grace_period_secs);
+    let pool = app_data.pool.clone();
+    let pool2 = pool.clone();
+    let workdir = job_artifacts::job_workdir(
+        &app_data.config.job_artifacts.workdir_root,
+        job_id,
+    );
+
+    // Create and configure process
+    tracing::debug!("Setting process arguments: {:?}", args);
+    let mut command =
+        job_process::job_command(&bin_path, &pty_wrapper_path, &args, pty);
+    let _ = command.envs(env).current_dir(&workdir);
+
+    // Track abort state
+    let aborted = Rc::new(RefCell::new(false));
+    tracing::debug!("Initialized abort state tracking");
+    let last_line = Rc::new(RefCell::new(None::<String>));
+    let mut started_at = None::<Instant>;
+
+    tracing::debug!("Starting process with arguments");
+    let res = match tokio::fs::create_dir_all(&workdir)
+        .await
+        .and_then(|()| JobProcess::spawn(command))
+    {
+        Err(err) => {
+            tracing::error!("Failed to start process: {:?}", err);
+            app_data.metrics.jobs.spawn_failed(template);
+            Err(DomainError::new_internal_error(format!(
+                "Failed to run process: {err:?}"
+            )))
+        }
+        Ok(JobProcess { pid, stdin, output }) => {
+            app_data.metrics.jobs.job_started(template);
+            started_at = Some(Instant::now());
+            let _running =
+                RunningJobGuard::new(&app_data.metrics.jobs.running, template);
+            notify_job_event(&app_data, job_id, JobEvent::Started).await;
             // Spawn abort handler task
             let aborted2 = aborted.clone();
+            let app_data2 = app_data.clone();
             let aborter: Task<()> = actix_rt::spawn(
                 async move {
                     // Initialize pubsub connection
-                    let mut ps = app_data.redis_conn_factory.clone().get_async_pubsub().await.map_err(|err| {
+                    let mut ps = redis_client.get_async_pubsub().await.map_err(|err| {
                         DomainError::new_internal_error(format!(
                             "Failed to initialize pubsub connection: {err}"
                         ))
@@ -123,75 +452,105 @@ pub async fn handle_run_command(
                     while let Some(msg) = r_stream.next().await {
                         // Safely handle message payload
                         let msg = msg.get_payload::<String>().unwrap_or_default();
+                        let abort = match serde_json::from_str::<JobAbortMessage>(&msg) {
+                            Ok(abort) => abort,
+                            Err(err) => {
+                                let _ = tracing::warn!("Ignoring invalid abort message {msg}: {err}");
+                                continue;
+                            }
+                        };
 
-                        if msg == "done" {
-                            let _ = tracing::info!("Received abort signal for job {}", job_id);                           
-                            // Abort the process
-                            let _ = proc2.borrow().abort();
-                            // Update abort state
-                            *aborted2.borrow_mut() = true;
-                            // Update job status in database
-                            let pool2 = pool.clone();
-                            web::block(move || {
-                                let mut conn = pool2.get()?;
-                                actions::misc::update_job_status(
-                                    job_id,
-                                    JobStatus::Aborted,
-                                    Some("Job aborted by user".to_owned()),
-                                    &mut conn,
-                                )
-                            })
-                            .await
-                            .map_err(|err| {
-                                DomainError::new_internal_error(format!(
-                                    "Failed to update job status: {err}"
-                                ))
-                            })??;
-                             // Track job abort
-                            break;
+                        let _ = tracing::info!("Received abort signal for job {job_id}: {abort:?}");
+                        // Update abort state
+                        *aborted2.borrow_mut() = true;
+                        // Update job status in database
+                        let pool2 = pool.clone();
+                        let status_message = abort.status_message();
+                        web::block(move || {
+                            let mut conn = pool2.get()?;
+                            actions::misc::update_job_status(
+                                job_id,
+                                JobStatus::Aborted,
+                                Some(status_message),
+                                &mut conn,
+                            )
+                        })
+                        .await
+                        .map_err(|err| {
+                            DomainError::new_internal_error(format!(
+                                "Failed to update job status: {err}"
+                            ))
+                        })??;
+                        notify_job_event(&app_data2, job_id, JobEvent::Aborted).await;
+
+                        // Stop the process, giving it the grace period
+                        // to clean up unless the abort was forced
+                        if !abort.force {
+                            signal_job(pid, JobSignal::Terminate);
+                            if job_process::wait_for_job_process_exit(pid, grace_period).await {
+                                break;
+                            }
+                            let _ = tracing::warn!(
+                                "Job {job_id} still running after {grace_period:?}, killing it"
+                            );
+                        }
+                        if job_process::is_job_process_running(pid) {
+                            signal_job(pid, JobSignal::Kill);
                         }
+                        break;
                     }
                     Ok(())
                 }
                 .instrument(info_span!("job_aborter", job_id = job_id.to_string())),
             );
+            // Spawn input forwarder task writing to the process stdin
+            let input_forwarder: Option<Task<()>> = stdin.map(|stdin| {
+                actix_rt::spawn(
+                    forward_job_input(
+                        stdin,
+                        input_chan_name,
+                        app_data.redis_conn_factory.clone(),
+                    )
+                    .instrument(info_span!(
+                        "job_input",
+                        job_id = job_id.to_string()
+                    )),
+                )
+            });
             // Spawn publisher task to handle process output
+            let last_line2 = last_line.clone();
+            let app_data3 = app_data.clone();
             let publisher: Task<()> = actix_rt::spawn(
                 async move {
-                    tracing::debug!("Starting process with arguments");
-                    let mut stream = proc
-                        .borrow_mut()
-                        .spawn_and_stream()
-                        .map_err(|err| {
-                            tracing::error!("Failed to start process: {:?}", err);
-                            DomainError::new_internal_error(format!(
-                                "Failed to run process: {err:?}"
-                            ))
-                        })?
-                        .map(|output| match output {
-                            ProcessItem::Output(value) => {
-                                tracing::trace!("Process output: {}", value);
-                                MyProcessItem::Line { value }
-                            },
-                            ProcessItem::Error(cause) => {
-                                if cause.starts_with("[ERROR]") || cause.starts_with("E:") {
-                                    tracing::warn!("Process error: {}", cause);
-                                    MyProcessItem::Error { cause }
-                                } else {
-                                    tracing::trace!("Process output: {}", cause);
-                                    MyProcessItem::Line { value: cause }
-                                }
+                    let mut stream = output.map(|item| match item {
+                        MyProcessItem::Line { value } => {
+                            tracing::trace!("Process output: {}", value);
+                            // terminals end lines with CRLF
+                            let value = if pty {
+                                value.trim_end_matches('\r').to_owned()
+                            } else {
+                                value
+                            };
+                            if !value.trim().is_empty() {
+                                *last_line2.borrow_mut() = Some(value.clone());
                             }
-                            ProcessItem::Exit(code) => {
-                                tracing::info!("Process exited with code: {}", code);
-                                MyProcessItem::Done { code }
-                            },
-                        });
+                            MyProcessItem::Line { value }
+                        }
+                        MyProcessItem::Error { cause } => {
+                            tracing::warn!("Process error: {}", cause);
+                            MyProcessItem::Error { cause }
+                        }
+                        MyProcessItem::Done { code } => {
+                            tracing::info!("Process exited with code: {}", code);
+                            MyProcessItem::Done { code }
+                        }
+                    });
 
                     // Publish process output to Redis channel
                     while let Some(rcm) = stream.next().await {
                         tracing::trace!("Publishing process output: {:?}", &rcm);
-                        let () = conn.publish(&job_chan_name, utils::jstr(&rcm)).await?;
+                        app_data3.metrics.jobs.job_output(template, &rcm);
+                        job_output::publish_job_output(&app_data3, job_id, &rcm).await?;
                         // Handle process completion
                         if let MyProcessItem::Done { code } = rcm {
                             let code = code.parse::<i32>().map_err(|err| {
@@ -217,41 +576,170 @@ pub async fn handle_run_command(
             let res = publisher.await?;
             tracing::info!("Job {} completed", job_id);
 
-            // Clean up abort handler
+            // Clean up abort handler and input forwarder
             aborter.abort();
-            tracing::debug!("Abort handler terminated");
-
-            // Update job status in database if not already aborted
-            if !*aborted.borrow() {
-                // Determine final job status
-            let (status, msg) = match res {
-                Ok(_) => {
-                    tracing::info!("Job {} completed successfully", job_id);
-                    (JobStatus::Completed, None)
-                },
-                Err(err) => {
-                    let msg = format!("Error running job: {err:?}");
-                    tracing::error!("Job {} failed: {}", job_id, msg);
-                    (JobStatus::Failed, Some(msg))
-                }
-            };
-                tracing::debug!("Updating job {} status to {:?}", job_id, status);
-                let mut conn = pool2.get()?;
-                web::block(move || {
-                    actions::misc::update_job_status(job_id, status, msg, &mut conn)
-                })
-                .await??;
+            if let Some(input_forwarder) = input_forwarder {
+                input_forwarder.abort();
             }
-            tracing::info!("Job {} processing complete", job_id);
-            Ok(())
+            tracing::debug!("Abort handler and input forwarder terminated");
+            res
         }
-        .instrument(info_span!("job", job_id = job_id.to_string())),
-    );
-    Ok(HttpResponse::Ok().json(job))
+    };
+
+    // Keep the declared artifacts, even of failed jobs, then clean up
+    let res = match job_artifacts::collect_job_artifacts(
+        job_id, &workdir, artifacts, &app_data,
+    )
+    .await
+    {
+        Ok(artifacts) => {
+            tracing::info!(
+                "Stored {} artifacts of job {}",
+                artifacts.len(),
+                job_id
+            );
+            res
+        }
+        Err(err) => {
+            tracing::error!(
+                "Failed to collect artifacts of job {}: {:?}",
+                job_id,
+                err
+            );
+            res.and(Err(err))
+        }
+    };
+    if let Err(err) = tokio::fs::remove_dir_all(&workdir).await {
+        tracing::warn!(
+            "Failed to remove working directory of job {}: {}",
+            job_id,
+            err
+        );
+    }
+
+    let output = last_line.borrow_mut().take();
+    // Update job status in database if not already aborted
+    let elapsed = started_at.map(|started_at| started_at.elapsed());
+    if *aborted.borrow() {
+        app_data.metrics.jobs.job_finished(
+            template,
+            &JobStatus::Aborted,
+            elapsed,
+        );
+        tracing::info!("Job {} processing complete", job_id);
+        return Ok(JobOutcome {
+            status: JobStatus::Aborted,
+            output,
+        });
+    }
+
+    // Determine final job status
+    let (status, msg) = match res {
+        Ok(_) => {
+            tracing::info!("Job {} completed successfully", job_id);
+            (JobStatus::Completed, None)
+        }
+        Err(err) => {
+            let msg = format!("Error running job: {err:?}");
+            tracing::error!("Job {} failed: {}", job_id, msg);
+            (JobStatus::Failed, Some(msg))
+        }
+    };
+    tracing::debug!("Updating job {} status to {:?}", job_id, status);
+    let mut conn = pool2.get()?;
+    let status2 = status.clone();
+    web::block(move || {
+        actions::misc::update_job_status(job_id, status2, msg, &mut conn)
+    })
+    .await??;
+    app_data
+        .metrics
+        .jobs
+        .job_finished(template, &status, elapsed);
+    if let Some(event) = JobEvent::for_final_status(&status) {
+        notify_job_event(&app_data, job_id, event).await;
+    }
+    tracing::info!("Job {} processing complete", job_id);
+    Ok(JobOutcome { status, output })
+}
+
+/// Guard for tracking running job processes
+struct RunningJobGuard<'a> {
+    metrics: &'a prometheus::IntGaugeVec,
+    template: JobTemplate,
+}
+
+impl<'a> RunningJobGuard<'a> {
+    fn new(
+        metrics: &'a prometheus::IntGaugeVec,
+        template: JobTemplate,
+    ) -> Self {
+        metrics.with_label_values(&[template.as_str()]).inc();
+        Self { metrics, template }
+    }
+}
+
+impl Drop for RunningJobGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .with_label_values(&[self.template.as_str()])
+            .dec();
+    }
+}
+
+/// Writes everything published on a job's input channel to the stdin of the
+/// job process. Input is routed through Redis so that it reaches the process
+/// no matter which instance the client sending it is connected to.
+async fn forward_job_input(
+    mut stdin: ChildStdin,
+    input_chan_name: String,
+    redis_client: redis::Client,
+) -> Result<(), DomainError> {
+    let mut ps = redis_client.get_async_pubsub().await.map_err(|err| {
+        DomainError::new_internal_error(format!(
+            "Failed to initialize pubsub connection: {err}"
+        ))
+    })?;
+
+    let _ = ps.subscribe(&input_chan_name).await.map_err(|err| {
+        DomainError::new_internal_error(format!(
+            "Failed to subscribe to input channel: {err}"
+        ))
+    })?;
+
+    let mut r_stream = ps.on_message();
+    while let Some(msg) = r_stream.next().await {
+        let data = msg.get_payload::<String>().unwrap_or_default();
+        let _ =
+            tracing::trace!("Writing {} bytes to process stdin", data.len());
+        let _ = stdin.write_all(data.as_bytes()).await.map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to write to process stdin: {err}"
+            ))
+        })?;
+        let _ = stdin.flush().await.map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to flush process stdin: {err}"
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Signals the process group of a job, a process that already exited is not
+/// an error
+fn signal_job(pid: u32, signal: JobSignal) {
+    if let Err(err) = job_process::signal_job_process(pid, signal) {
+        let _ =
+            tracing::warn!("Failed to send {signal:?} to job process: {err}");
+    }
 }
 
 /// Retrieves a job from the database by its UUID.
 ///
+/// Only the owner of the job, admins and users the job was shared with
+/// through its ACL can read it.
+///
 /// # Arguments
 ///
 /// * `app_data` - Shared application data, including database pool.
@@ -263,22 +751,233 @@ pub async fn handle_run_command(
 ///
 /// # Errors
 ///
-/// * `DomainError` - If the provided job ID is not a valid UUID, or if the job does not exist.
-#[tracing::instrument(level = "info", skip(app_data))]
+/// * `DomainError` - If the provided job ID is not a valid UUID, if the job does not exist
+///   or if the user is not allowed to view it.
+#[tracing::instrument(level = "info", skip(app_data, req))]
 pub async fn handle_get_job(
+    req: HttpRequest,
     app_data: web::Data<AppData>,
     job_id: web::Path<String>,
 ) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
     // Parse the job ID from the path parameter as a UUID.
-    let job_id = Uuid::parse_str(&job_id.into_inner()).map_err(|err| {
-        DomainError::new_bad_input_error(format!("Expected UUID: {err}"))
-    })?;
+    let job_id = parse_job_id(job_id.into_inner())?;
+
+    let pool = app_data.pool.clone();
+    let job = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_viewable_job(job_id, &user_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// Lists the ACL entries of a job. Only available to the job owner and admins.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_get_job_acl(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, DomainError> {
+    let job =
+        fetch_owned_job(&req, &auth, job_id.into_inner(), &app_data).await?;
+
+    let pool = app_data.pool.clone();
+    let entries = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_job_acl(job.job_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// Shares read access to a job with a user or with every holder of a role
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_add_job_acl_entry(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+    grantee: web::Json<JobAclGrantee>,
+) -> Result<HttpResponse, DomainError> {
+    let job =
+        fetch_owned_job(&req, &auth, job_id.into_inner(), &app_data).await?;
+
+    let entry = grantee.into_inner().into_new_entry(job.job_id);
+    let pool = app_data.pool.clone();
+    let entry = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::add_job_acl_entry(&entry, &mut conn)
+    })
+    .await??;
+
+    let _ = tracing::info!("Shared job {} with {:?}", job.job_id, &entry);
+
+    Ok(HttpResponse::Created().json(entry))
+}
+
+/// Revokes a previously granted read access on a job
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_delete_job_acl_entry(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    path: web::Path<(String, i32)>,
+) -> Result<HttpResponse, DomainError> {
+    let (job_id, acl_id) = path.into_inner();
+    let job = fetch_owned_job(&req, &auth, job_id, &app_data).await?;
+
+    let pool = app_data.pool.clone();
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::delete_job_acl_entry(job.job_id, acl_id, &mut conn)
+    })
+    .await??;
+
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(DomainError::new_entity_does_not_exist_error(format!(
+            "No ACL entry {acl_id} on job {}",
+            job.job_id
+        )))
+    }
+}
+
+/// Retries a job that is no longer running
+///
+/// The job is run again as a new job linked to the original through
+/// `retry_of`, with the request the original was started with. The new job
+/// is started by the caller.
+///
+/// # Errors
+///
+/// * `DomainError` - If the job belongs to a different user and the caller is
+///   not an admin, if the job is still running or if it was not started
+///   with a stored request, e.g. a pipeline
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_retry_job(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job =
+        fetch_owned_job(&req, &auth, job_id.into_inner(), &app_data).await?;
+    let job_id = job.job_id;
+    if job.status == JobStatus::Pending {
+        return Err(DomainError::new_bad_input_error(format!(
+            "Job {job_id} is still running"
+        )));
+    }
 
-    let job = fetch_job_by_uuid(job_id, app_data.as_ref()).await?;
+    let pool = app_data.pool.clone();
+    let request = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_job_request(job_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_bad_input_error(format!(
+            "Job {job_id} can't be retried"
+        ))
+    })?;
+    let payload = serde_json::from_value::<RunCommandRequest>(request)
+        .map_err(|err| DomainError::new_internal_error(err.to_string()))?;
 
+    let options = StartJobOptions {
+        retry_of: Some(job_id),
+        ..StartJobOptions::default()
+    };
+    let job = start_job(app_data, user_id, payload, options).await?;
+    let _ = tracing::info!("Job {job_id} retried as job {}", job.job_id);
     Ok(HttpResponse::Ok().json(job))
 }
 
+pub(crate) fn parse_job_id(job_id: String) -> Result<Uuid, DomainError> {
+    Uuid::parse_str(&job_id).map_err(|err| {
+        DomainError::new_bad_input_error(format!("Expected UUID: {err}"))
+    })
+}
+
+/// Fetches a job the caller is allowed to manage, i.e. their own job or any
+/// job when the caller is an admin
+async fn fetch_owned_job(
+    req: &HttpRequest,
+    auth: &AuthDetails<RoleEnum>,
+    job_id: String,
+    app_data: &AppData,
+) -> Result<Job, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job_id = parse_job_id(job_id)?;
+    let job = fetch_job_by_uuid(job_id, app_data).await?;
+
+    if job.started_by == Some(user_id) || utils::is_admin(auth) {
+        Ok(job)
+    } else {
+        Err(DomainError::new_auth_error(format!(
+            "Forbidden: Not allowed to manage job {job_id}"
+        )))
+    }
+}
+
+/// Lists jobs visible to the caller
+///
+/// Regular users only ever see their own jobs, admins can see everyone's and
+/// narrow them down with `started_by`.
+///
+/// # Arguments
+///
+/// * `query` - Filters (`status`, `created_after`, `created_before`,
+///   `started_by`), sort `order`, `cursor` and `limit`
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - A page of jobs with the total count
+///   and the cursor for the next page, if any
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_list_jobs(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    query: web::Query<JobsQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let mut query = query.into_inner();
+
+    let is_admin = utils::is_admin(&auth);
+
+    query.started_by = match query.started_by {
+        Some(other) if other != user_id && !is_admin => {
+            Err(DomainError::new_auth_error(
+                "Forbidden: Tried to list jobs of a different user".to_owned(),
+            ))
+        }
+        None if !is_admin => Ok(Some(user_id)),
+        started_by => Ok(started_by),
+    }?;
+
+    let pool = app_data.pool.clone();
+    let page = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::list_jobs(&query, &mut conn)
+    })
+    .await??;
+
+    let _ = tracing::info!(
+        "Found {} jobs out of {} total",
+        page.jobs.len(),
+        page.total
+    );
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
 async fn fetch_job_by_uuid(
     job_id: Uuid,
     app_data: &AppData,
@@ -334,10 +1033,15 @@ pub async fn handle_get_job_metrics(
 
 /// Aborts a command by sending a message to the Redis channel associated with the job.
 ///
+/// The job process receives SIGTERM and is killed once the configured grace
+/// period is over, or right away when `force` is set.
+///
 /// # Arguments
 ///
 /// * `app_data` - Shared application data, including Redis connection.
 /// * `job_id` - Path parameter representing the UUID of the job to abort.
+/// * `query` - Optional abort `reason` stored as the job status message and
+///   the `force` flag
 ///
 /// # Returns
 ///
@@ -345,40 +1049,200 @@ pub async fn handle_get_job_metrics(
 ///
 /// # Errors
 ///
-/// * `DomainError` - If there is an error publishing to the Redis channel.
-#[tracing::instrument(level = "info", skip(app_data))]
+/// * `DomainError` - If the job belongs to a different user and the caller is
+///   not an admin, if the job is not running or if there is an error
+///   publishing to the Redis channel.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
 pub async fn handle_abort_job(
     req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
     app_data: web::Data<AppData>,
     job_id: web::Path<String>,
+    query: web::Query<AbortJobQuery>,
 ) -> Result<HttpResponse, DomainError> {
     // Extract and validate user ID from auth header
     let user_id = utils::extract_user_id_from_header(req.headers())?;
 
-    // Get a Redis connection from the app_data.
-    let mut conn = app_data.redis_conn_manager.clone();
+    let query = validate_abort_query(query.into_inner())?;
 
-    // Parse the job ID from the path parameter as a UUID.
-    let job_id = Uuid::parse_str(&job_id.into_inner()).map_err(|err| {
-        DomainError::new_bad_input_error(format!("Expected UUID: {err}"))
-    })?;
+    // Owners can abort their own jobs, admins can abort any job
+    let job =
+        fetch_owned_job(&req, &auth, job_id.into_inner(), &app_data).await?;
+    let job_id = job.job_id;
 
-    let job = fetch_job_by_uuid(job_id, app_data.as_ref()).await?;
+    let msg = JobAbortMessage::new(Some(user_id), query.reason, query.force);
 
-    if user_id != job.started_by {
-        return Err(DomainError::new_auth_error(
-            "Forbidden: Tried to abort job of a different user".to_owned(),
-        ));
-    };
+    if publish_job_abort(job_id, &msg, &app_data).await? {
+        let _ = tracing::info!("Abort command sent for job with id: {job_id}");
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(DomainError::new_bad_input_error(format!(
+            "Job {job_id} is not running"
+        )))
+    }
+}
+
+/// Aborts all running jobs of the calling user
+///
+/// # Arguments
+///
+/// * `app_data` - Shared application data, including database pool and Redis connection.
+/// * `query` - Optional abort `reason` and `force` flag, applied to every job
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP response with the ids of the
+///   jobs an abort was sent to
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_abort_my_jobs(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    query: web::Query<AbortJobQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
+    let query = validate_abort_query(query.into_inner())?;
+
+    let pool = app_data.pool.clone();
+    let jobs = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_pending_jobs_by_user(&user_id, &mut conn)
+    })
+    .await??;
+
+    let msg = JobAbortMessage::new(Some(user_id), query.reason, query.force);
+
+    let mut job_ids = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        if publish_job_abort(job.job_id, &msg, &app_data).await? {
+            job_ids.push(job.job_id);
+        }
+    }
+
+    let _ = tracing::info!(
+        "Abort command sent for {} jobs of user {user_id}",
+        job_ids.len()
+    );
+
+    Ok(HttpResponse::Ok().json(AbortedJobs { job_ids }))
+}
+
+fn validate_abort_query(
+    query: AbortJobQuery,
+) -> Result<AbortJobQuery, DomainError> {
+    match &query.reason {
+        Some(reason) if reason.len() > AbortJobQuery::MAX_REASON_LENGTH => {
+            Err(DomainError::new_bad_input_error(format!(
+                "Abort reason must be at most {} bytes long",
+                AbortJobQuery::MAX_REASON_LENGTH
+            )))
+        }
+        _ => Ok(query),
+    }
+}
+
+/// Publishes an abort message on the job's abort channel. Returns whether
+/// any instance was running the job to receive it. Jobs on remote runners
+/// are flagged for abort instead and marked as aborted right away, the
+/// runner stops the process once it hears about it.
+pub(crate) async fn publish_job_abort(
+    job_id: Uuid,
+    msg: &JobAbortMessage,
+    app_data: &AppData,
+) -> Result<bool, DomainError> {
+    let mut conn = app_data.redis_conn_manager.clone();
 
     // Construct the Redis channel name for aborting the job.
     let abort_chan_name =
         (app_data.redis_prefix)(&format!("job.{job_id}.abort"));
 
     // Publish a message to the Redis channel to abort the job.
-    let () = conn.publish(abort_chan_name, "done").await?;
+    let receivers: i64 =
+        conn.publish(abort_chan_name, utils::jstr(msg)).await?;
+    if receivers > 0 {
+        return Ok(true);
+    }
+
+    let pool = app_data.pool.clone();
+    let status_message = msg.status_message();
+    let aborted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::abort_runner_job(
+            job_id,
+            status_message,
+            chrono::Utc::now().naive_utc(),
+            &mut conn,
+        )
+    })
+    .await??;
+    if aborted {
+        notify_job_event(app_data, job_id, JobEvent::Aborted).await;
+    }
+    Ok(aborted)
+}
+
+/// Sends input to the stdin of a running job
+///
+/// # Arguments
+///
+/// * `app_data` - Shared application data, including Redis connection.
+/// * `job_id` - Path parameter representing the UUID of the job.
+/// * `payload` - The data to write, sent as is, so it must include a trailing
+///   newline for line based programs
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP response indicating success or failure.
+///
+/// # Errors
+///
+/// * `DomainError` - If the job does not belong to the user or is not running.
+#[tracing::instrument(level = "info", skip(app_data, req, payload))]
+pub async fn handle_job_input(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+    payload: web::Json<JobInputRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job_id = parse_job_id(job_id.into_inner())?;
 
-    let _ = tracing::info!("Abort command sent for job with id: {}", job_id);
+    send_job_input(job_id, user_id, payload.into_inner().data, &app_data)
+        .await?;
 
     Ok(HttpResponse::Ok().finish())
 }
+
+/// Publishes input for a job on its Redis input channel, from where the
+/// instance running the job forwards it to the process stdin. Only the user
+/// who started the job may send input to it.
+pub async fn send_job_input(
+    job_id: Uuid,
+    user_id: UserId,
+    data: String,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    let job = fetch_job_by_uuid(job_id, app_data).await?;
+
+    if job.started_by != Some(user_id) {
+        Err(DomainError::new_auth_error(
+            "Forbidden: Tried to send input to job of a different user"
+                .to_owned(),
+        ))?;
+    }
+
+    let mut conn = app_data.redis_conn_manager.clone();
+    let input_chan_name =
+        (app_data.redis_prefix)(&format!("job.{job_id}.input"));
+
+    let receivers: i64 = conn.publish(input_chan_name, data).await?;
+
+    if receivers > 0 {
+        let _ = tracing::info!("Input sent to job with id: {job_id}");
+        Ok(())
+    } else {
+        Err(DomainError::new_bad_input_error(format!(
+            "Job {job_id} is not running"
+        )))
+    }
+}
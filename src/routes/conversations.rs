@@ -0,0 +1,472 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        conversation::{
+            validate_conversation_name, AddConversationMemberRequest,
+            ConversationChange, ConversationDetails, ConversationMember,
+            ConversationRole, CreateConversationRequest, NewConversation,
+            NewConversationMember, RenameConversationRequest,
+            UpdateConversationMemberRequest, MAX_CONVERSATION_MEMBERS,
+        },
+        message::MessagesQuery,
+        users::UserId,
+        ws::UserStreamEvent,
+    },
+    types::DbConnection,
+    utils::{self, ws},
+    AppData,
+};
+
+/// Lists the conversations of the user, most recently active first
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_list_conversations(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
+    let pool = app_data.pool.clone();
+    let conversations = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::conversations::get_conversations_of_user(user_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(conversations))
+}
+
+/// Creates a conversation, the caller becomes its owner
+///
+/// # Arguments
+///
+/// * `payload` - The `name` of the conversation and the `member_ids` of the
+///   users joining it as members
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 201 with the conversation
+///   and its members
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_create_conversation(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    payload: web::Json<CreateConversationRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let payload = payload.into_inner();
+    validate_conversation_name(&payload.name)
+        .map_err(DomainError::new_bad_input_error)?;
+    if payload.member_ids.len() >= MAX_CONVERSATION_MEMBERS {
+        return Err(DomainError::new_bad_input_error(format!(
+            "At most {MAX_CONVERSATION_MEMBERS} members are allowed per conversation"
+        )));
+    }
+
+    let new_conversation = NewConversation {
+        name: payload.name.trim().to_owned(),
+        created_by: user_id,
+    };
+    let pool = app_data.pool.clone();
+    let details = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::conversations::create_conversation(
+            &new_conversation,
+            &payload.member_ids,
+            &mut conn,
+        )
+    })
+    .await??;
+    let _ = tracing::info!(
+        "User {user_id} created conversation {} with {} members",
+        details.conversation.id,
+        details.members.len()
+    );
+
+    let changes = details
+        .members
+        .iter()
+        .filter(|member| member.user_id != user_id)
+        .map(|member| ConversationChange::MemberJoined {
+            user_id: member.user_id,
+            role: member.role,
+        })
+        .collect();
+    publish_changes(
+        &app_data,
+        details.conversation.id,
+        &member_ids(&details.members),
+        changes,
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(details))
+}
+
+/// Gets a conversation with its members, only for its members
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_conversation(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    conversation_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let conversation_id = conversation_id.into_inner();
+
+    let pool = app_data.pool.clone();
+    let details = web::block(move || {
+        let mut conn = pool.get()?;
+        let _ = find_membership(conversation_id, user_id, &mut conn)?;
+        let conversation = actions::conversations::get_conversation(
+            conversation_id,
+            &mut conn,
+        )?
+        .ok_or_else(|| conversation_not_found(conversation_id))?;
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        Ok::<_, DomainError>(ConversationDetails {
+            conversation,
+            members,
+        })
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(details))
+}
+
+/// Renames a conversation, for its admins and its owner
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_rename_conversation(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    conversation_id: web::Path<i32>,
+    payload: web::Json<RenameConversationRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let conversation_id = conversation_id.into_inner();
+    validate_conversation_name(&payload.name)
+        .map_err(DomainError::new_bad_input_error)?;
+    let name = payload.name.trim().to_owned();
+    let now = chrono::Utc::now().naive_utc();
+
+    let pool = app_data.pool.clone();
+    let (conversation, members) = web::block(move || {
+        let mut conn = pool.get()?;
+        let membership = find_membership(conversation_id, user_id, &mut conn)?;
+        if !membership.role.can_manage() {
+            return Err(DomainError::new_auth_error(
+                "Forbidden: only admins can rename the conversation".to_owned(),
+            ));
+        }
+        let conversation = actions::conversations::rename_conversation(
+            conversation_id,
+            &name,
+            now,
+            &mut conn,
+        )?;
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        Ok((conversation, members))
+    })
+    .await??;
+
+    publish_changes(
+        &app_data,
+        conversation_id,
+        &member_ids(&members),
+        vec![ConversationChange::Renamed {
+            name: conversation.name.clone(),
+        }],
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(conversation))
+}
+
+/// Invites a user into a conversation, for its admins and its owner
+///
+/// # Arguments
+///
+/// * `payload` - The `user_id` to add and their `role`, `member` unless
+///   given. Only the owner can add admins, the owner role is handed over
+///   by changing the role of a member.
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 201 with the new member
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_add_conversation_member(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    conversation_id: web::Path<i32>,
+    payload: web::Json<AddConversationMemberRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let conversation_id = conversation_id.into_inner();
+    let payload = payload.into_inner();
+    if payload.role == ConversationRole::Owner {
+        return Err(DomainError::new_bad_input_error(
+            "A conversation has a single owner".to_owned(),
+        ));
+    }
+
+    let pool = app_data.pool.clone();
+    let (member, members) = web::block(move || {
+        let mut conn = pool.get()?;
+        let membership = find_membership(conversation_id, user_id, &mut conn)?;
+        if !membership.role.can_manage() || payload.role >= membership.role {
+            return Err(DomainError::new_auth_error(format!(
+                "Forbidden: cannot add a member with role {:?}",
+                payload.role
+            )));
+        }
+        if actions::users::find_active_user_by_uid(&payload.user_id, &mut conn)?
+            .is_none()
+        {
+            return Err(DomainError::new_entity_does_not_exist_error(format!(
+                "No user with id: {}",
+                payload.user_id
+            )));
+        }
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        let member = actions::conversations::add_conversation_member(
+            &NewConversationMember {
+                conversation_id,
+                user_id: payload.user_id,
+                role: payload.role,
+            },
+            MAX_CONVERSATION_MEMBERS,
+            &mut conn,
+        )?
+        .ok_or_else(|| {
+            DomainError::new_bad_input_error(format!(
+                "User {} already is a member",
+                payload.user_id
+            ))
+        })?;
+        Ok((member, members))
+    })
+    .await??;
+    let _ = tracing::info!(
+        "User {user_id} added {} to conversation {conversation_id}",
+        member.user_id
+    );
+
+    let mut recipients = member_ids(&members);
+    recipients.push(member.user_id);
+    publish_changes(
+        &app_data,
+        conversation_id,
+        &recipients,
+        vec![ConversationChange::MemberJoined {
+            user_id: member.user_id,
+            role: member.role,
+        }],
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(member))
+}
+
+/// Changes the role of a member, for the owner only. Making a member the
+/// owner hands the ownership over, the previous owner becomes an admin.
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_update_conversation_member(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    path: web::Path<(i32, UserId)>,
+    payload: web::Json<UpdateConversationMemberRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let (conversation_id, member_id) = path.into_inner();
+    let role = payload.role;
+    if member_id == user_id {
+        return Err(DomainError::new_bad_input_error(
+            "The owner hands over the ownership to change their own role"
+                .to_owned(),
+        ));
+    }
+
+    let pool = app_data.pool.clone();
+    let (changed, members) = web::block(move || {
+        let mut conn = pool.get()?;
+        let membership = find_membership(conversation_id, user_id, &mut conn)?;
+        if membership.role != ConversationRole::Owner {
+            return Err(DomainError::new_auth_error(
+                "Forbidden: only the owner can change roles".to_owned(),
+            ));
+        }
+        let _ = find_membership(conversation_id, member_id, &mut conn)?;
+        let changed = actions::conversations::update_conversation_member_role(
+            conversation_id,
+            member_id,
+            role,
+            &mut conn,
+        )?;
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        Ok((changed, members))
+    })
+    .await??;
+
+    let changes = changed
+        .iter()
+        .map(|member| ConversationChange::RoleChanged {
+            user_id: member.user_id,
+            role: member.role,
+        })
+        .collect();
+    publish_changes(&app_data, conversation_id, &member_ids(&members), changes)
+        .await;
+
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// Removes a member from a conversation. Members can always leave, admins
+/// and the owner can remove members with a lower role.
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_remove_conversation_member(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    path: web::Path<(i32, UserId)>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let (conversation_id, member_id) = path.into_inner();
+
+    let pool = app_data.pool.clone();
+    let (new_owner, members) = web::block(move || {
+        let mut conn = pool.get()?;
+        let membership = find_membership(conversation_id, user_id, &mut conn)?;
+        if member_id != user_id {
+            let member =
+                find_membership(conversation_id, member_id, &mut conn)?;
+            if !membership.role.can_manage() || member.role >= membership.role {
+                return Err(DomainError::new_auth_error(
+                    "Forbidden: cannot remove this member".to_owned(),
+                ));
+            }
+        }
+        let new_owner = actions::conversations::remove_conversation_member(
+            conversation_id,
+            member_id,
+            &mut conn,
+        )?;
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        Ok((new_owner, members))
+    })
+    .await??;
+    let _ = tracing::info!(
+        "User {member_id} left conversation {conversation_id}, removed by {user_id}"
+    );
+
+    let mut changes =
+        vec![ConversationChange::MemberLeft { user_id: member_id }];
+    if let Some(owner) = new_owner {
+        changes.push(ConversationChange::RoleChanged {
+            user_id: owner.user_id,
+            role: owner.role,
+        });
+    }
+    // the removed member learns about it too
+    let mut recipients = member_ids(&members);
+    recipients.push(member_id);
+    publish_changes(&app_data, conversation_id, &recipients, changes).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lists the messages of a conversation, newest first, only for its members
+///
+/// # Arguments
+///
+/// * `query` - The `next_cursor` of the previous page as `before` and the
+///   page `limit`, at most 50
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_conversation_messages(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    conversation_id: web::Path<i32>,
+    query: web::Query<MessagesQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let conversation_id = conversation_id.into_inner();
+    let query = query.into_inner();
+
+    let pool = app_data.pool.clone();
+    let page = web::block(move || {
+        let mut conn = pool.get()?;
+        let _ = find_membership(conversation_id, user_id, &mut conn)?;
+        actions::conversations::get_conversation_messages(
+            conversation_id,
+            &query,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Membership of the user, conversations they are not a member of do not
+/// exist for them
+fn find_membership(
+    conversation_id: i32,
+    user_id: UserId,
+    conn: &mut DbConnection,
+) -> Result<ConversationMember, DomainError> {
+    actions::conversations::get_conversation_member(
+        conversation_id,
+        user_id,
+        conn,
+    )?
+    .ok_or_else(|| conversation_not_found(conversation_id))
+}
+
+fn conversation_not_found(conversation_id: i32) -> DomainError {
+    DomainError::new_entity_does_not_exist_error(format!(
+        "No conversation with id: {conversation_id}"
+    ))
+}
+
+fn member_ids(members: &[ConversationMember]) -> Vec<UserId> {
+    members.iter().map(|member| member.user_id).collect()
+}
+
+/// Delivers the changes to the devices of the recipients. The changes are
+/// already stored, so a failed delivery is only logged.
+async fn publish_changes(
+    app_data: &AppData,
+    conversation_id: i32,
+    recipients: &[UserId],
+    changes: Vec<ConversationChange>,
+) {
+    let mut conn = app_data.redis_conn_manager.clone();
+    for change in changes {
+        let event = UserStreamEvent::ConversationUpdated {
+            conversation_id,
+            change,
+        };
+        if let Err(err) =
+            ws::publish_user_event(&mut conn, app_data, recipients, &event)
+                .await
+        {
+            let _ = tracing::warn!(
+                "Failed to publish change of conversation {conversation_id}: {err:?}"
+            );
+        }
+    }
+}
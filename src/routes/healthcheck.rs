@@ -3,83 +3,84 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use crate::{get_build_info, AppData};
+use crate::{
+    get_build_info,
+    health::HealthCheck,
+    models::health::{HealthCheckResponse, ServiceHealth},
+    AppData,
+};
 use actix_web::{web::Data, HttpResponse, Responder};
-use serde::Serialize;
 
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum ServiceStatus {
-    Healthy,
-    Unhealthy(String),
+/// Whether the instance itself works, restarting it is the only remedy
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn live(app_data: Data<AppData>) -> impl Responder {
+    let services =
+        run_checks(&app_data, app_data.health_checks.liveness()).await;
+    respond(health_response(&app_data, services))
 }
 
-#[derive(Serialize)]
-struct HealthCheckResponse {
-    version: String,
-    timestamp: String,
-    uptime: u64,
-    success: bool,
-    services: HashMap<String, ServiceStatus>,
+/// Whether the instance should receive traffic, not while its critical
+/// dependencies are down or it is shutting down
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn ready(app_data: Data<AppData>) -> impl Responder {
+    if app_data.shutdown.is_shutting_down() {
+        return respond(HealthCheckResponse {
+            success: false,
+            ..health_response(&app_data, HashMap::new())
+        });
+    }
+    let services =
+        run_checks(&app_data, app_data.health_checks.dependencies()).await;
+    respond(health_response(&app_data, services))
 }
 
+/// Whether the instance finished starting up, i.e. its critical dependencies
+/// were reachable once
 #[tracing::instrument(level = "info", skip_all)]
-pub async fn healthcheck(app_data: Data<AppData>) -> impl Responder {
+pub async fn startup(app_data: Data<AppData>) -> impl Responder {
+    let health_checks = &app_data.health_checks;
+    if health_checks.is_started() {
+        return respond(health_response(&app_data, HashMap::new()));
+    }
+    let services = run_checks(&app_data, health_checks.dependencies()).await;
+    let response = health_response(&app_data, services);
+    if response.success {
+        health_checks.mark_started();
+    }
+    respond(response)
+}
+
+async fn run_checks<'a>(
+    app_data: &AppData,
+    checks: impl Iterator<Item = &'a HealthCheck>,
+) -> HashMap<String, ServiceHealth> {
+    let timeout =
+        Duration::from_secs(app_data.config.health_check_timeout_secs.into());
+    let cache_ttl =
+        Duration::from_secs(app_data.config.health_check_cache_ttl_secs);
+    let check_futures = checks.map(|check| async move {
+        (check.name.clone(), check.run(timeout, cache_ttl).await)
+    });
+    futures::future::join_all(check_futures)
+        .await
+        .into_iter()
+        .collect()
+}
+
+fn health_response(
+    app_data: &AppData,
+    services: HashMap<String, ServiceHealth>,
+) -> HealthCheckResponse {
     let uptime = SystemTime::now()
         .duration_since(app_data.start_time)
         .unwrap_or_default()
         .as_secs();
+    let version = get_build_info().crate_info.version.to_string();
+    HealthCheckResponse::new(version, uptime, services)
+}
 
-    let bi = get_build_info();
-
-    let checkers = &app_data.health_checkers;
-
-    let check_futures = checkers.iter().map(|(service_name, checker)| {
-        let service_name = service_name.to_string();
-        let timeout = Duration::from_secs(
-            app_data.config.health_check_timeout_secs.into(),
-        );
-
-        async move {
-            checker
-                .check_health(timeout)
-                .await
-                .map(|_| (service_name.clone(), ServiceStatus::Healthy))
-                .map_err(|err| {
-                    let _ = tracing::warn!(
-                        "Health check failed for {service_name}: {err}"
-                    );
-                    (service_name, ServiceStatus::Unhealthy(err.to_string()))
-                })
-        }
-    });
-
-    let check_results = futures::future::join_all(check_futures).await;
-
-    let (services, success) = check_results.into_iter().fold(
-        (HashMap::new(), true),
-        |(mut acc, all_healthy), result| match result {
-            Ok((name, status)) => {
-                let healthy = matches!(status, ServiceStatus::Healthy);
-                acc.insert(name, status);
-                (acc, all_healthy && healthy)
-            }
-            Err((name, status)) => {
-                acc.insert(name, status);
-                (acc, false)
-            }
-        },
-    );
-
-    let response = HealthCheckResponse {
-        version: bi.crate_info.version.to_string(),
-        timestamp: chrono::Utc::now().to_rfc3339(),
-        uptime,
-        success,
-        services,
-    };
-
-    if success {
+fn respond(response: HealthCheckResponse) -> HttpResponse {
+    if response.success {
         HttpResponse::Ok().json(response)
     } else {
         HttpResponse::ServiceUnavailable().json(response)
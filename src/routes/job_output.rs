@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use redis::streams::StreamReadOptions;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        misc::{JobStatus, LastEventId},
+        ws::MyProcessItem,
+    },
+    routes::command::parse_job_id,
+    types::DbPool,
+    utils::{
+        self, job_output::job_output_stream_key, shutdown::Shutdown,
+        RedisChannelReader,
+    },
+    AppData,
+};
+
+/// How long a read of the output stream blocks before a keep-alive comment
+/// is sent and the job status is checked
+const READ_BLOCK_MS: usize = 15_000;
+const READ_COUNT: usize = 100;
+
+/// Streams the output of a job as Server-Sent Events
+///
+/// Every output item is sent as an event named after its kind (`line`,
+/// `error` or `done`) with the item as JSON data and the id of its entry in
+/// the job's output stream. The stream replays the output kept so far,
+/// follows the job live and ends after the `done` event or once the job is
+/// over.
+///
+/// A `Last-Event-ID` header, as sent by `EventSource` when it reconnects,
+/// resumes the stream after that event.
+///
+/// # Errors
+///
+/// * `DomainError` - If the job does not exist, the user is not allowed to
+///   view it or the `Last-Event-ID` is not a stream entry id
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_stream_job_output(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job_id = parse_job_id(job_id.into_inner())?;
+    let last_event_id = req
+        .headers()
+        .get(LastEventId::HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|err| err.to_string())
+                .and_then(|value| LastEventId::try_from(value.to_owned()))
+                .map_err(DomainError::new_bad_input_error)
+        })
+        .transpose()?;
+
+    let pool = app_data.pool.clone();
+    let job = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_viewable_job(job_id, &user_id, &mut conn)
+    })
+    .await??;
+
+    // Reads block, so every stream gets its own connection instead of
+    // holding up the shared one
+    let conn = utils::get_blocking_redis_conn(
+        app_data.redis_conn_factory.clone(),
+        Duration::from_millis(READ_BLOCK_MS as u64),
+    )
+    .await?;
+    let reader = RedisChannelReader::<MyProcessItem>::new(
+        job_output_stream_key(&app_data.redis_prefix, job.job_id),
+        conn,
+        last_event_id.map(LastEventId::into_inner),
+        StreamReadOptions::default()
+            .block(READ_BLOCK_MS)
+            .count(READ_COUNT),
+    );
+    let _ = tracing::info!(
+        "Streaming output of job {job_id} from {:?}",
+        reader.last_msg_id()
+    );
+
+    let state = OutputStreamState {
+        reader,
+        pool: app_data.pool.clone(),
+        job_id: job.job_id,
+        job_over: job.status != JobStatus::Pending,
+        done: false,
+        shutdown: app_data.shutdown.clone(),
+    };
+    let events = futures::stream::unfold(state, |mut state| async move {
+        // clients resume from the last event id on another instance
+        if state.done || state.shutdown.is_shutting_down() {
+            return None;
+        }
+        let res = state.next_events().await;
+        if res.is_err() {
+            state.done = true;
+        }
+        Some((res.map(web::Bytes::from), state))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // keeps nginx from buffering the events
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+struct OutputStreamState {
+    reader: RedisChannelReader<MyProcessItem>,
+    pool: DbPool,
+    job_id: uuid::Uuid,
+    /// Set once the job is no longer running, the next empty read ends the
+    /// stream
+    job_over: bool,
+    done: bool,
+    shutdown: Shutdown,
+}
+
+impl OutputStreamState {
+    /// Reads the next batch of output formatted as events, or a keep-alive
+    /// comment when there was none
+    async fn next_events(&mut self) -> Result<String, DomainError> {
+        let entries = self.reader.get_items_unparsed().await?;
+        if entries.is_empty() {
+            if self.job_over {
+                let _ = tracing::info!(
+                    "Job {} is over, ending its output stream",
+                    self.job_id
+                );
+                self.done = true;
+                return Ok(String::new());
+            }
+            // the final output is written before the job status, a job that
+            // just ended gets one more read for it
+            let pool = self.pool.clone();
+            let job_id = self.job_id;
+            let job = web::block(move || {
+                let mut conn = pool.get()?;
+                actions::misc::get_job_by_uuid(job_id, &mut conn)
+            })
+            .await??;
+            self.job_over =
+                job.is_none_or(|job| job.status != JobStatus::Pending);
+            return Ok(": keep-alive\n\n".to_owned());
+        }
+
+        let mut events = String::new();
+        for entry in entries {
+            let Some(msg) = entry.get::<String>("message") else {
+                let _ = tracing::error!(
+                    "Did not find message key in output entry {}",
+                    entry.id
+                );
+                continue;
+            };
+            let item = match serde_json::from_str::<MyProcessItem>(&msg) {
+                Ok(item) => item,
+                Err(err) => {
+                    let _ = tracing::error!(
+                        "Failed to parse output entry {}: {err}",
+                        entry.id
+                    );
+                    continue;
+                }
+            };
+            let event = match item {
+                MyProcessItem::Line { .. } => "line",
+                MyProcessItem::Error { .. } => "error",
+                MyProcessItem::Done { .. } => {
+                    self.done = true;
+                    "done"
+                }
+            };
+            events.push_str(&format!(
+                "id: {}\nevent: {event}\ndata: {msg}\n\n",
+                entry.id
+            ));
+            if self.done {
+                break;
+            }
+        }
+        Ok(events)
+    }
+}
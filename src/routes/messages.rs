@@ -0,0 +1,95 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        message::{MarkReadQuery, MarkedRead, MessagesQuery},
+        users::UserId,
+    },
+    utils, AppData,
+};
+
+/// Lists the messages exchanged with another user, newest first
+///
+/// # Arguments
+///
+/// * `peer` - Id of the other user
+/// * `query` - The `next_cursor` of the previous page as `before` and the
+///   page `limit`, at most 50
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with the page of
+///   messages and the cursor of the next page, if any
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_conversation(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    peer: web::Path<UserId>,
+    query: web::Query<MessagesQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let peer = peer.into_inner();
+    let query = query.into_inner();
+
+    let pool = app_data.pool.clone();
+    let page = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::messages::get_conversation(user_id, peer, &query, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Marks the messages another user sent as read, which shows up as
+/// `is_read` in the history of both users
+///
+/// # Arguments
+///
+/// * `peer` - Id of the other user
+/// * `query` - With `up_to`, only the messages up to that id are marked
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_mark_conversation_read(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    peer: web::Path<UserId>,
+    query: web::Query<MarkReadQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let peer = peer.into_inner();
+    let up_to = query.up_to;
+
+    let pool = app_data.pool.clone();
+    let marked_read = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::messages::mark_conversation_read(
+            user_id, peer, up_to, &mut conn,
+        )
+    })
+    .await??;
+    let _ = tracing::info!(
+        "User {user_id} read {marked_read} messages from {peer}"
+    );
+
+    Ok(HttpResponse::Ok().json(MarkedRead { marked_read }))
+}
+
+/// Counts the unread messages of the user by sender
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_unread_counts(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
+    let pool = app_data.pool.clone();
+    let counts = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::messages::get_unread_counts(user_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(counts))
+}
@@ -0,0 +1,74 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        message::MarkedRead,
+        notification::{MarkNotificationsReadRequest, NotificationsQuery},
+    },
+    utils, AppData,
+};
+
+/// Gets the notifications of the current user, newest first
+///
+/// # Arguments
+///
+/// * `query` - `before` to page backwards, `limit` and `unread_only`
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with the page of
+///   notifications, the cursor of the next page and the unread count
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_notifications(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    query: web::Query<NotificationsQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let query = query.into_inner();
+
+    let pool = app_data.pool.clone();
+    let page = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::notifications::get_notifications(user_id, &query, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Marks notifications of the current user as read
+///
+/// # Arguments
+///
+/// * `request` - Either the `ids` of the notifications, or `up_to` to mark
+///   all of them up to and including that id
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_mark_notifications_read(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    request: web::Json<MarkNotificationsReadRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let request = request.into_inner();
+    request
+        .validate()
+        .map_err(DomainError::new_bad_input_error)?;
+
+    let pool = app_data.pool.clone();
+    let marked_read = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::notifications::mark_notifications_read(
+            user_id,
+            &request,
+            chrono::Utc::now().naive_utc(),
+            &mut conn,
+        )
+    })
+    .await??;
+    let _ = tracing::info!("User {user_id} read {marked_read} notifications");
+
+    Ok(HttpResponse::Ok().json(MarkedRead { marked_read }))
+}
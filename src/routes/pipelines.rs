@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::{stream::FuturesUnordered, StreamExt};
+use redis::AsyncCommands;
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    metrics::JobTemplate,
+    models::{
+        misc::{JobAbortMessage, JobStatus, NewJob},
+        pipeline::{
+            NewPipelineStep, PipelineDetails, PipelineStep,
+            PipelineStepDefinition, PipelineStepDetails, RunPipelineRequest,
+            StepFailurePolicy, MAX_STEP_OUTPUT_LENGTH,
+        },
+        users::UserId,
+        webhook::JobEvent,
+        ws::MyProcessItem,
+    },
+    routes::command::{self, JobOutcome, RunCommandRequest},
+    types::Task,
+    utils::{self, job_output::publish_job_output, webhooks::notify_job_event},
+    AppData,
+};
+
+/// Runs a pipeline of jobs whose steps depend on each other
+///
+/// # Arguments
+/// * `payload` - The pipeline `steps`, each with a `name`, command `args`,
+///   `pty` flag, the names of the steps it `depends_on` and its `on_failure`
+///   policy
+///
+/// # Returns
+/// Returns HTTP 200 with the pipeline job and its steps, all still pending
+///
+/// # Process
+/// 1. Creates the pipeline job and one job per step, in topological order
+/// 2. Starts every step whose dependencies completed, independent branches
+///    run in parallel
+/// 3. Passes the last output line of a step to its dependents in the
+///    `PIPELINE_STEP_{NAME}_OUTPUT` environment variable
+/// 4. Skips the dependents of a failed step and, when the step fails fast,
+///    aborts the rest of the pipeline
+/// 5. Publishes progress on the pipeline job channel and updates its status
+///    once all steps are done
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn handle_run_pipeline(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    payload: web::Json<RunPipelineRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let payload = payload.into_inner();
+    let order = payload
+        .validate()
+        .map_err(DomainError::new_bad_input_error)?;
+
+    let mut conn = app_data.redis_conn_manager.clone();
+    // Health check publish to verify Redis connection
+    let () = conn.publish("hc", "hc").await?;
+
+    let pipeline_job_id = Uuid::new_v4();
+    let running = app_data.shutdown.track_job(pipeline_job_id)?;
+    let new_job = |job_id| NewJob {
+        job_id,
+        started_by: user_id,
+        status: JobStatus::Pending,
+        status_message: None,
+        retry_of: None,
+        idempotency_key: None,
+        request: None,
+    };
+    let pipeline_job = new_job(pipeline_job_id);
+    // Steps are stored in topological order, so that the runner settles
+    // the dependencies of a step before getting to it
+    let mut steps = payload.steps.into_iter().map(Some).collect::<Vec<_>>();
+    let new_steps = order
+        .into_iter()
+        .filter_map(|idx| steps[idx].take())
+        .map(|step| {
+            let job_id = Uuid::new_v4();
+            let new_step = NewPipelineStep {
+                pipeline_job_id,
+                job_id,
+                name: step.name,
+                args: step.args,
+                pty: step.pty,
+                depends_on: step.depends_on,
+                on_failure: step.on_failure,
+                artifacts: step.artifacts,
+            };
+            (new_job(job_id), new_step)
+        })
+        .collect::<Vec<_>>();
+
+    let pool = app_data.pool.clone();
+    let (job, steps) = web::block(move || {
+        let mut conn = pool.get()?;
+        let steps = actions::pipelines::create_pipeline(
+            &pipeline_job,
+            &new_steps,
+            &mut conn,
+        )?;
+        let job = actions::misc::get_job_by_uuid(pipeline_job_id, &mut conn)?
+            .ok_or_else(|| {
+            DomainError::new_internal_error(
+                "failed to retrieve created pipeline job".to_owned(),
+            )
+        })?;
+        Ok::<_, DomainError>((job, steps))
+    })
+    .await??;
+    let _ = tracing::info!(
+        "Created pipeline {pipeline_job_id} with {} steps",
+        steps.len()
+    );
+
+    notify_job_event(&app_data, pipeline_job_id, JobEvent::Created).await;
+    for step in &steps {
+        notify_job_event(&app_data, step.job_id, JobEvent::Created).await;
+    }
+
+    let details = PipelineDetails {
+        job,
+        steps: steps
+            .iter()
+            .cloned()
+            .map(|step| PipelineStepDetails {
+                step,
+                status: JobStatus::Pending,
+                status_message: None,
+            })
+            .collect(),
+    };
+
+    let _task: Task<()> = actix_rt::spawn(
+        async move {
+            let _running = running;
+            run_pipeline(app_data, pipeline_job_id, user_id, steps).await
+        }
+        .instrument(info_span!(
+            "pipeline",
+            job_id = pipeline_job_id.to_string()
+        )),
+    );
+
+    Ok(HttpResponse::Ok().json(details))
+}
+
+/// Returns a pipeline job along with its steps and their status
+///
+/// Anyone who can view the pipeline job can view its steps.
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_get_pipeline(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let job_id = command::parse_job_id(job_id.into_inner())?;
+
+    let pool = app_data.pool.clone();
+    let (job, steps) = web::block(move || {
+        let mut conn = pool.get()?;
+        let job = actions::misc::get_viewable_job(job_id, &user_id, &mut conn)?;
+        let steps = actions::pipelines::get_pipeline_steps(job_id, &mut conn)?;
+        Ok::<_, DomainError>((job, steps))
+    })
+    .await??;
+
+    if steps.is_empty() {
+        Err(DomainError::new_entity_does_not_exist_error(format!(
+            "Job {job_id} is not a pipeline"
+        )))
+    } else {
+        Ok(HttpResponse::Ok().json(PipelineDetails { job, steps }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepState {
+    Waiting,
+    Running,
+    Completed,
+    /// Failed or aborted
+    Failed,
+    /// Never started because a dependency did not complete or the pipeline
+    /// was stopped
+    Skipped,
+}
+
+/// Drives a pipeline until all of its steps are settled. Steps are expected
+/// in topological order.
+async fn run_pipeline(
+    app_data: web::Data<AppData>,
+    pipeline_job_id: Uuid,
+    owner: UserId,
+    steps: Vec<PipelineStep>,
+) -> Result<(), DomainError> {
+    let abort_chan_name =
+        (app_data.redis_prefix)(&format!("job.{pipeline_job_id}.abort"));
+
+    let mut ps = app_data
+        .redis_conn_factory
+        .get_async_pubsub()
+        .await
+        .map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to initialize pubsub connection: {err}"
+            ))
+        })?;
+    let _ = ps.subscribe(&abort_chan_name).await.map_err(|err| {
+        DomainError::new_internal_error(format!(
+            "Failed to subscribe to abort channel: {err}"
+        ))
+    })?;
+    let mut aborts = ps.on_message();
+    notify_job_event(&app_data, pipeline_job_id, JobEvent::Started).await;
+
+    let dep_indices = steps
+        .iter()
+        .map(|step| {
+            step.depends_on
+                .iter()
+                .filter_map(|dep| steps.iter().position(|s| &s.name == dep))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut states = vec![StepState::Waiting; steps.len()];
+    let mut outputs = HashMap::<&str, String>::new();
+    let mut running = FuturesUnordered::new();
+    // Set once a fail fast step failed or the pipeline was aborted
+    let mut halted = false;
+    let mut abort = None::<JobAbortMessage>;
+
+    loop {
+        for (idx, step) in steps.iter().enumerate() {
+            if states[idx] != StepState::Waiting {
+                continue;
+            }
+            let deps = &dep_indices[idx];
+
+            if halted {
+                states[idx] = StepState::Skipped;
+                skip_step(step, "pipeline was stopped", &app_data).await?;
+            } else if let Some(dep) = deps.iter().find(|dep| {
+                matches!(states[**dep], StepState::Failed | StepState::Skipped)
+            }) {
+                states[idx] = StepState::Skipped;
+                let reason =
+                    format!("step {} did not complete", steps[*dep].name);
+                skip_step(step, &reason, &app_data).await?;
+            } else if deps
+                .iter()
+                .all(|dep| states[*dep] == StepState::Completed)
+            {
+                states[idx] = StepState::Running;
+                let env = std::iter::once((
+                    "PIPELINE_JOB_ID".to_owned(),
+                    pipeline_job_id.to_string(),
+                ))
+                .chain(step.depends_on.iter().filter_map(|dep| {
+                    outputs.get(dep.as_str()).map(|output| {
+                        (
+                            PipelineStepDefinition::output_env_var(dep),
+                            output.clone(),
+                        )
+                    })
+                }))
+                .collect::<Vec<_>>();
+                let payload = RunCommandRequest {
+                    args: step.args.clone(),
+                    pty: step.pty,
+                    artifacts: step.artifacts.clone(),
+                    retry: None,
+                    runs_on: Vec::new(),
+                };
+                let task: Task<JobOutcome> = actix_rt::spawn(
+                    command::run_job(
+                        app_data.clone(),
+                        step.job_id,
+                        payload,
+                        env,
+                        JobTemplate::Pipeline,
+                    )
+                    .instrument(info_span!(
+                        "job",
+                        job_id = step.job_id.to_string()
+                    )),
+                );
+                running.push(async move { (idx, task.await) });
+                publish_progress(
+                    &app_data,
+                    pipeline_job_id,
+                    format!(
+                        "Step {} started as job {}",
+                        step.name, step.job_id
+                    ),
+                )
+                .await?;
+            }
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            Some((idx, res)) = running.next() => {
+                let step = &steps[idx];
+                let outcome = match res {
+                    Ok(Ok(outcome)) => outcome,
+                    // The job never got to record its status
+                    Ok(Err(err)) => fail_step(step, format!("{err:?}"), &app_data).await?,
+                    Err(err) => fail_step(step, format!("{err:?}"), &app_data).await?,
+                };
+
+                if outcome.status == JobStatus::Completed {
+                    states[idx] = StepState::Completed;
+                    let output = outcome
+                        .output
+                        .map(|output| output.chars().take(MAX_STEP_OUTPUT_LENGTH).collect::<String>());
+                    if let Some(output) = &output {
+                        let _ = outputs.insert(step.name.as_str(), output.clone());
+                    }
+                    let pool = app_data.pool.clone();
+                    let job_id = step.job_id;
+                    web::block(move || {
+                        let mut conn = pool.get()?;
+                        actions::pipelines::set_pipeline_step_output(job_id, output, &mut conn)
+                    })
+                    .await??;
+                    publish_progress(&app_data, pipeline_job_id, format!("Step {} completed", step.name)).await?;
+                } else {
+                    states[idx] = StepState::Failed;
+                    publish_progress(
+                        &app_data,
+                        pipeline_job_id,
+                        format!("Step {} ended with status {:?}", step.name, outcome.status),
+                    )
+                    .await?;
+                    if step.on_failure == StepFailurePolicy::FailFast && !halted {
+                        let _ = tracing::warn!("Step {} failed, stopping pipeline", step.name);
+                        halted = true;
+                        let msg = JobAbortMessage::new(
+                            Some(owner),
+                            Some(format!("Pipeline step {} failed", step.name)),
+                            false,
+                        );
+                        abort_running_steps(&steps, &states, &msg, &app_data).await?;
+                    }
+                }
+
+                let finished = states
+                    .iter()
+                    .filter(|s| !matches!(s, StepState::Waiting | StepState::Running))
+                    .count();
+                if abort.is_none() {
+                    update_status(
+                        pipeline_job_id,
+                        JobStatus::Pending,
+                        Some(format!("{finished} of {} steps finished", steps.len())),
+                        &app_data,
+                    )
+                    .await?;
+                }
+            }
+            Some(msg) = aborts.next() => {
+                let msg = msg.get_payload::<String>().unwrap_or_default();
+                match serde_json::from_str::<JobAbortMessage>(&msg) {
+                    Ok(msg) if abort.is_none() => {
+                        let _ = tracing::info!("Received abort signal for pipeline {pipeline_job_id}: {msg:?}");
+                        halted = true;
+                        update_status(
+                            pipeline_job_id,
+                            JobStatus::Aborted,
+                            Some(msg.status_message()),
+                            &app_data,
+                        )
+                        .await?;
+                        abort_running_steps(&steps, &states, &msg, &app_data).await?;
+                        abort = Some(msg);
+                    }
+                    Ok(_) => (),
+                    Err(err) => {
+                        let _ = tracing::warn!("Ignoring invalid abort message {msg}: {err}");
+                    }
+                }
+            }
+        }
+    }
+
+    let failed = steps
+        .iter()
+        .zip(states.iter())
+        .filter(|(_, state)| **state != StepState::Completed)
+        .map(|(step, _)| step.name.as_str())
+        .collect::<Vec<_>>();
+    let (status, status_message) = match &abort {
+        Some(abort) => (JobStatus::Aborted, Some(abort.status_message())),
+        None if failed.is_empty() => (JobStatus::Completed, None),
+        None => (
+            JobStatus::Failed,
+            Some(format!("Steps did not complete: {}", failed.join(", "))),
+        ),
+    };
+    let _ = tracing::info!(
+        "Pipeline {pipeline_job_id} finished with status {status:?}"
+    );
+
+    let code = if status == JobStatus::Completed { 0 } else { 1 };
+    let event = JobEvent::for_final_status(&status);
+    update_status(pipeline_job_id, status, status_message, &app_data).await?;
+    if let Some(event) = event {
+        notify_job_event(&app_data, pipeline_job_id, event).await;
+    }
+    publish_job_output(
+        &app_data,
+        pipeline_job_id,
+        &MyProcessItem::Done {
+            code: code.to_string(),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Publishes a progress line as output of the pipeline job, the same way
+/// the output of a job process is published
+async fn publish_progress(
+    app_data: &AppData,
+    pipeline_job_id: Uuid,
+    value: String,
+) -> Result<(), DomainError> {
+    let _ = tracing::info!("{value}");
+    publish_job_output(
+        app_data,
+        pipeline_job_id,
+        &MyProcessItem::Line { value },
+    )
+    .await
+}
+
+async fn skip_step(
+    step: &PipelineStep,
+    reason: &str,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    let _ = tracing::info!("Skipping step {}: {reason}", step.name);
+    update_status(
+        step.job_id,
+        JobStatus::Aborted,
+        Some(format!("Skipped: {reason}")),
+        app_data,
+    )
+    .await?;
+    notify_job_event(app_data, step.job_id, JobEvent::Aborted).await;
+    Ok(())
+}
+
+async fn fail_step(
+    step: &PipelineStep,
+    err: String,
+    app_data: &AppData,
+) -> Result<JobOutcome, DomainError> {
+    let _ = tracing::error!("Step {} failed to run: {err}", step.name);
+    update_status(
+        step.job_id,
+        JobStatus::Failed,
+        Some(format!("Error running job: {err}")),
+        app_data,
+    )
+    .await?;
+    notify_job_event(app_data, step.job_id, JobEvent::Failed).await;
+    Ok(JobOutcome {
+        status: JobStatus::Failed,
+        output: None,
+    })
+}
+
+async fn abort_running_steps(
+    steps: &[PipelineStep],
+    states: &[StepState],
+    msg: &JobAbortMessage,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    for (step, _) in steps
+        .iter()
+        .zip(states.iter())
+        .filter(|(_, state)| **state == StepState::Running)
+    {
+        if !command::publish_job_abort(step.job_id, msg, app_data).await? {
+            let _ = tracing::warn!(
+                "Step {} was not listening for an abort",
+                step.name
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn update_status(
+    job_id: Uuid,
+    status: JobStatus,
+    status_message: Option<String>,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    let pool = app_data.pool.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::update_job_status(
+            job_id,
+            status,
+            status_message,
+            &mut conn,
+        )
+    })
+    .await?
+}
@@ -0,0 +1,32 @@
+use actix_web::{web, HttpResponse};
+
+use crate::{
+    errors::DomainError, models::presence::PresenceQuery, utils::presence,
+    AppData,
+};
+
+/// Gets the online status and last-seen time of users
+///
+/// # Arguments
+///
+/// * `query` - `user_ids`, the comma separated ids of at most 100 users
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with the presence of
+///   each user, in the order of their ids
+#[tracing::instrument(level = "info", skip(app_data))]
+pub async fn handle_get_presence(
+    app_data: web::Data<AppData>,
+    query: web::Query<PresenceQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let user_ids =
+        query.user_ids().map_err(DomainError::new_bad_input_error)?;
+
+    let mut conn = app_data.redis_conn_manager.clone();
+    let presence =
+        presence::get_presence(&mut conn, &app_data.redis_prefix, &user_ids)
+            .await?;
+
+    Ok(HttpResponse::Ok().json(presence))
+}
@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
+use rand::{distr::Alphanumeric, RngExt};
+
+use crate::{
+    actions,
+    errors::DomainError,
+    metrics::JobTemplate,
+    models::{
+        misc::JobStatus,
+        roles::RoleEnum,
+        runner::{
+            hash_runner_token, validate_label, validate_labels, ClaimJobQuery,
+            CompleteRunnerJobRequest, CreateRunnerRequest, CreatedRunner,
+            NewRunner, Runner, RunnerHeartbeat, RunnerJob, RunnerJobAssignment,
+            RunnerJobControl, MAX_RUNNER_NAME_LENGTH, RUNNER_TOKEN_HEADER,
+            RUNNER_TOKEN_LENGTH,
+        },
+        webhook::JobEvent,
+        ws::MyProcessItem,
+    },
+    routes::command::{parse_job_id, RunCommandRequest},
+    utils::{self, job_output::publish_job_output, webhooks::notify_job_event},
+    AppData,
+};
+
+/// Lists the registered runners. Only available to admins.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn handle_list_runners(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+
+    let pool = app_data.pool.clone();
+    let runners = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::get_runners(&mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(runners))
+}
+
+/// Registers a runner. Only available to admins.
+///
+/// # Arguments
+///
+/// * `payload` - The unique `name` of the runner and the `labels` it offers,
+///   a job is only handed to runners with all of the labels it `runs_on`
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 201 with the runner and the
+///   token it authenticates with. The token is only returned here.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_create_runner(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    payload: web::Json<CreateRunnerRequest>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let payload = payload.into_inner();
+    validate_label(&payload.name, MAX_RUNNER_NAME_LENGTH)
+        .map_err(DomainError::new_bad_input_error)?;
+    let labels = validate_labels(&payload.labels)
+        .map_err(DomainError::new_bad_input_error)?;
+
+    let token = generate_runner_token();
+    let new_runner = NewRunner {
+        name: payload.name,
+        token_hash: hash_runner_token(&token),
+        labels,
+        created_by: user_id,
+    };
+
+    let pool = app_data.pool.clone();
+    let runner = web::block(move || {
+        let mut conn = pool.get()?;
+        if actions::runners::get_runner_by_name(&new_runner.name, &mut conn)?
+            .is_some()
+        {
+            return Err(DomainError::new_bad_input_error(format!(
+                "Runner {} already exists",
+                new_runner.name
+            )));
+        }
+        actions::runners::create_runner(&new_runner, &mut conn)
+    })
+    .await??;
+
+    let _ = tracing::info!("Registered runner {} ({})", runner.name, runner.id);
+
+    Ok(HttpResponse::Created().json(CreatedRunner { runner, token }))
+}
+
+/// Removes a runner, the jobs it was running go back to the queue
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn handle_delete_runner(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    runner_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    ensure_admin(&auth)?;
+    let runner_id = runner_id.into_inner();
+
+    let pool = app_data.pool.clone();
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::delete_runner(runner_id, &mut conn)
+    })
+    .await??;
+
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(DomainError::new_entity_does_not_exist_error(format!(
+            "No runner with id: {runner_id}"
+        )))
+    }
+}
+
+/// Hands the oldest queued job matching its labels to the calling runner
+///
+/// # Arguments
+///
+/// * `query` - How long to wait for a job with `wait_secs` when none is
+///   queued, at most 30s
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with the job to run or
+///   HTTP 204 when there was none
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_claim_runner_job(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    query: web::Query<ClaimJobQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let runner = authenticate_runner(&req, &app_data).await?;
+    let deadline =
+        tokio::time::Instant::now() + Duration::from_secs(query.wait_secs());
+
+    let runner_job = loop {
+        let pool = app_data.pool.clone();
+        let runner2 = runner.clone();
+        let claimed = web::block(move || {
+            let mut conn = pool.get()?;
+            let now = chrono::Utc::now().naive_utc();
+            actions::runners::set_runner_heartbeat(runner2.id, now, &mut conn)?;
+            actions::runners::claim_runner_job(&runner2, now, &mut conn)
+        })
+        .await??;
+        match claimed {
+            Some(runner_job) => break runner_job,
+            None if tokio::time::Instant::now() >= deadline => {
+                return Ok(HttpResponse::NoContent().finish());
+            }
+            None => actix_rt::time::sleep(Duration::from_secs(1)).await,
+        }
+    };
+    let job_id = runner_job.job_id;
+
+    let pool = app_data.pool.clone();
+    let request = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::misc::get_job_request(job_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_internal_error(format!(
+            "Job {job_id} was queued without a request"
+        ))
+    })?;
+    let request = serde_json::from_value::<RunCommandRequest>(request)
+        .map_err(|err| DomainError::new_internal_error(err.to_string()))?;
+
+    let _ = tracing::info!("Runner {} claimed job {job_id}", runner.name);
+    app_data.metrics.jobs.job_started(JobTemplate::Runner);
+    publish_job_output(
+        &app_data,
+        job_id,
+        &MyProcessItem::Line {
+            value: format!("Running on runner {}", runner.name),
+        },
+    )
+    .await?;
+    notify_job_event(&app_data, job_id, JobEvent::Started).await;
+
+    Ok(HttpResponse::Ok().json(RunnerJobAssignment {
+        job_id,
+        args: request.args,
+        pty: request.pty,
+    }))
+}
+
+/// Publishes output of a job the calling runner is running to the job's
+/// channel, from where it reaches the subscribed clients
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with whether the job was
+///   aborted and the runner should stop it
+#[tracing::instrument(level = "info", skip(app_data, req, payload))]
+pub async fn handle_runner_job_output(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+    payload: web::Json<Vec<MyProcessItem>>,
+) -> Result<HttpResponse, DomainError> {
+    let runner = authenticate_runner(&req, &app_data).await?;
+    let runner_job =
+        fetch_claimed_job(&runner, job_id.into_inner(), &app_data).await?;
+
+    // the exit code is only published once the runner completes the job
+    for item in payload
+        .into_inner()
+        .iter()
+        .filter(|item| !matches!(item, MyProcessItem::Done { .. }))
+    {
+        app_data.metrics.jobs.job_output(JobTemplate::Runner, item);
+        publish_job_output(&app_data, runner_job.job_id, item).await?;
+    }
+
+    Ok(HttpResponse::Ok().json(RunnerJobControl {
+        abort: runner_job.abort_requested,
+    }))
+}
+
+/// Records the exit code of a job the calling runner finished and sets the
+/// final status of the job, unless it was aborted
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_complete_runner_job(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    job_id: web::Path<String>,
+    payload: web::Json<CompleteRunnerJobRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let runner = authenticate_runner(&req, &app_data).await?;
+    let runner_job =
+        fetch_claimed_job(&runner, job_id.into_inner(), &app_data).await?;
+    let job_id = runner_job.job_id;
+    let code = payload.exit_code;
+
+    publish_job_output(
+        &app_data,
+        job_id,
+        &MyProcessItem::Done {
+            code: code.to_string(),
+        },
+    )
+    .await?;
+
+    let (status, msg) = if code != 0 {
+        (
+            JobStatus::Failed,
+            Some(format!("Process failed with exit code: {code}")),
+        )
+    } else {
+        (JobStatus::Completed, None)
+    };
+    let pool = app_data.pool.clone();
+    let status = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::complete_runner_job(
+            job_id,
+            status,
+            msg,
+            chrono::Utc::now().naive_utc(),
+            &mut conn,
+        )
+    })
+    .await??;
+    let _ = tracing::info!(
+        "Runner {} finished job {job_id} with code {code}: {status:?}",
+        runner.name
+    );
+    let elapsed = runner_job.claimed_at.and_then(|claimed_at| {
+        (chrono::Utc::now().naive_utc() - claimed_at).to_std().ok()
+    });
+    app_data
+        .metrics
+        .jobs
+        .job_finished(JobTemplate::Runner, &status, elapsed);
+    if status != JobStatus::Aborted {
+        if let Some(event) = JobEvent::for_final_status(&status) {
+            notify_job_event(&app_data, job_id, event).await;
+        }
+    }
+    app_data.notifier.notify_job_finished(job_id, status).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Keeps the calling runner alive while it runs jobs
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 200 with the jobs of the
+///   runner that were aborted and should be stopped
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_runner_heartbeat(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let runner = authenticate_runner(&req, &app_data).await?;
+
+    let pool = app_data.pool.clone();
+    let abort = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::set_runner_heartbeat(
+            runner.id,
+            chrono::Utc::now().naive_utc(),
+            &mut conn,
+        )?;
+        actions::runners::get_aborted_runner_jobs(runner.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(RunnerHeartbeat { abort }))
+}
+
+fn generate_runner_token() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(RUNNER_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn ensure_admin(auth: &AuthDetails<RoleEnum>) -> Result<(), DomainError> {
+    if utils::is_admin(auth) {
+        Ok(())
+    } else {
+        Err(DomainError::new_auth_error(
+            "Forbidden: Only admins can manage runners".to_owned(),
+        ))
+    }
+}
+
+/// Looks up the runner by the token in the runner token header
+async fn authenticate_runner(
+    req: &HttpRequest,
+    app_data: &AppData,
+) -> Result<Runner, DomainError> {
+    let token = req
+        .headers()
+        .get(RUNNER_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| {
+            DomainError::new_auth_error("Missing runner token".to_owned())
+        })?;
+    let token_hash = hash_runner_token(token);
+
+    let pool = app_data.pool.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::get_runner_by_token_hash(&token_hash, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_auth_error("Invalid runner token".to_owned())
+    })
+}
+
+/// Fetches an unfinished job claimed by the runner
+async fn fetch_claimed_job(
+    runner: &Runner,
+    job_id: String,
+    app_data: &AppData,
+) -> Result<RunnerJob, DomainError> {
+    let job_id = parse_job_id(job_id)?;
+    let runner_id = runner.id;
+
+    let pool = app_data.pool.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::get_claimed_runner_job(job_id, runner_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_entity_does_not_exist_error(format!(
+            "Job {job_id} is not running on runner {}",
+            runner.name
+        ))
+    })
+}
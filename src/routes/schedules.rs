@@ -0,0 +1,267 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        artifact::validate_artifact_patterns,
+        roles::RoleEnum,
+        schedule::{
+            CreateJobScheduleRequest, JobSchedule, JobScheduleChangeset,
+            NewJobSchedule, UpdateJobScheduleRequest, MAX_SCHEDULE_NAME_LENGTH,
+        },
+    },
+    utils, AppData,
+};
+
+/// Lists job schedules. Regular users see their own schedules, admins see all
+/// of them.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_list_job_schedules(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let created_by = (!utils::is_admin(&auth)).then_some(user_id);
+
+    let pool = app_data.pool.clone();
+    let schedules = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::get_job_schedules(created_by, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(schedules))
+}
+
+/// Creates a job schedule that runs the job command as the calling user
+///
+/// # Arguments
+///
+/// * `payload` - Schedule `name`, `cron_expression` evaluated in the app
+///   timezone, command `args` and `pty` flag, `enabled` flag,
+///   `missed_run_policy`, the `artifacts` patterns and the `retry` policy of
+///   its jobs
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 201 with the schedule,
+///   including its next run
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_create_job_schedule(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    payload: web::Json<CreateJobScheduleRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let payload = payload.into_inner();
+    let name = validate_schedule_name(payload.name)?;
+    validate_artifact_patterns(&payload.artifacts)
+        .map_err(DomainError::new_bad_input_error)?;
+    if let Some(retry) = &payload.retry {
+        retry.validate().map_err(DomainError::new_bad_input_error)?;
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let next_run_at = payload
+        .enabled
+        .then(|| {
+            payload
+                .cron_expression
+                .next_run_after(now, app_data.config.timezone)
+        })
+        .flatten();
+
+    let new_schedule = NewJobSchedule {
+        name,
+        cron_expression: payload.cron_expression.into(),
+        args: payload.args,
+        pty: payload.pty,
+        created_by: user_id,
+        enabled: payload.enabled,
+        missed_run_policy: payload.missed_run_policy,
+        next_run_at,
+        artifacts: payload.artifacts,
+        retry: payload.retry.as_ref().map(utils::jvalue),
+    };
+
+    let pool = app_data.pool.clone();
+    let schedule = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::create_job_schedule(&new_schedule, &mut conn)
+    })
+    .await??;
+
+    let _ = tracing::info!(
+        "Created job schedule {} with next run at {:?}",
+        schedule.id,
+        schedule.next_run_at
+    );
+
+    Ok(HttpResponse::Created().json(schedule))
+}
+
+/// Returns a job schedule with its last and next run
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_get_job_schedule(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    schedule_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    let schedule = fetch_owned_job_schedule(
+        &req,
+        &auth,
+        schedule_id.into_inner(),
+        &app_data,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(schedule))
+}
+
+/// Updates a job schedule. Changing the cron expression or enabling the
+/// schedule recomputes its next run, disabling it clears the next run.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_update_job_schedule(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    schedule_id: web::Path<i32>,
+    payload: web::Json<UpdateJobScheduleRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let schedule = fetch_owned_job_schedule(
+        &req,
+        &auth,
+        schedule_id.into_inner(),
+        &app_data,
+    )
+    .await?;
+    let payload = payload.into_inner();
+
+    let name = payload.name.map(validate_schedule_name).transpose()?;
+    if let Some(artifacts) = &payload.artifacts {
+        validate_artifact_patterns(artifacts)
+            .map_err(DomainError::new_bad_input_error)?;
+    }
+    if let Some(retry) = &payload.retry {
+        retry.validate().map_err(DomainError::new_bad_input_error)?;
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let enabled = payload.enabled.unwrap_or(schedule.enabled);
+    // Disabled schedules have no next run, enabled ones are recomputed when
+    // their expression changes or when they get enabled again
+    let next_run_at = if !enabled {
+        Some(None)
+    } else if payload.cron_expression.is_some() || !schedule.enabled {
+        let expr = match &payload.cron_expression {
+            Some(expr) => Ok(expr.clone()),
+            None => schedule.cron_expression(),
+        };
+        Some(expr.ok().and_then(|expr| {
+            expr.next_run_after(now, app_data.config.timezone)
+        }))
+    } else {
+        None
+    };
+
+    let changeset = JobScheduleChangeset {
+        name,
+        cron_expression: payload.cron_expression.map(Into::into),
+        args: payload.args,
+        pty: payload.pty,
+        enabled: payload.enabled,
+        missed_run_policy: payload.missed_run_policy,
+        next_run_at,
+        updated_at: Some(now),
+        artifacts: payload.artifacts,
+        retry: payload
+            .retry
+            .as_ref()
+            .map(|retry| Some(utils::jvalue(retry))),
+    };
+
+    let pool = app_data.pool.clone();
+    let schedule = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::update_job_schedule(
+            schedule.id,
+            &changeset,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(schedule))
+}
+
+/// Deletes a job schedule. Jobs it already started are kept.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_delete_job_schedule(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    schedule_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    let schedule = fetch_owned_job_schedule(
+        &req,
+        &auth,
+        schedule_id.into_inner(),
+        &app_data,
+    )
+    .await?;
+
+    let pool = app_data.pool.clone();
+    let _ = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::delete_job_schedule(schedule.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn validate_schedule_name(name: String) -> Result<String, DomainError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_SCHEDULE_NAME_LENGTH {
+        Err(DomainError::new_field_validation_error(format!(
+            "Schedule name must be between 1 and {MAX_SCHEDULE_NAME_LENGTH} bytes long"
+        )))
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+/// Fetches a schedule the caller is allowed to manage, i.e. their own
+/// schedule or any schedule when the caller is an admin
+async fn fetch_owned_job_schedule(
+    req: &HttpRequest,
+    auth: &AuthDetails<RoleEnum>,
+    schedule_id: i32,
+    app_data: &AppData,
+) -> Result<JobSchedule, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
+    let pool = app_data.pool.clone();
+    let schedule = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::get_job_schedule(schedule_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_entity_does_not_exist_error(format!(
+            "No job schedule with id: {schedule_id}"
+        ))
+    })?;
+
+    if schedule.created_by == user_id || utils::is_admin(auth) {
+        Ok(schedule)
+    } else {
+        Err(DomainError::new_auth_error(format!(
+            "Forbidden: Not allowed to manage job schedule {schedule_id}"
+        )))
+    }
+}
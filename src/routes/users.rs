@@ -3,13 +3,15 @@ use awc::cookie::{Cookie, SameSite};
 use time::OffsetDateTime;
 
 use crate::models::misc::{Pagination, SearchQuery};
+use crate::models::presence::PublicUserProfile;
 // use crate::models::roles::RoleEnum;
 use crate::models::users::{NewUser, UpdateUserProfile, UserId};
 use crate::{actions, utils};
 use crate::{errors::DomainError, AppData};
 // use actix_web_grants::protect;
 
-/// Finds user by UID.
+/// Finds user by UID, along with whether they are online and when they
+/// were last seen.
 #[tracing::instrument(level = "info", skip(app_data))]
 // #[protect("RoleEnum::RoleAdmin", ty = "RoleEnum")]
 pub async fn get_user(
@@ -19,8 +21,8 @@ pub async fn get_user(
     let user_id = user_id.into_inner();
     let _ = tracing::info!("Getting user with id {user_id}");
     // use web::block to offload blocking Diesel code without blocking server thread
+    let pool = app_data.pool.clone();
     let res = web::block(move || {
-        let pool = &app_data.pool;
         let mut conn = pool.get()?;
         actions::users::find_user_by_uid(&user_id, &mut conn)
     })
@@ -28,7 +30,29 @@ pub async fn get_user(
     let _ = tracing::debug!("{:?}", res);
     if let Some(user) = res {
         let _ = tracing::info!("Found user");
-        Ok(HttpResponse::Ok().json(user))
+        let mut conn = app_data.redis_conn_manager.clone();
+        // the profile is still served without presence while Redis is down
+        let presence = match utils::presence::get_presence(
+            &mut conn,
+            &app_data.redis_prefix,
+            &[user_id],
+        )
+        .await
+        {
+            Ok(presence) => presence,
+            Err(err) => {
+                let _ = tracing::warn!(
+                    "Failed to get presence of user {user_id}: {err:?}"
+                );
+                Vec::new()
+            }
+        };
+        let presence = presence.first();
+        Ok(HttpResponse::Ok().json(PublicUserProfile {
+            user,
+            online: presence.is_some_and(|presence| presence.online),
+            last_seen_at: presence.and_then(|presence| presence.last_seen_at),
+        }))
     } else {
         let _ = tracing::warn!("Could not find user");
         let err = DomainError::new_entity_does_not_exist_error(format!(
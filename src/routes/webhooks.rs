@@ -0,0 +1,246 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        roles::RoleEnum,
+        webhook::{
+            validate_webhook_events, validate_webhook_url,
+            CreateWebhookRequest, CreatedWebhook, NewWebhook,
+            UpdateWebhookRequest, Webhook, WebhookChangeset,
+            WebhookDeliveriesQuery, MAX_WEBHOOKS_PER_USER,
+        },
+    },
+    utils, AppData,
+};
+
+/// Lists webhooks. Regular users see their own webhooks, admins see all of
+/// them.
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_list_webhooks(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let owner = (!utils::is_admin(&auth)).then_some(user_id);
+
+    let pool = app_data.pool.clone();
+    let webhooks = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::get_webhooks(owner, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// Registers a webhook notified about the lifecycle events of the caller's
+/// jobs
+///
+/// # Arguments
+///
+/// * `payload` - The `url` to POST events to, the `events` to send
+///   (`created`, `started`, `completed`, `failed`, `aborted`) and the
+///   `enabled` flag
+///
+/// # Returns
+///
+/// * `Result<HttpResponse, DomainError>` - HTTP 201 with the webhook and the
+///   secret its deliveries are signed with. The secret is only returned here.
+#[tracing::instrument(level = "info", skip(app_data, req))]
+pub async fn handle_create_webhook(
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+    payload: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+    let payload = payload.into_inner();
+    let url = validate_webhook_url(&payload.url)
+        .map_err(DomainError::new_bad_input_error)?;
+    utils::webhooks::check_webhook_target(
+        &url,
+        app_data.config.webhooks.allow_private_targets,
+    )
+    .await
+    .map_err(DomainError::new_bad_input_error)?;
+    let events = validate_webhook_events(payload.events)
+        .map_err(DomainError::new_bad_input_error)?;
+
+    let secret = utils::webhooks::generate_webhook_secret();
+    let new_webhook = NewWebhook {
+        user_id,
+        url,
+        secret: secret.clone(),
+        events,
+        enabled: payload.enabled,
+    };
+
+    let pool = app_data.pool.clone();
+    let webhook = web::block(move || {
+        let mut conn = pool.get()?;
+        let count =
+            actions::webhooks::count_webhooks_by_user(user_id, &mut conn)?;
+        if count >= i64::try_from(MAX_WEBHOOKS_PER_USER).unwrap_or(i64::MAX) {
+            return Err(DomainError::new_bad_input_error(format!(
+                "At most {MAX_WEBHOOKS_PER_USER} webhooks are allowed per user"
+            )));
+        }
+        actions::webhooks::create_webhook(&new_webhook, &mut conn)
+    })
+    .await??;
+
+    let _ = tracing::info!("Created webhook {} for user {user_id}", webhook.id);
+
+    Ok(HttpResponse::Created().json(CreatedWebhook { webhook, secret }))
+}
+
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_get_webhook(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    webhook_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    let webhook =
+        fetch_owned_webhook(&req, &auth, webhook_id.into_inner(), &app_data)
+            .await?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+/// Updates the URL, events or enabled flag of a webhook
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_update_webhook(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    webhook_id: web::Path<i32>,
+    payload: web::Json<UpdateWebhookRequest>,
+) -> Result<HttpResponse, DomainError> {
+    let webhook =
+        fetch_owned_webhook(&req, &auth, webhook_id.into_inner(), &app_data)
+            .await?;
+    let payload = payload.into_inner();
+
+    let url = payload
+        .url
+        .as_deref()
+        .map(validate_webhook_url)
+        .transpose()
+        .map_err(DomainError::new_bad_input_error)?;
+    if let Some(url) = &url {
+        utils::webhooks::check_webhook_target(
+            url,
+            app_data.config.webhooks.allow_private_targets,
+        )
+        .await
+        .map_err(DomainError::new_bad_input_error)?;
+    }
+    let events = payload
+        .events
+        .map(validate_webhook_events)
+        .transpose()
+        .map_err(DomainError::new_bad_input_error)?;
+
+    let changeset = WebhookChangeset {
+        url,
+        events,
+        enabled: payload.enabled,
+        updated_at: Some(chrono::Utc::now().naive_utc()),
+    };
+
+    let pool = app_data.pool.clone();
+    let webhook = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::update_webhook(webhook.id, &changeset, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+/// Deletes a webhook along with its pending deliveries and delivery log
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_delete_webhook(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    webhook_id: web::Path<i32>,
+) -> Result<HttpResponse, DomainError> {
+    let webhook =
+        fetch_owned_webhook(&req, &auth, webhook_id.into_inner(), &app_data)
+            .await?;
+
+    let pool = app_data.pool.clone();
+    let _ = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::delete_webhook(webhook.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lists the latest deliveries of a webhook with their status, number of
+/// attempts and the response or error of the last attempt
+///
+/// # Arguments
+///
+/// * `query` - Number of deliveries to return with `limit`, 50 by default
+///   and at most 200
+#[tracing::instrument(level = "info", skip(app_data, req, auth))]
+pub async fn handle_list_webhook_deliveries(
+    req: HttpRequest,
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+    webhook_id: web::Path<i32>,
+    query: web::Query<WebhookDeliveriesQuery>,
+) -> Result<HttpResponse, DomainError> {
+    let webhook =
+        fetch_owned_webhook(&req, &auth, webhook_id.into_inner(), &app_data)
+            .await?;
+    let limit = query.limit();
+
+    let pool = app_data.pool.clone();
+    let deliveries = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::get_webhook_deliveries(webhook.id, limit, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// Fetches a webhook the caller is allowed to manage, i.e. their own webhook
+/// or any webhook when the caller is an admin
+async fn fetch_owned_webhook(
+    req: &HttpRequest,
+    auth: &AuthDetails<RoleEnum>,
+    webhook_id: i32,
+    app_data: &AppData,
+) -> Result<Webhook, DomainError> {
+    let user_id = utils::extract_user_id_from_header(req.headers())?;
+
+    let pool = app_data.pool.clone();
+    let webhook = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::get_webhook(webhook_id, &mut conn)
+    })
+    .await??
+    .ok_or_else(|| {
+        DomainError::new_entity_does_not_exist_error(format!(
+            "No webhook with id: {webhook_id}"
+        ))
+    })?;
+
+    if webhook.user_id == user_id || utils::is_admin(auth) {
+        Ok(webhook)
+    } else {
+        Err(DomainError::new_auth_error(format!(
+            "Forbidden: Not allowed to manage webhook {webhook_id}"
+        )))
+    }
+}
@@ -0,0 +1,28 @@
+use actix_web::{web, HttpResponse};
+use actix_web_grants::authorities::AuthDetails;
+
+use crate::{
+    errors::DomainError, models::roles::RoleEnum, utils, workers, AppData,
+};
+
+/// Lists the background workers with their leader and the outcome of their
+/// latest runs. Only available to admins.
+#[tracing::instrument(level = "info", skip(app_data, auth))]
+pub async fn handle_get_workers(
+    auth: AuthDetails<RoleEnum>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, DomainError> {
+    if !utils::is_admin(&auth) {
+        return Err(DomainError::new_auth_error(
+            "Forbidden: Only admins can view workers".to_owned(),
+        ));
+    }
+
+    let statuses = workers::load_worker_statuses(
+        &mut app_data.redis_conn_manager.clone(),
+        &(app_data.redis_prefix)(&"workers"),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(statuses))
+}
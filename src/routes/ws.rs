@@ -1,7 +1,11 @@
 use std::{cell::RefCell, rc::Rc, time::Duration};
 
-use crate::{errors::DomainError, utils, AppData};
-use actix_http::header::HeaderMap;
+use crate::{
+    errors::DomainError,
+    models::ws::{ws_protocol_schema, WsProtocol},
+    utils, AppData,
+};
+use actix_http::header::{self, HeaderMap, HeaderValue};
 use actix_rt::time::sleep;
 use actix_web::{web, HttpRequest, HttpResponse};
 
@@ -13,12 +17,14 @@ use super::auth::validate_token;
 ///
 /// # Flow
 /// 1. Extracts and validates authentication token from request headers
-/// 2. Establishes WebSocket connection
-/// 3. Spawns three concurrent workers:
+/// 2. Negotiates the protocol version, v1 unless the client offers others
+///    in the `Sec-WebSocket-Protocol` header
+/// 3. Establishes WebSocket connection
+/// 4. Spawns three concurrent workers:
 ///    - Message receiver: Handles incoming messages from Redis
 ///    - WebSocket loop: Manages WebSocket communication
-///    - Heartbeat: Maintains connection health
-/// 4. Returns HTTP response with established WebSocket connection
+///    - Heartbeat: Maintains connection health and the presence of the user
+/// 5. Returns HTTP response with established WebSocket connection
 #[tracing::instrument(
     level = "info",
     skip_all,
@@ -74,14 +80,38 @@ pub async fn ws(
         );
     }
 
-    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let offered = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .map(|offered| offered.to_str().unwrap_or_default());
+    let protocol = match offered {
+        None => WsProtocol::V1,
+        Some(offered) => WsProtocol::negotiate(offered).ok_or_else(|| {
+            DomainError::new_bad_input_error(format!(
+                "Unsupported protocols {offered:?}, supported are {:?}",
+                WsProtocol::ALL.map(WsProtocol::as_str)
+            ))
+        })?,
+    };
+
+    let (mut response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    if offered.is_some() {
+        let _ = response.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(protocol.as_str()),
+        );
+    }
 
     let _ = tracing::info!(
-        "Websocket connection established for user {user_id} on device {device_id}"
+        "Websocket connection established for user {user_id} on device {device_id} with protocol {}",
+        protocol.as_str()
     );
 
     let session2 = session.clone();
-    let cm = utils::get_new_redis_conn(app_data.clone().into_inner()).await?;
+    let cm = utils::get_blocking_redis_conn(
+        app_data.redis_conn_factory.clone(),
+        utils::ws::MESSAGE_READ_BLOCK,
+    )
+    .await?;
 
     let _ = tracing::info!("Connected to Redis");
 
@@ -89,12 +119,18 @@ pub async fn ws(
     let handles = Rc::new(RefCell::new(Vec::new()));
 
     let _ = tracing::info!("Starting message receiver for user {user_id}");
+    let device_id_receiver = device_id.clone();
     let msg_receiver = Rc::new(actix_rt::spawn(
         async move {
             let _ = tracing::debug!("Entering message receive loop");
-            let res =
-                utils::ws::msg_receive_loop(user_id, cm, session2, app_data2)
-                    .await;
+            let res = utils::ws::msg_receive_loop(
+                user_id,
+                device_id_receiver,
+                cm,
+                session2,
+                app_data2,
+            )
+            .await;
             let _ = match res {
                 Ok(_) => {
                     let _ = tracing::info!("Message receive loop ended successfully for user {user_id}");
@@ -119,6 +155,9 @@ pub async fn ws(
 
     // Store device_id for use in the heartbeat
     let device_id_clone = device_id.clone();
+    let presence =
+        utils::presence::PresenceConnection::new(user_id, &device_id);
+    let app_data_hb = app_data.clone().into_inner();
 
     // Handles WebSocket communication with the client
     //
@@ -128,11 +167,19 @@ pub async fn ws(
     let ws_loop = Rc::new(actix_rt::spawn(
         async move {
             tracing::info!("Starting websocket loop for user {} on device {}", user_id, device_id_clone);
+            // the user stays online while the loop runs, also when it is aborted
+            let _presence = utils::presence::PresenceGuard::register(
+                app_data.clone().into_inner(),
+                presence,
+            )
+            .await;
             let res = utils::ws::ws_loop(
                 session2.clone(),
                 msg_stream,
                 &mut pub_cm,
                 user_id,
+                &device_id_clone,
+                protocol,
                 app_data.into_inner().clone(),
             )
             .await;
@@ -174,11 +221,15 @@ pub async fn ws(
     // 2. Detects connection failures
     // 3. Cleans up resources on connection loss
     // 4. Refreshes the session TTL
+    // 5. Stops once the server shuts down
     let _hb = actix_rt::spawn(
         async move {
             let _ = tracing::debug!("Starting heartbeat for user {} on device {}", user_id, device_id_clone);
             loop {
-                sleep(Duration::from_secs(30)).await;
+                tokio::select! {
+                    _ = app_data_hb.shutdown.cancelled() => break,
+                    _ = sleep(Duration::from_secs(30)) => {}
+                }
                 // Refresh session TTL on each heartbeat
                 let refresh_result = credentials_repo_clone.update_session_last_used_ws(&user_id, &session_id).await;
                 if let Err(err) = refresh_result {
@@ -203,6 +254,12 @@ pub async fn ws(
     Ok(response)
 }
 
+/// JSON Schema of the frames of the WebSocket protocol, for generating
+/// client types
+pub async fn handle_get_ws_schema() -> HttpResponse {
+    HttpResponse::Ok().json(ws_protocol_schema())
+}
+
 /// Extract the X-AUTH-TOKEN from the "cookie" header in the given HttpRequest.
 pub fn extract_auth_token(headers: &HeaderMap) -> Result<String, DomainError> {
     // Get the raw cookie header string
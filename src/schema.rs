@@ -1,13 +1,113 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "conversation_role"))]
+    pub struct ConversationRole;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "job_status"))]
     pub struct JobStatus;
 
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "missed_run_policy"))]
+    pub struct MissedRunPolicy;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "notification_kind"))]
+    pub struct NotificationKind;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "role_name"))]
     pub struct RoleName;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "step_failure_policy"))]
+    pub struct StepFailurePolicy;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "webhook_delivery_status"))]
+    pub struct WebhookDeliveryStatus;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ConversationRole;
+
+    conversation_members (conversation_id, user_id) {
+        conversation_id -> Int4,
+        user_id -> Int4,
+        role -> ConversationRole,
+        joined_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    conversation_messages (id) {
+        id -> Int4,
+        conversation_id -> Int4,
+        sender_id -> Nullable<Int4>,
+        message_text -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    conversations (id) {
+        id -> Int4,
+        name -> Varchar,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::RoleName;
+
+    job_acl (id) {
+        id -> Int4,
+        job_id -> Uuid,
+        user_id -> Nullable<Int4>,
+        role_name -> Nullable<RoleName>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    job_artifacts (id) {
+        id -> Int4,
+        job_id -> Uuid,
+        path -> Varchar,
+        object_key -> Varchar,
+        size_bytes -> Int8,
+        content_type -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MissedRunPolicy;
+
+    job_schedules (id) {
+        id -> Int4,
+        name -> Varchar,
+        cron_expression -> Varchar,
+        args -> Array<Text>,
+        pty -> Bool,
+        created_by -> Int4,
+        enabled -> Bool,
+        missed_run_policy -> MissedRunPolicy,
+        last_run_at -> Nullable<Timestamp>,
+        last_job_id -> Nullable<Uuid>,
+        next_run_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        artifacts -> Array<Text>,
+        retry -> Nullable<Jsonb>,
+    }
 }
 
 diesel::table! {
@@ -21,6 +121,54 @@ diesel::table! {
         status -> JobStatus,
         status_message -> Nullable<Varchar>,
         created_at -> Timestamp,
+        attempt -> Int4,
+        retry_of -> Nullable<Uuid>,
+        idempotency_key -> Nullable<Varchar>,
+        request -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    messages (id) {
+        id -> Int4,
+        sender_id -> Int4,
+        receiver_id -> Int4,
+        message_text -> Text,
+        created_at -> Timestamp,
+        is_read -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::NotificationKind;
+
+    notifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> NotificationKind,
+        data -> Jsonb,
+        created_at -> Timestamp,
+        read_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::StepFailurePolicy;
+
+    pipeline_steps (id) {
+        id -> Int4,
+        pipeline_job_id -> Uuid,
+        job_id -> Uuid,
+        name -> Varchar,
+        args -> Array<Text>,
+        pty -> Bool,
+        depends_on -> Array<Text>,
+        on_failure -> StepFailurePolicy,
+        output -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        artifacts -> Array<Text>,
     }
 }
 
@@ -34,6 +182,31 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    runner_jobs (job_id) {
+        job_id -> Uuid,
+        labels -> Array<Text>,
+        runner_id -> Nullable<Int4>,
+        claimed_at -> Nullable<Timestamp>,
+        requeues -> Int4,
+        abort_requested -> Bool,
+        finished_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    runners (id) {
+        id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        labels -> Array<Text>,
+        created_by -> Int4,
+        last_heartbeat_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -52,8 +225,79 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WebhookDeliveryStatus;
+
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        job_id -> Uuid,
+        event -> Varchar,
+        payload -> Jsonb,
+        status -> WebhookDeliveryStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        user_id -> Int4,
+        url -> Varchar,
+        secret -> Varchar,
+        events -> Array<Text>,
+        enabled -> Bool,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    worker_fences (worker) {
+        worker -> Varchar,
+        fencing_token -> Int8,
+    }
+}
+
+diesel::joinable!(conversation_members -> conversations (conversation_id));
+diesel::joinable!(conversation_members -> users (user_id));
+diesel::joinable!(conversation_messages -> conversations (conversation_id));
+diesel::joinable!(conversation_messages -> users (sender_id));
+diesel::joinable!(conversations -> users (created_by));
+diesel::joinable!(job_acl -> users (user_id));
+diesel::joinable!(job_schedules -> users (created_by));
 diesel::joinable!(jobs -> users (started_by));
+diesel::joinable!(notifications -> users (user_id));
+diesel::joinable!(runner_jobs -> runners (runner_id));
+diesel::joinable!(runners -> users (created_by));
 diesel::joinable!(users_roles -> roles (role_id));
 diesel::joinable!(users_roles -> users (user_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(jobs, roles, users, users_roles,);
+diesel::allow_tables_to_appear_in_same_query!(
+    conversation_members,
+    conversation_messages,
+    conversations,
+    job_acl,
+    job_artifacts,
+    job_schedules,
+    jobs,
+    messages,
+    notifications,
+    pipeline_steps,
+    roles,
+    runner_jobs,
+    runners,
+    users,
+    users_roles,
+    webhook_deliveries,
+    webhooks,
+    worker_fences,
+);
@@ -1,8 +1,15 @@
 // pub mod broadcast_demo;
 pub mod instrumented_redis_cache;
+pub mod job_artifacts;
+pub mod job_output;
+pub mod job_process;
+pub mod notifier;
+pub mod presence;
 pub mod redis_channel_reader;
 pub mod redis_credentials_repo;
 pub mod regex;
+pub mod shutdown;
+pub mod webhooks;
 pub mod ws;
 pub use self::instrumented_redis_cache::InstrumentedRedisCache;
 
@@ -14,6 +21,7 @@ use actix_web::web;
 use mime::Mime;
 
 use actix_http::header::HeaderMap;
+use actix_web_grants::authorities::{AuthDetails, AuthoritiesCheck};
 use futures::StreamExt;
 use jwt_simple::claims::JWTClaims;
 use jwt_simple::prelude::*;
@@ -21,6 +29,7 @@ use redis::aio::ConnectionManager;
 use serde::Serialize;
 
 use crate::errors::DomainError;
+use crate::models::roles::RoleEnum;
 use crate::models::users::UserId;
 use crate::routes::auth::VerifiedAuthDetails;
 use crate::AppData;
@@ -42,6 +51,18 @@ pub async fn get_new_redis_conn(
     Ok(ConnectionManager::new(client).await?)
 }
 
+/// A connection for blocking reads like `XREAD BLOCK`, its responses are
+/// allowed to take up to `block` longer than usual. Blocking commands stall
+/// everything else sent on the connection, so it should not be shared.
+pub async fn get_blocking_redis_conn(
+    client: redis::Client,
+    block: std::time::Duration,
+) -> Result<ConnectionManager, DomainError> {
+    let config = redis::aio::ConnectionManagerConfig::new()
+        .set_response_timeout(Some(block + std::time::Duration::from_secs(5)));
+    Ok(ConnectionManager::new_with_config(client, config).await?)
+}
+
 pub fn get_redis_prefix<T: Display>(
     prefix: T,
 ) -> impl Fn(&dyn Display) -> String {
@@ -55,6 +76,13 @@ where
     serde_json::to_string(value).expect("failed to serialize {value}")
 }
 
+pub fn jvalue<T>(value: &T) -> serde_json::Value
+where
+    T: ?Sized + Serialize,
+{
+    serde_json::to_value(value).expect("failed to serialize {value}")
+}
+
 pub fn from_str<'a, T, F>(value: &'a str, mk_default: F) -> T
 where
     T: serde::Deserialize<'a>,
@@ -193,3 +221,9 @@ pub fn extract_user_id_from_header(
         })
     })
 }
+
+/// Whether the authenticated user holds an administrative role
+pub fn is_admin(auth: &AuthDetails<RoleEnum>) -> bool {
+    auth.has_authority(&RoleEnum::RoleAdmin)
+        || auth.has_authority(&RoleEnum::RoleSuperUser)
+}
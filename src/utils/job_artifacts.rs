@@ -0,0 +1,231 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use actix_web::web;
+use minior::aws_sdk_s3::{
+    error::{ProvideErrorMetadata, SdkError},
+    primitives::ByteStream,
+};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::artifact::{JobArtifact, NewJobArtifact, MAX_ARTIFACTS_PER_JOB},
+    AppData,
+};
+
+/// Scratch working directory of a job
+pub fn job_workdir(workdir_root: &str, job_id: uuid::Uuid) -> PathBuf {
+    Path::new(workdir_root).join(job_id.to_string())
+}
+
+/// Bytes read from the start of an artifact to detect its content type
+const CONTENT_SNIFF_BYTES: u64 = 8192;
+
+/// Regular files in `workdir` matching any of the patterns, keyed by their
+/// path relative to `workdir`. Symlinks to files are skipped, and so are
+/// files that glob reached through a symlinked directory pointing outside
+/// `workdir`, so that a job can't collect files from elsewhere.
+fn find_artifact_files(
+    workdir: &Path,
+    patterns: &[String],
+    max_file_size_bytes: u64,
+) -> BTreeMap<String, (PathBuf, u64)> {
+    let mut files = BTreeMap::new();
+    let Ok(real_workdir) = std::fs::canonicalize(workdir) else {
+        return files;
+    };
+    for pattern in patterns {
+        let full_pattern = workdir.join(pattern);
+        let paths = match glob::glob(&full_pattern.to_string_lossy()) {
+            Ok(paths) => paths,
+            Err(err) => {
+                let _ =
+                    tracing::warn!("Invalid artifact pattern {pattern}: {err}");
+                continue;
+            }
+        };
+        for path in paths.filter_map(Result::ok) {
+            let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let Some(rel_path) = path
+                .strip_prefix(workdir)
+                .ok()
+                .and_then(|p| p.to_str())
+                .map(|p| p.to_owned())
+            else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let inside_workdir = std::fs::canonicalize(&path)
+                .is_ok_and(|real_path| real_path.starts_with(&real_workdir));
+            if !inside_workdir {
+                let _ = tracing::warn!(
+                    "Skipping artifact {rel_path}, it is outside of the working directory"
+                );
+                continue;
+            }
+            if metadata.len() > max_file_size_bytes {
+                let _ = tracing::warn!(
+                    "Skipping artifact {rel_path}, {} bytes is over the limit of {max_file_size_bytes}",
+                    metadata.len()
+                );
+                continue;
+            }
+            if files.len() >= MAX_ARTIFACTS_PER_JOB {
+                let _ = tracing::warn!(
+                    "Job has more than {MAX_ARTIFACTS_PER_JOB} artifacts, ignoring the rest"
+                );
+                return files;
+            }
+            let _ = files.insert(rel_path, (path, metadata.len()));
+        }
+    }
+    files
+}
+
+/// Uploads the files matching `patterns` in the job working directory to
+/// MinIO under `jobs/{job_id}/` and records them, replacing the artifacts of
+/// an earlier attempt of the job
+pub async fn collect_job_artifacts(
+    job_id: uuid::Uuid,
+    workdir: &Path,
+    patterns: Vec<String>,
+    app_data: &AppData,
+) -> Result<Vec<JobArtifact>, DomainError> {
+    if patterns.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let max_file_size_bytes = app_data.config.job_artifacts.max_file_size_bytes;
+    let workdir2 = workdir.to_owned();
+    let files = web::block(move || {
+        find_artifact_files(&workdir2, &patterns, max_file_size_bytes)
+    })
+    .await?;
+    let _ =
+        tracing::info!("Collecting {} artifacts of job {job_id}", files.len());
+
+    let mut new_artifacts = Vec::with_capacity(files.len());
+    for (path, (full_path, size_bytes)) in files {
+        let read_err = |err: String| {
+            DomainError::new_file_upload_failed(format!(
+                "Failed to read artifact {path}: {err}"
+            ))
+        };
+        let content_type = sniff_content_type(&full_path)
+            .await
+            .map_err(|err| read_err(err.to_string()))?;
+        // streamed from the file instead of read into memory
+        let body = ByteStream::from_path(&full_path)
+            .await
+            .map_err(|err| read_err(err.to_string()))?;
+        let object_key = JobArtifact::object_key_for(job_id, &path);
+
+        let _ = app_data
+            .minio
+            .client
+            .put_object()
+            .bucket(&app_data.config.minio.bucket_name)
+            .key(&object_key)
+            .body(body)
+            .content_type(&content_type)
+            .send()
+            .await
+            .map_err(|err| {
+                DomainError::new_file_upload_failed(format!(
+                    "Artifact upload of {path} failed: {err:?}"
+                ))
+            })?;
+
+        new_artifacts.push(NewJobArtifact {
+            job_id,
+            path,
+            object_key,
+            size_bytes: i64::try_from(size_bytes).unwrap_or(i64::MAX),
+            content_type,
+        });
+    }
+
+    let pool = app_data.pool.clone();
+    let (artifacts, replaced) = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::artifacts::replace_job_artifacts(
+            job_id,
+            &new_artifacts,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // objects of the same path were overwritten by the upload
+    for artifact in replaced.iter().filter(|old| {
+        !artifacts.iter().any(|new| new.object_key == old.object_key)
+    }) {
+        if let Err(err) =
+            delete_artifact_object(&artifact.object_key, app_data).await
+        {
+            let _ = tracing::warn!(
+                "Failed to delete replaced artifact {}: {err}",
+                artifact.object_key
+            );
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Content type detected from the first bytes of a file
+async fn sniff_content_type(path: &Path) -> std::io::Result<String> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut head = Vec::new();
+    let _ = file
+        .take(CONTENT_SNIFF_BYTES)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(infer::get(&head)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("application/octet-stream")
+        .to_owned())
+}
+
+/// Whether an S3 request failed because the object does not exist
+fn is_not_found<E: ProvideErrorMetadata, R>(err: &SdkError<E, R>) -> bool {
+    matches!(
+        err.as_service_error().and_then(|err| err.code()),
+        Some("NoSuchKey" | "NotFound")
+    )
+}
+
+/// Deletes an artifact object from MinIO. An object that is already gone is
+/// not an error.
+pub async fn delete_artifact_object(
+    object_key: &str,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    match app_data
+        .minio
+        .client
+        .delete_object()
+        .bucket(&app_data.config.minio.bucket_name)
+        .key(object_key)
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) if is_not_found(&err) => {
+            let _ = tracing::warn!(
+                "Artifact {object_key} not found, skipping deletion"
+            );
+            Ok(())
+        }
+        Err(err) => Err(DomainError::new_internal_error(format!(
+            "Failed to delete artifact from MinIO: {err:?}"
+        ))),
+    }
+}
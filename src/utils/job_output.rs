@@ -0,0 +1,47 @@
+use redis::streams::StreamMaxlen;
+
+use crate::{
+    errors::DomainError, models::ws::MyProcessItem, types::RedisPrefixFn,
+    utils, AppData,
+};
+
+/// Redis stream the output of a job is kept in for replay
+pub fn job_output_stream_key(
+    redis_prefix: &RedisPrefixFn,
+    job_id: uuid::Uuid,
+) -> String {
+    redis_prefix(&format!("job.{job_id}.output"))
+}
+
+/// Publishes an item of job output on the job channel for live subscribers
+/// and appends it to the job's output stream, from where it can be replayed.
+/// The stream is capped and expires once the job has been quiet for the
+/// retention period.
+pub async fn publish_job_output(
+    app_data: &AppData,
+    job_id: uuid::Uuid,
+    item: &MyProcessItem,
+) -> Result<(), DomainError> {
+    let mut conn = app_data.redis_conn_manager.clone();
+    let chan_name = (app_data.redis_prefix)(&format!("job.{job_id}"));
+    let stream_key = job_output_stream_key(&app_data.redis_prefix, job_id);
+    let msg = utils::jstr(item);
+    let retention = i64::try_from(app_data.config.job_output_retention_secs)
+        .unwrap_or(i64::MAX);
+
+    let () = redis::pipe()
+        .xadd_maxlen(
+            &stream_key,
+            StreamMaxlen::Approx(app_data.config.job_output_max_entries),
+            "*",
+            &[("message", &msg)],
+        )
+        .ignore()
+        .expire(&stream_key, retention)
+        .ignore()
+        .publish(&chan_name, &msg)
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::{
+    io, os::unix::process::ExitStatusExt, process::Stdio, time::Duration,
+};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{ChildStdin, Command},
+};
+
+use crate::models::ws::MyProcessItem;
+
+/// A job process spawned in its own process group
+pub struct JobProcess {
+    pub pid: u32,
+    pub stdin: Option<ChildStdin>,
+    /// Output lines of the process, ending with a `Done` item once it exits
+    pub output: BoxStream<'static, MyProcessItem>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum JobSignal {
+    Terminate,
+    Kill,
+}
+
+/// Command running `bin_path` with `args`. With `pty`, the command runs inside
+/// a pseudo terminal allocated by the `script` binary at `pty_wrapper_path`,
+/// so that interactive tools and progress bars behave as they would in a
+/// shell.
+pub fn job_command(
+    bin_path: &str,
+    pty_wrapper_path: &str,
+    args: &[String],
+    pty: bool,
+) -> Command {
+    if pty {
+        // `script` runs the command through the shell, so the arguments need
+        // to be quoted
+        let command = std::iter::once(bin_path)
+            .chain(args.iter().map(String::as_str))
+            .map(shell_quote)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut cmd = Command::new(pty_wrapper_path);
+        let _ =
+            cmd.args(["-q", "-e", "-f", "-c", command.as_str(), "/dev/null"]);
+        cmd
+    } else {
+        let mut cmd = Command::new(bin_path);
+        let _ = cmd.args(args);
+        cmd
+    }
+}
+
+/// Quotes an argument for use in a POSIX shell command line
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+impl JobProcess {
+    /// Spawns the command with piped stdio. The process leads a new process
+    /// group, so signals sent through [`signal_job_process`] also reach any
+    /// children it started.
+    pub fn spawn(mut command: Command) -> io::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+
+        let pid = child.id().ok_or_else(|| {
+            io::Error::other("Process exited before its pid could be read")
+        })?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().ok_or_else(|| {
+            io::Error::other("Failed to capture process stdout")
+        })?;
+        let stderr = child.stderr.take().ok_or_else(|| {
+            io::Error::other("Failed to capture process stderr")
+        })?;
+
+        let stdout = lines(stdout).map(|value| MyProcessItem::Line { value });
+        let stderr = lines(stderr).map(|cause| {
+            if cause.starts_with("[ERROR]") || cause.starts_with("E:") {
+                MyProcessItem::Error { cause }
+            } else {
+                MyProcessItem::Line { value: cause }
+            }
+        });
+        let exit = futures::stream::once(async move {
+            let code = match child.wait().await {
+                // follow the shell convention for processes killed by a signal
+                Ok(status) => status
+                    .code()
+                    .or_else(|| status.signal().map(|sig| 128 + sig))
+                    .unwrap_or(-1),
+                Err(err) => {
+                    let _ =
+                        tracing::error!("Failed to wait for process: {err}");
+                    -1
+                }
+            };
+            MyProcessItem::Done {
+                code: code.to_string(),
+            }
+        });
+
+        let output =
+            futures::stream::select(stdout, stderr).chain(exit).boxed();
+
+        Ok(Self { pid, stdin, output })
+    }
+}
+
+/// Sends a signal to the process group of a job process
+pub fn signal_job_process(pid: u32, signal: JobSignal) -> io::Result<()> {
+    let sig = match signal {
+        JobSignal::Terminate => Signal::SIGTERM,
+        JobSignal::Kill => Signal::SIGKILL,
+    };
+    let pgid = i32::try_from(pid).map_err(io::Error::other)?;
+    killpg(Pid::from_raw(pgid), sig).map_err(io::Error::from)
+}
+
+/// How often a stopping process group is checked for
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether any process of the group of a job process is still running. The
+/// group leader is reaped once its exit was read from the output stream.
+pub fn is_job_process_running(pid: u32) -> bool {
+    i32::try_from(pid)
+        .is_ok_and(|pgid| killpg(Pid::from_raw(pgid), None).is_ok())
+}
+
+/// Waits up to `timeout` for the process group of a job process to exit,
+/// returns whether it did
+pub async fn wait_for_job_process_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while is_job_process_running(pid) {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+    true
+}
+
+fn lines<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+) -> impl Stream<Item = String> + Send {
+    futures::stream::unfold(
+        BufReader::new(reader).lines(),
+        |mut reader| async move {
+            match reader.next_line().await {
+                Ok(Some(line)) => Some((line, reader)),
+                Ok(None) => None,
+                Err(err) => {
+                    let _ =
+                        tracing::warn!("Failed to read process output: {err}");
+                    None
+                }
+            }
+        },
+    )
+}
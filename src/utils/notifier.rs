@@ -0,0 +1,109 @@
+use actix_web::web;
+use redis::aio::ConnectionManager;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        misc::JobStatus,
+        notification::{NewNotification, Notification, NotificationEvent},
+        users::UserId,
+        ws::UserStreamEvent,
+    },
+    types::DbPool,
+    utils::ws,
+};
+
+/// Stores notifications and pushes them to the devices of the user over
+/// their message stream
+#[derive(new, Clone)]
+pub struct Notifier {
+    pool: DbPool,
+    redis: ConnectionManager,
+    /// Prefix of the message streams of the users
+    streams_key: String,
+    stream_max_entries: usize,
+}
+
+impl Notifier {
+    pub async fn notify(
+        &self,
+        user_id: UserId,
+        event: &NotificationEvent,
+    ) -> Result<Notification, DomainError> {
+        let pool = self.pool.clone();
+        let new_notification = NewNotification::new(user_id, event);
+        let notification = web::block(move || {
+            let mut conn = pool.get()?;
+            actions::notifications::create_notification(
+                &new_notification,
+                &mut conn,
+            )
+        })
+        .await??;
+
+        ws::append_user_event(
+            &mut self.redis.clone(),
+            &self.streams_key,
+            self.stream_max_entries,
+            &[user_id],
+            &UserStreamEvent::Notification {
+                notification: notification.clone(),
+            },
+        )
+        .await?;
+        Ok(notification)
+    }
+
+    /// Like `notify`, for producers which should not fail along with it
+    pub async fn notify_or_log(
+        &self,
+        user_id: UserId,
+        event: NotificationEvent,
+    ) {
+        if let Err(err) = self.notify(user_id, &event).await {
+            let _ = tracing::warn!(
+                "Failed to notify user {user_id} of {:?}: {err:?}",
+                event.kind()
+            );
+        }
+    }
+
+    /// Notifies the user who started a job that it finished, for jobs
+    /// finished away from the task that started them, such as jobs of
+    /// remote runners
+    pub async fn notify_job_finished(
+        &self,
+        job_id: uuid::Uuid,
+        status: JobStatus,
+    ) {
+        match self.get_job_owner(job_id).await {
+            Ok(Some(user_id)) => {
+                self.notify_or_log(
+                    user_id,
+                    NotificationEvent::JobFinished { job_id, status },
+                )
+                .await;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let _ = tracing::warn!(
+                    "Failed to notify that job {job_id} finished: {err:?}"
+                );
+            }
+        }
+    }
+
+    async fn get_job_owner(
+        &self,
+        job_id: uuid::Uuid,
+    ) -> Result<Option<UserId>, DomainError> {
+        let pool = self.pool.clone();
+        let job = web::block(move || {
+            let mut conn = pool.get()?;
+            actions::misc::get_job_by_uuid(job_id, &mut conn)
+        })
+        .await??;
+        Ok(job.and_then(|job| job.started_by))
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use actix_web::web;
+use chrono::{NaiveDateTime, Utc};
+use redis::aio::ConnectionManager;
+use tokio::task::JoinHandle;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        presence::{UserPresence, PRESENCE_TTL_SECS},
+        users::UserId,
+        ws::UserStreamEvent,
+    },
+    types::RedisPrefixFn,
+    utils::ws,
+    AppData,
+};
+
+/// How often the presence of a connection is refreshed, a third of
+/// `PRESENCE_TTL_SECS`
+const PRESENCE_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A WebSocket connection of a device. A device with several connections
+/// stays online until the last of them is closed.
+#[derive(Debug, Clone)]
+pub struct PresenceConnection {
+    pub user_id: UserId,
+    pub device_id: String,
+    member: String,
+}
+
+impl PresenceConnection {
+    pub fn new(user_id: UserId, device_id: &str) -> Self {
+        Self {
+            user_id,
+            device_id: device_id.to_owned(),
+            member: format!("{device_id}/{}", uuid::Uuid::new_v4()),
+        }
+    }
+}
+
+/// Sorted set of the connections of a user, scored by when they expire
+fn presence_key(redis_prefix: &RedisPrefixFn, user_id: UserId) -> String {
+    redis_prefix(&format!("presence.{user_id}"))
+}
+
+/// Unix millis of the last time the user was connected
+fn last_seen_key(redis_prefix: &RedisPrefixFn, user_id: UserId) -> String {
+    redis_prefix(&format!("presence.{user_id}.last_seen"))
+}
+
+/// Keeps the connection online for another `PRESENCE_TTL_SECS`, returns
+/// the time when the user came online with it
+pub async fn touch_connection(
+    conn: &mut ConnectionManager,
+    redis_prefix: &RedisPrefixFn,
+    connection: &PresenceConnection,
+) -> Result<Option<NaiveDateTime>, DomainError> {
+    let now = Utc::now();
+    let key = presence_key(redis_prefix, connection.user_id);
+    let (online_before,): (usize,) = redis::pipe()
+        .atomic()
+        .zrembyscore(&key, "-inf", now.timestamp())
+        .ignore()
+        .zcard(&key)
+        .zadd(
+            &key,
+            &connection.member,
+            now.timestamp() + PRESENCE_TTL_SECS,
+        )
+        .ignore()
+        .expire(&key, PRESENCE_TTL_SECS)
+        .ignore()
+        .set(
+            last_seen_key(redis_prefix, connection.user_id),
+            now.timestamp_millis(),
+        )
+        .ignore()
+        .query_async(conn)
+        .await?;
+    Ok((online_before == 0).then(|| now.naive_utc()))
+}
+
+/// Removes the connection, returns the time when the user went offline
+/// with it
+pub async fn remove_connection(
+    conn: &mut ConnectionManager,
+    redis_prefix: &RedisPrefixFn,
+    connection: &PresenceConnection,
+) -> Result<Option<NaiveDateTime>, DomainError> {
+    let now = Utc::now();
+    let key = presence_key(redis_prefix, connection.user_id);
+    let (online_after,): (usize,) = redis::pipe()
+        .atomic()
+        .zrem(&key, &connection.member)
+        .ignore()
+        .zrembyscore(&key, "-inf", now.timestamp())
+        .ignore()
+        .zcard(&key)
+        .set(
+            last_seen_key(redis_prefix, connection.user_id),
+            now.timestamp_millis(),
+        )
+        .ignore()
+        .query_async(conn)
+        .await?;
+    Ok((online_after == 0).then(|| now.naive_utc()))
+}
+
+pub async fn get_presence(
+    conn: &mut ConnectionManager,
+    redis_prefix: &RedisPrefixFn,
+    user_ids: &[UserId],
+) -> Result<Vec<UserPresence>, DomainError> {
+    let now = Utc::now().timestamp();
+    let mut connections_pipe = redis::pipe();
+    let mut last_seen_pipe = redis::pipe();
+    for user_id in user_ids {
+        let _ = connections_pipe.zrangebyscore(
+            presence_key(redis_prefix, *user_id),
+            format!("({now}"),
+            "+inf",
+        );
+        let _ = last_seen_pipe.get(last_seen_key(redis_prefix, *user_id));
+    }
+    let connections: Vec<Vec<String>> =
+        connections_pipe.query_async(conn).await?;
+    let last_seen: Vec<Option<i64>> = last_seen_pipe.query_async(conn).await?;
+
+    Ok(user_ids
+        .iter()
+        .zip(connections)
+        .zip(last_seen)
+        .map(|((user_id, connections), last_seen)| {
+            let devices = connections
+                .iter()
+                .map(|member| {
+                    member
+                        .rsplit_once('/')
+                        .map_or(member.as_str(), |(device, _)| device)
+                })
+                .collect::<HashSet<_>>()
+                .len();
+            UserPresence {
+                user_id: *user_id,
+                online: devices > 0,
+                devices,
+                last_seen_at: last_seen
+                    .and_then(chrono::DateTime::from_timestamp_millis)
+                    .map(|last_seen| last_seen.naive_utc()),
+            }
+        })
+        .collect())
+}
+
+/// Tells the users who share a conversation or direct messages with the
+/// user that they came online or went offline
+pub async fn publish_presence_change(
+    conn: &mut ConnectionManager,
+    app_data: &AppData,
+    user_id: UserId,
+    online: bool,
+    last_seen_at: NaiveDateTime,
+) -> Result<(), DomainError> {
+    let pool = app_data.pool.clone();
+    let watchers = web::block(move || {
+        let mut conn = pool.get()?;
+        let mut watchers =
+            actions::conversations::get_conversation_peers(user_id, &mut conn)?;
+        watchers
+            .extend(actions::messages::get_message_peers(user_id, &mut conn)?);
+        watchers.sort_by_key(|watcher| watcher.as_uint());
+        watchers.dedup();
+        Ok::<_, DomainError>(watchers)
+    })
+    .await??;
+
+    ws::publish_user_event(
+        conn,
+        app_data,
+        &watchers,
+        &UserStreamEvent::PresenceChanged {
+            user_id,
+            online,
+            last_seen_at: Some(last_seen_at),
+        },
+    )
+    .await
+}
+
+/// Refreshes the presence of the connection, on connect and then every
+/// `PRESENCE_REFRESH_INTERVAL`
+async fn refresh_presence(
+    app_data: &AppData,
+    connection: &PresenceConnection,
+) -> Result<(), DomainError> {
+    let mut conn = app_data.redis_conn_manager.clone();
+    let came_online =
+        touch_connection(&mut conn, &app_data.redis_prefix, connection).await?;
+    if let Some(at) = came_online {
+        let _ = tracing::info!("User {} came online", connection.user_id);
+        publish_presence_change(
+            &mut conn,
+            app_data,
+            connection.user_id,
+            true,
+            at,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn drop_presence(
+    app_data: &AppData,
+    connection: &PresenceConnection,
+) -> Result<(), DomainError> {
+    let mut conn = app_data.redis_conn_manager.clone();
+    let went_offline =
+        remove_connection(&mut conn, &app_data.redis_prefix, connection)
+            .await?;
+    if let Some(at) = went_offline {
+        let _ = tracing::info!("User {} went offline", connection.user_id);
+        publish_presence_change(
+            &mut conn,
+            app_data,
+            connection.user_id,
+            false,
+            at,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Keeps a connection online while alive. Dropping it, also by aborting the
+/// task holding it, takes the connection offline. Connections of a crashed
+/// server expire after `PRESENCE_TTL_SECS` without an offline event.
+pub struct PresenceGuard {
+    app_data: Arc<AppData>,
+    connection: PresenceConnection,
+    /// Refreshes the presence until the guard is dropped
+    refresher: JoinHandle<()>,
+}
+
+impl PresenceGuard {
+    pub async fn register(
+        app_data: Arc<AppData>,
+        connection: PresenceConnection,
+    ) -> Self {
+        if let Err(err) = refresh_presence(&app_data, &connection).await {
+            let _ = tracing::warn!(
+                "Failed to register presence of user {}: {err:?}",
+                connection.user_id
+            );
+        }
+        let refresher = actix_rt::spawn({
+            let app_data = app_data.clone();
+            let connection = connection.clone();
+            async move {
+                loop {
+                    actix_rt::time::sleep(PRESENCE_REFRESH_INTERVAL).await;
+                    if let Err(err) =
+                        refresh_presence(&app_data, &connection).await
+                    {
+                        let _ = tracing::warn!(
+                            "Failed to refresh presence of user {}: {err:?}",
+                            connection.user_id
+                        );
+                    }
+                }
+            }
+        });
+        Self {
+            app_data,
+            connection,
+            refresher,
+        }
+    }
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        // stopped before the removal is queued, so that a refresh can't put
+        // the connection back afterwards. A refresh already sent is ahead of
+        // the removal on the shared Redis connection.
+        self.refresher.abort();
+        let app_data = self.app_data.clone();
+        let connection = self.connection.clone();
+        let _ = actix_rt::spawn(async move {
+            if let Err(err) = drop_presence(&app_data, &connection).await {
+                let _ = tracing::warn!(
+                    "Failed to drop presence of user {}: {err:?}",
+                    connection.user_id
+                );
+            }
+        });
+    }
+}
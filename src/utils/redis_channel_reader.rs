@@ -77,8 +77,8 @@ where
                 }
             })
             .collect::<Vec<_>>();
-        let _ = if items.last().is_some() {
-            self.last_msg_id = Some(id.clone());
+        let _ = if let Some(x) = items.last() {
+            self.last_msg_id = Some(x.id.clone());
         };
         Ok(items)
     }
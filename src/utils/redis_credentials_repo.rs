@@ -1,12 +1,18 @@
 use prometheus::GaugeVec;
+use rand::{distr::Alphanumeric, RngExt};
 use redis::aio::ConnectionManager;
 use redis::AsyncCommands;
 use std::collections::HashMap;
 use uuid::Uuid;
 
 use crate::errors::DomainError;
-use crate::models::session::{SessionInfo, SessionStatus};
+use crate::models::notification::NotificationEvent;
+use crate::models::session::{
+    detect_login_anomalies, ActiveSession, LoginAnomaly, LoginRecord,
+    SessionInfo, SessionStatus, MAX_LOGIN_HISTORY,
+};
 use crate::models::users::UserId;
+use crate::utils::notifier::Notifier;
 
 #[derive(new, Clone)]
 pub struct RedisCredentialsRepo {
@@ -14,10 +20,19 @@ pub struct RedisCredentialsRepo {
     redis: ConnectionManager,
     max_sessions: usize,
     refresh_ttl_seconds: u64,
+    impossible_travel_secs: u64,
     active_sessions: GaugeVec,
+    /// Tells users about new logins into their account
+    #[new(default)]
+    notifier: Option<Notifier>,
 }
 
 impl RedisCredentialsRepo {
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
     pub fn get_key(&self, user_id: &UserId) -> String {
         format!("{}.{user_id}", self.base_key)
     }
@@ -31,6 +46,26 @@ impl RedisCredentialsRepo {
         format!("{}.expiry.{user_id}.{session_id}", self.base_key)
     }
 
+    // Sessions of all users, scored by when they expire, so that the live
+    // ones can be found without going through every user
+    pub fn get_active_key(&self) -> String {
+        format!("{}.active", self.base_key)
+    }
+
+    fn get_active_member(user_id: &UserId, session_id: &Uuid) -> String {
+        format!("{user_id}:{session_id}")
+    }
+
+    // Recent logins of the user, newest first
+    pub fn get_login_history_key(&self, user_id: &UserId) -> String {
+        format!("{}.logins.{user_id}", self.base_key)
+    }
+
+    // Session revoked by the "it wasn't me" link with this token
+    pub fn get_revoke_key(&self, token: &str) -> String {
+        format!("{}.revoke.{token}", self.base_key)
+    }
+
     // Method to check if a token is expired
     pub async fn is_token_expired(
         &self,
@@ -143,6 +178,84 @@ impl RedisCredentialsRepo {
         Ok(result)
     }
 
+    // Load the live sessions of all users, latest to expire first, along
+    // with how many there are. Expired entries are left in the index for the
+    // cleanup worker, which removes their sessions along with them.
+    pub async fn load_active_sessions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<ActiveSession>, usize), DomainError> {
+        let active_key = self.get_active_key();
+        let now = chrono::Utc::now().timestamp();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zcount(&active_key, format!("({now}"), "+inf")
+            .zrevrangebyscore_limit_withscores(
+                &active_key,
+                "+inf",
+                format!("({now}"),
+                offset as isize,
+                limit as isize,
+            );
+        let (total, members): (usize, Vec<(String, i64)>) = pipe
+            .query_async(&mut self.redis.clone())
+            .await
+            .map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to get active sessions: {err}"
+                ))
+            })?;
+
+        let members = members
+            .into_iter()
+            .filter_map(|(member, expires_at)| {
+                let (user_id, session_id) = member.split_once(':')?;
+                Some((
+                    user_id.parse::<UserId>().ok()?,
+                    Uuid::parse_str(session_id).ok()?,
+                    expires_at,
+                ))
+            })
+            .collect::<Vec<_>>();
+        if members.is_empty() {
+            return Ok((Vec::new(), total));
+        }
+
+        let mut pipe = redis::pipe();
+        for (user_id, session_id, _) in &members {
+            pipe.hget(self.get_key(user_id), session_id.to_string());
+        }
+        let session_info_strs: Vec<Option<String>> = pipe
+            .query_async(&mut self.redis.clone())
+            .await
+            .map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to get active sessions: {err}"
+                ))
+            })?;
+
+        let sessions = members
+            .into_iter()
+            .zip(session_info_strs)
+            .filter_map(|((user_id, _, expires_at), session_info_str)| {
+                let mut session: SessionInfo =
+                    serde_json::from_str(&session_info_str?).ok()?;
+                session.ttl_remaining = Some(expires_at - now);
+                Some(ActiveSession {
+                    user_id,
+                    session,
+                    expires_at: chrono::DateTime::from_timestamp(
+                        expires_at, 0,
+                    )?
+                    .naive_utc(),
+                })
+            })
+            .collect();
+        Ok((sessions, total))
+    }
+
     // Create a new session for a user. Will error if session already exists or max sessions exceeded.
     pub async fn create_session(
         &self,
@@ -193,10 +306,17 @@ impl RedisCredentialsRepo {
             })?;
 
         // Create a pipeline for atomic operations
+        let expires_at = chrono::Utc::now().timestamp() + ttl_seconds as i64;
         let mut pipe = redis::pipe();
         pipe.atomic()
             .hset(&key, &session_id_str, session_info_str)
             .set_ex(expiry_key, "1", ttl_seconds)
+            .zadd(
+                self.get_active_key(),
+                Self::get_active_member(user_id, session_id),
+                expires_at,
+            )
+            .ignore()
             .hlen(&key);
 
         let _ = tracing::info!("Creating user session");
@@ -215,9 +335,168 @@ impl RedisCredentialsRepo {
             .with_label_values(&[&user_id.to_string()])
             .set(count as f64);
 
+        if let Some(notifier) = &self.notifier {
+            let event = self
+                .login_notification(user_id, session_info, ttl_seconds)
+                .await;
+            notifier.notify_or_log(*user_id, event).await;
+        }
+
         Ok(())
     }
 
+    // Tells the user about the new session, with a link to revoke it when it
+    // differs from how they usually log in
+    async fn login_notification(
+        &self,
+        user_id: &UserId,
+        session_info: &SessionInfo,
+        ttl_seconds: u64,
+    ) -> NotificationEvent {
+        let new_session = NotificationEvent::NewSession {
+            session_id: session_info.session_id,
+            device_name: session_info.device_name.clone(),
+        };
+        let anomalies = match self.record_login(user_id, session_info).await {
+            Ok(anomalies) if !anomalies.is_empty() => anomalies,
+            Ok(_) => return new_session,
+            Err(err) => {
+                let _ = tracing::warn!(
+                    "Failed to check login of user {user_id}: {err:?}"
+                );
+                return new_session;
+            }
+        };
+        let _ = tracing::warn!(
+            "Suspicious login of user {user_id} from {:?}: {anomalies:?}",
+            session_info.ip
+        );
+        match self
+            .create_revoke_token(user_id, &session_info.session_id, ttl_seconds)
+            .await
+        {
+            Ok(token) => NotificationEvent::SuspiciousLogin {
+                session_id: session_info.session_id,
+                device_name: session_info.device_name.clone(),
+                ip: session_info.ip.clone(),
+                user_agent: session_info.user_agent.clone(),
+                country: session_info.country.clone(),
+                anomalies,
+                revoke_url: format!("/api/public/sessions/revoke/{token}"),
+            },
+            Err(err) => {
+                let _ = tracing::warn!(
+                    "Failed to create revoke token for user {user_id}: {err:?}"
+                );
+                new_session
+            }
+        }
+    }
+
+    // Adds the login to the history of the user, returns how it differs from
+    // the previous ones
+    pub async fn record_login(
+        &self,
+        user_id: &UserId,
+        session_info: &SessionInfo,
+    ) -> Result<Vec<LoginAnomaly>, DomainError> {
+        let key = self.get_login_history_key(user_id);
+        let login = LoginRecord::from_session(session_info);
+        let login_str = serde_json::to_string(&login).map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to serialize login: {err}"
+            ))
+        })?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .lrange(&key, 0, -1)
+            .lpush(&key, login_str)
+            .ignore()
+            .ltrim(&key, 0, MAX_LOGIN_HISTORY - 1)
+            .ignore();
+        let (history,): (Vec<String>,) = pipe
+            .query_async(&mut self.redis.clone())
+            .await
+            .map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to record login: {err}"
+                ))
+            })?;
+
+        let history = history
+            .iter()
+            .filter_map(|record| serde_json::from_str(record).ok())
+            .collect::<Vec<LoginRecord>>();
+        Ok(detect_login_anomalies(
+            &history,
+            &login,
+            self.impossible_travel_secs,
+        ))
+    }
+
+    // Creates the token of an "it wasn't me" link, valid as long as the
+    // session could be
+    pub async fn create_revoke_token(
+        &self,
+        user_id: &UserId,
+        session_id: &Uuid,
+        ttl_seconds: u64,
+    ) -> Result<String, DomainError> {
+        let token: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let () = self
+            .redis
+            .clone()
+            .set_ex(
+                self.get_revoke_key(&token),
+                format!("{user_id}:{session_id}"),
+                ttl_seconds,
+            )
+            .await
+            .map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to store revoke token: {err}"
+                ))
+            })?;
+        Ok(token)
+    }
+
+    // Deletes the session of an "it wasn't me" link. Each token works once,
+    // returns the user and session when it was valid.
+    pub async fn revoke_with_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<(UserId, Uuid)>, DomainError> {
+        let target: Option<String> = self
+            .redis
+            .clone()
+            .get_del(self.get_revoke_key(token))
+            .await
+            .map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to load revoke token: {err}"
+                ))
+            })?;
+        let Some((user_id, session_id)) = target
+            .as_deref()
+            .and_then(|target| target.split_once(':'))
+            .and_then(|(user_id, session_id)| {
+                Some((
+                    user_id.parse::<UserId>().ok()?,
+                    Uuid::parse_str(session_id).ok()?,
+                ))
+            })
+        else {
+            return Ok(None);
+        };
+        self.delete_session(&user_id, &session_id).await?;
+        Ok(Some((user_id, session_id)))
+    }
+
     // Update an existing session. Will error if session does not exist.
     pub async fn update_session(
         &self,
@@ -294,21 +573,31 @@ impl RedisCredentialsRepo {
         let _ = tracing::info!("Extending user session");
 
         // Update expiry
-        let () = self
-            .redis
-            .clone()
+        let expires_at = chrono::Utc::now().timestamp() + new_ttl;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
             .expire(expiry_key, new_ttl)
-            .await
-            .map_err(|err| {
-                DomainError::new_internal_error(format!(
-                    "Failed to update expiry on Redis key: {err}"
-                ))
-            })?;
+            .ignore()
+            .zadd(
+                self.get_active_key(),
+                Self::get_active_member(user_id, session_id),
+                expires_at,
+            )
+            .ignore();
+        let () =
+            pipe.query_async(&mut self.redis.clone())
+                .await
+                .map_err(|err| {
+                    DomainError::new_internal_error(format!(
+                        "Failed to update expiry on Redis key: {err}"
+                    ))
+                })?;
 
         Ok(())
     }
 
-    // Update last used time for a session
+    // Update last used time for a session. Heartbeats keep the session of
+    // an open connection alive but are not counted as renewals.
     pub async fn update_session_last_used_ws(
         &self,
         user_id: &UserId,
@@ -316,15 +605,17 @@ impl RedisCredentialsRepo {
     ) -> Result<(), DomainError> {
         let mb_session_info = self.load_session(user_id, session_id).await?;
 
-        if let Some(session_info) = mb_session_info {
-            self.update_session_last_used(session_id, session_info, user_id)
+        if let Some(mut session_info) = mb_session_info {
+            session_info.last_used_at = chrono::Utc::now().naive_utc();
+            self.update_session(user_id, session_id, &session_info)
                 .await?;
         }
 
         Ok(())
     }
 
-    // Update last used time for a session
+    // Update last used time for a session, renewing it when renewal is
+    // enabled
     pub async fn update_session_last_used(
         &self,
         session_id: &Uuid,
@@ -332,6 +623,9 @@ impl RedisCredentialsRepo {
         user_id: &UserId,
     ) -> Result<SessionInfo, DomainError> {
         session_info.last_used_at = chrono::Utc::now().naive_utc();
+        if self.refresh_ttl_seconds > 0 {
+            session_info.renewal_count += 1;
+        }
 
         // Update the session info and refresh the expiry
         self.update_session(user_id, session_id, &session_info)
@@ -350,7 +644,14 @@ impl RedisCredentialsRepo {
         let session_id_str = session_id.to_string();
 
         let mut pipe = redis::pipe();
-        pipe.atomic().hdel(&key, &session_id_str).hlen(&key);
+        pipe.atomic()
+            .hdel(&key, &session_id_str)
+            .zrem(
+                self.get_active_key(),
+                Self::get_active_member(user_id, session_id),
+            )
+            .ignore()
+            .hlen(&key);
 
         let (_, count): ((), i32) = pipe
             .query_async(&mut self.redis.clone())
@@ -375,9 +676,23 @@ impl RedisCredentialsRepo {
         user_id: &UserId,
     ) -> Result<(), DomainError> {
         let key = self.get_key(user_id);
+        let session_ids: Vec<String> =
+            self.redis.clone().hkeys(&key).await.map_err(|err| {
+                DomainError::new_internal_error(format!(
+                    "Failed to get session_ids from Redis: {err}"
+                ))
+            })?;
+        let members = session_ids
+            .iter()
+            .map(|session_id| format!("{user_id}:{session_id}"))
+            .collect::<Vec<_>>();
 
         let mut pipe = redis::pipe();
-        pipe.atomic().del(&key).hlen(&key);
+        pipe.atomic().del(&key);
+        if !members.is_empty() {
+            pipe.zrem(self.get_active_key(), members).ignore();
+        }
+        pipe.hlen(&key);
 
         let (_, count): ((), i32) = pipe
             .query_async(&mut self.redis.clone())
@@ -396,63 +711,163 @@ impl RedisCredentialsRepo {
         Ok(())
     }
 
-    // Add a cleanup method to be called periodically or during token validation
-    pub async fn cleanup_expired_session_ids(
+    // Removes up to `batch_size` of the sessions whose expiry passed,
+    // returns how many were removed. Only touches expired sessions, found
+    // through the active sessions index.
+    pub async fn remove_expired_sessions(
         &self,
-        user_id: &UserId,
-    ) -> Result<(), DomainError> {
-        let key = self.get_key(user_id);
-
-        // Get all session_ids for this user
-        let session_ids: Vec<String> =
-            self.redis.clone().hkeys(&key).await.map_err(|err| {
+        batch_size: usize,
+    ) -> Result<usize, DomainError> {
+        let now = chrono::Utc::now().timestamp();
+        let result: Vec<String> = redis::Script::new(REMOVE_EXPIRED_SCRIPT)
+            .key(self.get_active_key())
+            .arg(now)
+            .arg(batch_size)
+            .arg(&self.base_key)
+            .invoke_async(&mut self.redis.clone())
+            .await
+            .map_err(|err| {
                 DomainError::new_internal_error(format!(
-                    "Failed to get session_ids from Redis: {err}"
+                    "Failed to delete expired sessions: {err}"
                 ))
             })?;
 
-        let mut pipe = redis::pipe();
-        let mut expired_count = 0;
-
-        // Check each token's expiry key and queue deletions
-        for session_id_str in session_ids {
-            let session_id = Uuid::parse_str(&session_id_str)
-                .expect("Expected valid session_id");
-            let expiry_key = self.get_expiry_key(user_id, &session_id);
-            let exists: bool =
-                self.redis.clone().exists(expiry_key).await.map_err(|err| {
-                    DomainError::new_internal_error(format!(
-                        "Failed to check if expiry key exists: {err}"
-                    ))
-                })?;
-
-            // If expiry key doesn't exist, token has expired
-            if !exists {
-                pipe.hdel(&key, &session_id_str);
-                expired_count += 1;
+        // The number of removed sessions, then the remaining session count
+        // of each user who lost some
+        let (removed, user_counts) = match result.split_first() {
+            Some((removed, user_counts)) => {
+                (removed.parse().unwrap_or(0), user_counts)
             }
+            None => (0, &[][..]),
+        };
+        for user_count in user_counts.chunks_exact(2) {
+            let count = user_count[1].parse::<f64>().unwrap_or(0.0);
+            self.active_sessions
+                .with_label_values(&[&user_count[0]])
+                .set(count);
+        }
+        if removed > 0 {
+            let _ = tracing::info!("Removed {removed} expired sessions");
         }
+        Ok(removed)
+    }
 
-        // Execute batch deletions if any expired sessions found
-        if expired_count > 0 {
-            pipe.atomic().hlen(&key);
-            let count: i32 = pipe
-                .query_async(&mut self.redis.clone())
+    // Adds the sessions missing from the active sessions index, those
+    // created before it existed, so that they expire with the others.
+    // Returns how many were added.
+    pub async fn index_sessions(&self) -> Result<usize, DomainError> {
+        let mut conn = self.redis.clone();
+        let now = chrono::Utc::now().timestamp();
+        let pattern = format!("{}.*", self.base_key);
+        let mut cursor = 0u64;
+        let mut indexed = 0;
+        loop {
+            let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(500)
+                .arg("TYPE")
+                .arg("hash")
+                .query_async(&mut conn)
                 .await
                 .map_err(|err| {
                     DomainError::new_internal_error(format!(
-                        "Failed to delete expired sessions: {err}"
+                        "Failed to scan sessions: {err}"
                     ))
                 })?;
 
-            // Update active sessions metric for this user
-            self.active_sessions
-                .with_label_values(&[&user_id.to_string()])
-                .set(count as f64);
+            for key in keys {
+                let Some(user_id) = key
+                    .strip_prefix(&format!("{}.", self.base_key))
+                    .and_then(|user_id| user_id.parse::<UserId>().ok())
+                else {
+                    continue;
+                };
+                let session_ids: Vec<String> =
+                    conn.hkeys(&key).await.map_err(|err| {
+                        DomainError::new_internal_error(format!(
+                            "Failed to get session_ids from Redis: {err}"
+                        ))
+                    })?;
+                let session_ids = session_ids
+                    .iter()
+                    .filter_map(|session_id| Uuid::parse_str(session_id).ok())
+                    .collect::<Vec<_>>();
+                if session_ids.is_empty() {
+                    continue;
+                }
+
+                let mut pipe = redis::pipe();
+                for session_id in &session_ids {
+                    pipe.ttl(self.get_expiry_key(&user_id, session_id));
+                }
+                let ttls: Vec<i64> =
+                    pipe.query_async(&mut conn).await.map_err(|err| {
+                        DomainError::new_internal_error(format!(
+                            "Failed to get batch TTLs: {err}"
+                        ))
+                    })?;
 
-            let _ = tracing::info!("Removed {expired_count} expired sessions, {count} active sessions remaining");
-        }
+                // Expired sessions are indexed as expiring now, for the
+                // cleanup to remove them
+                let mut pipe = redis::pipe();
+                for (session_id, ttl) in session_ids.iter().zip(ttls) {
+                    pipe.cmd("ZADD")
+                        .arg(self.get_active_key())
+                        .arg("NX")
+                        .arg(now + ttl.max(0))
+                        .arg(Self::get_active_member(&user_id, session_id));
+                }
+                let added: Vec<usize> =
+                    pipe.query_async(&mut conn).await.map_err(|err| {
+                        DomainError::new_internal_error(format!(
+                            "Failed to index sessions: {err}"
+                        ))
+                    })?;
+                indexed += added.iter().sum::<usize>();
+            }
 
-        Ok(())
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+        Ok(indexed)
     }
 }
+
+/// Removes the expired sessions of a batch from the hashes of their users
+/// and from the index. Sessions whose expiry key is still alive are put back
+/// into the index with its TTL. Returns the number of removed sessions
+/// followed by pairs of a user id and how many sessions the user has left.
+const REMOVE_EXPIRED_SCRIPT: &str = r"
+local expired = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+local removed = 0
+local users = {}
+local seen = {}
+for _, member in ipairs(expired) do
+    local sep = string.find(member, ':', 1, true)
+    local user_id = string.sub(member, 1, sep - 1)
+    local session_id = string.sub(member, sep + 1)
+    local ttl = redis.call('TTL', ARGV[3] .. '.expiry.' .. user_id .. '.' .. session_id)
+    if ttl > 0 then
+        redis.call('ZADD', KEYS[1], tonumber(ARGV[1]) + ttl, member)
+    else
+        redis.call('HDEL', ARGV[3] .. '.' .. user_id, session_id)
+        redis.call('ZREM', KEYS[1], member)
+        removed = removed + 1
+        if not seen[user_id] then
+            seen[user_id] = true
+            table.insert(users, user_id)
+        end
+    end
+end
+local result = { tostring(removed) }
+for _, user_id in ipairs(users) do
+    table.insert(result, user_id)
+    table.insert(result, tostring(redis.call('HLEN', ARGV[3] .. '.' .. user_id)))
+end
+return result
+";
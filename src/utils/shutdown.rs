@@ -0,0 +1,230 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use actix_web::{dev::ServerHandle, web};
+use tokio::{sync::Notify, time::Instant};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::misc::{JobAbortMessage, JobStatus},
+    routes::command,
+    AppData,
+};
+
+/// Reason given to jobs aborted because the instance shuts down
+const SHUTDOWN_ABORT_REASON: &str = "Server is shutting down";
+
+/// Coordinates the graceful shutdown of the instance
+///
+/// The token is cancelled once a termination signal arrives. From then on no
+/// new jobs are started, WebSocket connections and output streams are
+/// closed and workers stop after their current run.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    deadline: Arc<OnceLock<Instant>>,
+    jobs: Arc<RunningJobs>,
+}
+
+#[derive(Default)]
+struct RunningJobs {
+    ids: Mutex<HashSet<Uuid>>,
+    finished: Notify,
+}
+
+/// Keeps a job among the running jobs of the instance until dropped
+pub struct RunningJobGuard {
+    job_id: Uuid,
+    jobs: Arc<RunningJobs>,
+}
+
+impl Drop for RunningJobGuard {
+    fn drop(&mut self) {
+        let _ = self.jobs.ids.lock().unwrap().remove(&self.job_id);
+        self.jobs.finished.notify_waiters();
+    }
+}
+
+impl Shutdown {
+    /// Cancelled once the shutdown began
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await;
+    }
+
+    /// When everything has to be stopped, set once the shutdown began
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.get().copied()
+    }
+
+    /// Completes once the shutdown began and its deadline passed
+    pub async fn deadline_passed(&self) {
+        self.cancelled().await;
+        if let Some(deadline) = self.deadline() {
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+
+    /// Begins the shutdown, everything has to be stopped within `timeout`
+    pub fn begin(&self, timeout: Duration) -> Instant {
+        let deadline = *self.deadline.get_or_init(|| Instant::now() + timeout);
+        self.token.cancel();
+        deadline
+    }
+
+    /// Registers a job running on this instance, refused once the shutdown
+    /// began
+    pub fn track_job(
+        &self,
+        job_id: Uuid,
+    ) -> Result<RunningJobGuard, DomainError> {
+        let mut ids = self.jobs.ids.lock().unwrap();
+        if self.is_shutting_down() {
+            return Err(DomainError::new_service_unavailable_error(
+                "The server is shutting down, no new jobs are started"
+                    .to_owned(),
+            ));
+        }
+        let _ = ids.insert(job_id);
+        Ok(RunningJobGuard {
+            job_id,
+            jobs: self.jobs.clone(),
+        })
+    }
+
+    pub fn running_jobs(&self) -> Vec<Uuid> {
+        self.jobs.ids.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Waits for the running jobs to finish. Returns false when some were
+    /// still running at the deadline.
+    pub async fn wait_for_jobs(&self, deadline: Instant) -> bool {
+        tokio::time::timeout_at(deadline, async {
+            loop {
+                let finished = self.jobs.finished.notified();
+                if self.jobs.ids.lock().unwrap().is_empty() {
+                    return;
+                }
+                finished.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+/// Waits for SIGTERM or Ctrl-C and shuts the instance down gracefully
+pub async fn shutdown_on_signal(app_data: &AppData, server: ServerHandle) {
+    match termination_signal().await {
+        Ok(()) => graceful_shutdown(app_data, server).await,
+        Err(err) => {
+            let _ = tracing::error!(
+                "Failed to listen for termination signals: {err}"
+            );
+        }
+    }
+}
+
+async fn termination_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => Ok(()),
+        res = tokio::signal::ctrl_c() => res,
+    }
+}
+
+/// Drains the instance within the shutdown timeout and stops the HTTP server
+pub async fn graceful_shutdown(app_data: &AppData, server: ServerHandle) {
+    drain(app_data).await;
+    let _ = tracing::info!("Stopping the HTTP server");
+    server.stop(true).await;
+}
+
+/// Begins the shutdown and waits for the running jobs
+///
+/// Running jobs get until the deadline, less the abort grace period, to
+/// finish. The rest are aborted, and jobs outliving the deadline are marked
+/// as aborted.
+pub async fn drain(app_data: &AppData) {
+    let timeout = Duration::from_secs(app_data.config.shutdown_timeout_secs);
+    let _ = tracing::info!("Shutting down within {timeout:?}");
+    let deadline = app_data.shutdown.begin(timeout);
+
+    let grace_period =
+        Duration::from_secs(app_data.config.job_abort_grace_period_secs);
+    let abort_at = deadline - grace_period.min(timeout);
+    if !app_data.shutdown.wait_for_jobs(abort_at).await {
+        let job_ids = app_data.shutdown.running_jobs();
+        let _ = tracing::warn!(
+            "Aborting {} jobs still running at shutdown",
+            job_ids.len()
+        );
+        let msg = JobAbortMessage::new(
+            None,
+            Some(SHUTDOWN_ABORT_REASON.to_owned()),
+            false,
+        );
+        for job_id in job_ids {
+            if let Err(err) =
+                command::publish_job_abort(job_id, &msg, app_data).await
+            {
+                let _ = tracing::error!("Failed to abort job {job_id}: {err}");
+            }
+        }
+
+        if !app_data.shutdown.wait_for_jobs(deadline).await {
+            let job_ids = app_data.shutdown.running_jobs();
+            let _ = tracing::warn!(
+                "{} jobs did not stop before the shutdown deadline",
+                job_ids.len()
+            );
+            if let Err(err) = mark_jobs_aborted(app_data, job_ids).await {
+                let _ =
+                    tracing::error!("Failed to mark jobs as aborted: {err}");
+            }
+        }
+    }
+}
+
+/// Jobs that outlived the deadline would stay pending forever otherwise
+async fn mark_jobs_aborted(
+    app_data: &AppData,
+    job_ids: Vec<Uuid>,
+) -> Result<(), DomainError> {
+    let status_message = JobAbortMessage::new(
+        None,
+        Some(SHUTDOWN_ABORT_REASON.to_owned()),
+        true,
+    )
+    .status_message();
+    let pool = app_data.pool.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        for job_id in job_ids {
+            actions::misc::update_job_status(
+                job_id,
+                JobStatus::Aborted,
+                Some(status_message.clone()),
+                &mut conn,
+            )?;
+        }
+        Ok::<_, DomainError>(())
+    })
+    .await??;
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::web;
+use rand::{distr::Alphanumeric, RngExt};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::webhook::{
+        delivery_retry_delay, is_public_ip, JobEvent, JobEventPayload,
+        NewWebhookDelivery, Webhook, WebhookDelivery, DELIVERY_HEADER,
+        EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+        WEBHOOK_SECRET_LENGTH,
+    },
+    AppData,
+};
+
+pub fn generate_webhook_secret() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(WEBHOOK_SECRET_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Client for webhook deliveries. Redirects are not followed and, unless
+/// `allow_private_targets`, host names resolving to internal addresses are
+/// refused, so that webhooks can't reach the internal network of the server.
+pub fn webhook_client(
+    allow_private_targets: bool,
+) -> reqwest::Result<reqwest::Client> {
+    let builder =
+        reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    let builder = if allow_private_targets {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicAddrResolver))
+    };
+    builder.build()
+}
+
+/// Resolves host names like the system resolver, failing when any of the
+/// addresses is internal. Checking at connect time instead of only when the
+/// webhook is saved keeps a host from being re-pointed at an internal
+/// address later.
+struct PublicAddrResolver;
+
+impl Resolve for PublicAddrResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public_addrs(name.as_str(), 0)
+                .await
+                .map_err(Box::<dyn std::error::Error + Send + Sync>::from)?;
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+async fn resolve_public_addrs(
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| format!("Failed to resolve {host}: {err}"))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(format!("{host} did not resolve to any address"));
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        return Err(format!(
+            "{host} resolves to the internal address {}",
+            addr.ip()
+        ));
+    }
+    Ok(addrs)
+}
+
+/// Checks that a webhook URL points at a public address, unless internal
+/// targets are allowed
+pub async fn check_webhook_target(
+    url: &str,
+    allow_private_targets: bool,
+) -> Result<(), String> {
+    if allow_private_targets {
+        return Ok(());
+    }
+    let url = url::Url::parse(url)
+        .map_err(|err| format!("Invalid webhook URL {url}: {err}"))?;
+    let host = match url.host() {
+        Some(url::Host::Domain(domain)) => domain.to_owned(),
+        // IP literals are connected to without going through the resolver
+        Some(url::Host::Ipv4(ip)) => return check_public_ip(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => return check_public_ip(IpAddr::V6(ip)),
+        None => return Err(format!("Webhook URL {url} has no host")),
+    };
+    let _ =
+        resolve_public_addrs(&host, url.port_or_known_default().unwrap_or(0))
+            .await?;
+    Ok(())
+}
+
+fn check_public_ip(ip: IpAddr) -> Result<(), String> {
+    if is_public_ip(ip) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Webhooks can't be sent to the internal address {ip}"
+        ))
+    }
+}
+
+/// Queues a delivery of `event` to every webhook of the job owner subscribed
+/// to it. Failing to queue is logged and otherwise ignored, webhooks must
+/// not get in the way of running the job.
+pub async fn notify_job_event(
+    app_data: &AppData,
+    job_id: uuid::Uuid,
+    event: JobEvent,
+) {
+    if let Err(err) = queue_job_event(app_data, job_id, event).await {
+        let _ = tracing::error!(
+            "Failed to queue {} webhooks of job {job_id}: {err}",
+            event.as_str()
+        );
+    }
+}
+
+async fn queue_job_event(
+    app_data: &AppData,
+    job_id: uuid::Uuid,
+    event: JobEvent,
+) -> Result<(), DomainError> {
+    let pool = app_data.pool.clone();
+    let queued = web::block(move || {
+        let mut conn = pool.get()?;
+        let Some(job) = actions::misc::get_job_by_uuid(job_id, &mut conn)?
+        else {
+            return Ok(0);
+        };
+        let Some(user_id) = job.started_by else {
+            return Ok(0);
+        };
+        let webhooks = actions::webhooks::get_subscribed_webhooks(
+            user_id, event, &mut conn,
+        )?;
+        if webhooks.is_empty() {
+            return Ok(0);
+        }
+        let payload = serde_json::to_value(JobEventPayload {
+            event,
+            job,
+            occurred_at: chrono::Utc::now().naive_utc(),
+        })
+        .map_err(|err| DomainError::new_internal_error(err.to_string()))?;
+        let deliveries = webhooks
+            .iter()
+            .map(|webhook| NewWebhookDelivery {
+                webhook_id: webhook.id,
+                job_id,
+                event: event.as_str().to_owned(),
+                payload: payload.clone(),
+            })
+            .collect::<Vec<_>>();
+        actions::webhooks::create_webhook_deliveries(&deliveries, &mut conn)
+    })
+    .await??;
+    if queued > 0 {
+        let _ = tracing::debug!(
+            "Queued {queued} {} webhook deliveries of job {job_id}",
+            event.as_str()
+        );
+    }
+    Ok(())
+}
+
+/// Deliveries claimed by a run of the delivery worker
+const DELIVERY_BATCH_SIZE: i64 = 50;
+
+/// Long enough for the whole batch to be sent one after another before
+/// anyone else picks the deliveries up again
+pub fn delivery_lease_secs(timeout_secs: u64) -> u64 {
+    timeout_secs
+        .saturating_mul(DELIVERY_BATCH_SIZE.unsigned_abs())
+        .saturating_add(60)
+}
+
+/// Sends the deliveries that are due, retrying failed ones with exponential
+/// backoff until the maximum number of attempts. Returns the number of
+/// deliveries that succeeded.
+pub async fn deliver_due_webhooks(
+    app_data: &AppData,
+) -> Result<usize, DomainError> {
+    let config = app_data.config.webhooks.clone();
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let now = chrono::Utc::now().naive_utc();
+    let lease_secs = delivery_lease_secs(config.timeout_secs);
+    let lease_until = now
+        + chrono::Duration::seconds(
+            i64::try_from(lease_secs).unwrap_or(i64::MAX),
+        );
+
+    let pool = app_data.pool.clone();
+    let deliveries = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::webhooks::claim_due_webhook_deliveries(
+            now,
+            lease_until,
+            DELIVERY_BATCH_SIZE,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let mut delivered = 0;
+    for (delivery, webhook) in deliveries {
+        // the rest is claimed again once the lease expired, by whichever
+        // instance gets to it first
+        let send_by = chrono::Utc::now().naive_utc()
+            + chrono::Duration::seconds(
+                i64::try_from(config.timeout_secs).unwrap_or(i64::MAX),
+            );
+        if send_by >= lease_until {
+            let _ = tracing::warn!(
+                "Webhook delivery lease is running out, leaving the rest of the batch"
+            );
+            break;
+        }
+        let delivery_id = delivery.id;
+        let res = send_delivery(app_data, &delivery, &webhook, timeout).await;
+        let pool = app_data.pool.clone();
+        match res {
+            Ok(status) => {
+                let _ = tracing::info!(
+                    "Delivered webhook {} of job {} to webhook {}",
+                    delivery.event,
+                    delivery.job_id,
+                    webhook.id
+                );
+                delivered += 1;
+                web::block(move || {
+                    let mut conn = pool.get()?;
+                    actions::webhooks::set_webhook_delivered(
+                        delivery_id,
+                        status,
+                        chrono::Utc::now().naive_utc(),
+                        &mut conn,
+                    )
+                })
+                .await??;
+            }
+            Err((status, error)) => {
+                let attempts = delivery.attempts + 1;
+                let next_attempt_at = (u32::try_from(attempts)
+                    .unwrap_or(u32::MAX)
+                    < config.max_attempts)
+                    .then(|| {
+                        chrono::Utc::now().naive_utc()
+                            + delivery_retry_delay(
+                                attempts,
+                                config.retry_initial_interval_secs,
+                                config.retry_max_interval_secs,
+                            )
+                    });
+                let _ = tracing::warn!(
+                    "Attempt {attempts} of webhook delivery {delivery_id} failed: {error}, next attempt at {next_attempt_at:?}"
+                );
+                web::block(move || {
+                    let mut conn = pool.get()?;
+                    actions::webhooks::set_webhook_delivery_failed(
+                        delivery_id,
+                        status,
+                        error,
+                        next_attempt_at,
+                        &mut conn,
+                    )
+                })
+                .await??;
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+/// Posts a delivery to its webhook, returning the response status on success
+/// and the status, if any, with an error otherwise
+async fn send_delivery(
+    app_data: &AppData,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+    timeout: Duration,
+) -> Result<i32, (Option<i32>, String)> {
+    // the address may have changed since the webhook was saved
+    check_webhook_target(
+        &webhook.url,
+        app_data.config.webhooks.allow_private_targets,
+    )
+    .await
+    .map_err(|err| (None, err))?;
+    let body = serde_json::to_string(&delivery.payload)
+        .map_err(|err| (None, err.to_string()))?;
+    let timestamp = chrono::Utc::now().timestamp();
+    let resp = app_data
+        .webhook_client
+        .post(&webhook.url)
+        .timeout(timeout)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, webhook.signature(timestamp, &body))
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| (None, err.to_string()))?;
+    let status = resp.status();
+    if status.is_success() {
+        Ok(i32::from(status.as_u16()))
+    } else {
+        Err((
+            Some(i32::from(status.as_u16())),
+            format!("Webhook responded with {status}"),
+        ))
+    }
+}
@@ -1,6 +1,12 @@
+mod message_cursor;
+pub use message_cursor::*;
+
 mod msg_receive_loop;
 pub use msg_receive_loop::*;
 
+mod user_stream;
+pub use user_stream::*;
+
 mod process_client_message;
 pub use process_client_message::*;
 
@@ -8,8 +14,14 @@ mod ws_loop;
 pub use ws_loop::*;
 
 mod handlers;
+pub use handlers::conversation_message::*;
+pub use handlers::job_input::*;
+pub use handlers::message_ack::*;
 pub use handlers::message_handler::*;
 pub use handlers::subscribe_job::*;
 
+mod reply;
+pub use reply::WsReply;
+
 mod session_extension;
 pub use session_extension::SessionExt;
@@ -1,2 +1,5 @@
+pub mod conversation_message;
+pub mod job_input;
+pub mod message_ack;
 pub mod message_handler;
 pub mod subscribe_job;
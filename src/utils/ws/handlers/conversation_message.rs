@@ -0,0 +1,91 @@
+use actix_web::web;
+use redis::aio::ConnectionManager;
+
+use crate::{
+    actions,
+    errors::DomainError,
+    models::{
+        conversation::NewConversationMessage, message::validate_message_text,
+        users::UserId, ws::UserStreamEvent,
+    },
+    utils::ws::{self, WsReply},
+    AppData,
+};
+
+/// Stores a message in the history of the conversation and fans it out to
+/// the message streams of all its members, the sender included so that their
+/// other devices see it too.
+///
+/// Only members can send, for others the command is rejected as if the
+/// conversation did not exist.
+pub async fn handle_send_conversation_message(
+    reply: WsReply,
+    conn: &mut ConnectionManager,
+    user_id: UserId,
+    conversation_id: i32,
+    message: String,
+    app_data: &AppData,
+) -> Result<(), DomainError> {
+    if let Err(cause) = validate_message_text(&message) {
+        return reply.nack(cause).await;
+    }
+
+    let pool = app_data.pool.clone();
+    let now = chrono::Utc::now().naive_utc();
+    let new_message = NewConversationMessage {
+        conversation_id,
+        sender_id: user_id,
+        message_text: message,
+    };
+    let stored = web::block(move || {
+        let mut conn = pool.get()?;
+        if actions::conversations::get_conversation_member(
+            conversation_id,
+            user_id,
+            &mut conn,
+        )?
+        .is_none()
+        {
+            return Ok(None);
+        }
+        let message = actions::conversations::create_conversation_message(
+            &new_message,
+            now,
+            &mut conn,
+        )?;
+        let members = actions::conversations::get_conversation_members(
+            conversation_id,
+            &mut conn,
+        )?;
+        Ok::<_, DomainError>(Some((message, members)))
+    })
+    .await??;
+    let Some((stored, members)) = stored else {
+        return reply
+            .nack(format!("No conversation with id: {conversation_id}"))
+            .await;
+    };
+
+    let member_ids = members
+        .iter()
+        .map(|member| member.user_id)
+        .collect::<Vec<_>>();
+    ws::publish_user_event(
+        conn,
+        app_data,
+        &member_ids,
+        &UserStreamEvent::ConversationMessage {
+            conversation_id,
+            message_id: stored.id,
+            sender: user_id,
+            message: stored.message_text,
+        },
+    )
+    .await?;
+    tracing::info!(
+        "Published message {} to {} members of conversation {conversation_id}",
+        stored.id,
+        member_ids.len()
+    );
+    reply.ack(Some(stored.id)).await
+}
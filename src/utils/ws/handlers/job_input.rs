@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use crate::{
+    errors::DomainError, models::users::UserId, routes, utils::ws::WsReply,
+    AppData,
+};
+
+pub async fn handle_job_input(
+    reply: WsReply,
+    job_id: uuid::Uuid,
+    data: String,
+    user_id: UserId,
+    app_data: Arc<AppData>,
+) -> Result<(), DomainError> {
+    let res =
+        routes::command::send_job_input(job_id, user_id, data, &app_data).await;
+
+    match res {
+        Ok(()) => reply.ack(None).await,
+        Err(err) => {
+            let cause = match &err {
+                DomainError::EntityDoesNotExistError { message }
+                | DomainError::AuthError { message }
+                | DomainError::BadInputError { message } => message.clone(),
+                _ => format!("Failed to send input to job {job_id}"),
+            };
+            reply.nack(cause).await?;
+            Err(err)
+        }
+    }
+}
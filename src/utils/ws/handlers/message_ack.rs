@@ -0,0 +1,24 @@
+use redis::aio::ConnectionManager;
+
+use crate::{
+    errors::DomainError,
+    models::{users::UserId, ws::MessageStreamId},
+    types::RedisPrefixFn,
+    utils::ws,
+};
+
+/// Records that the device received the messages up to `id`, a reconnect of
+/// the device resumes delivery after it
+pub async fn handle_ack_messages(
+    conn: &mut ConnectionManager,
+    user_id: UserId,
+    device_id: &str,
+    id: MessageStreamId,
+    redis_prefix: &RedisPrefixFn,
+) -> Result<(), DomainError> {
+    let cursor_key = ws::message_cursor_key(redis_prefix, user_id, device_id);
+    ws::store_message_cursor(conn, &cursor_key, id).await?;
+    let _ =
+        tracing::debug!("Device {device_id} acknowledged messages up to {id}");
+    Ok(())
+}
@@ -1,35 +1,66 @@
-use actix_ws::Session;
-use redis::{aio::ConnectionManager, AsyncCommands};
+use actix_web::web;
+use redis::aio::ConnectionManager;
 
 use crate::{
+    actions,
     errors::DomainError,
-    models::{users::UserId, ws::SentMessage},
-    types::RedisPrefixFn,
-    utils,
+    models::{
+        message::{validate_message_text, NewMessage},
+        users::UserId,
+        ws::UserStreamEvent,
+    },
+    utils::ws::{self, WsReply},
+    AppData,
 };
 
+/// Stores a message in the history and adds it to the stream of the
+/// receiver for live delivery. The stream is capped, messages trimmed from
+/// it are still in the history.
+///
+/// Invalid messages are rejected instead of closing the connection.
 pub async fn handle_send_message(
-    _session: Session,
+    reply: WsReply,
     conn: &mut ConnectionManager,
     user_id: UserId,
     receiver: UserId,
     message: String,
-    redis_prefix: &RedisPrefixFn,
+    app_data: &AppData,
 ) -> Result<(), DomainError> {
-    let chan_name = redis_prefix(&format!("messages.{receiver}"));
-    let id: String = conn
-        .xadd(
-            chan_name,
-            "*",
-            &[(
-                "message",
-                utils::jstr(&SentMessage {
-                    sender: user_id,
-                    message,
-                }),
-            )],
-        )
-        .await?;
-    tracing::info!("Published message with id={id}");
-    Ok(())
+    if let Err(cause) = validate_message_text(&message) {
+        return reply.nack(cause).await;
+    }
+
+    let pool = app_data.pool.clone();
+    let new_message = NewMessage {
+        sender_id: user_id,
+        receiver_id: receiver,
+        message_text: message,
+    };
+    let stored = web::block(move || {
+        let mut conn = pool.get()?;
+        if actions::users::find_active_user_by_uid(&receiver, &mut conn)?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        actions::messages::create_message(&new_message, &mut conn).map(Some)
+    })
+    .await??;
+    let Some(stored) = stored else {
+        return reply.nack(format!("No user with id: {receiver}")).await;
+    };
+
+    ws::publish_user_event(
+        conn,
+        app_data,
+        &[receiver],
+        &UserStreamEvent::SentMessage {
+            message_id: stored.id,
+            sender: user_id,
+            message: stored.message_text,
+        },
+    )
+    .await?;
+    tracing::info!("Published message {}", stored.id);
+    reply.ack(Some(stored.id)).await
 }
@@ -5,42 +5,45 @@ use std::sync::Arc;
 use crate::{
     actions,
     errors::DomainError,
-    models::ws::{MyProcessItem, WsServerEvent},
-    utils::{self, ws::SessionExt},
+    models::{
+        users::UserId,
+        ws::{MyProcessItem, WsServerEvent},
+    },
+    utils::{self, ws::WsReply},
     AppData,
 };
 use actix_web::web;
 
 pub async fn handle_subscribe_job(
     mut session: Session,
+    reply: WsReply,
     unverified_job_id: uuid::Uuid,
+    user_id: UserId,
     app_data: Arc<AppData>,
 ) -> Result<(), DomainError> {
-    let _ = tracing::info!("Verifying job exists before subscribing...");
+    let _ = tracing::info!(
+        "Verifying job exists and is visible before subscribing..."
+    );
     let pool = app_data.pool.clone();
-    let mb_job = web::block(move || {
+    let res = web::block(move || {
         let mut conn = pool.get()?;
-        actions::misc::get_job_by_uuid(unverified_job_id, &mut conn)
+        actions::misc::get_viewable_job(unverified_job_id, &user_id, &mut conn)
     })
-    .await??;
+    .await?;
 
-    let job = match mb_job {
-        Some(job) => {
+    let job = match res {
+        Ok(job) => {
             let _ = tracing::info!("Job with id: {unverified_job_id} exists.");
+            reply.ack(None).await?;
             Ok(job)
         }
-        None => {
-            let err = DomainError::new_entity_does_not_exist_error(format!(
-                "Job with id: {unverified_job_id} does not exist"
-            ));
-            session
-                .send_server_event(WsServerEvent::Error {
-                    id: None,
-                    cause: format!(
-                        "Job with id: {unverified_job_id} does not exist"
-                    ),
-                })
-                .await?;
+        Err(err) => {
+            let cause = match &err {
+                DomainError::EntityDoesNotExistError { message }
+                | DomainError::AuthError { message } => message.clone(),
+                _ => format!("Failed to load job {unverified_job_id}"),
+            };
+            reply.nack(cause).await?;
             Err(err)
         }
     }?;
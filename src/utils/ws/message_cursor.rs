@@ -0,0 +1,46 @@
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::{
+    errors::DomainError,
+    models::{
+        users::UserId,
+        ws::{MessageStreamId, MESSAGE_CURSOR_TTL_SECS},
+    },
+    types::RedisPrefixFn,
+};
+
+/// Key of the id of the last message a device acknowledged
+pub fn message_cursor_key(
+    redis_prefix: &RedisPrefixFn,
+    user_id: UserId,
+    device_id: &str,
+) -> String {
+    redis_prefix(&format!("messages.{user_id}.cursor.{device_id}"))
+}
+
+pub async fn load_message_cursor(
+    conn: &mut ConnectionManager,
+    cursor_key: &str,
+) -> Result<Option<MessageStreamId>, DomainError> {
+    let cursor: Option<String> = conn.get(cursor_key).await?;
+    Ok(cursor.and_then(|cursor| cursor.parse().ok()))
+}
+
+/// Moves the cursor of a device forward to `id`, acks of messages before
+/// the cursor are ignored
+pub async fn store_message_cursor(
+    conn: &mut ConnectionManager,
+    cursor_key: &str,
+    id: MessageStreamId,
+) -> Result<(), DomainError> {
+    let cursor = load_message_cursor(conn, cursor_key).await?;
+    if cursor.is_some_and(|cursor| cursor >= id) {
+        let _ =
+            tracing::debug!("Ignoring ack of {id}, cursor is at {cursor:?}");
+        return Ok(());
+    }
+    let () = conn
+        .set_ex(cursor_key, id.to_string(), MESSAGE_CURSOR_TTL_SECS)
+        .await?;
+    Ok(())
+}
@@ -3,37 +3,57 @@ use std::{sync::Arc, time::Duration};
 use crate::{
     errors::DomainError,
     models::users::UserId,
-    models::ws::{SentMessage, WsServerEvent},
-    utils::{self, RedisChannelReader, RedisReply, RedisReplyKind},
+    models::ws::{StoredUserStreamEvent, UserStreamEvent, WsServerEvent},
+    utils::{self, ws, RedisChannelReader, RedisReply, RedisReplyKind},
     AppData,
 };
 
-use actix_rt::time::sleep;
 use actix_ws::Session;
-use redis::{aio::ConnectionManager, streams::StreamReadOptions};
+use redis::{
+    aio::ConnectionManager, streams::StreamRangeReply,
+    streams::StreamReadOptions, AsyncCommands,
+};
+
+/// How long a read of the message stream blocks before it is retried
+pub const MESSAGE_READ_BLOCK: Duration = Duration::from_secs(5);
+const READ_COUNT: usize = 50;
 
+/// Delivers the messages sent to the user to one of their devices
+///
+/// Delivery resumes after the last message the device acknowledged, a device
+/// that never did starts with the messages sent from now on. Messages that
+/// were delivered but not acknowledged are delivered again on reconnect.
 pub async fn msg_receive_loop(
     user_id: UserId,
-    cm: ConnectionManager,
+    device_id: String,
+    mut cm: ConnectionManager,
     mut session: Session,
     app_data: Arc<AppData>,
 ) -> Result<(), DomainError> {
     let _ = tracing::info!("Starting message channel receive loop ");
 
-    let opts = StreamReadOptions::default().block(500).count(5);
-
     let redis_prefix = app_data.redis_prefix.as_ref();
+    let stream_key = redis_prefix(&format!("messages.{user_id}"));
+    let cursor_key = ws::message_cursor_key(redis_prefix, user_id, &device_id);
+
+    let start_id = match ws::load_message_cursor(&mut cm, &cursor_key).await? {
+        Some(cursor) => cursor.to_string(),
+        None => latest_stream_id(&mut cm, &stream_key).await?,
+    };
+    let _ = tracing::info!("Delivering messages after {start_id}");
 
-    let mut messages_reader = RedisChannelReader::<SentMessage>::new(
-        redis_prefix(&format!("messages.{user_id}")),
+    // the connection is only used by this loop, so reads can block on it
+    let opts = StreamReadOptions::default()
+        .block(MESSAGE_READ_BLOCK.as_millis() as usize)
+        .count(READ_COUNT);
+    let mut messages_reader = RedisChannelReader::<StoredUserStreamEvent>::new(
+        stream_key,
         cm,
-        None,
+        Some(start_id),
         opts,
     );
 
-    let mut running = true;
-    while running {
-        sleep(Duration::from_millis(500)).await;
+    loop {
         let msgs = messages_reader.get_items().await?;
         let len = msgs.len();
         if len > 0 {
@@ -45,11 +65,7 @@ pub async fn msg_receive_loop(
                 RedisReply {
                     id,
                     kind: RedisReplyKind::Ok { data },
-                } => WsServerEvent::SentMessage {
-                    id,
-                    sender: data.sender,
-                    message: data.message,
-                },
+                } => to_server_event(id, data.into()),
                 RedisReply {
                     id,
                     kind: RedisReplyKind::Error { cause },
@@ -58,12 +74,71 @@ pub async fn msg_receive_loop(
                     cause,
                 },
             };
-            let res = session.text(utils::jstr(&msg)).await;
-            let _ = if res.is_err() {
-                running = false;
-                break;
-            };
+            if session.text(utils::jstr(&msg)).await.is_err() {
+                let _ = tracing::info!("Session closed, stopping delivery");
+                return Ok(());
+            }
         }
     }
-    Ok(())
+}
+
+fn to_server_event(id: String, event: UserStreamEvent) -> WsServerEvent {
+    match event {
+        UserStreamEvent::SentMessage {
+            message_id,
+            sender,
+            message,
+        } => WsServerEvent::SentMessage {
+            id,
+            message_id,
+            sender,
+            message,
+        },
+        UserStreamEvent::ConversationMessage {
+            conversation_id,
+            message_id,
+            sender,
+            message,
+        } => WsServerEvent::ConversationMessage {
+            id,
+            conversation_id,
+            message_id,
+            sender,
+            message,
+        },
+        UserStreamEvent::ConversationUpdated {
+            conversation_id,
+            change,
+        } => WsServerEvent::ConversationUpdated {
+            id,
+            conversation_id,
+            change,
+        },
+        UserStreamEvent::PresenceChanged {
+            user_id,
+            online,
+            last_seen_at,
+        } => WsServerEvent::PresenceChanged {
+            id,
+            user_id,
+            online,
+            last_seen_at,
+        },
+        UserStreamEvent::Notification { notification } => {
+            WsServerEvent::Notification { id, notification }
+        }
+    }
+}
+
+/// Id of the newest entry of the stream, `0-0` when it is empty
+async fn latest_stream_id(
+    conn: &mut ConnectionManager,
+    stream_key: &str,
+) -> Result<String, DomainError> {
+    let reply: StreamRangeReply =
+        conn.xrevrange_count(stream_key, "+", "-", 1).await?;
+    Ok(reply
+        .ids
+        .first()
+        .map_or_else(|| "0-0".to_owned(), |entry| entry.id.clone()))
 }
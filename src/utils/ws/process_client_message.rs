@@ -16,8 +16,10 @@ use crate::utils::ws;
 /// # Arguments
 /// * `ws_msg` - The WebSocket message received from client
 /// * `session` - Active WebSocket session
+/// * `reply` - Answers the command according to the protocol of the session
 /// * `conn` - Redis connection manager for pub/sub operations
 /// * `user_id` - ID of the authenticated user
+/// * `device_id` - Device of the session the connection belongs to
 /// * `app_data` - Shared application data
 ///
 /// # Returns
@@ -31,11 +33,12 @@ use crate::utils::ws;
 pub async fn process_client_msg(
     ws_msg: WsClientEvent,
     session: Session,
+    reply: ws::WsReply,
     conn: &mut ConnectionManager,
     user_id: UserId,
+    device_id: &str,
     app_data: Arc<AppData>,
 ) -> Result<(), DomainError> {
-    let redis_prefix = &app_data.redis_prefix;
     tracing::debug!("Processing WebSocket message: {:?}", ws_msg);
     match ws_msg {
         WsClientEvent::SendMessage { receiver, message } => {
@@ -45,31 +48,70 @@ pub async fn process_client_msg(
                 receiver
             );
             ws::handle_send_message(
-                session,
+                reply, conn, user_id, receiver, message, &app_data,
+            )
+            .await
+        }
+        WsClientEvent::SendConversationMessage {
+            conversation_id,
+            message,
+        } => {
+            tracing::info!(
+                "Handling message from user {} to conversation {}",
+                user_id,
+                conversation_id
+            );
+            ws::handle_send_conversation_message(
+                reply,
                 conn,
                 user_id,
-                receiver,
+                conversation_id,
                 message,
-                redis_prefix,
+                &app_data,
             )
             .await
         }
+        WsClientEvent::AckMessages { id } => {
+            ws::handle_ack_messages(
+                conn,
+                user_id,
+                device_id,
+                id,
+                &app_data.redis_prefix,
+            )
+            .await?;
+            reply.ack(None).await
+        }
         WsClientEvent::Error { cause } => {
             let _ = tracing::error!("client indicated error {}", cause);
-            Ok(())
+            reply.ack(None).await
         }
         WsClientEvent::SubscribeJob { job_id } => {
             tracing::info!("User {} subscribing to job {}", user_id, job_id);
             actix_rt::spawn(
                 async move {
-                    let res =
-                        ws::handle_subscribe_job(session, job_id, app_data)
-                            .await;
+                    let res = ws::handle_subscribe_job(
+                        session, reply, job_id, user_id, app_data,
+                    )
+                    .await;
                     tracing::info!("Job subscription ended: {res:?}");
                 }
                 .instrument(tracing::info_span!("job_subscribe_loop")),
             );
             Ok(())
         }
+        WsClientEvent::JobInput { job_id, data } => {
+            let _ = tracing::debug!(
+                "User {user_id} sending {} bytes of input to job {job_id}",
+                data.len()
+            );
+            let res =
+                ws::handle_job_input(reply, job_id, data, user_id, app_data)
+                    .await;
+            if let Err(err) = res {
+                let _ = tracing::warn!("Failed to send job input: {err:?}");
+            }
+            Ok(())
+        }
     }
 }
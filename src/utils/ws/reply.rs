@@ -0,0 +1,64 @@
+use actix_ws::Session;
+
+use crate::{
+    errors::DomainError,
+    models::ws::{WsProtocol, WsServerEvent},
+    utils::ws::SessionExt,
+};
+
+/// Answers a client command according to the protocol of the connection.
+/// With v1 only rejected commands are answered, with an error event without
+/// an id. Newer versions answer each command with an `Ack` or a `Nack`
+/// carrying its request id.
+#[derive(Clone)]
+pub struct WsReply {
+    session: Session,
+    protocol: WsProtocol,
+    request_id: Option<String>,
+}
+
+impl WsReply {
+    pub fn new(
+        session: Session,
+        protocol: WsProtocol,
+        request_id: Option<String>,
+    ) -> Self {
+        Self {
+            session,
+            protocol,
+            request_id,
+        }
+    }
+
+    pub async fn ack(self, message_id: Option<i32>) -> Result<(), DomainError> {
+        let Self {
+            mut session,
+            protocol,
+            request_id,
+        } = self;
+        match protocol {
+            WsProtocol::V1 => Ok(()),
+            WsProtocol::V2 => {
+                session
+                    .send_server_event(WsServerEvent::Ack {
+                        request_id,
+                        message_id,
+                    })
+                    .await
+            }
+        }
+    }
+
+    pub async fn nack(self, cause: String) -> Result<(), DomainError> {
+        let Self {
+            mut session,
+            protocol,
+            request_id,
+        } = self;
+        let event = match protocol {
+            WsProtocol::V1 => WsServerEvent::Error { id: None, cause },
+            WsProtocol::V2 => WsServerEvent::Nack { request_id, cause },
+        };
+        session.send_server_event(event).await
+    }
+}
@@ -0,0 +1,54 @@
+use redis::{aio::ConnectionManager, streams::StreamMaxlen};
+
+use crate::{
+    errors::DomainError,
+    models::{users::UserId, ws::UserStreamEvent},
+    utils, AppData,
+};
+
+/// Adds the event to the message streams of the users, from which it is
+/// delivered to each of their devices. The streams are capped, events
+/// trimmed from them are lost for devices that were behind.
+pub async fn publish_user_event(
+    conn: &mut ConnectionManager,
+    app_data: &AppData,
+    user_ids: &[UserId],
+    event: &UserStreamEvent,
+) -> Result<(), DomainError> {
+    append_user_event(
+        conn,
+        &(app_data.redis_prefix)(&"messages"),
+        app_data.config.message_stream_max_entries,
+        user_ids,
+        event,
+    )
+    .await
+}
+
+/// Same as `publish_user_event`, for callers without the `AppData`. The
+/// stream of a user is `{streams_key}.{user_id}`.
+pub async fn append_user_event(
+    conn: &mut ConnectionManager,
+    streams_key: &str,
+    max_entries: usize,
+    user_ids: &[UserId],
+    event: &UserStreamEvent,
+) -> Result<(), DomainError> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+    let payload = utils::jstr(event);
+    let mut pipe = redis::pipe();
+    for user_id in user_ids {
+        let _ = pipe
+            .xadd_maxlen(
+                format!("{streams_key}.{user_id}"),
+                StreamMaxlen::Approx(max_entries),
+                "*",
+                &[("message", &payload)],
+            )
+            .ignore();
+    }
+    let () = pipe.query_async(conn).await?;
+    Ok(())
+}
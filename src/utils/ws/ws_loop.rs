@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use crate::{
-    errors::DomainError, models::users::UserId, models::ws::WsClientEvent,
-    models::ws::WsServerEvent, utils, AppData,
+    errors::DomainError,
+    models::users::UserId,
+    models::ws::{WsClientFrame, WsProtocol},
+    AppData,
 };
 
-use utils::ws;
+use crate::utils::ws;
 
-use actix_ws::{Message, MessageStream, Session};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
 use futures::StreamExt;
 use redis::aio::ConnectionManager;
 
@@ -36,13 +38,16 @@ impl Drop for WsConnectionGuard<'_> {
 /// Main WebSocket message processing loop
 ///
 /// Handles incoming WebSocket messages and processes them accordingly.
-/// Manages the WebSocket session and connection state.
+/// Manages the WebSocket session and connection state, closing it as going
+/// away once the server shuts down.
 ///
 /// # Arguments
 /// * `session` - WebSocket session
 /// * `msg_stream` - Stream of incoming WebSocket messages
 /// * `conn` - Redis connection manager
 /// * `user_id` - ID of the connected user
+/// * `device_id` - Device of the session the connection belongs to
+/// * `protocol` - Protocol version negotiated for the connection
 /// * `app_data` - Shared application data
 ///
 /// # Returns
@@ -52,6 +57,8 @@ pub async fn ws_loop(
     mut msg_stream: MessageStream,
     conn: &mut ConnectionManager,
     user_id: UserId,
+    device_id: &str,
+    protocol: WsProtocol,
     app_data: Arc<AppData>,
 ) -> Result<(), DomainError> {
     let _guard = WsConnectionGuard::new(
@@ -60,7 +67,26 @@ pub async fn ws_loop(
     );
     tracing::info!("Starting WebSocket loop for user {}", user_id);
 
-    while let Some(item) = msg_stream.next().await {
+    loop {
+        let item = tokio::select! {
+            item = msg_stream.next() => item,
+            _ = app_data.shutdown.cancelled() => {
+                tracing::info!(
+                    "Server is shutting down, closing connection of user {}",
+                    user_id
+                );
+                let _ = session
+                    .close(Some(CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("Server is shutting down".to_owned()),
+                    }))
+                    .await;
+                return Ok(());
+            }
+        };
+        let Some(item) = item else {
+            break;
+        };
         match item {
             Ok(Message::Ping(bytes)) => {
                 tracing::debug!("Received ping message");
@@ -73,29 +99,46 @@ pub async fn ws_loop(
                 tracing::info!("Received text message from user {}", user_id);
                 tracing::debug!("Message content: {}", s);
 
-                let res = match serde_json::from_str::<WsClientEvent>(&s) {
-                    Ok(ws_msg) => {
+                let res = match serde_json::from_str::<WsClientFrame>(&s) {
+                    Ok(WsClientFrame { request_id, event }) => {
                         tracing::debug!(
-                            "Processing client message: {:?}",
-                            ws_msg
+                            "Processing client message {:?}: {:?}",
+                            request_id,
+                            event
+                        );
+                        let reply = ws::WsReply::new(
+                            session.clone(),
+                            protocol,
+                            request_id,
                         );
-                        Ok(ws::process_client_msg(
-                            ws_msg,
+                        let res = ws::process_client_msg(
+                            event,
                             session.clone(),
+                            reply.clone(),
                             conn,
                             user_id,
+                            device_id,
                             app_data.clone(),
                         )
-                        .await?)
+                        .await;
+                        if res.is_err() && protocol != WsProtocol::V1 {
+                            let _ = reply
+                                .nack(
+                                    "Failed to process the command".to_owned(),
+                                )
+                                .await;
+                        }
+                        Ok(res?)
                     }
                     Err(err) => {
                         tracing::warn!("Failed to parse message: {}", err);
-                        let err = &WsServerEvent::Error {
-                            id: None,
-                            cause: err.to_string(),
-                        };
-                        let err = utils::jstr(err);
-                        session.text(err).await
+                        ws::WsReply::new(
+                            session.clone(),
+                            protocol,
+                            request_id_of(&s),
+                        )
+                        .nack(err.to_string())
+                        .await
                     }
                 };
 
@@ -126,3 +169,12 @@ pub async fn ws_loop(
     tracing::info!("WebSocket loop ended for user {}", user_id);
     Ok(())
 }
+
+/// Request id of a frame that failed to parse, so that it can be rejected
+fn request_id_of(frame: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(frame)
+        .ok()?
+        .get("request_id")?
+        .as_str()
+        .map(str::to_owned)
+}
@@ -1,100 +1,451 @@
-use std::time::Duration;
+mod registry;
 
-use tokio::{task::JoinHandle, time::sleep};
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use actix_web::web;
+use diesel::Connection;
+use futures::future::join_all;
+
+pub use registry::*;
 
 use crate::{
     actions,
     errors::DomainError,
-    models::{users::UserId, worker::WorkerConfig},
-    types::DbPool,
+    metrics::JobTemplate,
+    models::{
+        misc::JobStatus,
+        runner::MAX_JOB_REQUEUES,
+        schedule::MissedRunPolicy,
+        session::SESSION_CLEANUP_BATCH_SIZE,
+        webhook::JobEvent,
+        worker::{WorkerConfig, WorkerFence},
+        ws::MyProcessItem,
+    },
+    routes::command::{self, RunCommandRequest, StartJobOptions},
     utils::{
-        redis_credentials_repo::RedisCredentialsRepo, InstrumentedRedisCache,
+        job_artifacts, job_output,
+        redis_credentials_repo::RedisCredentialsRepo, webhooks,
     },
+    AppData,
 };
 
-pub async fn start_sessions_cleanup_worker(
+/// Removes expired sessions in batches, going through the active sessions
+/// index instead of the sessions of every user. The first run also indexes
+/// the sessions from before the index existed.
+pub fn sessions_cleanup_worker(
     config: WorkerConfig,
     credentials_repo: RedisCredentialsRepo,
-    user_ids_cache: InstrumentedRedisCache<String, Vec<UserId>>,
-    pool: DbPool,
-) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        // TODO backoff is unmaintained, use a different crate
-        let policy = backoff::ExponentialBackoffBuilder::new()
-            .with_initial_interval(Duration::from_secs(
-                config.backoff.initial_interval_secs,
-            ))
-            .with_multiplier(config.backoff.multiplier)
-            .with_max_interval(Duration::from_secs(
-                config.backoff.max_interval_secs,
-            ))
-            .with_max_elapsed_time(Some(Duration::from_secs(
-                config.backoff.max_elapsed_time_secs,
-            )))
-            .build();
-
-        loop {
-            let _ = tracing::debug!("Running sessions cleanup");
-            let mut conn = match pool.get() {
-                Ok(conn) => conn,
-                Err(err) => {
-                    let _ = tracing::error!("Failed to get connection: {err}");
-                    sleep(Duration::from_secs(5)).await;
-                    continue;
+) -> Worker {
+    let indexed = Rc::new(Cell::new(false));
+    Worker::new(
+        "sessions-cleanup",
+        Duration::from_secs(config.run_interval.into()),
+        config.backoff,
+        move |_ctx| {
+            let credentials_repo = credentials_repo.clone();
+            let indexed = indexed.clone();
+            async move {
+                if !indexed.get() {
+                    let count = credentials_repo.index_sessions().await?;
+                    if count > 0 {
+                        let _ =
+                            tracing::info!("Indexed {count} existing sessions");
+                    }
+                    indexed.set(true);
                 }
-            };
 
-            let user_ids_cache = user_ids_cache.clone();
+                let mut removed = 0;
+                loop {
+                    let batch = credentials_repo
+                        .remove_expired_sessions(SESSION_CLEANUP_BATCH_SIZE)
+                        .await?;
+                    removed += batch;
+                    // More sessions expired than fit into a batch
+                    if batch < SESSION_CLEANUP_BATCH_SIZE {
+                        break;
+                    }
+                }
+                Ok(removed)
+            }
+        },
+    )
+}
 
-            let user_ids = match tokio::task::spawn_blocking(move || {
-                actions::users::get_all_user_ids(&user_ids_cache, &mut conn)
-            })
+/// Starts scheduled jobs when they come due
+///
+/// Only the leader looks for due schedules. Jobs are started through
+/// [`command::start_job`], so this has to run on an actix runtime.
+pub fn job_scheduler_worker(app_data: web::Data<AppData>) -> Worker {
+    Worker::new(
+        "job-scheduler",
+        Duration::from_secs(app_data.config.job_scheduler.interval_secs),
+        app_data.config.worker_backoff.clone(),
+        move |ctx| {
+            let app_data = app_data.clone();
+            async move { run_due_job_schedules(&app_data, ctx.fence()).await }
+        },
+    )
+}
+
+/// Starts a job for every schedule that is due and moves the schedules on to
+/// their next run. Moving a schedule on fails once `fence` is stale. Returns
+/// the number of jobs started.
+pub async fn run_due_job_schedules(
+    app_data: &web::Data<AppData>,
+    fence: Option<WorkerFence>,
+) -> Result<usize, DomainError> {
+    let now = chrono::Utc::now().naive_utc();
+    let tz = app_data.config.timezone;
+    let tolerance = chrono::Duration::seconds(
+        i64::try_from(app_data.config.job_scheduler.missed_run_tolerance_secs)
+            .unwrap_or(i64::MAX),
+    );
+
+    let pool = app_data.pool.clone();
+    let schedules = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::schedules::get_due_job_schedules(now, &mut conn)
+    })
+    .await??;
+
+    let mut started = 0;
+    for schedule in schedules {
+        let schedule_id = schedule.id;
+        let next_run_at = schedule
+            .cron_expression()
+            .ok()
+            .and_then(|expr| expr.next_run_after(now, tz));
+        let missed = schedule
+            .next_run_at
+            .is_some_and(|due| now - due > tolerance);
+        let run =
+            !missed || schedule.missed_run_policy == MissedRunPolicy::RunOnce;
+
+        let pool = app_data.pool.clone();
+        let claimed_schedule = schedule.clone();
+        let claimed = web::block(move || {
+            let mut conn = pool.get()?;
+            actions::schedules::claim_job_schedule_run(
+                &claimed_schedule,
+                next_run_at,
+                run.then_some(now),
+                fence.as_ref(),
+                &mut conn,
+            )
+        })
+        .await??;
+
+        if !claimed {
+            let _ = tracing::debug!(
+                "Run of schedule {schedule_id} was already claimed"
+            );
+        } else if !run {
+            let _ = tracing::info!(
+                "Skipping missed run of schedule {schedule_id} due at {:?}",
+                schedule.next_run_at
+            );
+        } else {
+            let _ = tracing::info!("Starting job for schedule {schedule_id}");
+            let req = RunCommandRequest {
+                args: schedule.args,
+                pty: schedule.pty,
+                retry: schedule.retry_policy(),
+                artifacts: schedule.artifacts,
+                runs_on: Vec::new(),
+            };
+            match command::start_job(
+                app_data.clone(),
+                schedule.created_by,
+                req,
+                StartJobOptions {
+                    template: JobTemplate::Schedule,
+                    ..StartJobOptions::default()
+                },
+            )
             .await
             {
-                Ok(Ok(ids)) => ids,
-                Ok(Err(err)) => {
-                    let _ = tracing::error!("Failed to get user IDs: {err}");
-                    sleep(Duration::from_secs(5)).await;
-                    continue;
+                Ok(job) => {
+                    let pool = app_data.pool.clone();
+                    web::block(move || {
+                        let mut conn = pool.get()?;
+                        actions::schedules::set_job_schedule_last_job(
+                            schedule_id,
+                            job.job_id,
+                            &mut conn,
+                        )
+                    })
+                    .await??;
+                    started += 1;
                 }
                 Err(err) => {
                     let _ = tracing::error!(
-                        "Failed to execute blocking task: {err}"
+                        "Failed to start job for schedule {schedule_id}: {err}"
                     );
-                    sleep(Duration::from_secs(5)).await;
-                    continue;
                 }
-            };
-
-            for user_id in user_ids {
-                let operation = || async {
-                    let _ = tracing::debug!(
-                    "Attempting to clear expired sessions for user: {user_id}"
-                );
-
-                    credentials_repo
-                    .cleanup_expired_session_ids(&user_id)
-                    .await
-                    .map_err(|err| {
-                        backoff::Error::transient(
-                            DomainError::new_internal_error(format!(
-                                "Session cleanup failed for user: {user_id}: {err}"
-                            ))
-                        )
-                    })
-                };
+            }
+        }
+    }
 
-                let retry_result =
-                    backoff::future::retry(policy.clone(), operation).await;
+    Ok(started)
+}
 
-                if let Err(err) = retry_result {
-                    let _ = tracing::error!(
-                    "Permanent failure cleaning sessions for user: {user_id}: {err}"
-                );
-                }
+/// Deletes job artifacts once they are past their retention period
+///
+/// Like the job scheduler, only the leader does the cleanup.
+pub fn job_artifacts_cleanup_worker(app_data: web::Data<AppData>) -> Worker {
+    let config = &app_data.config.job_artifacts;
+    let interval = Duration::from_secs(config.cleanup_interval_secs);
+    let retention = chrono::Duration::days(config.retention_days.into());
+    Worker::new(
+        "job-artifacts-cleanup",
+        interval,
+        app_data.config.worker_backoff.clone(),
+        move |_ctx| {
+            let app_data = app_data.clone();
+            async move {
+                let before = chrono::Utc::now().naive_utc() - retention;
+                cleanup_expired_job_artifacts(&app_data, before).await
             }
+        },
+    )
+}
 
-            sleep(Duration::from_secs(config.run_interval.into())).await;
+/// Deletes the artifacts created before `before` from MinIO and the database.
+/// Returns the number of artifacts deleted.
+pub async fn cleanup_expired_job_artifacts(
+    app_data: &web::Data<AppData>,
+    before: chrono::NaiveDateTime,
+) -> Result<usize, DomainError> {
+    const BATCH_SIZE: i64 = 500;
+    let mut deleted = 0;
+
+    loop {
+        let pool = app_data.pool.clone();
+        let artifacts = web::block(move || {
+            let mut conn = pool.get()?;
+            actions::artifacts::get_expired_job_artifacts(
+                before, BATCH_SIZE, &mut conn,
+            )
+        })
+        .await??;
+        if artifacts.is_empty() {
+            break;
+        }
+
+        // Rows are only removed once their object is gone, so a failed
+        // deletion is retried on the next run
+        let mut artifact_ids = Vec::with_capacity(artifacts.len());
+        for artifact in &artifacts {
+            job_artifacts::delete_artifact_object(
+                &artifact.object_key,
+                app_data,
+            )
+            .await?;
+            artifact_ids.push(artifact.id);
+        }
+
+        let pool = app_data.pool.clone();
+        deleted += web::block(move || {
+            let mut conn = pool.get()?;
+            actions::artifacts::delete_job_artifacts(&artifact_ids, &mut conn)
+        })
+        .await??;
+
+        if artifacts.len() < BATCH_SIZE as usize {
+            break;
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Sends queued webhook deliveries as they come due
+///
+/// Deliveries are claimed with row locks, so every instance runs this
+/// worker without a leader.
+pub fn webhook_delivery_worker(app_data: web::Data<AppData>) -> Worker {
+    let config = app_data.config.webhooks.clone();
+    Worker::new(
+        "webhook-delivery",
+        Duration::from_secs(config.delivery_interval_secs),
+        app_data.config.worker_backoff.clone(),
+        move |_ctx| {
+            let app_data = app_data.clone();
+            async move { webhooks::deliver_due_webhooks(&app_data).await }
+        },
+    )
+    .on_every_instance()
+    // a run stops sending once the lease of its batch runs out
+    .with_max_run_time(Duration::from_secs(
+        webhooks::delivery_lease_secs(config.timeout_secs)
+            .saturating_add(config.timeout_secs),
+    ))
+}
+
+/// Checks the dependencies of the instance and exports the results as the
+/// `dependency_up` gauges, which keeps the results cached for the probes too
+///
+/// Every instance has its own view of the dependencies, so it runs on every
+/// instance.
+pub fn dependency_prober_worker(app_data: web::Data<AppData>) -> Worker {
+    Worker::new(
+        "dependency-prober",
+        Duration::from_secs(app_data.config.health_probe_interval_secs),
+        app_data.config.worker_backoff.clone(),
+        move |_ctx| {
+            let app_data = app_data.clone();
+            async move { probe_dependencies(&app_data).await }
+        },
+    )
+    .on_every_instance()
+}
+
+/// Runs the dependency health checks and updates their gauges. Returns the
+/// number of dependencies checked.
+pub async fn probe_dependencies(
+    app_data: &web::Data<AppData>,
+) -> Result<usize, DomainError> {
+    let timeout =
+        Duration::from_secs(app_data.config.health_check_timeout_secs.into());
+    let cache_ttl =
+        Duration::from_secs(app_data.config.health_check_cache_ttl_secs);
+    let results = join_all(app_data.health_checks.dependencies().map(
+        |check| async move { (check, check.run(timeout, cache_ttl).await) },
+    ))
+    .await;
+
+    let mut down = Vec::new();
+    for (check, health) in &results {
+        let up = health.is_healthy();
+        if !up {
+            down.push(check.name.as_str());
         }
+        app_data
+            .metrics
+            .dependency_up
+            .with_label_values(&[check.name.as_str()])
+            .set(i64::from(up));
+    }
+    if !down.is_empty() {
+        let _ = tracing::warn!(
+            "{} of {} dependencies are down: {}",
+            down.len(),
+            results.len(),
+            down.join(", ")
+        );
+    }
+    Ok(results.len())
+}
+
+/// Puts the jobs of runners that stopped sending heartbeats back in the queue
+///
+/// Only the leader looks for dead runners.
+pub fn runner_monitor_worker(app_data: web::Data<AppData>) -> Worker {
+    Worker::new(
+        "runner-monitor",
+        Duration::from_secs(app_data.config.runners.monitor_interval_secs),
+        app_data.config.worker_backoff.clone(),
+        move |ctx| {
+            let app_data = app_data.clone();
+            async move { requeue_dead_runner_jobs(&app_data, ctx.fence()).await }
+        },
+    )
+}
+
+/// Requeues the unfinished jobs of runners silent for longer than the
+/// heartbeat timeout. Jobs requeued too often fail instead and aborted jobs
+/// are just finished, jobs their runner finished in the meantime are left
+/// alone. Handling a job fails once `fence` is stale. Returns the number of
+/// jobs handled.
+pub async fn requeue_dead_runner_jobs(
+    app_data: &web::Data<AppData>,
+    fence: Option<WorkerFence>,
+) -> Result<usize, DomainError> {
+    let now = chrono::Utc::now().naive_utc();
+    let heartbeat_before = now
+        - chrono::Duration::seconds(
+            i64::try_from(app_data.config.runners.heartbeat_timeout_secs)
+                .unwrap_or(i64::MAX),
+        );
+
+    let pool = app_data.pool.clone();
+    let runner_jobs = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::runners::get_jobs_of_dead_runners(heartbeat_before, &mut conn)
     })
+    .await??;
+
+    let mut handled = 0;
+    for runner_job in &runner_jobs {
+        let job_id = runner_job.job_id;
+        let Some(runner_id) = runner_job.runner_id else {
+            continue;
+        };
+
+        let pool = app_data.pool.clone();
+        let status = web::block(move || {
+            let mut conn = pool.get()?;
+            conn.transaction(|conn| {
+                if let Some(fence) = &fence {
+                    actions::workers::check_worker_fence(fence, conn)?;
+                }
+                // the runner may have finished the job since it was listed
+                let Some(runner_job) = actions::runners::lock_dead_runner_job(
+                    job_id, runner_id, conn,
+                )?
+                else {
+                    return Ok(None);
+                };
+                if !runner_job.abort_requested
+                    && runner_job.requeues < MAX_JOB_REQUEUES
+                {
+                    let requeued = actions::runners::requeue_runner_job(
+                        job_id, runner_id, conn,
+                    )?;
+                    Ok(requeued.then_some(JobStatus::Pending))
+                } else {
+                    actions::runners::complete_runner_job(
+                        job_id,
+                        JobStatus::Failed,
+                        Some("Runner stopped responding".to_owned()),
+                        chrono::Utc::now().naive_utc(),
+                        conn,
+                    )
+                    .map(Some)
+                }
+            })
+        })
+        .await??;
+        let Some(status) = status else {
+            let _ = tracing::debug!(
+                "Job {job_id} was finished by its runner in the meantime"
+            );
+            continue;
+        };
+        handled += 1;
+
+        let value = if status == JobStatus::Pending {
+            "Runner stopped responding, job requeued"
+        } else {
+            "Runner stopped responding"
+        };
+        let _ = tracing::warn!("Job {job_id}: {value}");
+        job_output::publish_job_output(
+            app_data,
+            job_id,
+            &MyProcessItem::Line {
+                value: value.to_owned(),
+            },
+        )
+        .await?;
+        if status == JobStatus::Failed {
+            webhooks::notify_job_event(app_data, job_id, JobEvent::Failed)
+                .await;
+        }
+        if status != JobStatus::Pending {
+            app_data.notifier.notify_job_finished(job_id, status).await;
+        }
+    }
+
+    Ok(handled)
 }
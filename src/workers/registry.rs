@@ -0,0 +1,614 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use backoff::backoff::Backoff;
+use futures::future::LocalBoxFuture;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use tokio::{task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    errors::DomainError,
+    models::worker::{
+        WorkerBackoffConfig, WorkerFence, WorkerLeader, WorkerRunStatus,
+        WorkerStatus,
+    },
+};
+
+/// Shortest lease of a leader, for workers with short intervals
+const MIN_LEASE: Duration = Duration::from_secs(5);
+
+/// How long a run may take by default before the worker counts as hung
+const DEFAULT_MAX_RUN_TIME: Duration = Duration::from_secs(10 * 60);
+
+/// Takes the lease when it is free, or extends it when this instance already
+/// holds it. Every new lease gets the next fencing token of the worker.
+/// Returns the fencing token, 0 when another instance is the leader.
+const ACQUIRE_LEASE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder then
+    local sep = string.find(holder, ':', 1, true)
+    if string.sub(holder, 1, sep - 1) == ARGV[1] then
+        redis.call('PEXPIRE', KEYS[1], ARGV[2])
+        return tonumber(string.sub(holder, sep + 1))
+    end
+    return 0
+end
+local token = redis.call('INCR', KEYS[2])
+redis.call('SET', KEYS[1], ARGV[1] .. ':' .. token, 'PX', ARGV[2])
+return token
+";
+
+/// Extends the lease when it is still held with the fencing token
+const RENEW_LEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+";
+
+/// Gives the lease up when this instance holds it, so that another instance
+/// takes over right away
+const RELEASE_LEASE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder and string.sub(holder, 1, string.len(ARGV[1]) + 1) == ARGV[1] .. ':' then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// What a run of a worker gets to know about itself
+#[derive(Debug, Clone)]
+pub struct WorkerContext {
+    pub name: &'static str,
+    pub instance_id: String,
+    /// Increases with every change of leader, `None` for workers running on
+    /// every instance. Writes guarded by it reject older leaders.
+    pub fencing_token: Option<u64>,
+}
+
+impl WorkerContext {
+    /// The fence to pass to the writes of a leader, so that a leader which
+    /// lost its lease mid-run can't overwrite the work of the next one
+    pub fn fence(&self) -> Option<WorkerFence> {
+        self.fencing_token.map(|token| WorkerFence {
+            worker: self.name,
+            token,
+        })
+    }
+}
+
+type WorkerFn = Box<
+    dyn Fn(
+        WorkerContext,
+    ) -> LocalBoxFuture<'static, Result<usize, DomainError>>,
+>;
+
+/// A background task run every `interval`. Runs return how many items they
+/// processed.
+pub struct Worker {
+    name: &'static str,
+    interval: Duration,
+    backoff: WorkerBackoffConfig,
+    leader_only: bool,
+    max_run_time: Duration,
+    run: WorkerFn,
+}
+
+impl Worker {
+    /// A worker run by the leader among the instances only
+    pub fn new<F, Fut>(
+        name: &'static str,
+        interval: Duration,
+        backoff: WorkerBackoffConfig,
+        run: F,
+    ) -> Self
+    where
+        F: Fn(WorkerContext) -> Fut + 'static,
+        Fut: Future<Output = Result<usize, DomainError>> + 'static,
+    {
+        Self {
+            name,
+            interval,
+            backoff,
+            leader_only: true,
+            max_run_time: DEFAULT_MAX_RUN_TIME,
+            run: Box::new(move |ctx| Box::pin(run(ctx))),
+        }
+    }
+
+    /// Runs the worker on every instance, for work that is claimed with
+    /// locks of its own
+    pub fn on_every_instance(mut self) -> Self {
+        self.leader_only = false;
+        self
+    }
+
+    /// For workers whose runs take longer than [`DEFAULT_MAX_RUN_TIME`]
+    pub fn with_max_run_time(mut self, max_run_time: Duration) -> Self {
+        self.max_run_time = max_run_time;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The leader keeps the lease while it misses a couple of runs
+    fn lease(&self) -> Duration {
+        (self.interval * 3).max(MIN_LEASE)
+    }
+
+    /// Workers report before every run, so reports are at most the longest
+    /// run and the longest delay apart. A worker that misses a couple of
+    /// reports is hung or no longer running.
+    fn stale_after(&self) -> Duration {
+        let max_delay = self
+            .interval
+            .max(Duration::from_secs(self.backoff.max_interval_secs));
+        (max_delay + self.max_run_time) * 2
+    }
+}
+
+/// Heartbeats of the workers running on this instance, for the liveness
+/// probe
+#[derive(Clone, Default, Debug)]
+pub struct WorkerLiveness {
+    heartbeats: Arc<Mutex<HashMap<&'static str, Heartbeat>>>,
+}
+
+#[derive(Debug)]
+struct Heartbeat {
+    at: Instant,
+    stale_after: Duration,
+}
+
+impl WorkerLiveness {
+    fn beat(&self, worker: &Worker) {
+        let _ = self.heartbeats.lock().unwrap().insert(
+            worker.name,
+            Heartbeat {
+                at: Instant::now(),
+                stale_after: worker.stale_after(),
+            },
+        );
+    }
+
+    /// Workers stopped at shutdown are not stale
+    fn stopped(&self, worker: &Worker) {
+        let _ = self.heartbeats.lock().unwrap().remove(worker.name);
+    }
+
+    /// Workers that missed their heartbeats, sorted by name
+    pub fn stale_workers(&self) -> Vec<&'static str> {
+        let mut stale = self
+            .heartbeats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, heartbeat)| {
+                heartbeat.at.elapsed() > heartbeat.stale_after
+            })
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        stale.sort_unstable();
+        stale
+    }
+}
+
+/// Named background workers, coordinated between the instances through
+/// Redis. The status of every worker is kept in Redis as well, for
+/// [`load_worker_statuses`].
+pub struct WorkerRegistry {
+    redis: ConnectionManager,
+    key_prefix: String,
+    instance_id: String,
+    liveness: WorkerLiveness,
+    workers: Vec<Worker>,
+}
+
+struct WorkerKeys {
+    lease: String,
+    fencing: String,
+    status: String,
+}
+
+impl WorkerKeys {
+    fn new(key_prefix: &str, name: &str) -> Self {
+        Self {
+            lease: format!("{key_prefix}.{name}.leader"),
+            fencing: format!("{key_prefix}.{name}.fencing"),
+            status: format!("{key_prefix}.{name}.status"),
+        }
+    }
+}
+
+fn names_key(key_prefix: &str) -> String {
+    format!("{key_prefix}.names")
+}
+
+impl WorkerRegistry {
+    pub fn new(redis: ConnectionManager, key_prefix: String) -> Self {
+        Self {
+            redis,
+            key_prefix,
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            liveness: WorkerLiveness::default(),
+            workers: Vec::new(),
+        }
+    }
+
+    /// Reports the heartbeats of the workers to `liveness` instead of a
+    /// liveness of its own
+    pub fn with_liveness(mut self, liveness: WorkerLiveness) -> Self {
+        self.liveness = liveness;
+        self
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Heartbeats of the workers once they are started
+    pub fn liveness(&self) -> WorkerLiveness {
+        self.liveness.clone()
+    }
+
+    pub fn register(&mut self, worker: Worker) -> &mut Self {
+        assert!(
+            self.workers.iter().all(|other| other.name != worker.name),
+            "Worker {} is registered twice",
+            worker.name
+        );
+        self.workers.push(worker);
+        self
+    }
+
+    /// Starts every worker on the current `LocalSet`, as some of them spawn
+    /// actix tasks. Once `shutdown` is cancelled the workers stop after their
+    /// current run and hand their leadership over.
+    pub fn start(self, shutdown: CancellationToken) -> Vec<JoinHandle<()>> {
+        let runner = Rc::new(WorkerRunner {
+            redis: self.redis,
+            key_prefix: self.key_prefix,
+            instance_id: self.instance_id,
+            liveness: self.liveness,
+        });
+        let _ = tracing::info!(
+            "Starting {} workers on instance {}",
+            self.workers.len(),
+            runner.instance_id
+        );
+        self.workers
+            .into_iter()
+            .map(|worker| {
+                tokio::task::spawn_local(
+                    runner.clone().run(worker, shutdown.clone()),
+                )
+            })
+            .collect()
+    }
+}
+
+enum RunOutcome {
+    NotLeader,
+    Done,
+    Failed,
+}
+
+struct WorkerRunner {
+    redis: ConnectionManager,
+    key_prefix: String,
+    instance_id: String,
+    liveness: WorkerLiveness,
+}
+
+impl WorkerRunner {
+    async fn run(self: Rc<Self>, worker: Worker, shutdown: CancellationToken) {
+        let keys = WorkerKeys::new(&self.key_prefix, worker.name);
+        let mut conn = self.redis.clone();
+        if let Err(err) = conn
+            .sadd::<_, _, ()>(names_key(&self.key_prefix), worker.name)
+            .await
+        {
+            let _ = tracing::warn!(
+                "Failed to register worker {}: {err}",
+                worker.name
+            );
+        }
+
+        let mut backoff = build_backoff(&worker.backoff);
+        while !shutdown.is_cancelled() {
+            self.liveness.beat(&worker);
+            let delay = match self.run_once(&worker, &keys).await {
+                RunOutcome::NotLeader => worker.interval,
+                RunOutcome::Done => {
+                    backoff.reset();
+                    worker.interval
+                }
+                RunOutcome::Failed => {
+                    backoff.next_backoff().unwrap_or_else(|| {
+                        backoff.reset();
+                        worker.interval
+                    })
+                }
+            };
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = sleep(delay) => {}
+            }
+        }
+
+        if worker.leader_only {
+            if let Err(err) =
+                release_lease(&mut conn, &keys, &self.instance_id).await
+            {
+                let _ = tracing::warn!(
+                    "Failed to release the leadership of worker {}: {err}",
+                    worker.name
+                );
+            }
+        }
+        self.liveness.stopped(&worker);
+        let _ = tracing::info!("Worker {} stopped", worker.name);
+    }
+
+    async fn run_once(&self, worker: &Worker, keys: &WorkerKeys) -> RunOutcome {
+        let mut conn = self.redis.clone();
+        let fencing_token = if worker.leader_only {
+            match acquire_lease(
+                &mut conn,
+                keys,
+                &self.instance_id,
+                worker.lease(),
+            )
+            .await
+            {
+                Ok(Some(token)) => Some(token),
+                Ok(None) => {
+                    let _ = tracing::trace!(
+                        "Not the leader of worker {}",
+                        worker.name
+                    );
+                    return RunOutcome::NotLeader;
+                }
+                Err(err) => {
+                    let _ = tracing::error!(
+                        "Failed to acquire leadership of worker {}: {err}",
+                        worker.name
+                    );
+                    return RunOutcome::Failed;
+                }
+            }
+        } else {
+            None
+        };
+
+        let ctx = WorkerContext {
+            name: worker.name,
+            instance_id: self.instance_id.clone(),
+            fencing_token,
+        };
+        let run_at = chrono::Utc::now().naive_utc();
+        let result = self
+            .run_with_lease_renewal(
+                worker,
+                keys,
+                fencing_token,
+                (worker.run)(ctx),
+            )
+            .await;
+
+        let outcome = match &result {
+            Ok(0) => {
+                let _ =
+                    tracing::trace!("Worker {} had nothing to do", worker.name);
+                RunOutcome::Done
+            }
+            Ok(processed) => {
+                let _ = tracing::info!(
+                    "Worker {} processed {processed} items",
+                    worker.name
+                );
+                RunOutcome::Done
+            }
+            Err(err) => {
+                let _ = tracing::error!("Worker {} failed: {err}", worker.name);
+                RunOutcome::Failed
+            }
+        };
+        if let Err(err) =
+            self.record_run(&mut conn, keys, run_at, &result).await
+        {
+            let _ = tracing::warn!(
+                "Failed to record the run of worker {}: {err}",
+                worker.name
+            );
+        }
+        outcome
+    }
+
+    /// Renews the lease of a leader while the run is going on. A leader gives
+    /// up on the run once the lease is lost, before another leader gets to
+    /// the same work. Runs don't report the worker alive, so that a hung run
+    /// shows in the liveness probe.
+    async fn run_with_lease_renewal(
+        &self,
+        worker: &Worker,
+        keys: &WorkerKeys,
+        fencing_token: Option<u64>,
+        mut run: LocalBoxFuture<'static, Result<usize, DomainError>>,
+    ) -> Result<usize, DomainError> {
+        let lease = worker.lease();
+        let mut renewal = tokio::time::interval(lease / 3);
+        // the lease was just acquired
+        let _ = renewal.tick().await;
+        let mut conn = self.redis.clone();
+        loop {
+            tokio::select! {
+                result = &mut run => return result,
+                _ = renewal.tick() => {
+                    let Some(fencing_token) = fencing_token else {
+                        continue;
+                    };
+                    let renewed = renew_lease(
+                        &mut conn,
+                        keys,
+                        &self.instance_id,
+                        fencing_token,
+                        lease,
+                    )
+                    .await?;
+                    if !renewed {
+                        return Err(DomainError::new_internal_error(format!(
+                            "Lost the leadership of worker {} during a run",
+                            worker.name
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn record_run(
+        &self,
+        conn: &mut ConnectionManager,
+        keys: &WorkerKeys,
+        run_at: chrono::NaiveDateTime,
+        result: &Result<usize, DomainError>,
+    ) -> Result<(), DomainError> {
+        let previous: Option<String> = conn.get(&keys.status).await?;
+        let mut status: WorkerRunStatus = previous
+            .and_then(|status| serde_json::from_str(&status).ok())
+            .unwrap_or_default();
+        status.instance_id = Some(self.instance_id.clone());
+        status.last_run_at = Some(run_at);
+        match result {
+            Ok(processed) => {
+                status.last_success_at = Some(run_at);
+                status.last_processed = Some(*processed);
+                status.consecutive_failures = 0;
+            }
+            Err(err) => {
+                status.last_error = Some(err.to_string());
+                status.last_error_at = Some(run_at);
+                status.consecutive_failures += 1;
+            }
+        }
+        let status_str = serde_json::to_string(&status).map_err(|err| {
+            DomainError::new_internal_error(format!(
+                "Failed to serialize worker status: {err}"
+            ))
+        })?;
+        let () = conn.set(&keys.status, status_str).await?;
+        Ok(())
+    }
+}
+
+fn build_backoff(config: &WorkerBackoffConfig) -> backoff::ExponentialBackoff {
+    backoff::ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_secs(
+            config.initial_interval_secs,
+        ))
+        .with_multiplier(config.multiplier)
+        .with_max_interval(Duration::from_secs(config.max_interval_secs))
+        .with_max_elapsed_time(Some(Duration::from_secs(
+            config.max_elapsed_time_secs,
+        )))
+        .build()
+}
+
+fn lease_ms(lease: Duration) -> u64 {
+    u64::try_from(lease.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Returns the fencing token of the lease, `None` when another instance
+/// holds it
+async fn acquire_lease(
+    conn: &mut ConnectionManager,
+    keys: &WorkerKeys,
+    instance_id: &str,
+    lease: Duration,
+) -> Result<Option<u64>, DomainError> {
+    let token: u64 = redis::Script::new(ACQUIRE_LEASE_SCRIPT)
+        .key(&keys.lease)
+        .key(&keys.fencing)
+        .arg(instance_id)
+        .arg(lease_ms(lease))
+        .invoke_async(conn)
+        .await?;
+    Ok((token > 0).then_some(token))
+}
+
+async fn renew_lease(
+    conn: &mut ConnectionManager,
+    keys: &WorkerKeys,
+    instance_id: &str,
+    fencing_token: u64,
+    lease: Duration,
+) -> Result<bool, DomainError> {
+    let renewed: i32 = redis::Script::new(RENEW_LEASE_SCRIPT)
+        .key(&keys.lease)
+        .arg(format!("{instance_id}:{fencing_token}"))
+        .arg(lease_ms(lease))
+        .invoke_async(conn)
+        .await?;
+    Ok(renewed == 1)
+}
+
+async fn release_lease(
+    conn: &mut ConnectionManager,
+    keys: &WorkerKeys,
+    instance_id: &str,
+) -> Result<(), DomainError> {
+    let _released: i32 = redis::Script::new(RELEASE_LEASE_SCRIPT)
+        .key(&keys.lease)
+        .arg(instance_id)
+        .invoke_async(conn)
+        .await?;
+    Ok(())
+}
+
+/// Status of every worker any instance registered, sorted by name
+pub async fn load_worker_statuses(
+    conn: &mut ConnectionManager,
+    key_prefix: &str,
+) -> Result<Vec<WorkerStatus>, DomainError> {
+    let mut names: Vec<String> = conn.smembers(names_key(key_prefix)).await?;
+    names.sort();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pipe = redis::pipe();
+    for name in &names {
+        let keys = WorkerKeys::new(key_prefix, name);
+        let _ = pipe.get(&keys.lease).pttl(&keys.lease).get(&keys.status);
+    }
+    let values: Vec<(Option<String>, i64, Option<String>)> =
+        pipe.query_async(conn).await?;
+
+    Ok(names
+        .into_iter()
+        .zip(values)
+        .map(|(name, (leader, lease_ttl_ms, status))| WorkerStatus {
+            name,
+            leader: leader.as_deref().and_then(|leader| {
+                let (instance_id, fencing_token) = leader.split_once(':')?;
+                Some(WorkerLeader {
+                    instance_id: instance_id.to_owned(),
+                    fencing_token: fencing_token.parse().ok()?,
+                    lease_expires_in_ms: lease_ttl_ms.max(0),
+                })
+            }),
+            run: status
+                .and_then(|status| serde_json::from_str(&status).ok())
+                .unwrap_or_default(),
+        })
+        .collect())
+}
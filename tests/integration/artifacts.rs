@@ -0,0 +1,185 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::{
+        artifact::JobArtifact,
+        misc::{Job, JobStatus},
+    };
+    use actix_demo::workers;
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use std::time::Duration;
+
+    async fn wait_for_job(ctx: &TestContext, token: &str, job: &Job) -> Job {
+        for _ in 0..20 {
+            let mut resp = ctx
+                .test_server
+                .get(format!("/api/cmd/{}", job.job_id))
+                .with_token(token)
+                .send()
+                .await
+                .unwrap();
+            let job = resp.json::<Job>().await.unwrap();
+            if job.status != JobStatus::Pending {
+                return job;
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+        panic!("Job {} did not finish in time", job.job_id)
+    }
+
+    async fn list_artifacts(
+        ctx: &TestContext,
+        token: &str,
+        job: &Job,
+    ) -> (StatusCode, Option<Vec<JobArtifact>>) {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}/artifacts", job.job_id))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        let status = resp.status();
+        let artifacts = if status == StatusCode::OK {
+            Some(resp.json::<Vec<JobArtifact>>().await.unwrap())
+        } else {
+            None
+        };
+        (status, artifacts)
+    }
+
+    #[actix_rt::test]
+    async fn should_store_and_download_job_artifacts() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::artifacts_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        common::create_http_user(&ctx.addr, "art.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token =
+            common::get_http_token(&ctx.addr, "art.user", "test", &ctx.client)
+                .await
+                .unwrap();
+
+        // patterns have to stay inside the working directory
+        let resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .send_json(&serde_json::json!({
+                "args": [], "artifacts": ["../*.txt"]
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .send_json(&serde_json::json!({
+                "args": ["nightly"], "artifacts": ["reports/*.txt"]
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let job = resp.json::<Job>().await.unwrap();
+        let job = wait_for_job(&ctx, &token, &job).await;
+        assert_eq!(job.status, JobStatus::Completed);
+
+        let (status, artifacts) = list_artifacts(&ctx, &token, &job).await;
+        assert_eq!(status, StatusCode::OK);
+        let artifacts = artifacts.unwrap();
+        // build.log was not declared
+        assert_eq!(artifacts.len(), 1);
+        let artifact = &artifacts[0];
+        assert_eq!(artifact.path, "reports/report.txt");
+        assert_eq!(artifact.size_bytes, 19);
+
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}/artifacts/{}", job.job_id, artifact.id))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.body().await.unwrap();
+        assert_eq!(body.as_ref(), b"report for nightly\n");
+
+        // artifacts are private to whoever can view the job
+        let (status, _) = list_artifacts(&ctx, &user_token, &job).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // the cleanup worker removes artifacts past their retention
+        let deleted = workers::cleanup_expired_job_artifacts(
+            &ctx.app_data,
+            chrono::Utc::now().naive_utc() + chrono::Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(deleted, 1);
+        let (_, artifacts) = list_artifacts(&ctx, &token, &job).await;
+        assert!(artifacts.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn should_replace_artifacts_of_a_retried_job() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::flaky_artifacts_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .send_json(&serde_json::json!({
+                "args": [],
+                "artifacts": ["reports/*.txt"],
+                "retry": {
+                    "max_attempts": 2,
+                    "initial_interval_secs": 1,
+                    "multiplier": 1.0,
+                    "max_interval_secs": 1
+                }
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let job = resp.json::<Job>().await.unwrap();
+
+        sleep(Duration::from_millis(500)).await;
+        let job = wait_for_job(&ctx, &token, &job).await;
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.attempt, 2);
+
+        // only the artifacts of the successful attempt are kept
+        let (_, artifacts) = list_artifacts(&ctx, &token, &job).await;
+        let artifacts = artifacts.unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].path, "reports/report.txt");
+        let mut resp = ctx
+            .test_server
+            .get(format!(
+                "/api/cmd/{}/artifacts/{}",
+                job.job_id, artifacts[0].id
+            ))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        let body = resp.body().await.unwrap();
+        assert_eq!(body.as_ref(), b"second attempt\n");
+    }
+}
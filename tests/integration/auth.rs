@@ -12,7 +12,7 @@ mod tests {
         workers,
     };
     use actix_http::{header, StatusCode};
-    use tokio::{task::JoinHandle, time::sleep};
+    use tokio::time::sleep;
 
     use std::{str::FromStr, time::Duration};
 
@@ -188,16 +188,13 @@ mod tests {
 
         let ctx = TestContext::new(Some(options.clone())).await;
 
-        let _sessions_cleanup_worker_handle: JoinHandle<()> = {
-            let config = options.sessions_cleanup_worker_config;
-            workers::start_sessions_cleanup_worker(
-                config,
+        let _workers = common::start_workers(
+            &ctx.app_data,
+            vec![workers::sessions_cleanup_worker(
+                options.sessions_cleanup_worker_config,
                 ctx.app_data.credentials_repo.clone(),
-                ctx.app_data.user_ids_cache.clone(),
-                ctx.app_data.pool.clone(),
-            )
-            .await
-        };
+            )],
+        );
 
         // Perform login to create a session
         let _token = common::get_http_token(
@@ -234,4 +231,49 @@ mod tests {
             .unwrap();
         assert!(sessions.is_empty(), "Expected no sessions after cleanup");
     }
+
+    #[actix_rt::test]
+    async fn should_cleanup_sessions_missing_from_the_index() {
+        use actix_demo::models::session::SessionConfigBuilder;
+        use redis::AsyncCommands;
+
+        let options = TestAppOptionsBuilder::default()
+            .session_config(
+                SessionConfigBuilder::default()
+                    .expiration_secs(2)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options.clone())).await;
+        let credentials_repo = &ctx.app_data.credentials_repo;
+
+        // sessions from before the active sessions index existed
+        let () = ctx
+            .app_data
+            .redis_conn_manager
+            .clone()
+            .del(credentials_repo.get_active_key())
+            .await
+            .unwrap();
+        assert_eq!(
+            credentials_repo.remove_expired_sessions(10).await.unwrap(),
+            0
+        );
+
+        let _workers = common::start_workers(
+            &ctx.app_data,
+            vec![workers::sessions_cleanup_worker(
+                options.sessions_cleanup_worker_config,
+                credentials_repo.clone(),
+            )],
+        );
+        sleep(Duration::from_secs(6)).await;
+
+        let user_id = UserId::from_str("1").unwrap();
+        let sessions =
+            credentials_repo.load_all_sessions(&user_id).await.unwrap();
+        assert!(sessions.is_empty(), "Expected no sessions after cleanup");
+    }
 }
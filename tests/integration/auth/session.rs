@@ -1,3 +1,5 @@
+mod admin_sessions;
+mod login_anomalies;
 mod session_renewal;
 mod sessions_api;
 
@@ -0,0 +1,235 @@
+mod tests {
+    mod admin_sessions {
+        use std::{collections::HashMap, time::Duration};
+
+        use actix_demo::{
+            models::{
+                session::{
+                    ActiveSessionsPage, ClientPlatform, SessionConfigBuilder,
+                    SessionInfo,
+                },
+                users::{UserId, UserWithRoles},
+                worker::WorkerConfig,
+            },
+            utils, workers,
+        };
+        use actix_http::{header, StatusCode};
+        use actix_rt::time::sleep;
+        use serde_json::json;
+        use uuid::Uuid;
+
+        use crate::common::{self, TestContext, WithToken};
+
+        async fn login(ctx: &TestContext) -> String {
+            let resp = ctx
+                .test_server
+                .post("/api/login")
+                .append_header((header::CONTENT_TYPE, "application/json"))
+                .append_header((
+                    header::USER_AGENT,
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64)",
+                ))
+                .send_json(&json!({
+                    "username": "sessions.user",
+                    "password": "test",
+                }))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            utils::extract_auth_token(resp.headers()).unwrap()
+        }
+
+        async fn active_sessions(
+            ctx: &TestContext,
+            token: &str,
+        ) -> ActiveSessionsPage {
+            let mut resp = ctx
+                .test_server
+                .get("/api/sessions/active?limit=50")
+                .with_token(token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            resp.json().await.unwrap()
+        }
+
+        async fn status_of(ctx: &TestContext, token: &str) -> StatusCode {
+            ctx.test_server
+                .get("/api/users")
+                .with_token(token)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+
+        #[actix_rt::test]
+        async fn should_let_admins_manage_sessions_of_any_user() {
+            let ctx = TestContext::new(None).await;
+            common::create_http_user(
+                &ctx.addr,
+                "sessions.user",
+                "test",
+                &ctx.client,
+            )
+            .await
+            .unwrap();
+            let token = login(&ctx).await;
+            let other_token = login(&ctx).await;
+            let mut resp = ctx
+                .test_server
+                .get("/api/users")
+                .with_token(&token)
+                .send()
+                .await
+                .unwrap();
+            let user_id: UserId =
+                resp.json::<UserWithRoles>().await.unwrap().id;
+
+            // tokens are not exposed by the sessions listing
+            let mut resp = ctx
+                .test_server
+                .get("/api/sessions")
+                .with_token(&token)
+                .send()
+                .await
+                .unwrap();
+            let raw = resp.json::<serde_json::Value>().await.unwrap();
+            assert!(raw
+                .as_object()
+                .unwrap()
+                .values()
+                .all(|session| session.get("token").is_none()));
+            let sessions: HashMap<Uuid, SessionInfo> =
+                serde_json::from_value(raw).unwrap();
+            assert_eq!(sessions.len(), 2);
+            for session in sessions.values() {
+                assert_eq!(session.platform, Some(ClientPlatform::Windows));
+                assert!(session.ip.is_some());
+            }
+            assert!(sessions.values().any(|session| session.renewal_count > 0));
+
+            let resp = ctx
+                .test_server
+                .get("/api/sessions/active")
+                .with_token(&token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            let page = active_sessions(&ctx, &ctx._token).await;
+            assert_eq!(page.total, 3);
+            assert_eq!(
+                page.sessions
+                    .iter()
+                    .filter(|session| session.user_id == user_id)
+                    .count(),
+                2
+            );
+
+            let mut resp = ctx
+                .test_server
+                .get(format!("/api/sessions/users/{user_id}"))
+                .with_token(&ctx._token)
+                .send()
+                .await
+                .unwrap();
+            let user_sessions =
+                resp.json::<HashMap<Uuid, SessionInfo>>().await.unwrap();
+            assert_eq!(user_sessions.len(), 2);
+
+            let claims =
+                utils::get_claims(&common::TEST_JWT_KEY, &token).unwrap();
+            let resp = ctx
+                .test_server
+                .delete(format!(
+                    "/api/sessions/users/{user_id}/{}",
+                    claims.custom.session_id
+                ))
+                .with_token(&ctx._token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(status_of(&ctx, &token).await, StatusCode::UNAUTHORIZED);
+            assert_eq!(status_of(&ctx, &other_token).await, StatusCode::OK);
+
+            let resp = ctx
+                .test_server
+                .delete(format!("/api/sessions/users/{user_id}"))
+                .with_token(&ctx._token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                status_of(&ctx, &other_token).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(active_sessions(&ctx, &ctx._token).await.total, 1);
+        }
+
+        #[actix_rt::test]
+        async fn should_leave_expired_sessions_to_the_cleanup_worker() {
+            let options = common::TestAppOptionsBuilder::default()
+                .session_config(
+                    SessionConfigBuilder::default()
+                        .expiration_secs(2)
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap();
+            let ctx = TestContext::new(Some(options.clone())).await;
+            let credentials_repo = &ctx.app_data.credentials_repo;
+
+            // the first run indexes the sessions, the next one comes after
+            // the sessions were listed
+            let _workers = common::start_workers(
+                &ctx.app_data,
+                vec![workers::sessions_cleanup_worker(
+                    WorkerConfig {
+                        run_interval: 5,
+                        ..options.sessions_cleanup_worker_config
+                    },
+                    credentials_repo.clone(),
+                )],
+            );
+            let (_, user_id) = ctx.create_user("sessions.user").await;
+            let _token = login(&ctx).await;
+
+            sleep(Duration::from_secs(3)).await;
+            let admin_token = common::get_http_token(
+                &ctx.addr,
+                common::DEFAULT_USER,
+                common::DEFAULT_USER,
+                &ctx.client,
+            )
+            .await
+            .unwrap();
+            let page = active_sessions(&ctx, &admin_token).await;
+            assert!(page
+                .sessions
+                .iter()
+                .all(|session| session.user_id != user_id));
+            // listing doesn't remove anything
+            assert!(!credentials_repo
+                .load_all_sessions(&user_id)
+                .await
+                .unwrap()
+                .is_empty());
+
+            sleep(Duration::from_secs(4)).await;
+            assert!(
+                credentials_repo
+                    .load_all_sessions(&user_id)
+                    .await
+                    .unwrap()
+                    .is_empty(),
+                "Expected the expired session to be cleaned up"
+            );
+        }
+    }
+}
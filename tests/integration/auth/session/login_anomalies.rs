@@ -0,0 +1,160 @@
+mod tests {
+    mod login_anomalies {
+        use actix_demo::{
+            models::{
+                notification::{NotificationKind, NotificationsPage},
+                session::SessionConfigBuilder,
+            },
+            utils,
+        };
+        use actix_http::{header, StatusCode};
+        use serde_json::json;
+
+        use crate::common::{self, TestContext, WithToken};
+
+        async fn login(
+            ctx: &TestContext,
+            device_id: &str,
+            country: &str,
+        ) -> String {
+            let resp = ctx
+                .test_server
+                .post("/api/login")
+                .append_header((header::CONTENT_TYPE, "application/json"))
+                .append_header(("CF-IPCountry", country))
+                .append_header((header::USER_AGENT, "anomaly-test"))
+                .send_json(&json!({
+                    "username": "anomalies.user",
+                    "password": "test",
+                    "device_id": device_id,
+                }))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            utils::extract_auth_token(resp.headers()).unwrap()
+        }
+
+        #[actix_rt::test]
+        async fn should_flag_logins_from_new_devices_and_countries() {
+            // the test client stands in for the proxy setting the country
+            let options = common::TestAppOptionsBuilder::default()
+                .session_config(
+                    SessionConfigBuilder::default()
+                        .trusted_proxies(vec![
+                            "127.0.0.1".parse().unwrap(),
+                            "::1".parse().unwrap(),
+                        ])
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap();
+            let ctx = TestContext::new(Some(options)).await;
+            common::create_http_user(
+                &ctx.addr,
+                "anomalies.user",
+                "test",
+                &ctx.client,
+            )
+            .await
+            .unwrap();
+            let device = "6f1c4a52-8d0e-4b5e-9f3a-2b7c1d9e0a11";
+            let token = login(&ctx, device, "DE").await;
+            let _ = login(&ctx, device, "de").await;
+            let other_token =
+                login(&ctx, "0b8e3f6d-1a2c-4d5e-8f90-a1b2c3d4e5f6", "FR").await;
+
+            let mut resp = ctx
+                .test_server
+                .get("/api/notifications")
+                .with_token(&token)
+                .send()
+                .await
+                .unwrap();
+            let page = resp.json::<NotificationsPage>().await.unwrap();
+            let kinds = page
+                .notifications
+                .iter()
+                .map(|notification| notification.kind)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                kinds,
+                vec![
+                    NotificationKind::SuspiciousLogin,
+                    NotificationKind::NewSession,
+                    NotificationKind::NewSession,
+                ]
+            );
+            let suspicious = &page.notifications[0].data;
+            assert_eq!(
+                suspicious["anomalies"],
+                json!(["new_device", "new_country", "impossible_travel"])
+            );
+            assert_eq!(suspicious["country"], "FR");
+            assert_eq!(suspicious["user_agent"], "anomaly-test");
+
+            // the "it wasn't me" link asks for a confirmation, which revokes
+            // the suspicious session once
+            let revoke_url = suspicious["revoke_url"].as_str().unwrap();
+            let resp = ctx.test_server.get(revoke_url).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = ctx
+                .test_server
+                .get("/api/users")
+                .with_token(&other_token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = ctx.test_server.post(revoke_url).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = ctx.test_server.post(revoke_url).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            let resp = ctx
+                .test_server
+                .get("/api/users")
+                .with_token(&other_token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let sessions = ctx.get_sessions(&token).await;
+            assert_eq!(sessions.len(), 2);
+            assert!(sessions
+                .values()
+                .all(|session| session.country.as_deref() == Some("DE")));
+        }
+
+        #[actix_rt::test]
+        async fn should_ignore_forwarding_headers_of_untrusted_clients() {
+            let ctx = TestContext::new(None).await;
+            common::create_http_user(
+                &ctx.addr,
+                "anomalies.user",
+                "test",
+                &ctx.client,
+            )
+            .await
+            .unwrap();
+            let resp = ctx
+                .test_server
+                .post("/api/login")
+                .append_header(("X-Forwarded-For", "203.0.113.7"))
+                .append_header(("CF-IPCountry", "FR"))
+                .send_json(&json!({
+                    "username": "anomalies.user",
+                    "password": "test",
+                }))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let token = utils::extract_auth_token(resp.headers()).unwrap();
+
+            let sessions = ctx.get_sessions(&token).await;
+            let session = sessions.values().next().unwrap();
+            assert_eq!(session.ip.as_deref(), Some("127.0.0.1"));
+            assert_eq!(session.country, None);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 extern crate actix_demo;
 use actix_demo::actions::misc::create_database_if_needed;
 use actix_demo::config::MinioConfig;
+use actix_demo::health::{
+    HealthCheck, HealthChecks, MinioHealthChecker, PostgresHealthChecker,
+    RedisHealthChecker, WorkerHealthChecker,
+};
 use actix_demo::models::rate_limit::{
     KeyStrategy, RateLimitConfig, RateLimitPolicy,
 };
@@ -8,11 +12,19 @@ use actix_demo::models::roles::RoleEnum;
 use actix_demo::models::session::{
     SessionConfig, SessionConfigBuilder, SessionInfo,
 };
-use actix_demo::models::users::{NewUser, Password, User, Username};
-use actix_demo::models::worker::{WorkerBackoffConfig, WorkerConfig};
+use actix_demo::models::users::{
+    NewUser, Password, User, UserId, UserWithRoles, Username,
+};
+use actix_demo::models::worker::{
+    JobArtifactsConfig, JobSchedulerConfig, RunnerConfig, WebhookConfig,
+    WorkerBackoffConfig, WorkerConfig,
+};
 use actix_demo::telemetry::DomainRootSpanBuilder;
+use actix_demo::utils::notifier::Notifier;
 use actix_demo::utils::redis_credentials_repo::RedisCredentialsRepo;
+use actix_demo::utils::shutdown::Shutdown;
 use actix_demo::utils::InstrumentedRedisCache;
+use actix_demo::workers::{Worker, WorkerLiveness, WorkerRegistry};
 use actix_demo::{utils, AppConfig, AppData, SmtpConfig};
 use actix_http::header::HeaderMap;
 use actix_web::dev::ServiceResponse;
@@ -44,6 +56,7 @@ use testcontainers_modules::postgres::{self, Postgres};
 use testcontainers_modules::redis::{Redis, REDIS_PORT};
 use testcontainers_modules::testcontainers::runners::AsyncRunner;
 use testcontainers_modules::testcontainers::ContainerAsync;
+use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
 use tracing_actix_web::TracingLogger;
 use tracing_log::LogTracer;
@@ -116,6 +129,69 @@ pub fn failing_bin_file() -> BinFile {
     }
 }
 
+pub fn prompt_bin_file() -> BinFile {
+    BinFile {
+        location: "/tmp/prompt.sh".to_owned(),
+        contents: r#"#!/bin/bash
+    
+    read -r -t 10 name || exit 1
+    echo "hello $name"
+    "#
+        .to_owned(),
+    }
+}
+
+pub fn pipeline_bin_file() -> BinFile {
+    BinFile {
+        location: "/tmp/pipeline-step.sh".to_owned(),
+        contents: r#"#!/bin/bash
+    
+    if [ "$1" = "fail" ]; then
+        echo "step failed"
+        exit 1
+    fi
+    echo "${PIPELINE_STEP_BUILD_OUTPUT}$1"
+    "#
+        .to_owned(),
+    }
+}
+
+pub fn artifacts_bin_file() -> BinFile {
+    BinFile {
+        location: "/tmp/artifacts.sh".to_owned(),
+        contents: r#"#!/bin/bash
+    
+    mkdir -p reports
+    echo "report for $1" > reports/report.txt
+    echo "build log" > build.log
+    echo "done"
+    "#
+        .to_owned(),
+    }
+}
+
+/// Fails its first attempt after writing a report and a file the second
+/// attempt does not write
+pub fn flaky_artifacts_bin_file() -> BinFile {
+    BinFile {
+        location: "/tmp/flaky-artifacts.sh".to_owned(),
+        contents: r#"#!/bin/bash
+    
+    marker="/tmp/flaky-artifacts-$(basename "$PWD")"
+    mkdir -p reports
+    if [ ! -f "$marker" ]; then
+        touch "$marker"
+        echo "first attempt" > reports/report.txt
+        echo "first attempt only" > reports/partial.txt
+        exit 1
+    fi
+    rm -f "$marker"
+    echo "second attempt" > reports/report.txt
+    "#
+        .to_owned(),
+    }
+}
+
 static TRACING: Lazy<anyhow::Result<()>> = Lazy::new(|| {
     let _ = dotenvy::dotenv().context("Failed to set up env")?;
     let env_filter = EnvFilter::try_from_env("ACTIX_DEMO_TEST_RUST_LOG")
@@ -140,7 +216,11 @@ static CREATE_BIN_FILES: Lazy<anyhow::Result<()>> = Lazy::new(|| {
     let file1 = echo_bin_file();
     let file2 = sleep_bin_file();
     let file3 = failing_bin_file();
-    let files = vec![file1, file2, file3];
+    let file4 = prompt_bin_file();
+    let file5 = pipeline_bin_file();
+    let file6 = artifacts_bin_file();
+    let file7 = flaky_artifacts_bin_file();
+    let files = vec![file1, file2, file3, file4, file5, file6, file7];
     for f in &files {
         let mut file = fs::OpenOptions::new()
             .create(true)
@@ -168,6 +248,12 @@ pub struct TestAppOptions {
     pub session_config: SessionConfig,
     #[builder(default = "self.default_session_cleanup_worker_config()")]
     pub sessions_cleanup_worker_config: WorkerConfig,
+    /// Tests send webhooks to stand-ins on localhost
+    #[builder(default = "true")]
+    pub webhook_allow_private_targets: bool,
+    /// Heartbeats checked by the liveness probe
+    #[builder(default)]
+    pub worker_liveness: WorkerLiveness,
 }
 
 impl Default for TestAppOptions {
@@ -236,9 +322,40 @@ pub async fn app_data(
     let config = AppConfig {
         hash_cost: 4,
         job_bin_path: options.bin_file.location.clone(),
+        job_pty_wrapper_path: "/usr/bin/script".to_owned(),
+        job_abort_grace_period_secs: 2,
+        shutdown_timeout_secs: 5,
+        job_output_retention_secs: 3600,
+        job_output_max_entries: 1000,
+        message_stream_max_entries: 100,
+        job_scheduler: JobSchedulerConfig {
+            interval_secs: 1,
+            missed_run_tolerance_secs: 60,
+        },
+        job_artifacts: JobArtifactsConfig {
+            workdir_root: "/tmp/actix-demo-test/jobs".to_owned(),
+            max_file_size_bytes: 1024 * 1024,
+            retention_days: 7,
+            cleanup_interval_secs: 3600,
+        },
+        webhooks: WebhookConfig {
+            delivery_interval_secs: 1,
+            max_attempts: 3,
+            timeout_secs: 5,
+            retry_initial_interval_secs: 1,
+            retry_max_interval_secs: 2,
+            allow_private_targets: options.webhook_allow_private_targets,
+        },
+        runners: RunnerConfig {
+            heartbeat_timeout_secs: 2,
+            monitor_interval_secs: 1,
+        },
+        worker_backoff: options.sessions_cleanup_worker_config.backoff.clone(),
         rate_limit: create_rate_limit_config(options.clone()),
         session: options.session_config.clone(),
         health_check_timeout_secs: 10,
+        health_check_cache_ttl_secs: 5,
+        health_probe_interval_secs: 1,
         minio: MinioConfig {
             bucket_name: "actix-demo".to_owned(),
             max_avatar_size_bytes:
@@ -318,13 +435,21 @@ pub async fn app_data(
 
     let redis_prefix = Box::new(utils::get_redis_prefix("app"));
 
+    let notifier = Notifier::new(
+        pool.clone(),
+        cm.clone(),
+        redis_prefix(&"messages"),
+        config.message_stream_max_entries,
+    );
     let credentials_repo = RedisCredentialsRepo::new(
         redis_prefix(&"user-sessions"),
         cm.clone(),
         options.session_config.max_concurrent_sessions,
-        options.session_config.renewal.renewal_window_secs,
+        options.session_config.renewal.extension_secs(),
+        options.session_config.impossible_travel_secs,
         metrics.active_sessions.clone(),
-    );
+    )
+    .with_notifier(notifier.clone());
 
     let key = TEST_JWT_KEY.clone();
 
@@ -343,25 +468,49 @@ pub async fn app_data(
         .force_path_style(true) // apply bucketname as path param instead of pre-domain
         .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
         .build();
-    let s3_client = aws_sdk_s3::Client::from_conf(s3_config);
+    let s3_client = Arc::new(aws_sdk_s3::Client::from_conf(s3_config));
+
+    let health_checks = HealthChecks::new(vec![
+        HealthCheck::critical(
+            "postgresql",
+            PostgresHealthChecker::new(pool.clone()),
+        ),
+        HealthCheck::critical("redis", RedisHealthChecker::new(cm.clone())),
+        HealthCheck::critical(
+            "minio",
+            MinioHealthChecker::new(
+                minior::Minio {
+                    client: s3_client.clone(),
+                },
+                config.minio.bucket_name.clone(),
+            ),
+        ),
+        HealthCheck::critical(
+            "workers",
+            WorkerHealthChecker::new(options.worker_liveness.clone()),
+        ),
+    ]);
 
     let data = Data::new(AppData {
         start_time,
         config,
         pool,
         credentials_repo,
+        notifier,
         jwt_key: key,
         redis_conn_factory: client.clone(),
         redis_conn_manager: cm.clone(),
         redis_prefix,
-        sessions_cleanup_worker_handle: None,
         metrics,
         prometheus,
         user_ids_cache,
-        health_checkers: Vec::new(),
-        minio: minior::Minio {
-            client: Arc::new(s3_client),
-        },
+        health_checks,
+        minio: minior::Minio { client: s3_client },
+        http_client: reqwest::Client::new(),
+        webhook_client: utils::webhooks::webhook_client(
+            options.webhook_allow_private_targets,
+        )?,
+        shutdown: Shutdown::default(),
     });
     Ok(data)
 }
@@ -488,6 +637,22 @@ impl WithToken for ClientRequest {
     }
 }
 
+/// Starts the workers on the current `LocalSet`, coordinated through the
+/// Redis of the app
+pub fn start_workers(
+    app_data: &AppData,
+    workers: Vec<Worker>,
+) -> Vec<JoinHandle<()>> {
+    let mut registry = WorkerRegistry::new(
+        app_data.redis_conn_manager.clone(),
+        (app_data.redis_prefix)(&"workers"),
+    );
+    for worker in workers {
+        let _ = registry.register(worker);
+    }
+    registry.start(app_data.shutdown.token())
+}
+
 pub async fn get_http_token(
     addr: &str,
     username: &str,
@@ -587,6 +752,25 @@ impl TestContext {
         }
     }
 
+    /// Registers a user with the password `test`, returns their token and id
+    pub async fn create_user(&self, username: &str) -> (String, UserId) {
+        create_http_user(&self.addr, username, "test", &self.client)
+            .await
+            .unwrap();
+        let token = get_http_token(&self.addr, username, "test", &self.client)
+            .await
+            .unwrap();
+        let mut resp = self
+            .test_server
+            .get("/api/users")
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        let user_id = resp.json::<UserWithRoles>().await.unwrap().id;
+        (token, user_id)
+    }
+
     pub async fn create_tokens(&self, count: usize) -> Vec<String> {
         let mut tokens = Vec::new();
 
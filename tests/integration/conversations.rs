@@ -0,0 +1,178 @@
+#[cfg(test)]
+mod tests {
+    use crate::common::{TestContext, WithToken};
+    use crate::ws::ws_utils::*;
+    use actix_demo::models::{
+        conversation::{
+            Conversation, ConversationChange, ConversationDetails,
+            ConversationMember, ConversationMessagesPage, ConversationRole,
+        },
+        ws::{WsClientEvent, WsServerEvent},
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::{sleep, timeout};
+    use futures::SinkExt;
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn take_event(ws: &mut WsClient) -> WsServerEvent {
+        timeout(Duration::from_secs(2), ws_take_one(ws))
+            .await
+            .expect("no event received")
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn should_manage_conversations_and_fan_out_messages() {
+        let ctx = TestContext::new(None).await;
+        let owner_token = ctx._token.clone();
+        let (member_token, member_id) = ctx.create_user("conv.member").await;
+        let (admin_token, admin_id) = ctx.create_user("conv.admin").await;
+        let (outsider_token, _) = ctx.create_user("conv.outsider").await;
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/conversations")
+            .with_token(&owner_token)
+            .send_json(&json!({"name": "team", "member_ids": [member_id]}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let details = resp.json::<ConversationDetails>().await.unwrap();
+        let conversation_id = details.conversation.id;
+        let roles = details
+            .members
+            .iter()
+            .map(|member| member.role)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            roles,
+            vec![ConversationRole::Owner, ConversationRole::Member]
+        );
+
+        // conversations do not exist for those outside of them
+        let resp = ctx
+            .test_server
+            .get(format!("/api/conversations/{conversation_id}"))
+            .with_token(&outsider_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // members can neither invite nor rename
+        let resp = ctx
+            .test_server
+            .post(format!("/api/conversations/{conversation_id}/members"))
+            .with_token(&member_token)
+            .send_json(&json!({"user_id": admin_id}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let mut resp = ctx
+            .test_server
+            .post(format!("/api/conversations/{conversation_id}/members"))
+            .with_token(&owner_token)
+            .send_json(&json!({"user_id": admin_id, "role": "admin"}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let member = resp.json::<ConversationMember>().await.unwrap();
+        assert_eq!(member.role, ConversationRole::Admin);
+
+        let mut resp = ctx
+            .test_server
+            .patch(format!("/api/conversations/{conversation_id}"))
+            .with_token(&admin_token)
+            .send_json(&json!({"name": "renamed"}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<Conversation>().await.unwrap().name, "renamed");
+
+        let (_resp, mut member_ws) =
+            connect_ws(&ctx.addr, &member_token, &ctx.client)
+                .await
+                .unwrap();
+        let (_resp, mut admin_ws) =
+            connect_ws(&ctx.addr, &admin_token, &ctx.client)
+                .await
+                .unwrap();
+        let (_resp, mut outsider_ws) =
+            connect_ws(&ctx.addr, &outsider_token, &ctx.client)
+                .await
+                .unwrap();
+        sleep(Duration::from_millis(500)).await;
+
+        let send = |message: &str| {
+            ws_msg(&WsClientEvent::SendConversationMessage {
+                conversation_id,
+                message: message.to_owned(),
+            })
+        };
+        outsider_ws.send(send("let me in")).await.unwrap();
+        assert!(matches!(
+            take_event(&mut outsider_ws).await,
+            WsServerEvent::Error { .. }
+        ));
+
+        member_ws.send(send("hello team")).await.unwrap();
+        for ws in [&mut member_ws, &mut admin_ws] {
+            match take_event(ws).await {
+                WsServerEvent::ConversationMessage {
+                    conversation_id: id,
+                    sender,
+                    message,
+                    ..
+                } => {
+                    assert_eq!(id, conversation_id);
+                    assert_eq!(sender, member_id);
+                    assert_eq!(message, "hello team");
+                }
+                other => panic!("expected a conversation message: {other:?}"),
+            }
+        }
+
+        let resp = ctx
+            .test_server
+            .delete(format!(
+                "/api/conversations/{conversation_id}/members/{member_id}"
+            ))
+            .with_token(&member_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        for ws in [&mut member_ws, &mut admin_ws] {
+            match take_event(ws).await {
+                WsServerEvent::ConversationUpdated { change, .. } => {
+                    assert_eq!(
+                        change,
+                        ConversationChange::MemberLeft { user_id: member_id }
+                    )
+                }
+                other => panic!("expected a conversation update: {other:?}"),
+            }
+        }
+
+        let resp = ctx
+            .test_server
+            .get(format!("/api/conversations/{conversation_id}/messages"))
+            .with_token(&member_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/conversations/{conversation_id}/messages"))
+            .with_token(&admin_token)
+            .send()
+            .await
+            .unwrap();
+        let page = resp.json::<ConversationMessagesPage>().await.unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].sender_id, Some(member_id));
+    }
+}
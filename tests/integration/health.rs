@@ -0,0 +1,167 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TestContext;
+    use actix_demo::{
+        health::{HealthCheck, TcpHealthChecker},
+        models::health::{Criticality, HealthCheckResponse, ServiceStatus},
+        models::worker::WorkerBackoffConfig,
+        workers::{self, Worker, WorkerLiveness, WorkerRegistry},
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use std::time::Duration;
+
+    async fn probe(
+        ctx: &TestContext,
+        path: &str,
+    ) -> (StatusCode, HealthCheckResponse) {
+        let mut resp = ctx.test_server.get(path).send().await.unwrap();
+        let status = resp.status();
+        (status, resp.json::<HealthCheckResponse>().await.unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn should_report_the_dependencies_when_ready() {
+        let ctx = TestContext::new(None).await;
+
+        let (status, res) = probe(&ctx, "/hc/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.success);
+        assert!(!res.degraded);
+        let mut names = res.services.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["minio", "postgresql", "redis"]);
+        let postgres = &res.services["postgresql"];
+        assert!(postgres.is_healthy());
+        assert_eq!(postgres.criticality, Criticality::Critical);
+        assert!(!postgres.cached);
+
+        // within the cache ttl the dependencies are not checked again
+        let (status, res) = probe(&ctx, "/hc").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.services.values().all(|service| service.cached));
+    }
+
+    #[actix_rt::test]
+    async fn should_report_the_workers_when_live() {
+        let ctx = TestContext::new(None).await;
+
+        let (status, res) = probe(&ctx, "/hc/live").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.success);
+        assert_eq!(res.services.keys().collect::<Vec<_>>(), vec!["workers"]);
+    }
+
+    #[actix_rt::test]
+    async fn should_succeed_the_startup_probe_once_started() {
+        let ctx = TestContext::new(None).await;
+
+        let (status, res) = probe(&ctx, "/hc/startup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.services.contains_key("redis"));
+
+        // dependencies are not checked anymore once started
+        let (status, res) = probe(&ctx, "/hc/startup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(res.services.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn should_not_be_ready_while_shutting_down() {
+        let ctx = TestContext::new(None).await;
+
+        let _ = ctx.app_data.shutdown.begin(Duration::from_secs(5));
+        let (status, res) = probe(&ctx, "/hc/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!res.success);
+
+        // the instance itself still works
+        let (status, _) = probe(&ctx, "/hc/live").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn should_export_dependency_gauges() {
+        let ctx = TestContext::new(None).await;
+        let dependency_up = &ctx.app_data.metrics.dependency_up;
+
+        let _workers = common::start_workers(
+            &ctx.app_data,
+            vec![workers::dependency_prober_worker(ctx.app_data.clone())],
+        );
+        sleep(Duration::from_secs(2)).await;
+
+        for name in ["postgresql", "redis", "minio"] {
+            assert_eq!(dependency_up.with_label_values(&[name]).get(), 1);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn should_fail_a_declared_check_on_an_unreachable_dependency() {
+        // nothing listens on port 1
+        let check = HealthCheck::optional(
+            "closed",
+            TcpHealthChecker::new("127.0.0.1:1".to_owned()),
+        );
+
+        let health = check
+            .run(Duration::from_secs(2), Duration::from_secs(5))
+            .await;
+        assert!(matches!(health.status, ServiceStatus::Unhealthy(_)));
+        assert_eq!(health.criticality, Criticality::Optional);
+        assert!(!health.cached);
+
+        let health = check
+            .run(Duration::from_secs(2), Duration::from_secs(5))
+            .await;
+        assert!(health.cached);
+    }
+
+    #[actix_rt::test]
+    async fn should_fail_the_liveness_probe_while_a_worker_hangs() {
+        let liveness = WorkerLiveness::default();
+        let options = common::TestAppOptionsBuilder::default()
+            .worker_liveness(liveness.clone())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+
+        let hung = Worker::new(
+            "hung",
+            Duration::from_secs(1),
+            WorkerBackoffConfig {
+                initial_interval_secs: 1,
+                multiplier: 1.0,
+                max_interval_secs: 1,
+                max_elapsed_time_secs: 0,
+            },
+            |_ctx| async {
+                futures::future::pending::<()>().await;
+                Ok(0)
+            },
+        )
+        .on_every_instance()
+        .with_max_run_time(Duration::from_secs(1));
+        let mut registry = WorkerRegistry::new(
+            ctx.app_data.redis_conn_manager.clone(),
+            (ctx.app_data.redis_prefix)(&"workers"),
+        )
+        .with_liveness(liveness);
+        let _ = registry.register(hung);
+        let _workers = registry.start(ctx.app_data.shutdown.token());
+
+        sleep(Duration::from_millis(500)).await;
+        let (status, _) = probe(&ctx, "/hc/live").await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the run has been going on for longer than the worker may take, and
+        // the first result is no longer cached
+        sleep(Duration::from_secs(6)).await;
+        let (status, res) = probe(&ctx, "/hc/live").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!res.services["workers"].is_healthy());
+    }
+}
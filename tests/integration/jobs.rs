@@ -0,0 +1,589 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::misc::{
+        AbortedJobs, Job, JobAclEntry, JobStatus, JobsPage,
+    };
+    use actix_http::{header, StatusCode};
+    use actix_rt::time::sleep;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    async fn run_job(ctx: &TestContext, token: &str) -> Job {
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .append_header((header::CONTENT_TYPE, "application/json"))
+            .with_token(token)
+            .send_body(r#"{"args":[]}"#)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<Job>().await.unwrap()
+    }
+
+    async fn list_jobs(
+        ctx: &TestContext,
+        token: &str,
+        query: &str,
+    ) -> (StatusCode, Option<JobsPage>) {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd?{query}"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        let status = resp.status();
+        let page = if status == StatusCode::OK {
+            Some(resp.json::<JobsPage>().await.unwrap())
+        } else {
+            None
+        };
+        (status, page)
+    }
+
+    #[actix_rt::test]
+    async fn should_list_jobs_with_pagination_and_ownership() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+
+        common::create_http_user(&ctx.addr, "job.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token =
+            common::get_http_token(&ctx.addr, "job.user", "test", &ctx.client)
+                .await
+                .unwrap();
+
+        for _ in 0..3 {
+            let _ = run_job(&ctx, &admin_token).await;
+        }
+        let user_job = run_job(&ctx, &user_token).await;
+
+        // regular users only see their own jobs
+        let (status, page) = list_jobs(&ctx, &user_token, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let page = page.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.jobs[0].job_id, user_job.job_id);
+        assert!(page.next_cursor.is_none());
+
+        // and cannot ask for someone else's
+        let (status, _) = list_jobs(&ctx, &user_token, "started_by=1").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // admins see everything, newest first
+        let (status, page) = list_jobs(&ctx, &admin_token, "limit=3").await;
+        assert_eq!(status, StatusCode::OK);
+        let page = page.unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(page.jobs.len(), 3);
+        assert_eq!(page.jobs[0].job_id, user_job.job_id);
+        let cursor = page.next_cursor.expect("Expected a next page");
+
+        let (_, page) =
+            list_jobs(&ctx, &admin_token, &format!("limit=3&cursor={cursor}"))
+                .await;
+        let page = page.unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(page.jobs.len(), 1);
+        assert!(page.next_cursor.is_none());
+
+        let (_, page) =
+            list_jobs(&ctx, &admin_token, "started_by=1&status=failed").await;
+        assert_eq!(page.unwrap().total, 0);
+    }
+
+    async fn get_job_status(
+        ctx: &TestContext,
+        token: &str,
+        job: &Job,
+    ) -> StatusCode {
+        ctx.test_server
+            .get(format!("/api/cmd/{}", job.job_id))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[actix_rt::test]
+    async fn should_restrict_job_reads_to_owner_admins_and_acl() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+
+        for name in ["job.owner", "job.viewer"] {
+            common::create_http_user(&ctx.addr, name, "test", &ctx.client)
+                .await
+                .unwrap();
+        }
+        let owner_token =
+            common::get_http_token(&ctx.addr, "job.owner", "test", &ctx.client)
+                .await
+                .unwrap();
+        let viewer_token = common::get_http_token(
+            &ctx.addr,
+            "job.viewer",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+        let viewer_id =
+            actix_demo::utils::get_claims(&common::TEST_JWT_KEY, &viewer_token)
+                .unwrap()
+                .custom
+                .user_id;
+
+        let job = run_job(&ctx, &owner_token).await;
+
+        assert_eq!(
+            get_job_status(&ctx, &owner_token, &job).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_job_status(&ctx, &admin_token, &job).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_job_status(&ctx, &viewer_token, &job).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // only the owner can share the job
+        let resp = ctx
+            .test_server
+            .post(format!("/api/cmd/{}/acl", job.job_id))
+            .with_token(&viewer_token)
+            .send_json(&serde_json::json!({ "user_id": viewer_id }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let mut resp = ctx
+            .test_server
+            .post(format!("/api/cmd/{}/acl", job.job_id))
+            .with_token(&owner_token)
+            .send_json(&serde_json::json!({ "user_id": viewer_id }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let entry = resp.json::<JobAclEntry>().await.unwrap();
+        assert_eq!(entry.user_id, Some(viewer_id));
+
+        assert_eq!(
+            get_job_status(&ctx, &viewer_token, &job).await,
+            StatusCode::OK
+        );
+
+        let resp = ctx
+            .test_server
+            .delete(format!("/api/cmd/{}/acl/{}", job.job_id, entry.id))
+            .with_token(&owner_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            get_job_status(&ctx, &viewer_token, &job).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // role grants apply to every holder of the role
+        let resp = ctx
+            .test_server
+            .post(format!("/api/cmd/{}/acl", job.job_id))
+            .with_token(&owner_token)
+            .send_json(&serde_json::json!({ "role": "role_user" }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(
+            get_job_status(&ctx, &viewer_token, &job).await,
+            StatusCode::OK
+        );
+    }
+
+    async fn send_job_input(
+        ctx: &TestContext,
+        token: &str,
+        job: &Job,
+        data: &str,
+    ) -> StatusCode {
+        ctx.test_server
+            .post(format!("/api/cmd/{}/input", job.job_id))
+            .with_token(token)
+            .send_json(&serde_json::json!({ "data": data }))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[actix_rt::test]
+    async fn should_forward_input_to_running_job_of_owner() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::prompt_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let admin_token = ctx._token.clone();
+
+        common::create_http_user(&ctx.addr, "input.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "input.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+
+        let job = run_job(&ctx, &user_token).await;
+        // give the job time to subscribe to its input channel
+        sleep(Duration::from_millis(500)).await;
+
+        // only the owner may write to the job, not even admins
+        assert_eq!(
+            send_job_input(&ctx, &admin_token, &job, "admin\n").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send_job_input(&ctx, &user_token, &job, "user\n").await,
+            StatusCode::OK
+        );
+
+        sleep(Duration::from_millis(500)).await;
+
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}", job.job_id))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        let job_resp = resp.json::<Job>().await.unwrap();
+        assert_eq!(job_resp.status, JobStatus::Completed);
+
+        // finished jobs no longer accept input
+        assert_eq!(
+            send_job_input(&ctx, &user_token, &job, "again\n").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    async fn fetch_job(ctx: &TestContext, token: &str, job: &Job) -> Job {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}", job.job_id))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        resp.json::<Job>().await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn should_abort_jobs_with_reason_and_in_bulk() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::sleep_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let admin_token = ctx._token.clone();
+
+        common::create_http_user(&ctx.addr, "abort.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "abort.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+
+        let first = run_job(&ctx, &user_token).await;
+        let second = run_job(&ctx, &user_token).await;
+        // give the jobs time to subscribe to their abort channels
+        sleep(Duration::from_millis(500)).await;
+
+        // admins can abort jobs of other users
+        let resp = ctx
+            .test_server
+            .delete(format!(
+                "/api/cmd/{}?reason=maintenance%20window",
+                first.job_id
+            ))
+            .with_token(&admin_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        sleep(Duration::from_millis(500)).await;
+
+        let job = fetch_job(&ctx, &user_token, &first).await;
+        assert_eq!(job.status, JobStatus::Aborted);
+        assert_eq!(
+            job.status_message.as_deref(),
+            Some("Job aborted by user 1: maintenance window")
+        );
+
+        // the remaining job is aborted in bulk
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd/abort?force=true")
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let aborted = resp.json::<AbortedJobs>().await.unwrap();
+        assert_eq!(aborted.job_ids, vec![second.job_id]);
+
+        sleep(Duration::from_millis(500)).await;
+
+        let job = fetch_job(&ctx, &user_token, &second).await;
+        assert_eq!(job.status, JobStatus::Aborted);
+
+        // jobs that are no longer running cannot be aborted
+        let resp = ctx
+            .test_server
+            .delete(format!("/api/cmd/{}", second.job_id))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn wait_for_job(ctx: &TestContext, token: &str, job: &Job) -> Job {
+        for _ in 0..20 {
+            let job = fetch_job(ctx, token, job).await;
+            if job.status != JobStatus::Pending {
+                return job;
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+        panic!("Job {} did not finish in time", job.job_id)
+    }
+
+    #[actix_rt::test]
+    async fn should_retry_failed_jobs_and_dedupe_idempotent_requests() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::failing_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        let body = serde_json::json!({
+            "args": [],
+            "retry": {
+                "max_attempts": 2,
+                "initial_interval_secs": 1,
+                "multiplier": 1.0,
+                "max_interval_secs": 1
+            }
+        });
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .append_header(("Idempotency-Key", "nightly-2026-10-18"))
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let job = resp.json::<Job>().await.unwrap();
+        assert_eq!(job.attempt, 1);
+
+        // a repeated key returns the same job instead of starting another
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .append_header(("Idempotency-Key", "nightly-2026-10-18"))
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<Job>().await.unwrap().job_id, job.job_id);
+
+        // the failing job is run once more before it stays failed
+        sleep(Duration::from_millis(500)).await;
+        let job = wait_for_job(&ctx, &token, &job).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.attempt, 2);
+
+        let invalid = serde_json::json!({
+            "args": [], "retry": {"max_attempts": 0}
+        });
+        let resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .send_json(&invalid)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // manual retries start a new job linked to the original one
+        let mut resp = ctx
+            .test_server
+            .post(format!("/api/cmd/{}/retry", job.job_id))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let retry = resp.json::<Job>().await.unwrap();
+        assert_ne!(retry.job_id, job.job_id);
+        assert_eq!(retry.retry_of, Some(job.job_id));
+        assert_eq!(retry.attempt, 1);
+
+        // jobs that are still running can't be retried
+        let resp = ctx
+            .test_server
+            .post(format!("/api/cmd/{}/retry", retry.job_id))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn stream_job_output(
+        ctx: &TestContext,
+        token: &str,
+        job: &Job,
+        last_event_id: Option<&str>,
+    ) -> (StatusCode, String) {
+        let mut req = ctx
+            .test_server
+            .get(format!("/api/cmd/{}/stream", job.job_id))
+            .with_token(token);
+        if let Some(id) = last_event_id {
+            req = req.insert_header(("Last-Event-ID", id));
+        }
+        let mut resp = req.send().await.unwrap();
+        let status = resp.status();
+        if status == StatusCode::OK {
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/event-stream"
+            );
+        }
+        let body = resp.body().await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn event_ids(events: &str) -> Vec<&str> {
+        events
+            .lines()
+            .filter_map(|line| line.strip_prefix("id: "))
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn should_stream_job_output_as_server_sent_events() {
+        let ctx = TestContext::new(None).await;
+        let token = ctx._token.clone();
+
+        let job = run_job(&ctx, &token).await;
+        let job = wait_for_job(&ctx, &token, &job).await;
+        assert_eq!(job.status, JobStatus::Completed);
+
+        // the output of a finished job is replayed and the stream ends
+        let (status, events) =
+            stream_job_output(&ctx, &token, &job, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(events.contains("event: line"));
+        assert!(events.contains("hello world"));
+        assert!(events.trim_end().ends_with(r#""kind":"Done","code":"0"}"#));
+        let ids = event_ids(&events);
+        assert!(ids.len() >= 2);
+
+        // reconnecting resumes after the last event seen
+        let (status, resumed) =
+            stream_job_output(&ctx, &token, &job, Some(ids[0])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(event_ids(&resumed), ids[1..]);
+        assert!(resumed.contains("event: done"));
+
+        let (status, _) =
+            stream_job_output(&ctx, &token, &job, Some("not-an-id")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn should_stream_output_of_a_running_job() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::sleep_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        let job = run_job(&ctx, &token).await;
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}/stream", job.job_id))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // lines arrive while the job is still running
+        let mut events = String::new();
+        while !events.contains("1 still sleeping") {
+            let chunk = resp.next().await.unwrap().unwrap();
+            events.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(!events.contains("event: done"));
+        assert_eq!(
+            fetch_job(&ctx, &token, &job).await.status,
+            JobStatus::Pending
+        );
+
+        // and the stream ends once it exited
+        while let Some(chunk) = resp.next().await {
+            events.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+        }
+        assert!(events.contains("done sleeping"));
+        assert!(events.trim_end().ends_with(r#""kind":"Done","code":"0"}"#));
+    }
+
+    #[actix_rt::test]
+    async fn should_record_job_metrics() {
+        let ctx = TestContext::new(None).await;
+        let token = ctx._token.clone();
+
+        let job = run_job(&ctx, &token).await;
+        let job = wait_for_job(&ctx, &token, &job).await;
+        assert_eq!(job.status, JobStatus::Completed);
+
+        let mut resp = ctx.test_server.get("/metrics").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.body().await.unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        for metric in [
+            r#"api_jobs_total{status="started",template="command"} 1"#,
+            r#"api_jobs_total{status="completed",template="command"} 1"#,
+            r#"api_jobs_running{template="command"} 0"#,
+            r#"api_job_duration_seconds_count{status="completed",template="command"} 1"#,
+            r#"api_job_output_bytes_total{kind="line",template="command"}"#,
+        ] {
+            assert!(metrics.contains(metric), "{metric} missing");
+        }
+    }
+}
@@ -1,6 +1,19 @@
 #![allow(clippy::let_unit_value)]
+mod artifacts;
 mod auth;
 mod common;
+mod conversations;
+mod health;
+mod jobs;
+mod messages;
 mod misc;
+mod notifications;
+mod pipelines;
+mod presence;
+mod runners;
+mod schedules;
+mod shutdown;
 mod users;
+mod webhooks;
+mod workers;
 mod ws;
@@ -0,0 +1,193 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use crate::ws::ws_utils::*;
+    use actix_demo::models::{
+        message::{MarkedRead, MessagesPage, UnreadCounts},
+        users::UserId,
+        ws::{WsClientEvent, WsServerEvent},
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::{sleep, timeout};
+    use futures::SinkExt;
+    use std::{str::FromStr, time::Duration};
+
+    async fn get_conversation(
+        ctx: &TestContext,
+        token: &str,
+        peer: UserId,
+        query: &str,
+    ) -> MessagesPage {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/messages/{peer}?{query}"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<MessagesPage>().await.unwrap()
+    }
+
+    async fn get_unread_counts(ctx: &TestContext, token: &str) -> UnreadCounts {
+        let mut resp = ctx
+            .test_server
+            .get("/api/messages/unread")
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<UnreadCounts>().await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn should_persist_messages_with_history_and_read_receipts() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+        let admin_id = UserId::from_str("1").unwrap();
+
+        let (user_token, user_id) = ctx.create_user("chat.user").await;
+
+        let (_resp, mut ws) = connect_ws(&ctx.addr, &user_token, &ctx.client)
+            .await
+            .unwrap();
+        for i in 0..3 {
+            ws.send(ws_msg(&WsClientEvent::SendMessage {
+                receiver: admin_id,
+                message: format!("hello {i}"),
+            }))
+            .await
+            .unwrap();
+        }
+        // invalid messages are rejected without closing the connection
+        ws.send(ws_msg(&WsClientEvent::SendMessage {
+            receiver: admin_id,
+            message: " ".to_owned(),
+        }))
+        .await
+        .unwrap();
+        let msg = ws_take_one(&mut ws).await.unwrap();
+        assert!(matches!(msg, WsServerEvent::Error { .. }));
+        sleep(Duration::from_millis(500)).await;
+
+        let unread = get_unread_counts(&ctx, &admin_token).await;
+        assert_eq!(unread.total, 3);
+        assert_eq!(unread.peers[0].peer, user_id);
+
+        // newest first, paged by the id of the last message
+        let page =
+            get_conversation(&ctx, &admin_token, user_id, "limit=2").await;
+        assert_eq!(page.messages.len(), 2);
+        assert_eq!(page.messages[0].message_text, "hello 2");
+        let cursor = page.next_cursor.unwrap();
+        let next = get_conversation(
+            &ctx,
+            &admin_token,
+            user_id,
+            &format!("limit=2&before={cursor}"),
+        )
+        .await;
+        assert_eq!(next.messages.len(), 1);
+        assert_eq!(next.messages[0].message_text, "hello 0");
+        assert!(next.next_cursor.is_none());
+
+        let mut resp = ctx
+            .test_server
+            .post(format!("/api/messages/{user_id}/read?up_to={cursor}"))
+            .with_token(&admin_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<MarkedRead>().await.unwrap().marked_read, 2);
+        assert_eq!(get_unread_counts(&ctx, &admin_token).await.total, 1);
+
+        // the sender sees the read receipts
+        let page = get_conversation(&ctx, &user_token, admin_id, "").await;
+        let read = page
+            .messages
+            .iter()
+            .map(|message| message.is_read)
+            .collect::<Vec<_>>();
+        assert_eq!(read, vec![false, true, true]);
+    }
+
+    async fn take_message(ws: &mut WsClient) -> Option<(String, String)> {
+        match timeout(Duration::from_secs(2), ws_take_one(ws)).await {
+            Ok(Ok(WsServerEvent::SentMessage { id, message, .. })) => {
+                Some((id, message))
+            }
+            Ok(other) => panic!("expected a message, got {other:?}"),
+            Err(_) => None,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn should_resume_message_delivery_after_last_ack_of_device() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+        let admin_id = UserId::from_str("1").unwrap();
+
+        common::create_http_user(&ctx.addr, "ack.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token =
+            common::get_http_token(&ctx.addr, "ack.user", "test", &ctx.client)
+                .await
+                .unwrap();
+        let (_resp, mut sender) =
+            connect_ws(&ctx.addr, &user_token, &ctx.client)
+                .await
+                .unwrap();
+        let (_resp, mut receiver) =
+            connect_ws(&ctx.addr, &admin_token, &ctx.client)
+                .await
+                .unwrap();
+        // give the receive loop time to pick its starting point
+        sleep(Duration::from_millis(500)).await;
+
+        let send = |message: &str| {
+            ws_msg(&WsClientEvent::SendMessage {
+                receiver: admin_id,
+                message: message.to_owned(),
+            })
+        };
+        sender.send(send("first")).await.unwrap();
+        let (first_id, message) = take_message(&mut receiver).await.unwrap();
+        assert_eq!(message, "first");
+        // delivered once per connection
+        assert!(take_message(&mut receiver).await.is_none());
+
+        // unacknowledged messages are delivered again after a reconnect
+        drop(receiver);
+        let (_resp, mut receiver) =
+            connect_ws(&ctx.addr, &admin_token, &ctx.client)
+                .await
+                .unwrap();
+        let (id, _) = take_message(&mut receiver).await.unwrap();
+        assert_eq!(id, first_id);
+
+        receiver
+            .send(ws_msg(&WsClientEvent::AckMessages {
+                id: first_id.parse().unwrap(),
+            }))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(500)).await;
+        drop(receiver);
+
+        let (_resp, mut receiver) =
+            connect_ws(&ctx.addr, &admin_token, &ctx.client)
+                .await
+                .unwrap();
+        sleep(Duration::from_millis(500)).await;
+        sender.send(send("second")).await.unwrap();
+        let (_, message) = take_message(&mut receiver).await.unwrap();
+        assert_eq!(message, "second");
+        assert!(take_message(&mut receiver).await.is_none());
+    }
+}
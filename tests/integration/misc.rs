@@ -68,7 +68,7 @@ mod tests {
             let job_resp: Job = test::read_body_json(resp).await;
 
             let job_id = job_resp.job_id.to_string();
-            assert_eq!(job_resp.started_by, user_id);
+            assert_eq!(job_resp.started_by, Some(user_id));
             assert_eq!(job_resp.status, JobStatus::Pending);
 
             sleep(Duration::from_millis(500)).await;
@@ -81,7 +81,7 @@ mod tests {
             assert_eq!(resp.status(), StatusCode::OK);
             let job_resp: Job = test::read_body_json(resp).await;
 
-            assert_eq!(job_resp.started_by, user_id);
+            assert_eq!(job_resp.started_by, Some(user_id));
             assert_eq!(job_resp.status, JobStatus::Failed);
             Ok(())
         }
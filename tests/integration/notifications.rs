@@ -0,0 +1,101 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use crate::ws::ws_utils::*;
+    use actix_demo::models::{
+        message::MarkedRead,
+        notification::{NotificationKind, NotificationsPage},
+        ws::WsServerEvent,
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::{sleep, timeout};
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn get_notifications(
+        ctx: &TestContext,
+        token: &str,
+        query: &str,
+    ) -> NotificationsPage {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/notifications{query}"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<NotificationsPage>().await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn should_notify_of_new_logins() {
+        let ctx = TestContext::new(None).await;
+        common::create_http_user(&ctx.addr, "notified", "test", &ctx.client)
+            .await
+            .unwrap();
+        let token =
+            common::get_http_token(&ctx.addr, "notified", "test", &ctx.client)
+                .await
+                .unwrap();
+
+        let (_resp, mut ws) =
+            connect_ws(&ctx.addr, &token, &ctx.client).await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+
+        // logging in on another device is pushed to the connected ones
+        let _other_token =
+            common::get_http_token(&ctx.addr, "notified", "test", &ctx.client)
+                .await
+                .unwrap();
+        let pushed =
+            match timeout(Duration::from_secs(2), ws_take_one(&mut ws)).await {
+                Ok(Ok(WsServerEvent::Notification {
+                    notification, ..
+                })) => notification,
+                other => panic!("expected a notification, got {other:?}"),
+            };
+        assert_eq!(pushed.kind, NotificationKind::SuspiciousLogin);
+        assert_eq!(pushed.data["anomalies"], json!(["new_device"]));
+        assert_eq!(pushed.read_at, None);
+
+        let page = get_notifications(&ctx, &token, "").await;
+        assert_eq!(page.unread_count, 2);
+        assert_eq!(page.notifications.len(), 2);
+        assert_eq!(page.notifications[0].id, pushed.id);
+
+        let resp = ctx
+            .test_server
+            .patch("/api/notifications")
+            .with_token(&token)
+            .send_json(&json!({}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let mut resp = ctx
+            .test_server
+            .patch("/api/notifications")
+            .with_token(&token)
+            .send_json(&json!({"up_to": pushed.id}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<MarkedRead>().await.unwrap().marked_read, 2);
+
+        let page = get_notifications(&ctx, &token, "?unread_only=true").await;
+        assert_eq!(page.unread_count, 0);
+        assert!(page.notifications.is_empty());
+
+        // notifications are per user
+        let page =
+            get_notifications(&ctx, &ctx._token, "?unread_only=true").await;
+        assert!(page
+            .notifications
+            .iter()
+            .all(|notification| notification.id != pushed.id));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::misc::JobStatus;
+    use actix_demo::models::pipeline::PipelineDetails;
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use std::time::Duration;
+
+    async fn run_pipeline(
+        ctx: &TestContext,
+        token: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, Option<PipelineDetails>) {
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd/pipelines")
+            .with_token(token)
+            .send_json(&body)
+            .await
+            .unwrap();
+        let status = resp.status();
+        let pipeline = if status == StatusCode::OK {
+            Some(resp.json::<PipelineDetails>().await.unwrap())
+        } else {
+            None
+        };
+        (status, pipeline)
+    }
+
+    /// Polls the pipeline until its job is no longer pending
+    async fn wait_for_pipeline(
+        ctx: &TestContext,
+        token: &str,
+        pipeline: &PipelineDetails,
+    ) -> PipelineDetails {
+        for _ in 0..20 {
+            let mut resp = ctx
+                .test_server
+                .get(format!("/api/cmd/pipelines/{}", pipeline.job.job_id))
+                .with_token(token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let pipeline = resp.json::<PipelineDetails>().await.unwrap();
+            if pipeline.job.status != JobStatus::Pending {
+                return pipeline;
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+        panic!("Pipeline {} did not finish in time", pipeline.job.job_id)
+    }
+
+    #[actix_rt::test]
+    async fn should_run_pipeline_steps_in_order_and_pass_outputs() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::pipeline_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        let (status, _) = run_pipeline(
+            &ctx,
+            &token,
+            serde_json::json!({ "steps": [
+                { "name": "a", "depends_on": ["b"] },
+                { "name": "b", "depends_on": ["a"] }
+            ]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, pipeline) = run_pipeline(
+            &ctx,
+            &token,
+            serde_json::json!({ "steps": [
+                { "name": "test", "args": ["-tested"], "depends_on": ["build"] },
+                { "name": "build", "args": ["built"] }
+            ]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let pipeline = pipeline.unwrap();
+        // steps come back in the order they run in
+        let names = pipeline
+            .steps
+            .iter()
+            .map(|s| s.step.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["build", "test"]);
+
+        let pipeline = wait_for_pipeline(&ctx, &token, &pipeline).await;
+        assert_eq!(pipeline.job.status, JobStatus::Completed);
+        assert!(pipeline
+            .steps
+            .iter()
+            .all(|s| s.status == JobStatus::Completed));
+        assert_eq!(pipeline.steps[0].step.output.as_deref(), Some("built"));
+        assert_eq!(
+            pipeline.steps[1].step.output.as_deref(),
+            Some("built-tested")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn should_skip_dependents_of_failed_steps() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::pipeline_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        let (_, pipeline) = run_pipeline(
+            &ctx,
+            &token,
+            serde_json::json!({ "steps": [
+                { "name": "lint", "args": ["fail"], "on_failure": "continue" },
+                { "name": "build", "args": ["built"] },
+                { "name": "deploy", "depends_on": ["lint", "build"] }
+            ]}),
+        )
+        .await;
+        let pipeline =
+            wait_for_pipeline(&ctx, &token, &pipeline.unwrap()).await;
+        assert_eq!(pipeline.job.status, JobStatus::Failed);
+
+        let step = |name: &str| {
+            pipeline
+                .steps
+                .iter()
+                .find(|s| s.step.name == name)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(step("lint").status, JobStatus::Failed);
+        // the independent branch still ran
+        assert_eq!(step("build").status, JobStatus::Completed);
+        let deploy = step("deploy");
+        assert_eq!(deploy.status, JobStatus::Aborted);
+        assert!(deploy
+            .status_message
+            .unwrap_or_default()
+            .starts_with("Skipped"));
+
+        // every step is a job of its own
+        let resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}", deploy.step.job_id))
+            .with_token(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod tests {
+    use crate::common::{TestContext, WithToken};
+    use crate::ws::ws_utils::*;
+    use actix_demo::models::{
+        presence::{PublicUserProfile, UserPresence},
+        users::UserId,
+        ws::WsServerEvent,
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::{sleep, timeout};
+    use serde_json::json;
+    use std::time::Duration;
+
+    async fn get_presence(
+        ctx: &TestContext,
+        token: &str,
+        user_id: UserId,
+    ) -> UserPresence {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/presence?user_ids={user_id}"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<Vec<UserPresence>>().await.unwrap().remove(0)
+    }
+
+    async fn take_presence(ws: &mut WsClient) -> (UserId, bool) {
+        match timeout(Duration::from_secs(5), ws_take_one(ws)).await {
+            Ok(Ok(WsServerEvent::PresenceChanged {
+                user_id, online, ..
+            })) => (user_id, online),
+            other => panic!("expected a presence change, got {other:?}"),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn should_track_presence_of_connected_users() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+
+        let (user_token, user_id) = ctx.create_user("presence.user").await;
+
+        // presence changes go to the users sharing a conversation
+        let resp = ctx
+            .test_server
+            .post("/api/conversations")
+            .with_token(&admin_token)
+            .send_json(&json!({"name": "presence", "member_ids": [user_id]}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let presence = get_presence(&ctx, &admin_token, user_id).await;
+        assert!(!presence.online);
+        assert!(presence.last_seen_at.is_none());
+
+        let (_resp, mut admin_ws) =
+            connect_ws(&ctx.addr, &admin_token, &ctx.client)
+                .await
+                .unwrap();
+        sleep(Duration::from_millis(500)).await;
+
+        let (_resp, user_ws) = connect_ws(&ctx.addr, &user_token, &ctx.client)
+            .await
+            .unwrap();
+        assert_eq!(take_presence(&mut admin_ws).await, (user_id, true));
+        let presence = get_presence(&ctx, &admin_token, user_id).await;
+        assert!(presence.online);
+        assert_eq!(presence.devices, 1);
+
+        drop(user_ws);
+        assert_eq!(take_presence(&mut admin_ws).await, (user_id, false));
+        let presence = get_presence(&ctx, &admin_token, user_id).await;
+        assert!(!presence.online);
+        assert_eq!(presence.devices, 0);
+
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/public/users/{user_id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let profile = resp.json::<PublicUserProfile>().await.unwrap();
+        assert!(!profile.online);
+        assert!(profile.last_seen_at.is_some());
+
+        let resp = ctx
+            .test_server
+            .get("/api/presence?user_ids=a")
+            .with_token(&admin_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
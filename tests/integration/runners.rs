@@ -0,0 +1,292 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::{
+        misc::{Job, JobStatus},
+        notification::{NotificationKind, NotificationsPage},
+        runner::{
+            CreatedRunner, RunnerJobAssignment, RunnerJobControl,
+            RUNNER_TOKEN_HEADER,
+        },
+    };
+    use actix_demo::{actions, workers};
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use std::time::Duration;
+
+    async fn run_job_on(
+        ctx: &TestContext,
+        token: &str,
+        labels: &[&str],
+    ) -> Job {
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(token)
+            .send_json(
+                &serde_json::json!({"args": ["hello"], "runs_on": labels}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<Job>().await.unwrap()
+    }
+
+    async fn claim_job(
+        ctx: &TestContext,
+        runner_token: &str,
+    ) -> (StatusCode, Option<RunnerJobAssignment>) {
+        let mut resp = ctx
+            .test_server
+            .post("/runner/jobs/claim?wait_secs=0")
+            .insert_header((RUNNER_TOKEN_HEADER, runner_token))
+            .send()
+            .await
+            .unwrap();
+        let status = resp.status();
+        let job = if status == StatusCode::OK {
+            Some(resp.json::<RunnerJobAssignment>().await.unwrap())
+        } else {
+            None
+        };
+        (status, job)
+    }
+
+    async fn send_output(
+        ctx: &TestContext,
+        runner_token: &str,
+        job: &Job,
+    ) -> RunnerJobControl {
+        let mut resp = ctx
+            .test_server
+            .post(format!("/runner/jobs/{}/output", job.job_id))
+            .insert_header((RUNNER_TOKEN_HEADER, runner_token))
+            .send_json(&serde_json::json!([{"kind": "Line", "value": "hello"}]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<RunnerJobControl>().await.unwrap()
+    }
+
+    async fn get_job(ctx: &TestContext, token: &str, job: &Job) -> Job {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}", job.job_id))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<Job>().await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn should_run_jobs_on_remote_runners() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+
+        // only admins register runners
+        common::create_http_user(&ctx.addr, "runner.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "runner.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+        let body = serde_json::json!({"name": "linux-1", "labels": ["linux"]});
+        let resp = ctx
+            .test_server
+            .post("/api/runners")
+            .with_token(&user_token)
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/runners")
+            .with_token(&admin_token)
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let runner = resp.json::<CreatedRunner>().await.unwrap();
+        assert_eq!(runner.runner.labels, vec!["linux"]);
+
+        let (status, _) = claim_job(&ctx, "not-a-runner-token").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // jobs are only handed to runners with all of their labels
+        let gpu_job = run_job_on(&ctx, &user_token, &["gpu", "linux"]).await;
+        let (status, _) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let job = run_job_on(&ctx, &user_token, &["linux"]).await;
+        let (status, assignment) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(status, StatusCode::OK);
+        let assignment = assignment.unwrap();
+        assert_eq!(assignment.job_id, job.job_id);
+        assert_eq!(assignment.args, vec!["hello"]);
+
+        assert!(!send_output(&ctx, &runner.token, &job).await.abort);
+        let resp = ctx
+            .test_server
+            .post(format!("/runner/jobs/{}/complete", job.job_id))
+            .insert_header((RUNNER_TOKEN_HEADER, runner.token.as_str()))
+            .send_json(&serde_json::json!({"exit_code": 0}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            get_job(&ctx, &user_token, &job).await.status,
+            JobStatus::Completed
+        );
+
+        // runners report -1 for jobs they had to kill or lost the exit code of
+        let job = run_job_on(&ctx, &user_token, &["linux"]).await;
+        let (_, assignment) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(assignment.unwrap().job_id, job.job_id);
+        let resp = ctx
+            .test_server
+            .post(format!("/runner/jobs/{}/complete", job.job_id))
+            .insert_header((RUNNER_TOKEN_HEADER, runner.token.as_str()))
+            .send_json(&serde_json::json!({"exit_code": -1}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            get_job(&ctx, &user_token, &job).await.status,
+            JobStatus::Failed
+        );
+
+        // the user is notified of both jobs finishing
+        let mut resp = ctx
+            .test_server
+            .get("/api/notifications")
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        let page = resp.json::<NotificationsPage>().await.unwrap();
+        let finished = page
+            .notifications
+            .iter()
+            .filter(|notification| {
+                notification.kind == NotificationKind::JobFinished
+            })
+            .count();
+        assert_eq!(finished, 2);
+
+        // queued jobs are aborted right away, running ones once the runner
+        // hears about it
+        let resp = ctx
+            .test_server
+            .delete(format!("/api/cmd/{}", gpu_job.job_id))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            get_job(&ctx, &user_token, &gpu_job).await.status,
+            JobStatus::Aborted
+        );
+
+        let job = run_job_on(&ctx, &user_token, &["linux"]).await;
+        let (_, assignment) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(assignment.unwrap().job_id, job.job_id);
+        let resp = ctx
+            .test_server
+            .delete(format!("/api/cmd/{}", job.job_id))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(send_output(&ctx, &runner.token, &job).await.abort);
+    }
+
+    #[actix_rt::test]
+    async fn should_requeue_jobs_of_dead_runners() {
+        let ctx = TestContext::new(None).await;
+        let token = ctx._token.clone();
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/runners")
+            .with_token(&token)
+            .send_json(
+                &serde_json::json!({"name": "flaky", "labels": ["linux"]}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let runner = resp.json::<CreatedRunner>().await.unwrap();
+
+        let job = run_job_on(&ctx, &token, &["linux"]).await;
+        let (status, _) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // nothing to do while the runner is alive
+        assert_eq!(
+            workers::requeue_dead_runner_jobs(&ctx.app_data, None)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // the runner misses the heartbeat timeout of the test config
+        sleep(Duration::from_secs(3)).await;
+        assert_eq!(
+            workers::requeue_dead_runner_jobs(&ctx.app_data, None)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_job(&ctx, &token, &job).await.status,
+            JobStatus::Pending
+        );
+
+        let (status, assignment) = claim_job(&ctx, &runner.token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(assignment.unwrap().job_id, job.job_id);
+
+        // the runner finishes the job after it was listed as dead, which the
+        // monitor must not undo
+        let resp = ctx
+            .test_server
+            .post(format!("/runner/jobs/{}/complete", job.job_id))
+            .insert_header((RUNNER_TOKEN_HEADER, runner.token.as_str()))
+            .send_json(&serde_json::json!({"exit_code": 0}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let mut conn = ctx.app_data.pool.get().unwrap();
+        assert!(actions::runners::lock_dead_runner_job(
+            job.job_id,
+            runner.runner.id,
+            &mut conn,
+        )
+        .unwrap()
+        .is_none());
+        assert!(!actions::runners::requeue_runner_job(
+            job.job_id,
+            runner.runner.id,
+            &mut conn,
+        )
+        .unwrap());
+        assert_eq!(
+            get_job(&ctx, &token, &job).await.status,
+            JobStatus::Completed
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::misc::Job;
+    use actix_demo::models::schedule::JobSchedule;
+    use actix_demo::models::worker::WorkerFence;
+    use actix_demo::workers;
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use diesel::prelude::*;
+    use std::time::Duration;
+
+    async fn create_schedule(
+        ctx: &TestContext,
+        token: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, Option<JobSchedule>) {
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd/schedules")
+            .with_token(token)
+            .send_json(&body)
+            .await
+            .unwrap();
+        let status = resp.status();
+        let schedule = if status == StatusCode::CREATED {
+            Some(resp.json::<JobSchedule>().await.unwrap())
+        } else {
+            None
+        };
+        (status, schedule)
+    }
+
+    async fn get_schedule(
+        ctx: &TestContext,
+        token: &str,
+        schedule_id: i32,
+    ) -> (StatusCode, Option<JobSchedule>) {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/schedules/{schedule_id}"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        let status = resp.status();
+        let schedule = if status == StatusCode::OK {
+            Some(resp.json::<JobSchedule>().await.unwrap())
+        } else {
+            None
+        };
+        (status, schedule)
+    }
+
+    #[actix_rt::test]
+    async fn should_manage_job_schedules() {
+        let ctx = TestContext::new(None).await;
+        let admin_token = ctx._token.clone();
+
+        common::create_http_user(&ctx.addr, "sched.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "sched.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+
+        let (status, _) = create_schedule(
+            &ctx,
+            &user_token,
+            serde_json::json!({ "name": "nightly", "cron_expression": "every night" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, schedule) = create_schedule(
+            &ctx,
+            &user_token,
+            serde_json::json!({
+                "name": "nightly",
+                "cron_expression": "0 3 * * *",
+                "args": ["cleanup"],
+                "missed_run_policy": "run_once"
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let schedule = schedule.unwrap();
+        assert_eq!(schedule.cron_expression, "0 0 3 * * *");
+        assert!(schedule.next_run_at.is_some());
+        assert!(schedule.last_run_at.is_none());
+
+        // schedules are private to their creator and admins
+        let (status, _) = get_schedule(&ctx, &user_token, schedule.id).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get_schedule(&ctx, &admin_token, schedule.id).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, admin_schedule) = create_schedule(
+            &ctx,
+            &admin_token,
+            serde_json::json!({ "name": "hourly", "cron_expression": "0 * * * *" }),
+        )
+        .await;
+        let admin_schedule = admin_schedule.unwrap();
+        let (status, _) =
+            get_schedule(&ctx, &user_token, admin_schedule.id).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut resp = ctx
+            .test_server
+            .get("/api/cmd/schedules")
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        let schedules = resp.json::<Vec<JobSchedule>>().await.unwrap();
+        assert_eq!(schedules.len(), 1);
+
+        // disabling a schedule clears its next run
+        let mut resp = ctx
+            .test_server
+            .patch(format!("/api/cmd/schedules/{}", schedule.id))
+            .with_token(&user_token)
+            .send_json(&serde_json::json!({ "enabled": false }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let updated = resp.json::<JobSchedule>().await.unwrap();
+        assert!(!updated.enabled);
+        assert!(updated.next_run_at.is_none());
+
+        let resp = ctx
+            .test_server
+            .delete(format!("/api/cmd/schedules/{}", schedule.id))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let (status, _) = get_schedule(&ctx, &user_token, schedule.id).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn should_run_due_schedules_and_skip_missed_runs() {
+        let ctx = TestContext::new(None).await;
+
+        common::create_http_user(&ctx.addr, "cron.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token =
+            common::get_http_token(&ctx.addr, "cron.user", "test", &ctx.client)
+                .await
+                .unwrap();
+
+        let (_, schedule) = create_schedule(
+            &ctx,
+            &user_token,
+            serde_json::json!({ "name": "every second", "cron_expression": "* * * * * *" }),
+        )
+        .await;
+        let schedule = schedule.unwrap();
+
+        sleep(Duration::from_millis(1100)).await;
+
+        let started = workers::run_due_job_schedules(&ctx.app_data, None)
+            .await
+            .unwrap();
+        assert_eq!(started, 1);
+
+        let (_, schedule) = get_schedule(&ctx, &user_token, schedule.id).await;
+        let schedule = schedule.unwrap();
+        assert!(schedule.last_run_at.is_some());
+        let job_id = schedule.last_job_id.unwrap();
+
+        // the job runs as the user who created the schedule
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{job_id}"))
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let job = resp.json::<Job>().await.unwrap();
+        assert_eq!(Some(schedule.created_by), job.started_by);
+
+        // pretend the scheduler was down for a while
+        let missed_at =
+            chrono::Utc::now().naive_utc() - chrono::Duration::hours(2);
+        let _ = {
+            use actix_demo::schema::job_schedules::dsl as job_schedules;
+            let mut conn = ctx.app_data.pool.get().unwrap();
+            diesel::update(
+                job_schedules::job_schedules
+                    .filter(job_schedules::id.eq(schedule.id)),
+            )
+            .set(job_schedules::next_run_at.eq(missed_at))
+            .execute(&mut conn)
+            .unwrap()
+        };
+
+        let started = workers::run_due_job_schedules(&ctx.app_data, None)
+            .await
+            .unwrap();
+        assert_eq!(started, 0);
+
+        let (_, skipped) = get_schedule(&ctx, &user_token, schedule.id).await;
+        let skipped = skipped.unwrap();
+        assert_eq!(skipped.last_job_id, Some(job_id));
+        assert!(skipped.next_run_at.unwrap() > missed_at);
+    }
+
+    #[actix_rt::test]
+    async fn should_reject_schedule_runs_of_stale_leaders() {
+        let ctx = TestContext::new(None).await;
+
+        common::create_http_user(&ctx.addr, "fenced.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "fenced.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+
+        let (_, schedule) = create_schedule(
+            &ctx,
+            &user_token,
+            serde_json::json!({ "name": "every second", "cron_expression": "* * * * * *" }),
+        )
+        .await;
+        let schedule = schedule.unwrap();
+
+        sleep(Duration::from_millis(1100)).await;
+
+        // a name of its own, so that the scheduler of the app doesn't
+        // interfere
+        let fence = |token| WorkerFence {
+            worker: "job-scheduler-fence-test",
+            token,
+        };
+        let started =
+            workers::run_due_job_schedules(&ctx.app_data, Some(fence(2)))
+                .await
+                .unwrap();
+        assert_eq!(started, 1);
+        let (_, schedule) = get_schedule(&ctx, &user_token, schedule.id).await;
+        let schedule = schedule.unwrap();
+
+        sleep(Duration::from_millis(1100)).await;
+
+        // the previous leader still thinks it holds the lease
+        assert!(
+            workers::run_due_job_schedules(&ctx.app_data, Some(fence(1)))
+                .await
+                .is_err()
+        );
+        let (_, unchanged) = get_schedule(&ctx, &user_token, schedule.id).await;
+        let unchanged = unchanged.unwrap();
+        assert_eq!(unchanged.next_run_at, schedule.next_run_at);
+        assert_eq!(unchanged.last_job_id, schedule.last_job_id);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use crate::ws::ws_utils;
+    use actix_demo::{
+        models::misc::{Job, JobStatus},
+        utils::shutdown,
+    };
+    use actix_http::{
+        header,
+        ws::{CloseCode, Frame},
+        StatusCode,
+    };
+    use actix_rt::time::sleep;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    async fn run_job(ctx: &TestContext) -> (StatusCode, Option<Job>) {
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .append_header((header::CONTENT_TYPE, "application/json"))
+            .with_token(&ctx._token)
+            .send_body(r#"{"args":[]}"#)
+            .await
+            .unwrap();
+        let status = resp.status();
+        let job = if status == StatusCode::OK {
+            Some(resp.json::<Job>().await.unwrap())
+        } else {
+            None
+        };
+        (status, job)
+    }
+
+    #[actix_rt::test]
+    async fn should_abort_running_jobs_and_refuse_new_ones_at_shutdown() {
+        let options = common::TestAppOptionsBuilder::default()
+            .bin_file(common::sleep_bin_file())
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+
+        let (_, job) = run_job(&ctx).await;
+        let job = job.unwrap();
+        // give the job time to subscribe to its abort channel
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(ctx.app_data.shutdown.running_jobs(), vec![job.job_id]);
+
+        // the sleeper outlives the shutdown timeout, so it gets aborted
+        shutdown::drain(&ctx.app_data).await;
+        assert!(ctx.app_data.shutdown.running_jobs().is_empty());
+
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/cmd/{}", job.job_id))
+            .with_token(&ctx._token)
+            .send()
+            .await
+            .unwrap();
+        let job = resp.json::<Job>().await.unwrap();
+        assert_eq!(job.status, JobStatus::Aborted);
+        assert_eq!(
+            job.status_message.as_deref(),
+            Some("Job aborted: Server is shutting down")
+        );
+
+        let (status, _) = run_job(&ctx).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_rt::test]
+    async fn should_close_websockets_as_going_away_at_shutdown() {
+        let ctx = TestContext::new(None).await;
+        let (_resp, mut ws) =
+            ws_utils::connect_ws(&ctx.addr, &ctx._token, &ctx.client)
+                .await
+                .unwrap();
+
+        let _ = ctx.app_data.shutdown.begin(Duration::from_secs(5));
+
+        let reason = loop {
+            match ws.next().await {
+                Some(Ok(Frame::Ping(_))) => continue,
+                Some(Ok(Frame::Close(reason))) => break reason,
+                other => panic!("Expected a close frame, got {other:?}"),
+            }
+        };
+        assert_eq!(reason.map(|reason| reason.code), Some(CloseCode::Away));
+    }
+
+    #[actix_rt::test]
+    async fn should_pass_the_deadline_once_the_shutdown_timeout_is_over() {
+        let ctx = TestContext::new(None).await;
+        let shutdown = ctx.app_data.shutdown.clone();
+
+        // nothing to wait for before the shutdown began
+        assert!(actix_rt::time::timeout(
+            Duration::from_millis(200),
+            shutdown.deadline_passed()
+        )
+        .await
+        .is_err());
+
+        let started = std::time::Instant::now();
+        let _ = shutdown.begin(Duration::from_secs(1));
+        actix_rt::time::timeout(
+            Duration::from_secs(3),
+            shutdown.deadline_passed(),
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+}
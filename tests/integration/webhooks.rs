@@ -0,0 +1,253 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::models::{
+        misc::{Job, JobStatus},
+        webhook::{
+            hmac_sha256_hex, CreatedWebhook, JobEvent, JobEventPayload,
+            WebhookDelivery, WebhookDeliveryStatus, EVENT_HEADER,
+            SIGNATURE_HEADER, TIMESTAMP_HEADER,
+        },
+    };
+    use actix_demo::utils::webhooks;
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use std::{sync::Mutex, time::Duration};
+
+    #[derive(Debug, Clone)]
+    struct ReceivedDelivery {
+        event: String,
+        timestamp: String,
+        signature: String,
+        body: String,
+    }
+
+    type Received = Mutex<Vec<ReceivedDelivery>>;
+
+    fn header(req: &HttpRequest, name: &str) -> String {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned()
+    }
+
+    async fn receive(
+        req: HttpRequest,
+        body: String,
+        received: web::Data<Received>,
+    ) -> HttpResponse {
+        received.lock().unwrap().push(ReceivedDelivery {
+            event: header(&req, EVENT_HEADER),
+            timestamp: header(&req, TIMESTAMP_HEADER),
+            signature: header(&req, SIGNATURE_HEADER),
+            body,
+        });
+        HttpResponse::Ok().finish()
+    }
+
+    async fn reject() -> HttpResponse {
+        HttpResponse::InternalServerError().finish()
+    }
+
+    async fn create_webhook(
+        ctx: &TestContext,
+        token: &str,
+        body: serde_json::Value,
+    ) -> CreatedWebhook {
+        let mut resp = ctx
+            .test_server
+            .post("/api/webhooks")
+            .with_token(token)
+            .send_json(&body)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        resp.json::<CreatedWebhook>().await.unwrap()
+    }
+
+    async fn list_deliveries(
+        ctx: &TestContext,
+        token: &str,
+        webhook_id: i32,
+    ) -> (StatusCode, Option<Vec<WebhookDelivery>>) {
+        let mut resp = ctx
+            .test_server
+            .get(format!("/api/webhooks/{webhook_id}/deliveries"))
+            .with_token(token)
+            .send()
+            .await
+            .unwrap();
+        let status = resp.status();
+        let deliveries = if status == StatusCode::OK {
+            Some(resp.json::<Vec<WebhookDelivery>>().await.unwrap())
+        } else {
+            None
+        };
+        (status, deliveries)
+    }
+
+    #[actix_rt::test]
+    async fn should_deliver_signed_job_events_and_retry_failures() {
+        let ctx = TestContext::new(None).await;
+        let token = ctx._token.clone();
+
+        // local stand-in for the receiving end of the webhooks
+        let received = web::Data::new(Received::default());
+        let received2 = received.clone();
+        let stand_in = actix_test::start(move || {
+            App::new()
+                .app_data(received2.clone())
+                .route("/hooks", web::post().to(receive))
+                .route("/failing", web::post().to(reject))
+        });
+
+        let resp = ctx
+            .test_server
+            .post("/api/webhooks")
+            .with_token(&token)
+            .send_json(&serde_json::json!({
+                "url": "ftp://example.com/hooks", "events": ["completed"]
+            }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let webhook = create_webhook(
+            &ctx,
+            &token,
+            serde_json::json!({
+                "url": stand_in.url("/hooks"),
+                "events": ["created", "completed"]
+            }),
+        )
+        .await;
+        assert_eq!(webhook.webhook.events, vec!["completed", "created"]);
+        let failing = create_webhook(
+            &ctx,
+            &token,
+            serde_json::json!({
+                "url": stand_in.url("/failing"),
+                "events": ["completed"]
+            }),
+        )
+        .await;
+
+        let mut resp = ctx
+            .test_server
+            .post("/api/cmd")
+            .with_token(&token)
+            .send_json(&serde_json::json!({"args": ["hello"]}))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let job = resp.json::<Job>().await.unwrap();
+        for _ in 0..20 {
+            let mut resp = ctx
+                .test_server
+                .get(format!("/api/cmd/{}", job.job_id))
+                .with_token(&token)
+                .send()
+                .await
+                .unwrap();
+            if resp.json::<Job>().await.unwrap().status != JobStatus::Pending {
+                break;
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        let delivered =
+            webhooks::deliver_due_webhooks(&ctx.app_data).await.unwrap();
+        assert_eq!(delivered, 2);
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        for delivery in &received {
+            let expected = hmac_sha256_hex(
+                &webhook.secret,
+                &format!("{}.{}", delivery.timestamp, delivery.body),
+            );
+            assert_eq!(delivery.signature, format!("sha256={expected}"));
+            let payload =
+                serde_json::from_str::<JobEventPayload>(&delivery.body)
+                    .unwrap();
+            assert_eq!(payload.job.job_id, job.job_id);
+            assert_eq!(payload.event.as_str(), delivery.event);
+        }
+        assert_eq!(received[0].event, JobEvent::Created.as_str());
+        assert_eq!(received[1].event, JobEvent::Completed.as_str());
+
+        let (_, deliveries) =
+            list_deliveries(&ctx, &token, webhook.webhook.id).await;
+        let deliveries = deliveries.unwrap();
+        assert_eq!(deliveries.len(), 2);
+        assert!(deliveries
+            .iter()
+            .all(|d| d.status == WebhookDeliveryStatus::Delivered));
+
+        // failed deliveries are retried with backoff until they run out of
+        // attempts
+        let (_, deliveries) =
+            list_deliveries(&ctx, &token, failing.webhook.id).await;
+        let delivery = deliveries.unwrap().remove(0);
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(500));
+
+        for wait_ms in [1500, 2500] {
+            sleep(Duration::from_millis(wait_ms)).await;
+            let _ =
+                webhooks::deliver_due_webhooks(&ctx.app_data).await.unwrap();
+        }
+        let (_, deliveries) =
+            list_deliveries(&ctx, &token, failing.webhook.id).await;
+        let delivery = deliveries.unwrap().remove(0);
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 3);
+
+        // the delivery log is private to the webhook owner and admins
+        common::create_http_user(&ctx.addr, "hook.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token =
+            common::get_http_token(&ctx.addr, "hook.user", "test", &ctx.client)
+                .await
+                .unwrap();
+        let (status, _) =
+            list_deliveries(&ctx, &user_token, webhook.webhook.id).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn should_refuse_webhooks_to_internal_addresses() {
+        let options = common::TestAppOptionsBuilder::default()
+            .webhook_allow_private_targets(false)
+            .build()
+            .unwrap();
+        let ctx = TestContext::new(Some(options)).await;
+        let token = ctx._token.clone();
+
+        for url in [
+            "http://127.0.0.1/hooks",
+            "http://localhost:8080/hooks",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/hooks",
+            "http://[::1]/hooks",
+        ] {
+            let resp = ctx
+                .test_server
+                .post("/api/webhooks")
+                .with_token(&token)
+                .send_json(&serde_json::json!({
+                    "url": url, "events": ["completed"]
+                }))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{url}");
+        }
+    }
+}
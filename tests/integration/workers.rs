@@ -0,0 +1,145 @@
+use crate::common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{TestContext, WithToken};
+    use actix_demo::{
+        errors::DomainError,
+        models::worker::{WorkerBackoffConfig, WorkerStatus},
+        workers::{Worker, WorkerRegistry},
+    };
+    use actix_http::StatusCode;
+    use actix_rt::time::sleep;
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    fn backoff() -> WorkerBackoffConfig {
+        WorkerBackoffConfig {
+            initial_interval_secs: 1,
+            multiplier: 1.0,
+            max_interval_secs: 1,
+            max_elapsed_time_secs: 30,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn should_report_worker_status_to_admins() {
+        let ctx = TestContext::new(None).await;
+
+        let _workers = common::start_workers(
+            &ctx.app_data,
+            vec![
+                Worker::new(
+                    "test-succeeding",
+                    Duration::from_secs(1),
+                    backoff(),
+                    |_ctx| async { Ok(3) },
+                ),
+                Worker::new(
+                    "test-failing",
+                    Duration::from_secs(1),
+                    backoff(),
+                    |_ctx| async {
+                        Err(DomainError::new_internal_error(
+                            "worker failed".to_owned(),
+                        ))
+                    },
+                )
+                .on_every_instance(),
+            ],
+        );
+        sleep(Duration::from_millis(1500)).await;
+
+        common::create_http_user(&ctx.addr, "worker.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let user_token = common::get_http_token(
+            &ctx.addr,
+            "worker.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+        let resp = ctx
+            .test_server
+            .get("/api/workers")
+            .with_token(&user_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let mut resp = ctx
+            .test_server
+            .get("/api/workers")
+            .with_token(&ctx._token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let statuses = resp.json::<Vec<WorkerStatus>>().await.unwrap();
+        let names: Vec<_> =
+            statuses.iter().map(|status| status.name.as_str()).collect();
+        assert_eq!(names, vec!["test-failing", "test-succeeding"]);
+
+        let failing = &statuses[0];
+        assert!(failing.leader.is_none());
+        assert!(failing.run.last_success_at.is_none());
+        assert!(failing
+            .run
+            .last_error
+            .as_deref()
+            .is_some_and(|err| err.contains("worker failed")));
+        assert!(failing.run.consecutive_failures >= 1);
+
+        let succeeding = &statuses[1];
+        let leader = succeeding.leader.as_ref().unwrap();
+        assert_eq!(leader.fencing_token, 1);
+        assert!(leader.lease_expires_in_ms > 0);
+        assert_eq!(
+            succeeding.run.instance_id.as_deref(),
+            Some(leader.instance_id.as_str())
+        );
+        assert_eq!(succeeding.run.last_processed, Some(3));
+        assert!(succeeding.run.last_error.is_none());
+        assert_eq!(succeeding.run.consecutive_failures, 0);
+    }
+
+    #[actix_rt::test]
+    async fn should_run_leader_only_workers_on_a_single_instance() {
+        let ctx = TestContext::new(None).await;
+        let runs: Rc<RefCell<Vec<(String, Option<u64>)>>> = Rc::default();
+
+        let mut _workers = Vec::new();
+        for _ in 0..2 {
+            let mut registry = WorkerRegistry::new(
+                ctx.app_data.redis_conn_manager.clone(),
+                (ctx.app_data.redis_prefix)(&"workers"),
+            );
+            let runs = runs.clone();
+            let _ = registry.register(Worker::new(
+                "test-leader-only",
+                Duration::from_secs(1),
+                backoff(),
+                move |worker_ctx| {
+                    runs.borrow_mut().push((
+                        worker_ctx.instance_id.clone(),
+                        worker_ctx.fencing_token,
+                    ));
+                    async { Ok(0) }
+                },
+            ));
+            _workers.extend(registry.start(ctx.app_data.shutdown.token()));
+        }
+        sleep(Duration::from_millis(3500)).await;
+
+        let runs = runs.borrow();
+        assert!(runs.len() >= 3, "Expected a run per interval: {runs:?}");
+        assert!(
+            runs.iter().all(|run| run == &runs[0]),
+            "Expected every run on the leader with the same token: {runs:?}"
+        );
+        assert_eq!(runs[0].1, Some(1));
+    }
+}
@@ -5,7 +5,7 @@ use crate::common;
 
 use actix_codec::Framed;
 use actix_demo::models::users::UserId;
-use actix_demo::models::ws::{WsClientEvent, WsServerEvent};
+use actix_demo::models::ws::{WsClientEvent, WsClientFrame, WsServerEvent};
 use actix_demo::utils;
 use actix_http::header;
 use actix_http::ws::{Codec, Frame};
@@ -37,6 +37,34 @@ pub mod ws_utils {
             .map_err(|err| anyhow!("{err}"))
     }
 
+    /// Connects offering the given protocol versions
+    pub async fn connect_ws_with_protocols(
+        addr: &str,
+        token: &str,
+        client: &Client,
+        protocols: &[&str],
+    ) -> anyhow::Result<(ClientResponse, WsClient)> {
+        client
+            .ws(format!("http://{addr}/ws"))
+            .protocols(protocols)
+            .cookie(Cookie::new("X-AUTH-TOKEN", token))
+            .connect()
+            .await
+            .map_err(|err| anyhow!("{err}"))
+    }
+
+    /// Client event in the envelope of protocol v2
+    pub fn ws_frame(request_id: &str, event: &WsClientEvent) -> Message {
+        ws_msg_raw(&utils::jstr(&WsClientFrame {
+            request_id: Some(request_id.to_owned()),
+            event: event.clone(),
+        }))
+    }
+
+    pub fn ws_msg_raw(msg: &str) -> Message {
+        Message::Text(ByteString::from(msg))
+    }
+
     pub async fn ws_take_one(
         ws: &mut WsClient,
     ) -> anyhow::Result<WsServerEvent> {
@@ -108,9 +136,7 @@ mod tests {
         let msg = ws_take_one(&mut ws).await.unwrap();
 
         if let WsServerEvent::SentMessage {
-            id: _,
-            sender,
-            message,
+            sender, message, ..
         } = msg
         {
             assert_eq!(sender.as_uint(), 1);
@@ -150,7 +176,7 @@ mod tests {
             .unwrap();
         let job_resp = resp.json::<Job>().await.unwrap();
         let job_id = job_resp.job_id;
-        assert_eq!(job_resp.started_by, user_id);
+        assert_eq!(job_resp.started_by, Some(user_id));
         assert_eq!(job_resp.status, JobStatus::Pending);
 
         let _ = tracing::info!(
@@ -208,7 +234,7 @@ mod tests {
             .await
             .unwrap();
         let job_resp = resp.json::<Job>().await.unwrap();
-        assert_eq!(job_resp.started_by, user_id);
+        assert_eq!(job_resp.started_by, Some(user_id));
         assert_eq!(job_resp.status, JobStatus::Completed);
 
         let _ = tracing::info!("Verified that job status was set to completed");
@@ -246,7 +272,7 @@ mod tests {
             .unwrap();
         let job_resp = resp.json::<Job>().await.unwrap();
         let job_id = job_resp.job_id;
-        assert_eq!(job_resp.started_by, user_id);
+        assert_eq!(job_resp.started_by, Some(user_id));
         assert_eq!(job_resp.status, JobStatus::Pending);
 
         ws.send(ws_msg(&WsClientEvent::SubscribeJob { job_id }))
@@ -277,7 +303,7 @@ mod tests {
             .await
             .unwrap();
         let job_resp = resp.json::<Job>().await.unwrap();
-        assert_eq!(job_resp.started_by, user_id);
+        assert_eq!(job_resp.started_by, Some(user_id));
         assert_eq!(job_resp.status, JobStatus::Aborted);
     }
 
@@ -319,4 +345,90 @@ mod tests {
             panic!("error wrong message type: {:?}", msg);
         }
     }
+
+    #[actix_rt::test]
+    async fn should_answer_commands_with_acks_under_protocol_v2() {
+        let ctx = common::TestContext::new(None).await;
+        common::create_http_user(&ctx.addr, "ws.v2.user", "test", &ctx.client)
+            .await
+            .unwrap();
+        let token = common::get_http_token(
+            &ctx.addr,
+            "ws.v2.user",
+            "test",
+            &ctx.client,
+        )
+        .await
+        .unwrap();
+
+        // clients offering only unknown protocols are turned away
+        assert!(connect_ws_with_protocols(
+            &ctx.addr,
+            &token,
+            &ctx.client,
+            &["chat"]
+        )
+        .await
+        .is_err());
+
+        let (resp, mut ws) = connect_ws_with_protocols(
+            &ctx.addr,
+            &token,
+            &ctx.client,
+            &["actix-demo.v1", "actix-demo.v2"],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            resp.headers().get(header::SEC_WEBSOCKET_PROTOCOL).unwrap(),
+            "actix-demo.v2"
+        );
+
+        let send = |message: &str| WsClientEvent::SendMessage {
+            receiver: UserId::from_str("1").unwrap(),
+            message: message.to_owned(),
+        };
+        ws.send(ws_frame("r1", &send("hello"))).await.unwrap();
+        match ws_take_one(&mut ws).await.unwrap() {
+            WsServerEvent::Ack {
+                request_id,
+                message_id,
+            } => {
+                assert_eq!(request_id.as_deref(), Some("r1"));
+                assert!(message_id.is_some());
+            }
+            other => panic!("expected an ack, got {other:?}"),
+        }
+
+        ws.send(ws_frame("r2", &send(" "))).await.unwrap();
+        match ws_take_one(&mut ws).await.unwrap() {
+            WsServerEvent::Nack { request_id, .. } => {
+                assert_eq!(request_id.as_deref(), Some("r2"));
+            }
+            other => panic!("expected a nack, got {other:?}"),
+        }
+
+        // frames that fail to parse are rejected with their request id
+        ws.send(ws_msg_raw(r#"{"request_id":"r3","kind":"Unknown"}"#))
+            .await
+            .unwrap();
+        match ws_take_one(&mut ws).await.unwrap() {
+            WsServerEvent::Nack { request_id, .. } => {
+                assert_eq!(request_id.as_deref(), Some("r3"));
+            }
+            other => panic!("expected a nack, got {other:?}"),
+        }
+
+        let mut resp = ctx
+            .test_server
+            .get("/api/public/ws/schema")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let schema = resp.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(schema["protocols"][1], "actix-demo.v2");
+        assert!(schema["client"].is_object());
+        assert!(schema["server"].is_object());
+    }
 }